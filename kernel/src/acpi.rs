@@ -0,0 +1,590 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use heapless::Vec;
+use x86_64::instructions::port::Port;
+use spin::Mutex;
+use crate::{aml, console, klog, memory, wait};
+
+const SDT_HEADER_LEN: usize = 36;
+const MAX_TABLES: usize = 32;
+
+#[derive(Copy, Clone)]
+pub struct TableInfo {
+    pub signature: [u8; 4],
+    pub phys: u64,
+    pub length: u32,
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct GenericAddress {
+    pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct Fadt {
+    pub revision: u8,
+    pub facs: u64,
+    pub dsdt: u64,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm1_evt_len: u8,
+    pub gpe0_blk: u32,
+    pub gpe1_blk: u32,
+    pub gpe0_blk_len: u8,
+    pub gpe1_blk_len: u8,
+    pub gpe1_base: u8,
+    pub flags: u32,
+    pub reset_reg: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+pub const FADT_RESET_REG_SUP: u32 = 1 << 10;
+
+struct AcpiState {
+    revision: u8,
+    tables: Vec<TableInfo, MAX_TABLES>,
+    fadt: Option<Fadt>,
+}
+
+static ACPI: Mutex<Option<AcpiState>> = Mutex::new(None);
+
+fn read_bytes<const N: usize>(phys: u64) -> Option<[u8; N]> {
+    let virt = memory::phys_to_virt(phys)?;
+    let mut out = [0u8; N];
+    unsafe { core::ptr::copy_nonoverlapping(virt as *const u8, out.as_mut_ptr(), N) };
+    Some(out)
+}
+
+pub fn read_u8(phys: u64) -> Option<u8> {
+    read_bytes::<1>(phys).map(|b| b[0])
+}
+
+pub fn read_u16(phys: u64) -> Option<u16> {
+    read_bytes::<2>(phys).map(u16::from_le_bytes)
+}
+
+pub fn read_u32(phys: u64) -> Option<u32> {
+    read_bytes::<4>(phys).map(u32::from_le_bytes)
+}
+
+pub fn read_u64(phys: u64) -> Option<u64> {
+    read_bytes::<8>(phys).map(u64::from_le_bytes)
+}
+
+fn checksum_ok(phys: u64, len: usize) -> bool {
+    let Some(virt) = memory::phys_to_virt(phys) else { return false; };
+    let bytes = unsafe { core::slice::from_raw_parts(virt as *const u8, len) };
+    bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == 0
+}
+
+fn read_gas(phys: u64) -> Option<GenericAddress> {
+    let raw = read_bytes::<12>(phys)?;
+    let mut addr = [0u8; 8];
+    addr.copy_from_slice(&raw[4..12]);
+    Some(GenericAddress {
+        space_id: raw[0],
+        bit_width: raw[1],
+        bit_offset: raw[2],
+        access_size: raw[3],
+        address: u64::from_le_bytes(addr),
+    })
+}
+
+fn parse_fadt(phys: u64, len: u32) -> Option<Fadt> {
+    let revision = read_u8(phys + 8)?;
+    let mut fadt = Fadt {
+        revision,
+        facs: read_u32(phys + 36)? as u64,
+        dsdt: read_u32(phys + 40)? as u64,
+        sci_int: read_u16(phys + 46)?,
+        smi_cmd: read_u32(phys + 48)?,
+        acpi_enable: read_u8(phys + 52)?,
+        acpi_disable: read_u8(phys + 53)?,
+        pm1a_evt_blk: read_u32(phys + 56)?,
+        pm1b_evt_blk: read_u32(phys + 60)?,
+        pm1a_cnt_blk: read_u32(phys + 64)?,
+        pm1b_cnt_blk: read_u32(phys + 68)?,
+        pm1_evt_len: read_u8(phys + 88)?,
+        gpe0_blk: read_u32(phys + 80)?,
+        gpe1_blk: read_u32(phys + 84)?,
+        gpe0_blk_len: read_u8(phys + 92)?,
+        gpe1_blk_len: read_u8(phys + 93)?,
+        gpe1_base: read_u8(phys + 94)?,
+        flags: 0,
+        reset_reg: None,
+        reset_value: 0,
+    };
+    if len >= 116 {
+        fadt.flags = read_u32(phys + 112)?;
+    }
+    if len >= 129 {
+        fadt.reset_reg = read_gas(phys + 116);
+        fadt.reset_value = read_u8(phys + 128)?;
+    }
+    if len >= 148 {
+        let x_facs = read_u64(phys + 140)?;
+        if x_facs != 0 {
+            fadt.facs = x_facs;
+        }
+    }
+    if len >= 156 {
+        let x_dsdt = read_u64(phys + 148)?;
+        if x_dsdt != 0 {
+            fadt.dsdt = x_dsdt;
+        }
+    }
+    Some(fadt)
+}
+
+pub fn init(rsdp_phys: Option<u64>) {
+    let Some(rsdp) = rsdp_phys else { return; };
+    let Some(sig) = read_bytes::<8>(rsdp) else { return; };
+    if &sig != b"RSD PTR " || !checksum_ok(rsdp, 20) {
+        return;
+    }
+    let revision = read_u8(rsdp + 15).unwrap_or(0);
+
+    let (root, entry_size) = match (revision >= 2, read_u64(rsdp + 24)) {
+        (true, Some(xsdt)) if xsdt != 0 => (xsdt, 8),
+        _ => (read_u32(rsdp + 16).unwrap_or(0) as u64, 4),
+    };
+    if root == 0 {
+        return;
+    }
+
+    let root_len = read_u32(root + 4).unwrap_or(0) as usize;
+    if root_len < SDT_HEADER_LEN || !checksum_ok(root, root_len) {
+        return;
+    }
+
+    let mut state = AcpiState { revision, tables: Vec::new(), fadt: None };
+    let count = (root_len - SDT_HEADER_LEN) / entry_size;
+    for i in 0..count {
+        let entry = root + (SDT_HEADER_LEN + i * entry_size) as u64;
+        let phys = if entry_size == 8 {
+            read_u64(entry).unwrap_or(0)
+        } else {
+            read_u32(entry).unwrap_or(0) as u64
+        };
+        if phys == 0 {
+            continue;
+        }
+        let (Some(signature), Some(length)) = (read_bytes::<4>(phys), read_u32(phys + 4)) else {
+            continue;
+        };
+        if &signature == b"FACP" {
+            state.fadt = parse_fadt(phys, length);
+            if let Some(dsdt) = state.fadt.map(|f| f.dsdt).filter(|d| *d != 0) {
+                if let (Some(sig), Some(len)) = (read_bytes::<4>(dsdt), read_u32(dsdt + 4)) {
+                    let _ = state.tables.push(TableInfo { signature: sig, phys: dsdt, length: len });
+                }
+            }
+        }
+        let _ = state.tables.push(TableInfo { signature, phys, length });
+    }
+
+    if let Some(f) = state.fadt {
+        if f.sci_int < 16 {
+            SCI_IRQ.store(f.sci_int as u8, Ordering::Relaxed);
+        }
+    }
+    *ACPI.lock() = Some(state);
+}
+
+pub fn is_available() -> bool {
+    ACPI.lock().is_some()
+}
+
+pub fn fadt() -> Option<Fadt> {
+    ACPI.lock().as_ref().and_then(|s| s.fadt)
+}
+
+pub fn tables() -> Vec<TableInfo, MAX_TABLES> {
+    ACPI.lock().as_ref().map(|s| s.tables.clone()).unwrap_or_default()
+}
+
+pub fn find_table(signature: &[u8; 4]) -> Option<TableInfo> {
+    ACPI.lock()
+        .as_ref()
+        .and_then(|s| s.tables.iter().find(|t| &t.signature == signature).copied())
+}
+
+/// Returns the body of a table (everything after the SDT header) as a byte slice.
+pub fn table_body(table: &TableInfo) -> Option<&'static [u8]> {
+    let len = (table.length as usize).checked_sub(SDT_HEADER_LEN)?;
+    let virt = memory::phys_to_virt(table.phys + SDT_HEADER_LEN as u64)?;
+    Some(unsafe { core::slice::from_raw_parts(virt as *const u8, len) })
+}
+
+/// SLP_TYPa and SLP_TYPb for sleep state `state`, from the `\_Sx` package.
+pub fn sleep_type(state: u8) -> Option<(u8, u8)> {
+    if let Ok(aml::Value::Package(items)) = aml::evaluate(&format!("\\_S{}", state), &[]) {
+        if let Some(Ok(a)) = items.first().map(aml::Value::int) {
+            let b = items.get(1).and_then(|v| v.int().ok()).unwrap_or(0);
+            return Some((a as u8, b as u8));
+        }
+    }
+    scan_sleep_type(state)
+}
+
+/// Scans the DSDT for `Name(_Sx_, Package() { SLP_TYPa, SLP_TYPb, ... })`,
+/// for packages the interpreter could not evaluate.
+fn scan_sleep_type(state: u8) -> Option<(u8, u8)> {
+    let dsdt = find_table(b"DSDT")?;
+    let aml = table_body(&dsdt)?;
+    let name = [b'_', b'S', b'0' + state, b'_'];
+
+    let mut i = 0;
+    while i + 4 < aml.len() {
+        if aml[i..i + 4] != name {
+            i += 1;
+            continue;
+        }
+        let named = (i >= 1 && aml[i - 1] == 0x08) || (i >= 2 && aml[i - 2] == 0x08 && aml[i - 1] == b'\\');
+        if !named || aml.get(i + 4) != Some(&0x12) {
+            i += 1;
+            continue;
+        }
+        let mut p = i + 5;
+        let pkg_len_bytes = ((*aml.get(p)? >> 6) & 0x3) as usize;
+        p += pkg_len_bytes + 2;
+        let (a, next) = read_small_int(aml, p)?;
+        let (b, _) = read_small_int(aml, next).unwrap_or((0, next));
+        return Some((a, b));
+    }
+    None
+}
+
+/// Reads an AML integer (Zero, One or a Byte/Word/DWord/QWord constant) that
+/// must fit a byte, as sleep types do.
+fn read_small_int(aml: &[u8], p: usize) -> Option<(u8, usize)> {
+    let width = match *aml.get(p)? {
+        0x00 => return Some((0, p + 1)),
+        0x01 => return Some((1, p + 1)),
+        0x0A => 1,
+        0x0B => 2,
+        0x0C => 4,
+        0x0E => 8,
+        _ => return None,
+    };
+    let bytes = aml.get(p + 1..p + 1 + width)?;
+    if bytes[1..].iter().any(|&b| b != 0) {
+        return None;
+    }
+    Some((bytes[0], p + 1 + width))
+}
+
+/// Writes a byte to a Generic Address Structure register (I/O, memory, or PCI config).
+pub fn write_register(reg: &GenericAddress, value: u8) {
+    match reg.space_id {
+        0 => {
+            if let Some(virt) = memory::phys_to_virt(reg.address) {
+                unsafe { core::ptr::write_volatile(virt as *mut u8, value) };
+            }
+        }
+        1 => unsafe { Port::<u8>::new(reg.address as u16).write(value) },
+        2 => {
+            let dev = ((reg.address >> 32) & 0x1F) as u32;
+            let func = ((reg.address >> 16) & 0x7) as u32;
+            let offset = (reg.address & 0xFF) as u32;
+            let addr = 0x8000_0000 | (dev << 11) | (func << 8) | (offset & 0xFC);
+            unsafe {
+                Port::<u32>::new(0xCF8).write(addr);
+                Port::<u8>::new(0xCFC + (offset & 3) as u16).write(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 7 << PM1_SLP_TYP_SHIFT;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+fn write_sleep_type(cnt_blk: u32, typ: u8) {
+    let mut port = Port::<u16>::new(cnt_blk as u16);
+    unsafe {
+        let cnt = port.read() & !PM1_SLP_TYP_MASK;
+        port.write(cnt | ((typ as u16) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN);
+    }
+}
+
+/// Enters sleep state `state` the ACPI way: `\_PTS`, then SLP_TYP and SLP_EN
+/// in the PM1 control registers. If the machine is still running a moment
+/// later, `\_WAK` undoes `\_PTS` and this returns why.
+pub fn enter_sleep_state(state: u8) -> Result<(), &'static str> {
+    let fadt = fadt().ok_or("no FADT")?;
+    let (typ_a, typ_b) = sleep_type(state).ok_or("no sleep package for this state")?;
+    if aml::exists("\\_PTS") {
+        if let Err(e) = aml::evaluate("\\_PTS", &[state as u64]) {
+            klog::warn(&format!("acpi: \\_PTS({}) failed: {}", state, e.describe()));
+        }
+    }
+    write_sleep_type(fadt.pm1a_cnt_blk, typ_a);
+    if fadt.pm1b_cnt_blk != 0 {
+        write_sleep_type(fadt.pm1b_cnt_blk, typ_b);
+    }
+    wait::micros(500_000);
+    if aml::exists("\\_WAK") {
+        let _ = aml::evaluate("\\_WAK", &[state as u64]);
+    }
+    Err("firmware ignored the sleep request")
+}
+
+const PM1_PWRBTN: u16 = 1 << 8;
+const PM1_CNT_SCI_EN: u16 = 1 << 0;
+
+static POWER_BUTTON_PENDING: AtomicBool = AtomicBool::new(false);
+// Cached for the SCI handler so it never has to take the ACPI lock.
+static SCI_IRQ: AtomicU8 = AtomicU8::new(0xFF);
+static PM1A_EVT: AtomicU32 = AtomicU32::new(0);
+static PM1B_EVT: AtomicU32 = AtomicU32::new(0);
+// GPE blocks as (port, length, first GPE number), zero length when absent.
+static GPE0: AtomicU32 = AtomicU32::new(0);
+static GPE1: AtomicU32 = AtomicU32::new(0);
+/// GPEs the SCI handler masked and left for `dispatch_gpes`, one bit each.
+static GPE_PENDING: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+/// IRQ line the SCI is wired to on the legacy PIC, if it fits there.
+pub fn sci_irq() -> Option<u8> {
+    match SCI_IRQ.load(Ordering::Relaxed) {
+        0xFF => None,
+        irq => Some(irq),
+    }
+}
+
+fn pm1_enable_port(blk: u32, evt_len: u8) -> Port<u16> {
+    Port::new((blk + (evt_len as u32 / 2)) as u16)
+}
+
+/// Packs a GPE block for the SCI handler: port in the low 16 bits, status
+/// register count (half the block) next, then the first GPE number.
+fn pack_gpe(blk: u32, len: u8, base: u8) -> u32 {
+    if blk == 0 || blk > 0xFFFF || len < 2 {
+        return 0;
+    }
+    blk | ((len as u32 / 2) << 16) | ((base as u32) << 24)
+}
+
+fn unpack_gpe(packed: u32) -> Option<(u16, u16, u16)> {
+    (packed != 0).then_some((packed as u16, (packed >> 16) as u8 as u16, (packed >> 24) as u16))
+}
+
+fn gpe_blocks() -> [Option<(u16, u16, u16)>; 2] {
+    [unpack_gpe(GPE0.load(Ordering::Relaxed)), unpack_gpe(GPE1.load(Ordering::Relaxed))]
+}
+
+/// The handler method for a GPE, `\_GPE._Lxx` (level) or `_Exx` (edge).
+fn gpe_method(gpe: u16) -> Option<String> {
+    ["L", "E"].iter().map(|kind| format!("\\_GPE._{}{:02X}", kind, gpe)).find(|m| aml::exists(m))
+}
+
+/// Sets or clears a GPE's enable bit.
+fn gpe_enable(gpe: u16, on: bool) {
+    for (port, half, base) in gpe_blocks().into_iter().flatten() {
+        if gpe < base || gpe >= base + half * 8 {
+            continue;
+        }
+        let i = (gpe - base) / 8;
+        let bit = 1u8 << ((gpe - base) % 8);
+        unsafe {
+            let mut en = Port::<u8>::new(port + half + i);
+            let cur = en.read();
+            en.write(if on { cur | bit } else { cur & !bit });
+        }
+    }
+}
+
+fn gpe_clear(gpe: u16) {
+    for (port, half, base) in gpe_blocks().into_iter().flatten() {
+        if gpe >= base && gpe < base + half * 8 {
+            unsafe { Port::<u8>::new(port + (gpe - base) / 8).write(1 << ((gpe - base) % 8)) };
+        }
+    }
+}
+
+/// Masks every GPE and clears its status, then enables the ones the
+/// firmware has a handler method for. Anything else left enabled would keep
+/// the SCI asserting with nobody to clear it.
+fn arm_gpes(fadt: &Fadt) {
+    GPE0.store(pack_gpe(fadt.gpe0_blk, fadt.gpe0_blk_len, 0), Ordering::Relaxed);
+    GPE1.store(pack_gpe(fadt.gpe1_blk, fadt.gpe1_blk_len, fadt.gpe1_base), Ordering::Relaxed);
+    let mut armed = 0;
+    for (port, half, base) in gpe_blocks().into_iter().flatten() {
+        for i in 0..half {
+            unsafe {
+                Port::<u8>::new(port + half + i).write(0);
+                Port::<u8>::new(port + i).write(0xFF);
+            }
+        }
+        for gpe in base..base + half * 8 {
+            if gpe_method(gpe).is_some() {
+                gpe_enable(gpe, true);
+                armed += 1;
+            }
+        }
+    }
+    if armed > 0 {
+        klog::info(&format!("acpi: {} GPEs armed", armed));
+    }
+}
+
+/// Switches the chipset into ACPI mode, arms the fixed power button event
+/// and the GPEs the firmware handles.
+pub fn enable_events() -> bool {
+    let Some(fadt) = fadt() else { return false; };
+    if fadt.pm1a_evt_blk == 0 || fadt.pm1a_cnt_blk == 0 {
+        return false;
+    }
+
+    unsafe {
+        let mut cnt = Port::<u16>::new(fadt.pm1a_cnt_blk as u16);
+        if cnt.read() & PM1_CNT_SCI_EN == 0 && fadt.smi_cmd != 0 && fadt.acpi_enable != 0 {
+            Port::<u8>::new(fadt.smi_cmd as u16).write(fadt.acpi_enable);
+            crate::wait::until_micros(1_000_000, || cnt.read() & PM1_CNT_SCI_EN != 0);
+        }
+
+        for blk in [fadt.pm1a_evt_blk, fadt.pm1b_evt_blk] {
+            if blk == 0 {
+                continue;
+            }
+            Port::<u16>::new(blk as u16).write(PM1_PWRBTN);
+            let mut en = pm1_enable_port(blk, fadt.pm1_evt_len);
+            let cur = en.read();
+            en.write(cur | PM1_PWRBTN);
+        }
+    }
+    PM1A_EVT.store(fadt.pm1a_evt_blk, Ordering::Relaxed);
+    PM1B_EVT.store(fadt.pm1b_evt_blk, Ordering::Relaxed);
+    arm_gpes(&fadt);
+    true
+}
+
+pub fn handle_sci() {
+    let blocks = [PM1A_EVT.load(Ordering::Relaxed), PM1B_EVT.load(Ordering::Relaxed)];
+    for blk in blocks {
+        if blk == 0 {
+            continue;
+        }
+        unsafe {
+            let mut sts = Port::<u16>::new(blk as u16);
+            if sts.read() & PM1_PWRBTN != 0 {
+                sts.write(PM1_PWRBTN);
+                POWER_BUTTON_PENDING.store(true, Ordering::Release);
+            }
+        }
+    }
+    // Fired GPEs are masked and acknowledged here, and their AML handlers
+    // run later from the shell loop, where the interpreter may take locks.
+    for (port, half, base) in gpe_blocks().into_iter().flatten() {
+        for i in 0..half {
+            unsafe {
+                let mut sts = Port::<u8>::new(port + i);
+                let mut en = Port::<u8>::new(port + half + i);
+                let enabled = en.read();
+                let fired = sts.read() & enabled;
+                if fired == 0 {
+                    continue;
+                }
+                en.write(enabled & !fired);
+                sts.write(fired);
+                let first = (base + i * 8) as usize;
+                for bit in 0..8 {
+                    if fired & (1 << bit) != 0 {
+                        let gpe = first + bit;
+                        if let Some(word) = GPE_PENDING.get(gpe / 64) {
+                            word.fetch_or(1 << (gpe % 64), Ordering::AcqRel);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs the AML handlers of GPEs the SCI handler set aside, then re-enables
+/// them. A GPE whose handler fails stays masked rather than firing forever.
+/// Returns the device notifications the handlers raised.
+pub fn dispatch_gpes() -> alloc::vec::Vec<(String, u64)> {
+    let mut ran = false;
+    for (word, pending) in GPE_PENDING.iter().enumerate() {
+        let mut bits = pending.swap(0, Ordering::AcqRel);
+        while bits != 0 {
+            let gpe = (word * 64 + bits.trailing_zeros() as usize) as u16;
+            bits &= bits - 1;
+            ran = true;
+            let Some(method) = gpe_method(gpe) else { continue; };
+            match aml::evaluate(&method, &[]) {
+                Ok(_) => {
+                    gpe_clear(gpe);
+                    gpe_enable(gpe, true);
+                }
+                Err(e) => klog::warn(&format!("acpi: {} failed, GPE {:#x} left masked: {}", method, gpe, e.describe())),
+            }
+        }
+    }
+    if ran {
+        aml::take_notifications()
+    } else {
+        alloc::vec::Vec::new()
+    }
+}
+
+pub fn take_power_button_event() -> bool {
+    POWER_BUTTON_PENDING.swap(false, Ordering::AcqRel)
+}
+
+pub fn facs_waking_vector() -> Option<u64> {
+    let facs = fadt()?.facs;
+    if facs == 0 || read_bytes::<4>(facs)? != *b"FACS" {
+        return None;
+    }
+    let x_vector = read_u64(facs + 24).unwrap_or(0);
+    if x_vector != 0 {
+        return Some(x_vector);
+    }
+    read_u32(facs + 12).map(|v| v as u64)
+}
+
+/// `s3check`: how far the firmware supports suspend-to-RAM. There is no
+/// suspend command until a resume trampoline exists, so this always ends in
+/// an error: the machine cannot be suspended either way.
+pub fn s3check_cmd() -> crate::commands::Status {
+    if !is_available() {
+        console::write_err("s3check: ACPI tables not found.");
+        return Err(1);
+    }
+    let Some(fadt) = fadt() else {
+        console::write_err("s3check: FADT not found.");
+        return Err(1);
+    };
+    let Some((typ_a, typ_b)) = sleep_type(3) else {
+        console::write_err("s3check: firmware does not advertise S3 (no \\_S3 package).");
+        return Err(1);
+    };
+
+    console::write_line(&format!(
+        "S3 sleep type: a={:#x} b={:#x}, PM1a_CNT={:#x}",
+        typ_a, typ_b, fadt.pm1a_cnt_blk
+    ));
+    if facs_waking_vector().is_none() {
+        console::write_err("s3check: FACS not found, cannot install a waking vector.");
+        return Err(1);
+    }
+    // Entering S3 without a real-mode resume trampoline would turn every wake into a cold boot.
+    console::write_err("s3check: the firmware supports S3, but there is no resume path to suspend with.");
+    Err(1)
+}
@@ -0,0 +1,1101 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::{acpi, console, klog, memory, paging, wait};
+
+// A small AML interpreter. It loads the namespace from the DSDT and SSDTs
+// (names, methods, operation regions and their fields) and runs methods that
+// stick to integer arithmetic, control flow and field access in system
+// memory or I/O space, which is what \_PTS, \_WAK and the \_Sx packages
+// usually need. Anything outside that fails the evaluation with the opcode
+// instead of guessing.
+//
+// Paths are kept as "\" followed by the 4-byte name segments, with no dots:
+// "\_SB_PCI0" for \_SB.PCI0.
+
+const MAX_OBJECTS: usize = 2048;
+const MAX_DEPTH: usize = 16;
+const MAX_STEPS: usize = 200_000;
+/// Total Sleep and Stall time in one evaluation, so a Sleep inside a While
+/// can't keep the machine waiting for minutes before the step limit hits.
+const MAX_WAIT_US: u64 = 10_000_000;
+/// Largest buffer or package built at run time, against corrupt sizes.
+const MAX_ELEMENTS: u64 = 0x10000;
+
+#[derive(Clone, Copy)]
+pub enum AmlError {
+    NoTables,
+    Malformed,
+    NotFound,
+    /// The opcode, with extended opcodes as 0x5Bxx.
+    Unsupported(u16),
+    /// An operation region in a space other than memory or I/O ports.
+    Space(u8),
+    /// Too deep a call chain, too many steps or too long a wait, probably a
+    /// loop.
+    Limit,
+    /// System memory that is not RAM in the memory map or not mapped.
+    Unmapped(u64),
+}
+
+impl AmlError {
+    pub fn describe(self) -> String {
+        match self {
+            AmlError::NoTables => String::from("no DSDT"),
+            AmlError::Malformed => String::from("malformed AML"),
+            AmlError::NotFound => String::from("name not found"),
+            AmlError::Unsupported(op) => format!("unsupported opcode {:#x}", op),
+            AmlError::Space(space) => format!("unsupported region space {}", space),
+            AmlError::Limit => String::from("step, depth or wait limit reached"),
+            AmlError::Unmapped(addr) => format!("memory at {:#x} is not mapped RAM", addr),
+        }
+    }
+}
+
+type Result<T> = core::result::Result<T, AmlError>;
+
+#[derive(Clone)]
+pub enum Value {
+    Int(u64),
+    Str(String),
+    Buffer(Vec<u8>),
+    Package(Vec<Value>),
+}
+
+impl Value {
+    pub fn int(&self) -> Result<u64> {
+        match self {
+            Value::Int(v) => Ok(*v),
+            Value::Buffer(b) => Ok(b.iter().take(8).rev().fold(0, |acc, &x| acc << 8 | x as u64)),
+            _ => Err(AmlError::Malformed),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Value::Int(v) => format!("{:#x}", v),
+            Value::Str(s) => format!("\"{}\"", s),
+            Value::Buffer(b) => format!("Buffer({} bytes)", b.len()),
+            Value::Package(items) => {
+                let inner: Vec<String> = items.iter().map(Value::describe).collect();
+                format!("Package {{ {} }}", inner.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Object {
+    /// A `Name` whose data object has not been evaluated; the slice starts
+    /// at the data object. The string is the scope it was declared in.
+    Name(&'static [u8], String),
+    /// A `Name` after something was stored in it.
+    Value(Value),
+    Method { body: &'static [u8], args: u8 },
+    Region { space: u8, offset: u64 },
+    Field { region: String, bit_offset: u64, bit_len: u64, flags: u8 },
+    /// Devices, mutexes and the like: they exist, but hold no value.
+    Other,
+}
+
+struct Namespace {
+    objects: Vec<(String, Object)>,
+    /// All ones, for 32-bit integers in revision 1 tables.
+    ones: u64,
+}
+
+impl Namespace {
+    fn get(&self, path: &str) -> Option<&Object> {
+        self.objects.iter().find(|(p, _)| p == path).map(|(_, o)| o)
+    }
+
+    fn insert(&mut self, path: String, object: Object) {
+        if let Some(slot) = self.objects.iter_mut().find(|(p, _)| *p == path) {
+            slot.1 = object;
+        } else if self.objects.len() < MAX_OBJECTS {
+            self.objects.push((path, object));
+        }
+    }
+
+    /// Finds `name` as seen from `scope`. A single bare segment is searched
+    /// for in each enclosing scope in turn, as the spec asks.
+    fn lookup(&self, scope: &str, name: &NameRef) -> Option<String> {
+        let exact = name.resolve(scope);
+        if name.root || name.up > 0 || name.segs.len() != 1 {
+            return self.get(&exact).map(|_| exact);
+        }
+        let mut s = String::from(scope);
+        loop {
+            let mut candidate = s.clone();
+            candidate.push_str(seg_str(&name.segs[0]));
+            if self.get(&candidate).is_some() {
+                return Some(candidate);
+            }
+            if s.len() <= 1 {
+                return None;
+            }
+            s.truncate(s.len() - 4);
+        }
+    }
+}
+
+static NAMESPACE: Mutex<Option<Namespace>> = Mutex::new(None);
+/// Device and value of each `Notify` run since the last `take_notifications`.
+static NOTIFICATIONS: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+const MAX_NOTIFICATIONS: usize = 16;
+
+fn seg_str(seg: &[u8; 4]) -> &str {
+    core::str::from_utf8(seg).unwrap_or("____")
+}
+
+struct NameRef {
+    root: bool,
+    up: usize,
+    segs: Vec<[u8; 4]>,
+}
+
+impl NameRef {
+    fn resolve(&self, scope: &str) -> String {
+        let mut path = if self.root {
+            String::from("\\")
+        } else {
+            let mut s = String::from(scope);
+            let keep = s.len().saturating_sub(self.up * 4).max(1);
+            s.truncate(keep);
+            s
+        };
+        for seg in &self.segs {
+            path.push_str(seg_str(seg));
+        }
+        path
+    }
+}
+
+fn byte(aml: &[u8], p: usize) -> Result<u8> {
+    aml.get(p).copied().ok_or(AmlError::Malformed)
+}
+
+/// Decodes a PkgLength at `p`: the length (counting the PkgLength bytes
+/// themselves) and how many bytes it took.
+fn pkg_length(aml: &[u8], p: usize) -> Result<(usize, usize)> {
+    let lead = byte(aml, p)?;
+    let extra = (lead >> 6) as usize;
+    if extra == 0 {
+        return Ok(((lead & 0x3F) as usize, 1));
+    }
+    let mut len = (lead & 0x0F) as usize;
+    for i in 0..extra {
+        len |= (byte(aml, p + 1 + i)? as usize) << (4 + 8 * i);
+    }
+    Ok((len, 1 + extra))
+}
+
+/// The end of a PkgLength-sized object whose PkgLength starts at `p`.
+fn pkg_end(aml: &[u8], p: usize) -> Result<(usize, usize)> {
+    let (len, used) = pkg_length(aml, p)?;
+    let end = p + len;
+    if end > aml.len() || len < used {
+        return Err(AmlError::Malformed);
+    }
+    Ok((end, p + used))
+}
+
+fn is_name_lead(b: u8) -> bool {
+    matches!(b, b'A'..=b'Z' | b'_' | b'\\' | b'^' | 0x2E | 0x2F)
+}
+
+fn name_string(aml: &[u8], mut p: usize) -> Result<(NameRef, usize)> {
+    let mut name = NameRef { root: false, up: 0, segs: Vec::new() };
+    if byte(aml, p)? == b'\\' {
+        name.root = true;
+        p += 1;
+    } else {
+        while byte(aml, p)? == b'^' {
+            name.up += 1;
+            p += 1;
+        }
+    }
+    let count = match byte(aml, p)? {
+        0x00 => {
+            p += 1;
+            0
+        }
+        0x2E => {
+            p += 1;
+            2
+        }
+        0x2F => {
+            p += 2;
+            byte(aml, p - 1)? as usize
+        }
+        _ => 1,
+    };
+    for _ in 0..count {
+        let seg = aml.get(p..p + 4).ok_or(AmlError::Malformed)?;
+        name.segs.push([seg[0], seg[1], seg[2], seg[3]]);
+        p += 4;
+    }
+    Ok((name, p))
+}
+
+/// Skips a data object, as found after `Name`.
+fn skip_data(aml: &[u8], p: usize) -> Result<usize> {
+    Ok(match byte(aml, p)? {
+        0x00 | 0x01 | 0xFF => p + 1,
+        0x0A => p + 2,
+        0x0B => p + 3,
+        0x0C => p + 5,
+        0x0E => p + 9,
+        0x0D => p + 1 + aml[p + 1..].iter().position(|&b| b == 0).ok_or(AmlError::Malformed)? + 1,
+        0x11..=0x13 => pkg_end(aml, p + 1)?.0,
+        b if is_name_lead(b) => name_string(aml, p)?.1,
+        op => return Err(AmlError::Unsupported(op as u16)),
+    })
+}
+
+/// An integer operand at load time: a constant, or a `Name` holding one.
+fn load_int(ns: &Namespace, aml: &[u8], p: usize, scope: &str) -> Result<(u64, usize)> {
+    let le = |len: usize| -> Result<u64> {
+        let bytes = aml.get(p + 1..p + 1 + len).ok_or(AmlError::Malformed)?;
+        Ok(bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64))
+    };
+    Ok(match byte(aml, p)? {
+        0x00 => (0, p + 1),
+        0x01 => (1, p + 1),
+        0xFF => (ns.ones, p + 1),
+        0x0A => (le(1)?, p + 2),
+        0x0B => (le(2)?, p + 3),
+        0x0C => (le(4)?, p + 5),
+        0x0E => (le(8)?, p + 9),
+        b if is_name_lead(b) => {
+            let (name, next) = name_string(aml, p)?;
+            let path = ns.lookup(scope, &name).ok_or(AmlError::NotFound)?;
+            match ns.get(&path) {
+                Some(Object::Name(data, s)) => (load_int(ns, data, 0, s)?.0, next),
+                _ => return Err(AmlError::NotFound),
+            }
+        }
+        op => return Err(AmlError::Unsupported(op as u16)),
+    })
+}
+
+/// Adds the declarations in `aml` to `ns`. Stops at the first term it does
+/// not understand, keeping what came before. A Scope or Device whose body
+/// fails is skipped by its length, so one bad body doesn't lose the rest of
+/// the table.
+fn load_terms(ns: &mut Namespace, aml: &'static [u8], scope: &str) -> Result<()> {
+    let mut p = 0;
+    while p < aml.len() {
+        match aml[p] {
+            0x08 => {
+                let (name, data) = name_string(aml, p + 1)?;
+                p = skip_data(aml, data)?;
+                ns.insert(name.resolve(scope), Object::Name(&aml[data..p], String::from(scope)));
+            }
+            0x10 => {
+                let (end, at) = pkg_end(aml, p + 1)?;
+                let (name, body) = name_string(aml, at)?;
+                let path = name.resolve(scope);
+                load_body(ns, &aml[body..end], &path);
+                p = end;
+            }
+            0x14 => {
+                let (end, at) = pkg_end(aml, p + 1)?;
+                let (name, flags_at) = name_string(aml, at)?;
+                let args = byte(aml, flags_at)? & 7;
+                ns.insert(name.resolve(scope), Object::Method { body: &aml[flags_at + 1..end], args });
+                p = end;
+            }
+            0x15 => {
+                let (_, next) = name_string(aml, p + 1)?;
+                p = next + 2;
+            }
+            0x06 => {
+                let (_, next) = name_string(aml, p + 1)?;
+                p = name_string(aml, next)?.1;
+            }
+            // Conditional declarations are left out.
+            0xA0..=0xA2 => p = pkg_end(aml, p + 1)?.0,
+            0x5B => {
+                let op = byte(aml, p + 1)?;
+                p = load_ext(ns, aml, p + 2, op, scope)?;
+            }
+            op => return Err(AmlError::Unsupported(op as u16)),
+        }
+    }
+    Ok(())
+}
+
+fn load_body(ns: &mut Namespace, aml: &'static [u8], scope: &str) {
+    if let Err(e) = load_terms(ns, aml, scope) {
+        klog::warn(&format!("aml: skipped the rest of {}: {}", asl_path(scope), e.describe()));
+    }
+}
+
+fn load_ext(ns: &mut Namespace, aml: &'static [u8], p: usize, op: u8, scope: &str) -> Result<usize> {
+    match op {
+        // Mutex, Event
+        0x01 | 0x02 => {
+            let (name, next) = name_string(aml, p)?;
+            ns.insert(name.resolve(scope), Object::Other);
+            Ok(next + if op == 0x01 { 1 } else { 0 })
+        }
+        // OperationRegion
+        0x80 => {
+            let (name, at) = name_string(aml, p)?;
+            let space = byte(aml, at)?;
+            let (offset, at) = load_int(ns, aml, at + 1, scope)?;
+            let (_, next) = load_int(ns, aml, at, scope)?;
+            ns.insert(name.resolve(scope), Object::Region { space, offset });
+            Ok(next)
+        }
+        // Field
+        0x81 => {
+            let (end, at) = pkg_end(aml, p)?;
+            let (region, at) = name_string(aml, at)?;
+            let region = ns.lookup(scope, &region).unwrap_or_else(|| region.resolve(scope));
+            let mut flags = byte(aml, at)?;
+            let mut q = at + 1;
+            let mut bit = 0u64;
+            while q < end {
+                match aml[q] {
+                    0x00 => {
+                        let (bits, used) = pkg_length(aml, q + 1)?;
+                        bit += bits as u64;
+                        q += 1 + used;
+                    }
+                    0x01 => {
+                        flags = (flags & 0xF0) | (byte(aml, q + 1)? & 0x0F);
+                        q += 3;
+                    }
+                    0x03 => {
+                        flags = (flags & 0xF0) | (byte(aml, q + 1)? & 0x0F);
+                        q += 4;
+                    }
+                    // Connections are for GPIO and serial buses; not needed here.
+                    0x02 => break,
+                    _ => {
+                        let seg = aml.get(q..q + 4).ok_or(AmlError::Malformed)?;
+                        let (bits, used) = pkg_length(aml, q + 4)?;
+                        let mut path = String::from(scope);
+                        path.push_str(core::str::from_utf8(seg).map_err(|_| AmlError::Malformed)?);
+                        ns.insert(path, Object::Field { region: region.clone(), bit_offset: bit, bit_len: bits as u64, flags });
+                        bit += bits as u64;
+                        q += 4 + used;
+                    }
+                }
+            }
+            Ok(end)
+        }
+        // Device, ThermalZone
+        0x82 | 0x85 => {
+            let (end, at) = pkg_end(aml, p)?;
+            let (name, body) = name_string(aml, at)?;
+            let path = name.resolve(scope);
+            ns.insert(path.clone(), Object::Other);
+            load_body(ns, &aml[body..end], &path);
+            Ok(end)
+        }
+        // Processor, PowerResource: fixed fields before the body.
+        0x83 | 0x84 => {
+            let (end, at) = pkg_end(aml, p)?;
+            let (name, fixed) = name_string(aml, at)?;
+            let body = fixed + if op == 0x83 { 6 } else { 3 };
+            let path = name.resolve(scope);
+            ns.insert(path.clone(), Object::Other);
+            load_body(ns, aml.get(body..end).ok_or(AmlError::Malformed)?, &path);
+            Ok(end)
+        }
+        // IndexField, BankField: skipped whole.
+        0x86 | 0x87 => Ok(pkg_end(aml, p)?.0),
+        op => Err(AmlError::Unsupported(0x5B00 | op as u16)),
+    }
+}
+
+fn load_table(ns: &mut Namespace, signature: &[u8; 4], body: &'static [u8]) {
+    if let Err(e) = load_terms(ns, body, "\\") {
+        klog::warn(&format!(
+            "aml: stopped loading {}: {}",
+            core::str::from_utf8(signature).unwrap_or("????"),
+            e.describe()
+        ));
+    }
+}
+
+/// Loads the namespace on first use. False if there is no DSDT.
+fn ensure_loaded() -> bool {
+    let mut guard = NAMESPACE.lock();
+    if guard.is_some() {
+        return true;
+    }
+    let Some(dsdt) = acpi::find_table(b"DSDT") else { return false; };
+    let Some(body) = acpi::table_body(&dsdt) else { return false; };
+    let revision = acpi::read_u8(dsdt.phys + 8).unwrap_or(2);
+    let mut ns = Namespace { objects: Vec::new(), ones: if revision < 2 { 0xFFFF_FFFF } else { u64::MAX } };
+    load_table(&mut ns, b"DSDT", body);
+    for table in acpi::tables().iter().filter(|t| &t.signature == b"SSDT") {
+        if let Some(body) = acpi::table_body(table) {
+            load_table(&mut ns, b"SSDT", body);
+        }
+    }
+    klog::info(&format!("aml: {} objects loaded", ns.objects.len()));
+    *guard = Some(ns);
+    true
+}
+
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+enum Target {
+    None,
+    Local(usize),
+    Arg(usize),
+    Debug,
+    Name(String),
+}
+
+struct Frame {
+    scope: String,
+    args: [Value; 7],
+    locals: [Value; 8],
+}
+
+impl Frame {
+    fn new(scope: &str) -> Self {
+        Frame {
+            scope: String::from(scope),
+            args: core::array::from_fn(|_| Value::Int(0)),
+            locals: core::array::from_fn(|_| Value::Int(0)),
+        }
+    }
+}
+
+struct Interp<'a> {
+    ns: &'a mut Namespace,
+    depth: usize,
+    steps: usize,
+    waited_us: u64,
+}
+
+fn bool_value(ones: u64, b: bool) -> Value {
+    Value::Int(if b { ones } else { 0 })
+}
+
+impl Interp<'_> {
+    fn step(&mut self) -> Result<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(AmlError::Limit);
+        }
+        Ok(())
+    }
+
+    /// Counts a Sleep or Stall of `us` microseconds against `MAX_WAIT_US`.
+    fn add_wait(&mut self, us: u64) -> Result<()> {
+        self.waited_us = self.waited_us.saturating_add(us);
+        if self.waited_us > MAX_WAIT_US {
+            return Err(AmlError::Limit);
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, path: &str, args: &[Value]) -> Result<Value> {
+        let Some(Object::Method { body, .. }) = self.ns.get(path).cloned() else {
+            return self.read_named(path);
+        };
+        if self.depth >= MAX_DEPTH {
+            return Err(AmlError::Limit);
+        }
+        let mut frame = Frame::new(path);
+        for (slot, arg) in frame.args.iter_mut().zip(args) {
+            *slot = arg.clone();
+        }
+        self.depth += 1;
+        let flow = self.exec(&mut frame, body);
+        self.depth -= 1;
+        match flow? {
+            Flow::Return(v) => Ok(v),
+            _ => Ok(Value::Int(0)),
+        }
+    }
+
+    fn read_named(&mut self, path: &str) -> Result<Value> {
+        match self.ns.get(path).cloned() {
+            Some(Object::Name(data, scope)) => {
+                let mut frame = Frame::new(&scope);
+                Ok(self.eval(&mut frame, data, 0)?.0)
+            }
+            Some(Object::Value(v)) => Ok(v),
+            Some(Object::Method { .. }) => self.call(path, &[]),
+            Some(Object::Field { .. }) => Ok(Value::Int(self.field(path, None)?)),
+            Some(_) => Err(AmlError::Malformed),
+            None => Err(AmlError::NotFound),
+        }
+    }
+
+    fn exec(&mut self, f: &mut Frame, aml: &'static [u8]) -> Result<Flow> {
+        let mut p = 0;
+        while p < aml.len() {
+            self.step()?;
+            match aml[p] {
+                0xA0 => {
+                    let (end, at) = pkg_end(aml, p + 1)?;
+                    let (pred, body) = self.eval(f, aml, at)?;
+                    p = end;
+                    let mut else_body = None;
+                    if aml.get(end) == Some(&0xA1) {
+                        let (else_end, else_at) = pkg_end(aml, end + 1)?;
+                        else_body = Some(&aml[else_at..else_end]);
+                        p = else_end;
+                    }
+                    let flow = if pred.int()? != 0 {
+                        self.exec(f, aml.get(body..end).ok_or(AmlError::Malformed)?)?
+                    } else if let Some(else_body) = else_body {
+                        self.exec(f, else_body)?
+                    } else {
+                        Flow::Next
+                    };
+                    if !matches!(flow, Flow::Next) {
+                        return Ok(flow);
+                    }
+                }
+                0xA2 => {
+                    let (end, at) = pkg_end(aml, p + 1)?;
+                    loop {
+                        self.step()?;
+                        let (pred, body) = self.eval(f, aml, at)?;
+                        if pred.int()? == 0 {
+                            break;
+                        }
+                        match self.exec(f, aml.get(body..end).ok_or(AmlError::Malformed)?)? {
+                            Flow::Break => break,
+                            Flow::Return(v) => return Ok(Flow::Return(v)),
+                            Flow::Next | Flow::Continue => {}
+                        }
+                    }
+                    p = end;
+                }
+                0xA4 => {
+                    let (v, _) = self.eval(f, aml, p + 1)?;
+                    return Ok(Flow::Return(v));
+                }
+                0xA5 => return Ok(Flow::Break),
+                0x9F => return Ok(Flow::Continue),
+                0xA3 => p += 1,
+                // Notify: kept for acpi to act on once the method is done.
+                0x86 => {
+                    let (target, at) = self.target(f, aml, p + 1)?;
+                    let (v, next) = self.eval(f, aml, at)?;
+                    if let Target::Name(path) = target {
+                        let mut pending = NOTIFICATIONS.lock();
+                        if pending.len() < MAX_NOTIFICATIONS {
+                            pending.push((asl_path(&path), v.int()?));
+                        }
+                    }
+                    p = next;
+                }
+                0x5B if matches!(aml.get(p + 1), Some(0x21 | 0x22)) => {
+                    let (v, next) = self.eval(f, aml, p + 2)?;
+                    let v = v.int()?;
+                    if aml[p + 1] == 0x22 {
+                        self.add_wait(v.saturating_mul(1000))?;
+                        wait::bms(v);
+                    } else {
+                        self.add_wait(v.min(1000))?;
+                        wait::micros(v.min(1000));
+                    }
+                    p = next;
+                }
+                // Release, Signal, Reset: no other AML runs at the same time.
+                0x5B if matches!(aml.get(p + 1), Some(0x27 | 0x24 | 0x26)) => {
+                    p = self.target(f, aml, p + 2)?.1;
+                }
+                _ => p = self.eval(f, aml, p)?.1,
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn target(&mut self, f: &Frame, aml: &[u8], p: usize) -> Result<(Target, usize)> {
+        Ok(match byte(aml, p)? {
+            0x00 => (Target::None, p + 1),
+            op @ 0x60..=0x67 => (Target::Local((op - 0x60) as usize), p + 1),
+            op @ 0x68..=0x6E => (Target::Arg((op - 0x68) as usize), p + 1),
+            0x5B if byte(aml, p + 1)? == 0x31 => (Target::Debug, p + 2),
+            b if is_name_lead(b) => {
+                let (name, next) = name_string(aml, p)?;
+                let path = self.ns.lookup(&f.scope, &name).ok_or(AmlError::NotFound)?;
+                (Target::Name(path), next)
+            }
+            op => return Err(AmlError::Unsupported(op as u16)),
+        })
+    }
+
+    fn read_target(&mut self, f: &Frame, t: &Target) -> Result<Value> {
+        match t {
+            Target::Local(i) => Ok(f.locals[*i].clone()),
+            Target::Arg(i) => Ok(f.args[*i].clone()),
+            Target::Name(path) => self.read_named(path),
+            Target::None | Target::Debug => Ok(Value::Int(0)),
+        }
+    }
+
+    fn store(&mut self, f: &mut Frame, t: &Target, v: Value) -> Result<()> {
+        match t {
+            Target::None => {}
+            Target::Local(i) => f.locals[*i] = v,
+            Target::Arg(i) => f.args[*i] = v,
+            Target::Debug => klog::info(&format!("aml: debug {}", v.describe())),
+            Target::Name(path) => match self.ns.get(path) {
+                Some(Object::Field { .. }) => {
+                    self.field(path, Some(v.int()?))?;
+                }
+                Some(Object::Name(..) | Object::Value(_)) => self.ns.insert(path.clone(), Object::Value(v)),
+                _ => return Err(AmlError::Malformed),
+            },
+        }
+        Ok(())
+    }
+
+    fn int_op(&mut self, f: &mut Frame, aml: &'static [u8], p: usize) -> Result<(u64, usize)> {
+        let (v, next) = self.eval(f, aml, p)?;
+        Ok((v.int()?, next))
+    }
+
+    fn eval(&mut self, f: &mut Frame, aml: &'static [u8], p: usize) -> Result<(Value, usize)> {
+        self.step()?;
+        let ones = self.ns.ones;
+        let le = |len: usize| -> Result<u64> {
+            let bytes = aml.get(p + 1..p + 1 + len).ok_or(AmlError::Malformed)?;
+            Ok(bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64))
+        };
+        let op = byte(aml, p)?;
+        Ok(match op {
+            0x00 => (Value::Int(0), p + 1),
+            0x01 => (Value::Int(1), p + 1),
+            0xFF => (Value::Int(ones), p + 1),
+            0x0A => (Value::Int(le(1)?), p + 2),
+            0x0B => (Value::Int(le(2)?), p + 3),
+            0x0C => (Value::Int(le(4)?), p + 5),
+            0x0E => (Value::Int(le(8)?), p + 9),
+            0x0D => {
+                let len = aml[p + 1..].iter().position(|&b| b == 0).ok_or(AmlError::Malformed)?;
+                (Value::Str(String::from_utf8_lossy(&aml[p + 1..p + 1 + len]).into_owned()), p + len + 2)
+            }
+            0x11 => {
+                let (end, at) = pkg_end(aml, p + 1)?;
+                let (size, data) = self.int_op(f, aml, at)?;
+                if size > MAX_ELEMENTS {
+                    return Err(AmlError::Malformed);
+                }
+                let mut bytes = Vec::from(aml.get(data..end).ok_or(AmlError::Malformed)?);
+                bytes.resize(size as usize, 0);
+                (Value::Buffer(bytes), end)
+            }
+            0x12 | 0x13 => {
+                let (end, at) = pkg_end(aml, p + 1)?;
+                let (count, mut q) = if op == 0x12 {
+                    (byte(aml, at)? as u64, at + 1)
+                } else {
+                    self.int_op(f, aml, at)?
+                };
+                if count > MAX_ELEMENTS {
+                    return Err(AmlError::Malformed);
+                }
+                let mut items = Vec::new();
+                while q < end {
+                    // Names in a package are references; keep their path.
+                    if is_name_lead(aml[q]) {
+                        let (name, next) = name_string(aml, q)?;
+                        items.push(Value::Str(name.resolve(&f.scope)));
+                        q = next;
+                    } else {
+                        let (v, next) = self.eval(f, aml, q)?;
+                        items.push(v);
+                        q = next;
+                    }
+                }
+                while (items.len() as u64) < count {
+                    items.push(Value::Int(0));
+                }
+                (Value::Package(items), end)
+            }
+            0x60..=0x67 => (f.locals[(op - 0x60) as usize].clone(), p + 1),
+            0x68..=0x6E => (f.args[(op - 0x68) as usize].clone(), p + 1),
+            0x70 => {
+                let (v, at) = self.eval(f, aml, p + 1)?;
+                let (t, next) = self.target(f, aml, at)?;
+                self.store(f, &t, v.clone())?;
+                (v, next)
+            }
+            0x72 | 0x74 | 0x77 | 0x79 | 0x7A | 0x7B | 0x7C | 0x7D | 0x7E | 0x7F | 0x85 => {
+                let (a, at) = self.int_op(f, aml, p + 1)?;
+                let (b, at) = self.int_op(f, aml, at)?;
+                let (t, next) = self.target(f, aml, at)?;
+                let r = match op {
+                    0x72 => a.wrapping_add(b),
+                    0x74 => a.wrapping_sub(b),
+                    0x77 => a.wrapping_mul(b),
+                    0x79 => a.checked_shl(b as u32).unwrap_or(0),
+                    0x7A => a.checked_shr(b as u32).unwrap_or(0),
+                    0x7B => a & b,
+                    0x7C => !(a & b),
+                    0x7D => a | b,
+                    0x7E => !(a | b),
+                    0x7F => a ^ b,
+                    _ => a.checked_rem(b).ok_or(AmlError::Malformed)?,
+                } & ones;
+                self.store(f, &t, Value::Int(r))?;
+                (Value::Int(r), next)
+            }
+            0x78 => {
+                let (a, at) = self.int_op(f, aml, p + 1)?;
+                let (b, at) = self.int_op(f, aml, at)?;
+                if b == 0 {
+                    return Err(AmlError::Malformed);
+                }
+                let (rem, at) = self.target(f, aml, at)?;
+                let (quot, next) = self.target(f, aml, at)?;
+                self.store(f, &rem, Value::Int(a % b))?;
+                self.store(f, &quot, Value::Int(a / b))?;
+                (Value::Int(a / b), next)
+            }
+            0x80 | 0x99 => {
+                let (a, at) = self.int_op(f, aml, p + 1)?;
+                let (t, next) = self.target(f, aml, at)?;
+                let r = if op == 0x80 { !a & ones } else { a };
+                self.store(f, &t, Value::Int(r))?;
+                (Value::Int(r), next)
+            }
+            0x75 | 0x76 => {
+                let (t, next) = self.target(f, aml, p + 1)?;
+                let v = self.read_target(f, &t)?.int()?;
+                let r = if op == 0x75 { v.wrapping_add(1) } else { v.wrapping_sub(1) } & ones;
+                self.store(f, &t, Value::Int(r))?;
+                (Value::Int(r), next)
+            }
+            0x90 | 0x91 | 0x93 | 0x94 | 0x95 => {
+                let (a, at) = self.int_op(f, aml, p + 1)?;
+                let (b, next) = self.int_op(f, aml, at)?;
+                let r = match op {
+                    0x90 => a != 0 && b != 0,
+                    0x91 => a != 0 || b != 0,
+                    0x93 => a == b,
+                    0x94 => a > b,
+                    _ => a < b,
+                };
+                (bool_value(ones, r), next)
+            }
+            // LNot, and LNotEqual, LLessEqual, LGreaterEqual as LNot of the opposite.
+            0x92 => {
+                let (v, next) = self.eval(f, aml, p + 1)?;
+                (bool_value(ones, v.int()? == 0), next)
+            }
+            0x83 => self.eval(f, aml, p + 1)?,
+            0x87 => {
+                let (t, next) = self.target(f, aml, p + 1)?;
+                let len = match self.read_target(f, &t)? {
+                    Value::Str(s) => s.len(),
+                    Value::Buffer(b) => b.len(),
+                    Value::Package(items) => items.len(),
+                    Value::Int(_) => return Err(AmlError::Malformed),
+                };
+                (Value::Int(len as u64), next)
+            }
+            0x88 => {
+                let (v, at) = self.eval(f, aml, p + 1)?;
+                let (i, at) = self.int_op(f, aml, at)?;
+                let (t, next) = self.target(f, aml, at)?;
+                let item = match v {
+                    Value::Package(items) => items.get(i as usize).cloned(),
+                    Value::Buffer(b) => b.get(i as usize).map(|&x| Value::Int(x as u64)),
+                    Value::Str(s) => s.as_bytes().get(i as usize).map(|&x| Value::Int(x as u64)),
+                    Value::Int(_) => None,
+                }
+                .ok_or(AmlError::Malformed)?;
+                self.store(f, &t, item.clone())?;
+                (item, next)
+            }
+            0x5B => match byte(aml, p + 1)? {
+                // Acquire always succeeds; nothing else runs AML.
+                0x23 => {
+                    let (_, at) = self.target(f, aml, p + 2)?;
+                    (Value::Int(0), at + 2)
+                }
+                // CondRefOf, storing nothing: enough for `If (CondRefOf (X))`.
+                0x12 => {
+                    let (name, at) = name_string(aml, p + 2)?;
+                    let found = self.ns.lookup(&f.scope, &name).is_some();
+                    let (_, next) = self.target(f, aml, at)?;
+                    (bool_value(ones, found), next)
+                }
+                ext => return Err(AmlError::Unsupported(0x5B00 | ext as u16)),
+            },
+            b if is_name_lead(b) => {
+                let (name, mut next) = name_string(aml, p)?;
+                let path = self.ns.lookup(&f.scope, &name).ok_or(AmlError::NotFound)?;
+                if let Some(Object::Method { args, .. }) = self.ns.get(&path) {
+                    let mut values: Vec<Value> = Vec::new();
+                    for _ in 0..*args {
+                        let (v, at) = self.eval(f, aml, next)?;
+                        values.push(v);
+                        next = at;
+                    }
+                    (self.call(&path, &values)?, next)
+                } else {
+                    (self.read_named(&path)?, next)
+                }
+            }
+            op => return Err(AmlError::Unsupported(op as u16)),
+        })
+    }
+
+    /// Reads the field at `path`, or writes `value` to it, in units of its
+    /// access width.
+    fn field(&mut self, path: &str, value: Option<u64>) -> Result<u64> {
+        let Some(Object::Field { region, bit_offset, bit_len, flags }) = self.ns.get(path).cloned() else {
+            return Err(AmlError::Malformed);
+        };
+        let Some(&Object::Region { space, offset }) = self.ns.get(&region) else {
+            return Err(AmlError::NotFound);
+        };
+        if bit_len == 0 || bit_len > 64 {
+            return Err(AmlError::Unsupported(0x81));
+        }
+        let width = match flags & 0x0F {
+            2 => 2,
+            3 => 4,
+            4 => 8,
+            _ => 1,
+        };
+        let unit_bits = width * 8;
+        let mask = |n: u64| if n >= 64 { u64::MAX } else { (1u64 << n) - 1 };
+        let mut result = 0;
+        for unit in bit_offset / unit_bits..=(bit_offset + bit_len - 1) / unit_bits {
+            let start = unit * unit_bits;
+            let lo = bit_offset.max(start);
+            let hi = (bit_offset + bit_len).min(start + unit_bits);
+            let bits = mask(hi - lo);
+            let addr = offset + unit * width;
+            match value {
+                None => {
+                    let raw = region_read(space, addr, width)?;
+                    result |= ((raw >> (lo - start)) & bits) << (lo - bit_offset);
+                }
+                Some(v) => {
+                    let in_unit = bits << (lo - start);
+                    let base = if hi - lo == unit_bits {
+                        0
+                    } else {
+                        match (flags >> 5) & 3 {
+                            1 => mask(unit_bits),
+                            2 => 0,
+                            _ => region_read(space, addr, width)?,
+                        }
+                    };
+                    let new = ((v >> (lo - bit_offset)) & bits) << (lo - start);
+                    region_write(space, addr, width, (base & !in_unit) | new)?;
+                }
+            }
+        }
+        Ok(value.unwrap_or(result))
+    }
+}
+
+/// Where `width` bytes of system memory at `addr` are mapped. Regions outside
+/// RAM are most likely device registers, which the physical memory map may
+/// not cover or may cover cached.
+fn region_virt(addr: u64, width: u64) -> Result<u64> {
+    if !memory::is_ram_range(addr, width) {
+        return Err(AmlError::Unmapped(addr));
+    }
+    let virt = memory::phys_to_virt(addr).ok_or(AmlError::Unmapped(addr))?;
+    if paging::translate(virt).is_none() || paging::translate(virt + width - 1).is_none() {
+        return Err(AmlError::Unmapped(addr));
+    }
+    Ok(virt)
+}
+
+fn region_read(space: u8, addr: u64, width: u64) -> Result<u64> {
+    match space {
+        0 => {
+            let virt = region_virt(addr, width)?;
+            Ok(unsafe {
+                match width {
+                    1 => core::ptr::read_volatile(virt as *const u8) as u64,
+                    2 => core::ptr::read_volatile(virt as *const u16) as u64,
+                    4 => core::ptr::read_volatile(virt as *const u32) as u64,
+                    _ => core::ptr::read_volatile(virt as *const u64),
+                }
+            })
+        }
+        1 => {
+            let port = addr as u16;
+            Ok(unsafe {
+                match width {
+                    1 => Port::<u8>::new(port).read() as u64,
+                    2 => Port::<u16>::new(port).read() as u64,
+                    4 => Port::<u32>::new(port).read() as u64,
+                    _ => Port::<u32>::new(port).read() as u64 | (Port::<u32>::new(port + 4).read() as u64) << 32,
+                }
+            })
+        }
+        _ => Err(AmlError::Space(space)),
+    }
+}
+
+fn region_write(space: u8, addr: u64, width: u64, value: u64) -> Result<()> {
+    match space {
+        0 => {
+            let virt = region_virt(addr, width)?;
+            unsafe {
+                match width {
+                    1 => core::ptr::write_volatile(virt as *mut u8, value as u8),
+                    2 => core::ptr::write_volatile(virt as *mut u16, value as u16),
+                    4 => core::ptr::write_volatile(virt as *mut u32, value as u32),
+                    _ => core::ptr::write_volatile(virt as *mut u64, value),
+                }
+            }
+            Ok(())
+        }
+        1 => {
+            let port = addr as u16;
+            unsafe {
+                match width {
+                    1 => Port::<u8>::new(port).write(value as u8),
+                    2 => Port::<u16>::new(port).write(value as u16),
+                    4 => Port::<u32>::new(port).write(value as u32),
+                    _ => {
+                        Port::<u32>::new(port).write(value as u32);
+                        Port::<u32>::new(port + 4).write((value >> 32) as u32);
+                    }
+                }
+            }
+            Ok(())
+        }
+        _ => Err(AmlError::Space(space)),
+    }
+}
+
+/// Turns a path as written in ASL, like \_SB.PCI0 or \_S5, into the
+/// internal form, padding short segments with underscores.
+fn parse_path(text: &str) -> Option<String> {
+    let rest = text.strip_prefix('\\').unwrap_or(text);
+    let mut path = String::from("\\");
+    for seg in rest.split('.').filter(|s| !s.is_empty()) {
+        if seg.len() > 4 || !seg.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_') {
+            return None;
+        }
+        path.push_str(seg);
+        for _ in seg.len()..4 {
+            path.push('_');
+        }
+    }
+    Some(path)
+}
+
+/// The ASL form of an internal path: \_SB_LID0 becomes \_SB_.LID0.
+fn asl_path(path: &str) -> String {
+    let rest = path.strip_prefix('\\').unwrap_or(path);
+    let segs: Vec<&str> = rest.as_bytes().chunks(4).filter_map(|c| core::str::from_utf8(c).ok()).collect();
+    format!("\\{}", segs.join("."))
+}
+
+/// Turns a compressed EISA ID, as `EisaId("PNP0C0D")` stores it, back into text.
+fn eisa_id(id: u64) -> String {
+    let b = (id as u32).to_le_bytes();
+    let vendor = u16::from_be_bytes([b[0], b[1]]);
+    let letter = |shift: u16| (((vendor >> shift) & 0x1F) as u8 + 0x40) as char;
+    format!("{}{}{}{:02X}{:02X}", letter(10), letter(5), letter(0), b[2], b[3])
+}
+
+/// The first device whose `_HID` is `hid`, like "PNP0C0D" for a lid, in ASL form.
+pub fn find_device(hid: &str) -> Option<String> {
+    if !ensure_loaded() {
+        return None;
+    }
+    let ids: Vec<String> = NAMESPACE.lock().as_ref()?.objects.iter()
+        .filter(|(p, _)| p.len() > 5 && p.ends_with("_HID"))
+        .map(|(p, _)| p.clone())
+        .collect();
+    ids.into_iter().find_map(|p| {
+        let found = match evaluate(&asl_path(&p), &[]).ok()? {
+            Value::Int(id) => eisa_id(id) == hid,
+            Value::Str(s) => s == hid,
+            _ => false,
+        };
+        found.then(|| asl_path(&p[..p.len() - 4]))
+    })
+}
+
+/// Every thermal zone, in ASL form: the scopes that define `_TMP`.
+pub fn thermal_zones() -> Vec<String> {
+    if !ensure_loaded() {
+        return Vec::new();
+    }
+    NAMESPACE.lock().as_ref().map_or_else(Vec::new, |ns| {
+        ns.objects.iter()
+            .filter(|(p, _)| p.len() > 5 && p.ends_with("_TMP"))
+            .map(|(p, _)| asl_path(&p[..p.len() - 4]))
+            .collect()
+    })
+}
+
+/// Takes the notifications methods have raised since the last call.
+pub fn take_notifications() -> Vec<(String, u64)> {
+    core::mem::take(&mut *NOTIFICATIONS.lock())
+}
+
+/// Evaluates the object at `path` (ASL form, from the root), calling it
+/// with `args` if it is a method.
+pub fn evaluate(path: &str, args: &[u64]) -> core::result::Result<Value, AmlError> {
+    if !ensure_loaded() {
+        return Err(AmlError::NoTables);
+    }
+    let path = parse_path(path).ok_or(AmlError::NotFound)?;
+    let mut guard = NAMESPACE.lock();
+    let ns = guard.as_mut().ok_or(AmlError::NoTables)?;
+    if ns.get(&path).is_none() {
+        return Err(AmlError::NotFound);
+    }
+    let args: Vec<Value> = args.iter().map(|&a| Value::Int(a)).collect();
+    Interp { ns, depth: 0, steps: 0, waited_us: 0 }.call(&path, &args)
+}
+
+/// Whether the firmware defines `path`.
+pub fn exists(path: &str) -> bool {
+    ensure_loaded()
+        && parse_path(path).is_some_and(|p| NAMESPACE.lock().as_ref().is_some_and(|ns| ns.get(&p).is_some()))
+}
+
+/// `aml <path> [int...]`: evaluates a name or calls a method and prints the result.
+pub fn aml_cmd(args: &[&str]) -> crate::commands::Status {
+    let mut ints = Vec::new();
+    for a in &args[1..] {
+        let parsed = match a.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => a.parse().ok(),
+        };
+        let Some(v) = parsed else {
+            console::write_err(&format!("aml: {} is not an integer", a));
+            return Err(1);
+        };
+        ints.push(v);
+    }
+    match evaluate(args[0], &ints) {
+        Ok(v) => {
+            console::write_line(&v.describe());
+            Ok(())
+        }
+        Err(e) => {
+            console::write_err(&format!("aml: {}: {}", args[0], e.describe()));
+            Err(1)
+        }
+    }
+}
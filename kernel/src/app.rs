@@ -0,0 +1,333 @@
+use alloc::format;
+use core::cell::Cell;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::{String as HString, Vec};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::memory::{self, AppId};
+use crate::task::{self, TaskId};
+use crate::{console, events, idle};
+
+pub type AppEntry = fn(AppId) -> i32;
+
+const MAX_RUNNING: usize = 16;
+const FIRST_DYNAMIC_ID: AppId = 100;
+
+pub const DEFAULT_QUOTA: usize = 64 * 1024;
+
+struct AppRecord {
+    id: AppId,
+    name: HString<16>,
+    task: TaskId,
+    entry: AppEntry,
+    background: bool,
+    status: Option<i32>,
+}
+
+static RUNNING: Mutex<Vec<AppRecord, MAX_RUNNING>> = Mutex::new(Vec::new());
+/// Background apps that exited and have not been reported at the prompt yet.
+static EXITED: Mutex<Vec<(AppId, HString<16>, i32), MAX_RUNNING>> = Mutex::new(Vec::new());
+static OUTPUT_TAGGED: AtomicBool = AtomicBool::new(true);
+static PROMPT_DISTURBED: AtomicBool = AtomicBool::new(false);
+
+const TAG_COLORS: [u32; 6] = [0x7FB8FF, 0xFFD37F, 0x9CFF7F, 0xFF8FD8, 0x7FFFE8, 0xFFA060];
+
+pub fn init() {
+    task::register_exit_hook(on_task_exit);
+    events::subscribe(events::APP_EXITED, on_app_exited);
+    set_bare_names(true);
+}
+
+pub fn set_bare_names(on: bool) {
+    if on {
+        crate::commands::register_fallback("apps", run_by_name);
+    } else {
+        crate::commands::unregister_fallback("apps");
+    }
+}
+
+/// Unknown commands that name a built-in app run it, as if typed after `run`.
+fn run_by_name(parts: &[&str]) -> Option<crate::commands::Status> {
+    find_builtin(parts[0])?;
+    Some(run_cmd(parts))
+}
+
+/// Reserves `quota` bytes of app heap for `id` and starts `entry` on a new task.
+pub fn spawn(id: AppId, name: &str, quota: usize, entry: AppEntry, background: bool) -> Result<TaskId, &'static str> {
+    if interrupts::without_interrupts(|| RUNNING.lock().iter().any(|r| r.id == id)) {
+        return Err("app id already in use");
+    }
+    if !memory::register_app(id, quota) {
+        return Err("not enough app memory");
+    }
+    let mut app_name = HString::new();
+    for ch in name.chars() {
+        if app_name.push(ch).is_err() {
+            break;
+        }
+    }
+
+    // Hold the table while the task is created so the entry can always find its record.
+    let result = interrupts::without_interrupts(|| {
+        let mut running = RUNNING.lock();
+        if running.is_full() {
+            return Err("too many apps running");
+        }
+        let Some(tid) = task::spawn(name, Some(id), app_main, id as usize) else {
+            return Err("could not create task");
+        };
+        let _ = running.push(AppRecord { id, name: app_name, task: tid, entry, background, status: None });
+        Ok(tid)
+    });
+    if result.is_err() {
+        memory::unregister_app(id);
+    }
+    result
+}
+
+fn app_main(arg: usize) -> i32 {
+    let id = arg as AppId;
+    let entry = interrupts::without_interrupts(|| {
+        RUNNING.lock().iter().find(|r| r.id == id).map(|r| r.entry)
+    });
+    match entry {
+        Some(entry) => entry(id),
+        None => -1,
+    }
+}
+
+fn on_task_exit(_task: TaskId, app: Option<AppId>, code: i32) {
+    let Some(id) = app else { return; };
+    memory::unregister_app(id);
+    interrupts::without_interrupts(|| {
+        if let Some(r) = RUNNING.lock().iter_mut().find(|r| r.id == id) {
+            r.status = Some(code);
+        }
+    });
+    let mut data: HString<24> = HString::new();
+    let _ = write!(data, "{} {}", id, code);
+    events::publish(events::APP_EXITED, &data);
+}
+
+/// Takes a finished background app out of the table and queues its status
+/// for the prompt. Foreground apps are left for `wait`.
+fn on_app_exited(event: &events::Event) {
+    let mut fields = event.data.split(' ').map(str::parse::<i64>);
+    let (Some(Ok(id)), Some(Ok(status))) = (fields.next(), fields.next()) else { return; };
+    interrupts::without_interrupts(|| {
+        let mut running = RUNNING.lock();
+        let Some(idx) = running.iter().position(|r| r.id == id as AppId && r.background) else { return; };
+        let r = running.swap_remove(idx);
+        let _ = EXITED.lock().push((r.id, r.name, status as i32));
+    });
+}
+
+/// Blocks the calling task until app `id` exits and returns its status.
+pub fn wait(id: AppId) -> Option<i32> {
+    loop {
+        let done = interrupts::without_interrupts(|| {
+            let mut running = RUNNING.lock();
+            let idx = running.iter().position(|r| r.id == id)?;
+            let status = running[idx].status?;
+            running.swap_remove(idx);
+            Some(Some(status))
+        });
+        if let Some(status) = done {
+            return status;
+        }
+        if !interrupts::without_interrupts(|| RUNNING.lock().iter().any(|r| r.id == id)) {
+            return None;
+        }
+        idle::idle();
+    }
+}
+
+/// Prints exit statuses of finished background apps. Returns true if anything
+/// was printed since the last call, including output from background apps.
+pub fn report_exits() -> bool {
+    let mut printed = PROMPT_DISTURBED.swap(false, Ordering::Relaxed);
+    loop {
+        let finished = interrupts::without_interrupts(|| {
+            let mut exited = EXITED.lock();
+            (!exited.is_empty()).then(|| exited.remove(0))
+        });
+        let Some((id, name, status)) = finished else { break; };
+        if !printed && console::with_console(|c| c.cursor_position().0) != 0 {
+            console::write_line("");
+        }
+        console::write_line(&format!("[{}] {} exited with status {}", id, name, status));
+        printed = true;
+    }
+    printed
+}
+
+pub fn next_free_id() -> AppId {
+    interrupts::without_interrupts(|| {
+        let running = RUNNING.lock();
+        let mut id = FIRST_DYNAMIC_ID;
+        while running.iter().any(|r| r.id == id) || memory::app_stats(id).is_some() {
+            id += 1;
+        }
+        id
+    })
+}
+
+/// Task and name of the app that owns `id`'s memory, if it is still tracked.
+pub fn owner(id: AppId) -> Option<(TaskId, HString<16>)> {
+    interrupts::without_interrupts(|| {
+        RUNNING.lock().iter().find(|r| r.id == id).map(|r| (r.task, r.name.clone()))
+    })
+}
+
+/// Name of app `id` without waiting for the app table, for fault handlers.
+pub fn try_name(id: AppId) -> Option<HString<16>> {
+    RUNNING.try_lock()?.iter().find(|r| r.id == id).map(|r| r.name.clone())
+}
+
+pub fn set_output_tagged(tagged: bool) {
+    OUTPUT_TAGGED.store(tagged, Ordering::Relaxed);
+}
+
+pub fn output_tagged() -> bool {
+    OUTPUT_TAGGED.load(Ordering::Relaxed)
+}
+
+/// Console handle for an app. In tagged mode every line it starts is
+/// prefixed with `[name]` in a per-app color.
+pub struct AppConsole {
+    id: AppId,
+    name: HString<16>,
+    background: bool,
+    at_line_start: Cell<bool>,
+}
+
+pub fn console(id: AppId) -> AppConsole {
+    let (name, background) = interrupts::without_interrupts(|| {
+        RUNNING
+            .lock()
+            .iter()
+            .find(|r| r.id == id)
+            .map(|r| (r.name.clone(), r.background))
+            .unwrap_or((HString::new(), false))
+    });
+    AppConsole { id, name, background, at_line_start: Cell::new(true) }
+}
+
+impl AppConsole {
+    pub fn write(&self, s: &str) {
+        let tagged = output_tagged();
+        let tag_fg = TAG_COLORS[self.id as usize % TAG_COLORS.len()];
+        console::wait_while_paused();
+        console::with_console(|c| {
+            let (fg, bg) = c.default_colors();
+            if self.background {
+                // Keep background output off the shell's input line.
+                if self.at_line_start.get() && c.cursor_position().0 != 0 {
+                    c.newline();
+                }
+                PROMPT_DISTURBED.store(true, Ordering::Relaxed);
+            }
+            for (i, part) in s.split('\n').enumerate() {
+                if i > 0 {
+                    c.put_char('\n');
+                    self.at_line_start.set(true);
+                }
+                if part.is_empty() {
+                    continue;
+                }
+                if tagged && self.at_line_start.get() {
+                    c.cwrite(&format!("[{}] ", self.name), tag_fg, bg);
+                }
+                c.cwrite(part, fg, bg);
+                self.at_line_start.set(false);
+            }
+        });
+    }
+
+    pub fn write_line(&self, s: &str) {
+        self.write(s);
+        self.write("\n");
+    }
+}
+
+const BUILTIN_APPS: &[(&str, &str, AppEntry)] = &[
+    ("hello", "Greets you from its own task", hello_app),
+    ("ticker", "Prints a tick every second, five times", ticker_app),
+    ("fail", "Exits with status 1", fail_app),
+];
+
+pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+    BUILTIN_APPS.iter().map(|(n, _, _)| *n)
+}
+
+pub fn find_builtin(name: &str) -> Option<AppEntry> {
+    BUILTIN_APPS
+        .iter()
+        .find(|(n, _, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, _, e)| *e)
+}
+
+fn hello_app(id: AppId) -> i32 {
+    let out = console(id);
+    out.write_line(&format!("Hello from app {} (task {})!", id, task::current_id()));
+    let p = unsafe { memory::app_alloc(id, 256, 8) };
+    if p.is_null() {
+        return 1;
+    }
+    unsafe { memory::app_dealloc(id, p, 256, 8) };
+    0
+}
+
+fn ticker_app(id: AppId) -> i32 {
+    let out = console(id);
+    for i in 1..=5 {
+        crate::wait::bsec(1);
+        out.write_line(&format!("tick {}", i));
+    }
+    0
+}
+
+fn fail_app(_id: AppId) -> i32 {
+    1
+}
+
+pub fn run_cmd(args: &[&str]) -> crate::commands::Status {
+    let Some(name) = args.first() else {
+        console::write_line("Usage: run <app> [&]");
+        console::write_line("Apps:");
+        for (n, desc, _) in BUILTIN_APPS.iter() {
+            console::write_line(&format!("  {:<10} - {}", n, desc));
+        }
+        return Ok(());
+    };
+    let Some(entry) = find_builtin(name) else {
+        console::write_err(&format!("run: no such app or script: {}", name));
+        return Err(127);
+    };
+    let background = args.get(1) == Some(&"&");
+
+    let id = next_free_id();
+    if let Err(e) = spawn(id, name, DEFAULT_QUOTA, entry, background) {
+        console::write_err(&format!("run: {}", e));
+        return Err(1);
+    }
+    if background {
+        console::write_line(&format!("[{}] {} started", id, name));
+        return Ok(());
+    }
+    match wait(id) {
+        Some(0) => {
+            console::write_line(&format!("{} exited with status 0", name));
+            Ok(())
+        }
+        Some(status) => {
+            console::write_line(&format!("{} exited with status {}", name, status));
+            Err(status)
+        }
+        None => {
+            console::write_err(&format!("{} vanished without an exit status", name));
+            Err(1)
+        }
+    }
+}
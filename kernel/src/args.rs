@@ -0,0 +1,198 @@
+use alloc::format;
+use alloc::string::String;
+use heapless::Vec;
+use crate::{commands, console};
+
+// Declarative argument lists for command handlers. A handler describes its
+// arguments once as a `Spec`; parsing, error messages and the usage line
+// shown by `help` are all derived from it.
+
+const MAX_ARGS: usize = 8;
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    /// Decimal, or hex with 0x, within `min..=max`.
+    Int { min: u64, max: u64 },
+    /// RGB in 3 or 6 hex digits.
+    Color,
+    Word,
+    Path,
+    /// One of a fixed set of words, matched case-insensitively.
+    Choice(&'static [&'static str]),
+    /// Every remaining word; must be last.
+    Rest,
+}
+
+pub const ANY_INT: Kind = Kind::Int { min: 0, max: u64::MAX };
+
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub required: bool,
+}
+
+pub const fn req(name: &'static str, kind: Kind) -> Arg {
+    Arg { name, kind, required: true }
+}
+
+pub const fn opt(name: &'static str, kind: Kind) -> Arg {
+    Arg { name, kind, required: false }
+}
+
+pub struct Spec {
+    /// Command as typed, including any subcommand words, e.g. "os bg".
+    pub command: &'static str,
+    pub args: &'static [Arg],
+}
+
+#[derive(Clone, Copy)]
+pub enum Value<'a> {
+    Int(u64),
+    Color(u32),
+    Word(&'a str),
+    Rest(&'a [&'a str]),
+}
+
+/// Parsed values, indexed like the spec's argument list. Optional arguments
+/// that were not given are None.
+pub struct Args<'a> {
+    values: Vec<Option<Value<'a>>, MAX_ARGS>,
+}
+
+impl<'a> Args<'a> {
+    fn get(&self, i: usize) -> Option<Value<'a>> {
+        self.values.get(i).copied().flatten()
+    }
+
+    pub fn int(&self, i: usize) -> Option<u64> {
+        match self.get(i) {
+            Some(Value::Int(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self, i: usize) -> Option<u32> {
+        match self.get(i) {
+            Some(Value::Color(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Word, path or choice arguments. Choices come back as typed.
+    pub fn word(&self, i: usize) -> Option<&'a str> {
+        match self.get(i) {
+            Some(Value::Word(w)) => Some(w),
+            _ => None,
+        }
+    }
+
+    pub fn rest(&self, i: usize) -> &'a [&'a str] {
+        match self.get(i) {
+            Some(Value::Rest(r)) => r,
+            _ => &[],
+        }
+    }
+}
+
+pub enum ArgError<'a> {
+    Missing(&'static Arg),
+    Invalid(&'static Arg, &'a str),
+    Unexpected(&'a str),
+}
+
+fn placeholder(arg: &Arg) -> String {
+    let inner = match arg.kind {
+        Kind::Choice(words) => {
+            let list = words.join("|");
+            return if arg.required { list } else { format!("[{}]", list) };
+        }
+        Kind::Int { min, max } if max != u64::MAX => format!("{} {}-{}", arg.name, min, max),
+        Kind::Rest => format!("{}...", arg.name),
+        _ => String::from(arg.name),
+    };
+    if arg.required { format!("<{}>", inner) } else { format!("[{}]", inner) }
+}
+
+fn expected(kind: Kind) -> String {
+    match kind {
+        Kind::Int { min, max } if max != u64::MAX => format!("a number from {} to {}", min, max),
+        Kind::Int { .. } => String::from("a number (0x for hex)"),
+        Kind::Color => String::from("a hex color of 3 or 6 digits, e.g. FF00FF"),
+        Kind::Choice(words) => format!("one of {}", words.join(", ")),
+        Kind::Path => String::from("a path"),
+        Kind::Word | Kind::Rest => String::from("a word"),
+    }
+}
+
+fn parse_one(kind: Kind, s: &str) -> Option<Value<'_>> {
+    match kind {
+        Kind::Int { min, max } => commands::parse_num(s).filter(|v| (min..=max).contains(v)).map(Value::Int),
+        Kind::Color => commands::parse_rgb_hex(s).filter(|&v| v <= 0xFFFFFF).map(Value::Color),
+        Kind::Choice(words) => words.iter().any(|w| w.eq_ignore_ascii_case(s)).then_some(Value::Word(s)),
+        Kind::Path => (!s.chars().any(|c| c.is_control())).then_some(Value::Word(s)),
+        Kind::Word | Kind::Rest => Some(Value::Word(s)),
+    }
+}
+
+impl Spec {
+    /// "Usage: <command> <args>".
+    pub fn usage(&self) -> String {
+        let mut s = format!("Usage: {}", self.command);
+        for arg in self.args {
+            s.push(' ');
+            s.push_str(&placeholder(arg));
+        }
+        s
+    }
+
+    /// Matches `input` against the spec in order. An optional argument that
+    /// does not parse is skipped, so `[count] <name>` accepts just a name.
+    pub fn parse<'a>(&'static self, input: &'a [&'a str]) -> Result<Args<'a>, ArgError<'a>> {
+        let mut values = Vec::new();
+        let mut rest = input;
+        for arg in self.args {
+            let value = match (arg.kind, rest.split_first()) {
+                (Kind::Rest, _) if !rest.is_empty() => {
+                    let all = rest;
+                    rest = &[];
+                    Some(Value::Rest(all))
+                }
+                (Kind::Rest, _) | (_, None) if arg.required => return Err(ArgError::Missing(arg)),
+                (Kind::Rest, _) | (_, None) => None,
+                (kind, Some((word, tail))) => match parse_one(kind, word) {
+                    Some(v) => {
+                        rest = tail;
+                        Some(v)
+                    }
+                    None if arg.required => return Err(ArgError::Invalid(arg, word)),
+                    None => None,
+                },
+            };
+            let _ = values.push(value);
+        }
+        if let Some(extra) = rest.first() {
+            return Err(ArgError::Unexpected(extra));
+        }
+        Ok(Args { values })
+    }
+
+    pub fn error_message(&self, err: &ArgError) -> String {
+        match err {
+            ArgError::Missing(arg) => format!("{}: missing {}", self.command, placeholder(arg)),
+            ArgError::Invalid(arg, got) => {
+                format!("{}: {} must be {}, got '{}'", self.command, placeholder(arg), expected(arg.kind), got)
+            }
+            ArgError::Unexpected(got) => format!("{}: unexpected argument '{}'", self.command, got),
+        }
+    }
+
+    /// Parses `input`, or prints the problem and the usage line and fails
+    /// with exit status 2.
+    pub fn check<'a>(&'static self, input: &'a [&'a str]) -> Result<Args<'a>, i32> {
+        self.parse(input).map_err(|err| {
+            console::write_err(&self.error_message(&err));
+            console::write_err(&self.usage());
+            2
+        })
+    }
+}
@@ -0,0 +1,163 @@
+use alloc::format;
+use alloc::string::String;
+use core::hint::black_box;
+use crate::args::{req, Kind, Spec};
+use crate::{commands, console, memory, serial, wait, OS_VERSION};
+
+// Small, repeatable benchmarks for catching performance regressions between
+// kernel builds. Each reports a rate and a score: the rate divided by a fixed
+// reference rate, times 1000. The references are round numbers, not a real
+// machine, so compare scores between builds on one machine, not across machines.
+
+const CPU_ROUNDS: u64 = 20_000_000;
+const CPU_REFERENCE: u64 = 500_000_000; // rounds per second
+const MEM_BYTES: usize = 4 * 1024 * 1024;
+const MEM_PASSES: usize = 16;
+const MEM_REFERENCE: u64 = 4096; // MiB per second
+const GFX_FRAMES: u64 = 32;
+const GFX_REFERENCE: u64 = 120; // full presents per second
+
+#[derive(Clone, Copy)]
+enum Bench {
+    Cpu,
+    Mem,
+    Gfx,
+    Disk,
+}
+
+const ALL: [Bench; 4] = [Bench::Cpu, Bench::Mem, Bench::Gfx, Bench::Disk];
+
+impl Bench {
+    fn name(self) -> &'static str {
+        match self {
+            Bench::Cpu => "cpu",
+            Bench::Mem => "mem",
+            Bench::Gfx => "gfx",
+            Bench::Disk => "disk",
+        }
+    }
+}
+
+struct Outcome {
+    rate: u64,
+    unit: &'static str,
+    score: u64,
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// `count` units done in `cycles` TSC cycles, as units per second.
+fn per_second(count: u64, cycles: u64) -> u64 {
+    (count as u128 * wait::tsc_hz() as u128 / cycles.max(1) as u128) as u64
+}
+
+fn score(rate: u64, reference: u64) -> u64 {
+    rate.saturating_mul(1000) / reference
+}
+
+/// Integer mixing and multiplies with a data dependency between rounds.
+fn cpu() -> Result<Outcome, &'static str> {
+    let t0 = rdtsc();
+    let mut x: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut acc: u64 = 0;
+    for i in 0..CPU_ROUNDS {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        acc = acc.wrapping_add(x.wrapping_mul(i | 1)).rotate_left(5);
+    }
+    black_box(acc);
+    let rate = per_second(CPU_ROUNDS, rdtsc() - t0);
+    Ok(Outcome { rate: rate / 1_000_000, unit: "Mrounds/s", score: score(rate, CPU_REFERENCE) })
+}
+
+/// Copies one half of a buffer over the other, back and forth.
+fn mem() -> Result<Outcome, &'static str> {
+    let buf = memory::alloc_buffer(MEM_BYTES).ok_or("not enough memory")?;
+    let half = MEM_BYTES / 2;
+    buf.fill(0x5A);
+    let t0 = rdtsc();
+    for pass in 0..MEM_PASSES {
+        if pass % 2 == 0 {
+            buf.copy_within(..half, half);
+        } else {
+            buf.copy_within(half.., 0);
+        }
+        black_box(&mut *buf);
+    }
+    let cycles = rdtsc() - t0;
+    memory::free_buffer(buf);
+    let mib = per_second((half * MEM_PASSES) as u64, cycles) / (1024 * 1024);
+    Ok(Outcome { rate: mib, unit: "MiB/s", score: score(mib, MEM_REFERENCE) })
+}
+
+/// Full-screen presents with the active copy routine.
+fn gfx() -> Result<Outcome, &'static str> {
+    if console::display_buffer_stats().is_none() {
+        return Err("console not initialized");
+    }
+    let t0 = rdtsc();
+    for _ in 0..GFX_FRAMES {
+        console::with_console(|c| c.present_full());
+    }
+    let fps = per_second(GFX_FRAMES, rdtsc() - t0);
+    Ok(Outcome { rate: fps, unit: "frames/s", score: score(fps, GFX_REFERENCE) })
+}
+
+fn run(bench: Bench) -> Result<Outcome, &'static str> {
+    match bench {
+        Bench::Cpu => cpu(),
+        Bench::Mem => mem(),
+        Bench::Gfx => gfx(),
+        Bench::Disk => Err("no disk driver"),
+    }
+}
+
+pub static BENCHMARK_ARGS: Spec = Spec {
+    command: "benchmark",
+    args: &[req("suite", Kind::Choice(&["all", "cpu", "mem", "gfx", "disk"]))],
+};
+
+/// `benchmark all|cpu|mem|gfx|disk`: runs the benches, prints a table and
+/// sends one `BENCH key=value ...` line to COM1 for scripts to collect.
+pub fn benchmark_cmd(args: &[&str]) -> commands::Status {
+    let a = BENCHMARK_ARGS.check(args)?;
+    let which = a.word(0).unwrap_or("all");
+    if wait::tsc_hz() == 0 {
+        console::write_err("benchmark: TSC not calibrated");
+        return Err(1);
+    }
+
+    console::write_line(&format!("{:<6} {:>20} {:>7}", "bench", "result", "score"));
+    let mut report = format!("BENCH version={}", OS_VERSION);
+    let (mut total, mut counted) = (0u64, 0u64);
+    for bench in ALL.into_iter().filter(|b| which.eq_ignore_ascii_case("all") || which.eq_ignore_ascii_case(b.name())) {
+        match run(bench) {
+            Ok(o) => {
+                let result: String = format!("{} {}", o.rate, o.unit);
+                console::write_line(&format!("{:<6} {:>20} {:>7}", bench.name(), result, o.score));
+                report.push_str(&format!(" {}={}", bench.name(), o.score));
+                total += o.score;
+                counted += 1;
+            }
+            Err(why) => {
+                console::write_line(&format!("{:<6} {:>20} {:>7}", bench.name(), why, "-"));
+                report.push_str(&format!(" {}=na", bench.name()));
+            }
+        }
+    }
+    let mean = total.checked_div(counted);
+    if let Some(mean) = mean {
+        if counted > 1 {
+            console::write_line(&format!("{:<6} {:>20} {:>7}", "mean", "", mean));
+        }
+        report.push_str(&format!(" mean={}", mean));
+    }
+    serial::write(&report);
+    if mean.is_none() {
+        return Err(1);
+    }
+    Ok(())
+}
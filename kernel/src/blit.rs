@@ -0,0 +1,185 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicU8, Ordering};
+use raw_cpuid::CpuId;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use crate::{console, memory};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CopyMode {
+    Slice,
+    RepMovsb,
+    Sse2,
+}
+
+const MODES: [CopyMode; 3] = [CopyMode::Slice, CopyMode::RepMovsb, CopyMode::Sse2];
+
+static MODE: AtomicU8 = AtomicU8::new(CopyMode::Slice as u8);
+
+// The kernel is built soft-float, so the compiler never keeps values in XMM
+// registers; this routine may use them freely. rdi = dst, rsi = src, rdx = len.
+// Copies forward, so overlapping moves towards lower addresses are safe.
+global_asm!(
+    ".global stratos_copy_sse2",
+    "stratos_copy_sse2:",
+    "cmp rdx, 64",
+    "jb .Lsse_tail",
+    ".Lsse_loop:",
+    "movdqu xmm0, [rsi]",
+    "movdqu xmm1, [rsi + 16]",
+    "movdqu xmm2, [rsi + 32]",
+    "movdqu xmm3, [rsi + 48]",
+    "movdqu [rdi], xmm0",
+    "movdqu [rdi + 16], xmm1",
+    "movdqu [rdi + 32], xmm2",
+    "movdqu [rdi + 48], xmm3",
+    "add rsi, 64",
+    "add rdi, 64",
+    "sub rdx, 64",
+    "cmp rdx, 64",
+    "jae .Lsse_loop",
+    ".Lsse_tail:",
+    "test rdx, rdx",
+    "jz .Lsse_done",
+    ".Lsse_byte:",
+    "mov al, [rsi]",
+    "mov [rdi], al",
+    "inc rsi",
+    "inc rdi",
+    "dec rdx",
+    "jnz .Lsse_byte",
+    ".Lsse_done:",
+    "ret",
+);
+
+extern "C" {
+    fn stratos_copy_sse2(dst: *mut u8, src: *const u8, len: usize);
+}
+
+pub fn available(mode: CopyMode) -> bool {
+    let cpuid = CpuId::new();
+    match mode {
+        CopyMode::Slice => true,
+        CopyMode::RepMovsb => cpuid
+            .get_extended_feature_info()
+            .map(|f| f.has_rep_movsb_stosb())
+            .unwrap_or(false),
+        CopyMode::Sse2 => {
+            let has_sse2 = cpuid.get_feature_info().map(|f| f.has_sse2()).unwrap_or(false);
+            // SSE instructions #UD unless the OS (here: the firmware) enabled them.
+            has_sse2
+                && Cr4::read().contains(Cr4Flags::OSFXSR)
+                && !Cr0::read().contains(Cr0Flags::EMULATE_COPROCESSOR)
+        }
+    }
+}
+
+/// Picks the fastest copy routine the CPU supports.
+pub fn init() {
+    let best = if available(CopyMode::RepMovsb) {
+        CopyMode::RepMovsb
+    } else if available(CopyMode::Sse2) {
+        CopyMode::Sse2
+    } else {
+        CopyMode::Slice
+    };
+    set_mode(best);
+}
+
+pub fn set_mode(mode: CopyMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn mode() -> CopyMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => CopyMode::RepMovsb,
+        2 => CopyMode::Sse2,
+        _ => CopyMode::Slice,
+    }
+}
+
+/// Forward copy of `len` bytes. Overlap is only allowed when `dst <= src`.
+unsafe fn copy_raw(dst: *mut u8, src: *const u8, len: usize) {
+    match mode() {
+        CopyMode::RepMovsb => {
+            asm!(
+                "rep movsb",
+                inout("rcx") len => _,
+                inout("rdi") dst => _,
+                inout("rsi") src => _,
+                options(nostack, preserves_flags),
+            );
+        }
+        CopyMode::Sse2 => stratos_copy_sse2(dst, src, len),
+        CopyMode::Slice => core::ptr::copy(src, dst, len),
+    }
+}
+
+pub fn copy(dst: &mut [u8], src: &[u8]) {
+    assert_eq!(dst.len(), src.len());
+    unsafe { copy_raw(dst.as_mut_ptr(), src.as_ptr(), src.len()) }
+}
+
+/// Moves `buf[src..end]` to the start of `buf`, like `copy_within(src..end, 0)`.
+pub fn move_to_front(buf: &mut [u8], src: usize, end: usize) {
+    assert!(src <= end && end <= buf.len());
+    let p = buf.as_mut_ptr();
+    unsafe { copy_raw(p, p.add(src), end - src) }
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+const BENCH_ROUNDS: usize = 20;
+
+pub fn gfxbench_cmd() {
+    let active = mode();
+    let Some(screen_bytes) = console::display_buffer_stats().map(|s| s.framebuffer_bytes) else {
+        console::write_line("gfxbench: console not initialized");
+        return;
+    };
+    let Some(scratch) = memory::alloc_buffer(screen_bytes) else {
+        console::write_line("gfxbench: not enough memory for a scratch buffer");
+        return;
+    };
+    let row = screen_bytes / 64;
+
+    console::write_line(&format!(
+        "Copying {} KiB x{} per test (cycles per copy, lower is better):",
+        screen_bytes / 1024,
+        BENCH_ROUNDS
+    ));
+    let mut baseline = None;
+    for m in MODES {
+        if !available(m) {
+            console::write_line(&format!("  {:<9} not supported", format!("{:?}", m)));
+            continue;
+        }
+        set_mode(m);
+        let t0 = rdtsc();
+        for _ in 0..BENCH_ROUNDS {
+            console::with_console(|c| c.present_full());
+        }
+        let present = (rdtsc() - t0) / BENCH_ROUNDS as u64;
+        let t0 = rdtsc();
+        for _ in 0..BENCH_ROUNDS {
+            move_to_front(scratch, row, screen_bytes);
+        }
+        let scroll = (rdtsc() - t0) / BENCH_ROUNDS as u64;
+        let base = *baseline.get_or_insert(present.max(1));
+        console::write_line(&format!(
+            "  {:<9} present {:>11}  scroll {:>11}  ({}.{:02}x){}",
+            format!("{:?}", m),
+            present,
+            scroll,
+            base / present.max(1),
+            (base * 100 / present.max(1)) % 100,
+            if m == active { "  [active]" } else { "" }
+        ));
+    }
+    set_mode(active);
+    memory::free_buffer(scratch);
+}
@@ -1,10 +1,12 @@
 #![allow(dead_code)]
 
 use crate::console::{with_console, DrawPos, size_chars};
+use crate::keyboard::{self, KeyEvent};
 use crate::wait;
 use alloc::format;
 
-pub fn show() {
+/// Shows the boot splash. Returns true if S was pressed to ask for safe mode.
+pub fn show() -> bool {
     const ART: &[&str] = &[
         r" ____ _____ ____      _  _____ ___  ____  ",
         r"/ ___|_   _|  _ \    / \|_   _/ _ \/ ___| ",
@@ -14,6 +16,7 @@ pub fn show() {
         "",
         "",
     ];
+    const HINT: &str = "press S for safe mode";
 
     const STATUS_FRAMES: &[&str] = &[
         "booting StratOS.",
@@ -38,17 +41,29 @@ pub fn show() {
             let padded = format!("{:<width$}", *line, width = block_width);
             c.draw_text_at_char(DrawPos::Char(start_x, start_y + i), &padded);
         }
+        c.draw_text_at_char(DrawPos::Char(cols.saturating_sub(HINT.len()) / 2, rows.saturating_sub(2)), HINT);
     });
 
+    let mut safe_mode = false;
     for i in 0..8 {
-        let msg = STATUS_FRAMES[i % STATUS_FRAMES.len()];
+        let msg = if safe_mode { "safe mode requested" } else { STATUS_FRAMES[i % STATUS_FRAMES.len()] };
         let padded = format!("{:<width$}", msg, width = block_width);
         with_console(|c| {
             c.draw_text_at_char(DrawPos::Char(start_x, status_row), &padded);
         });
-        wait::bms(400);
+        let finished = wait::bms_interruptible(400);
+        while let Some(key) = keyboard::poll_event() {
+            safe_mode |= matches!(key, KeyEvent::Char('s' | 'S'));
+        }
+        if !finished {
+            break;
+        }
     }
 
-    wait::bms(600);
+    // Ctrl+C skips the rest of the animation; it is not left for the shell.
+    if !keyboard::clear_cancel() {
+        wait::bms(600);
+    }
     with_console(|c| c.clear());
+    safe_mode
 }
\ No newline at end of file
@@ -0,0 +1,219 @@
+use alloc::format;
+use alloc::string::String;
+use crate::{commands, console};
+
+// `calc` evaluates an arithmetic expression, mostly for address math. Whole
+// numbers are 64-bit two's complement and wrap like a register, so kernel
+// addresses above 0x8000_0000_0000_0000 come out right in hex; a number with
+// a fraction or exponent makes the result a float. Precedence is C's, with
+// Python's `**` (right-associative, above unary minus on its left).
+
+#[derive(Clone, Copy)]
+enum Value {
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    fn float(self) -> f64 {
+        match self {
+            Value::Int(n) => n as f64,
+            Value::Float(f) => f,
+        }
+    }
+}
+
+/// A message and the byte offset it applies to.
+type Error = (&'static str, usize);
+
+/// Binary operators from loosest to tightest; `**` is handled apart.
+const LEVELS: &[&[&str]] = &[&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+fn apply(op: &str, a: Value, b: Value) -> Result<Value, &'static str> {
+    if let (Value::Int(x), Value::Int(y)) = (a, b) {
+        return Ok(Value::Int(match op {
+            "+" => x.wrapping_add(y),
+            "-" => x.wrapping_sub(y),
+            "*" => x.wrapping_mul(y),
+            "/" | "%" if y == 0 => return Err("division by zero"),
+            "/" => x.wrapping_div(y),
+            "%" => x.wrapping_rem(y),
+            "&" => x & y,
+            "|" => x | y,
+            "^" => x ^ y,
+            "<<" | ">>" if !(0..64).contains(&y) => return Err("shift out of range"),
+            "<<" => x << y,
+            ">>" => x >> y,
+            "**" if y < 0 => return Ok(Value::Float(libm::pow(x as f64, y as f64))),
+            "**" => x.wrapping_pow(y.min(u32::MAX as i64) as u32),
+            _ => unreachable!(),
+        }));
+    }
+    let (x, y) = (a.float(), b.float());
+    Ok(Value::Float(match op {
+        "+" => x + y,
+        "-" => x - y,
+        "*" => x * y,
+        "/" | "%" if y == 0.0 => return Err("division by zero"),
+        "/" => x / y,
+        "%" => libm::fmod(x, y),
+        "**" => libm::pow(x, y),
+        _ => return Err("bit operators need whole numbers"),
+    }))
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_spaces(&mut self) {
+        while self.src.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.src[self.pos..].starts_with(token.as_bytes()) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, Error> {
+        let Some(ops) = LEVELS.get(level) else { return self.unary(); };
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            self.skip_spaces();
+            let at = self.pos;
+            let Some(op) = ops.iter().find(|op| self.eat(op)) else { return Ok(lhs); };
+            let rhs = self.binary(level + 1)?;
+            lhs = apply(op, lhs, rhs).map_err(|msg| (msg, at))?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, Error> {
+        self.skip_spaces();
+        let at = self.pos;
+        if self.eat("-") {
+            return Ok(match self.unary()? {
+                Value::Int(n) => Value::Int(n.wrapping_neg()),
+                Value::Float(f) => Value::Float(-f),
+            });
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        if self.eat("~") {
+            return match self.unary()? {
+                Value::Int(n) => Ok(Value::Int(!n)),
+                Value::Float(_) => Err(("bit operators need whole numbers", at)),
+            };
+        }
+        let base = self.atom()?;
+        self.skip_spaces();
+        let at = self.pos;
+        if self.eat("**") {
+            let exp = self.unary()?;
+            return apply("**", base, exp).map_err(|msg| (msg, at));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Value, Error> {
+        self.skip_spaces();
+        let at = self.pos;
+        if self.eat("(") {
+            let value = self.binary(0)?;
+            if !self.eat(")") {
+                return Err(("expected ')'", self.pos));
+            }
+            return Ok(value);
+        }
+        match self.src.get(at) {
+            Some(b) if b.is_ascii_digit() || *b == b'.' => self.number(),
+            Some(_) => Err(("expected a number", at)),
+            None => Err(("expression ends early", at)),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.pos;
+        let radix = match self.src.get(start..start + 2) {
+            Some(b"0x" | b"0X") => 16,
+            Some(b"0b" | b"0B") => 2,
+            Some(b"0o" | b"0O") => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.pos += 2;
+        }
+        let digits_at = self.pos;
+        let mut is_float = false;
+        while let Some(&b) = self.src.get(self.pos) {
+            let more = match b {
+                b'_' => true,
+                b'.' if radix == 10 => {
+                    is_float = true;
+                    true
+                }
+                b'e' | b'E' if radix == 10 => {
+                    is_float = true;
+                    // Let a sign follow the exponent marker.
+                    if matches!(self.src.get(self.pos + 1), Some(b'+' | b'-')) {
+                        self.pos += 1;
+                    }
+                    true
+                }
+                _ => (b as char).is_digit(radix),
+            };
+            if !more {
+                break;
+            }
+            self.pos += 1;
+        }
+        let text: String = self.src[digits_at..self.pos].iter().filter(|&&b| b != b'_').map(|&b| b as char).collect();
+        if is_float {
+            return text.parse::<f64>().map(Value::Float).map_err(|_| ("bad number", start));
+        }
+        if text.is_empty() {
+            return Err(("bad number", start));
+        }
+        // Read as unsigned so a full 64-bit address fits.
+        u64::from_str_radix(&text, radix).map(|n| Value::Int(n as i64)).map_err(|_| ("number too large", start))
+    }
+}
+
+fn evaluate(expr: &str) -> Result<Value, Error> {
+    let mut p = Parser { src: expr.as_bytes(), pos: 0 };
+    let value = p.binary(0)?;
+    p.skip_spaces();
+    if p.pos < p.src.len() {
+        return Err(("unexpected input", p.pos));
+    }
+    Ok(value)
+}
+
+/// `calc <expr...>`: evaluates the words as one expression and prints the
+/// result, whole numbers in both decimal and hex.
+pub fn calc_cmd(args: &[&str]) -> commands::Status {
+    let expr = args.join(" ");
+    match evaluate(&expr) {
+        Ok(Value::Int(n)) => console::write_line(&format!("= {} ({:#x})", n, n as u64)),
+        Ok(Value::Float(f)) if f.is_finite() => console::write_line(&format!("= {}", f)),
+        Ok(Value::Float(_)) => {
+            console::write_err("calc: result is not a finite number");
+            return Err(1);
+        }
+        Err((msg, at)) => {
+            console::write_err(&format!("calc: {} at column {}", msg, at + 1));
+            return Err(1);
+        }
+    }
+    Ok(())
+}
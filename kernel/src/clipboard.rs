@@ -0,0 +1,198 @@
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::keyboard::{self, KeyEvent};
+use crate::{commands, console, serial};
+
+/// Entries kept; the newest is the clipboard proper.
+pub const HISTORY: usize = 8;
+
+static CLIPBOARD: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Whether the clipboard's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    CLIPBOARD.is_locked()
+}
+
+pub fn set(text: String) {
+    interrupts::without_interrupts(|| {
+        let mut clip = CLIPBOARD.lock();
+        clip.retain(|t| *t != text);
+        if clip.len() == HISTORY {
+            clip.pop_back();
+        }
+        clip.push_front(text);
+    });
+}
+
+/// Entry `n`, where 0 is the newest.
+pub fn entry(n: usize) -> Option<String> {
+    interrupts::without_interrupts(|| CLIPBOARD.lock().get(n).cloned())
+}
+
+pub fn len() -> usize {
+    interrupts::without_interrupts(|| CLIPBOARD.lock().len())
+}
+
+pub fn clear() {
+    interrupts::without_interrupts(|| CLIPBOARD.lock().clear());
+}
+
+/// First line of `text`, cut to `width` characters, noting how many lines were left out.
+fn preview(text: &str, width: usize) -> String {
+    let first = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("").trim();
+    let more = text.lines().count().saturating_sub(1);
+    let suffix = if more > 0 { format!("  (+{} lines)", more) } else { String::new() };
+    let room = width.saturating_sub(suffix.len());
+    let mut s: String = first.chars().take(room).collect();
+    if first.chars().count() > room && room > 3 {
+        s = s.chars().take(room - 3).collect();
+        s.push_str("...");
+    }
+    s.push_str(&suffix);
+    s
+}
+
+/// Text as one line, for pasting into the shell's input.
+pub fn flatten(text: &str) -> String {
+    let mut out = String::new();
+    for word in text.split_whitespace() {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(word);
+    }
+    out
+}
+
+const PICK_FG: u32 = 0x7FB8FF;
+
+/// Ctrl+Shift+V: lists the history below the input line and lets the user
+/// choose with the arrow keys. Returns the chosen entry, or None on Esc.
+/// The caller redraws its prompt afterwards.
+pub fn pick() -> Option<String> {
+    let entries: alloc::vec::Vec<String> = interrupts::without_interrupts(|| CLIPBOARD.lock().iter().cloned().collect());
+    console::with_console(|c| c.newline());
+    if entries.is_empty() {
+        console::write_line("(clipboard is empty)");
+        return None;
+    }
+    let width = console::size_chars().0.saturating_sub(1);
+    let rows: alloc::vec::Vec<String> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, t)| format!("  {} {}", i + 1, preview(t, width.saturating_sub(4))))
+        .collect();
+    for row in rows.iter() {
+        console::write_line(row);
+    }
+    console::write_line("Up/Down or 1-8 to choose, Enter to paste, Esc to cancel.");
+    let first_row = console::with_console(|c| c.cursor_position().1).saturating_sub(rows.len() + 1);
+
+    let draw = |i: usize, selected: bool| {
+        let row = &rows[i];
+        let len = row.chars().count();
+        let highlight = selected.then_some((0..len, PICK_FG));
+        let text = if selected { format!(">{}", &row[1..]) } else { row.clone() };
+        console::render_line_at(0, first_row + i, &text, len, 0, highlight);
+    };
+    let mut selected = 0;
+    draw(selected, true);
+    let choice = loop {
+        let next = match keyboard::wait_event() {
+            KeyEvent::Up => selected.checked_sub(1).unwrap_or(rows.len() - 1),
+            KeyEvent::Down => (selected + 1) % rows.len(),
+            KeyEvent::Char(c @ '1'..='8') if (c as usize - '1' as usize) < rows.len() => {
+                break Some(c as usize - '1' as usize);
+            }
+            KeyEvent::Enter => break Some(selected),
+            KeyEvent::Escape => break None,
+            _ => continue,
+        };
+        draw(selected, false);
+        draw(next, true);
+        selected = next;
+    };
+    // Put the cursor back under the list for the caller's prompt.
+    let bottom = first_row + rows.len() + 1;
+    console::with_console(|c| c.move_cursor_to(0, bottom));
+    choice.map(|i| entries[i].clone())
+}
+
+const COPY_USAGE: &str = "Usage: copy [--serial] <command> [args...]";
+
+/// `copy [--serial] <command...>`: runs the command and puts its output on
+/// the clipboard. With --serial the captured text is also sent to COM1.
+pub fn copy_cmd(args: &[&str]) -> commands::Status {
+    let (to_serial, rest) = match args.split_first() {
+        Some((flag, rest)) if flag.eq_ignore_ascii_case("--serial") => (true, rest),
+        _ => (false, args),
+    };
+    if rest.is_empty() {
+        console::write_err(COPY_USAGE);
+        return Err(2);
+    }
+    let line = commands::join_quoted(rest);
+
+    if !console::begin_output_capture() {
+        console::write_err("copy: too many nested captures");
+        return Err(1);
+    }
+    commands::handle_line(&line);
+    let status = commands::last_result();
+    let text = console::end_capture().unwrap_or_default();
+
+    if to_serial {
+        for l in text.lines() {
+            serial::write(l);
+        }
+    }
+    let (lines, bytes) = (text.lines().count(), text.len());
+    set(text);
+    console::write_line(&format!("Copied {} lines ({} bytes) to the clipboard.", lines, bytes));
+    status
+}
+
+/// `clipboard [clear | list | <n>]`: shows, lists or empties the clipboard history.
+pub fn clipboard_cmd(args: &[&str]) -> commands::Status {
+    let n = match args.first() {
+        Some(a) if a.eq_ignore_ascii_case("clear") => {
+            clear();
+            console::write_line("Clipboard cleared.");
+            return Ok(());
+        }
+        Some(a) if a.eq_ignore_ascii_case("list") => {
+            if len() == 0 {
+                console::write_line("(clipboard is empty)");
+            }
+            let width = console::size_chars().0.saturating_sub(5);
+            for i in 0..len() {
+                if let Some(text) = entry(i) {
+                    console::write_line(&format!("{:>2} {}", i + 1, preview(&text, width)));
+                }
+            }
+            return Ok(());
+        }
+        Some(a) => match a.parse::<usize>() {
+            Ok(n) if n >= 1 => n - 1,
+            _ => {
+                console::write_err("Usage: clipboard [clear | list | <n>]");
+                return Err(2);
+            }
+        },
+        None => 0,
+    };
+    match entry(n) {
+        Some(text) if !text.is_empty() => {
+            let mut pager = crate::pager::Pager::new();
+            for l in text.lines() {
+                pager.line(l);
+            }
+            pager.show();
+        }
+        _ => console::write_line("(clipboard is empty)"),
+    }
+    Ok(())
+}
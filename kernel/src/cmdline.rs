@@ -0,0 +1,78 @@
+#![allow(dead_code)]
+
+use bootloader_api::BootInfo;
+use heapless::String as HString;
+
+// The bootloader passes no command line, so it is baked in at build time:
+// STRATOS_CMDLINE="testmode foo=bar" cargo build ...
+const CMDLINE: &str = match option_env!("STRATOS_CMDLINE") {
+    Some(s) => s,
+    None => "",
+};
+
+// stratos.cfg from the FAT partition, delivered as the bootloader ramdisk.
+// Copied out so it survives once the ramdisk's memory is reused. The
+// config ends at a NUL; the build puts the symbol table after it.
+const CONFIG_MAX: usize = 2048;
+static CONFIG: spin::Once<HString<CONFIG_MAX>> = spin::Once::new();
+
+/// Reads the config file, if the bootloader loaded one. Call before anything
+/// looks at options.
+pub fn init(boot: &BootInfo) {
+    let mut text = HString::new();
+    if let Some(addr) = boot.ramdisk_addr.into_option() {
+        let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, boot.ramdisk_len as usize) };
+        let bytes = bytes.split(|&b| b == 0).next().unwrap_or(&[]);
+        let utf8 = match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+        };
+        for ch in utf8.chars() {
+            if text.push(ch).is_err() {
+                break;
+            }
+        }
+    }
+    CONFIG.call_once(|| text);
+}
+
+pub fn get() -> &'static str {
+    CMDLINE
+}
+
+/// The config file as loaded, or "" if there was none.
+pub fn config() -> &'static str {
+    CONFIG.get().map_or("", |s| s.as_str())
+}
+
+/// Config lines with comments and blank lines removed.
+fn config_lines() -> impl Iterator<Item = &'static str> {
+    config()
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or("").trim())
+        .filter(|l| !l.is_empty())
+}
+
+/// True if the bare word `name` appears on the command line or as a flag in
+/// the config file.
+pub fn has_flag(name: &str) -> bool {
+    CMDLINE.split_whitespace().any(|w| w == name)
+        || config_lines().filter(|l| !l.contains('=')).flat_map(str::split_whitespace).any(|w| w == name)
+}
+
+/// Value of the first `key=value` pair with this key. The command line is
+/// searched first; config values run to the end of their line, so they may
+/// contain spaces.
+pub fn value(key: &str) -> Option<&'static str> {
+    CMDLINE
+        .split_whitespace()
+        .filter_map(|w| w.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+        .or_else(|| {
+            config_lines()
+                .filter_map(|l| l.split_once('='))
+                .find(|(k, _)| k.trim() == key)
+                .map(|(_, v)| v.trim())
+        })
+}
@@ -0,0 +1,215 @@
+use alloc::format;
+use crate::args::{opt, Kind, Spec};
+use crate::console::{self, DrawPos};
+use crate::keyboard::{self, KeyEvent};
+use crate::commands;
+
+// Full-screen picker: a palette of hues by lightness with a row of grays,
+// three RGB sliders for fine tuning and a preview. Tab moves between the
+// palette and the sliders.
+
+const HUES: usize = 16;
+const SHADES: usize = 8;
+const CELL_W: usize = 3;
+const BAR_W: usize = 32;
+
+const PALETTE_X: usize = 2;
+const PALETTE_Y: usize = 2;
+const SLIDERS_Y: usize = PALETTE_Y + SHADES + 2;
+const PREVIEW_Y: usize = SLIDERS_Y + 4;
+const HELP_Y: usize = PREVIEW_Y + 3;
+const MIN_COLS: usize = PALETTE_X + HUES * CELL_W + 2;
+
+const LABEL_FG: u32 = 0x7FB8FF;
+const SLIDER_FG: [u32; 3] = [0xFF5050, 0x50FF50, 0x5080FF];
+
+pub static COLORPICK_ARGS: Spec = Spec {
+    command: "colorpick",
+    args: &[opt("apply to", Kind::Choice(&["text", "bg", "cursor"]))],
+};
+
+fn rgb(r: u32, g: u32, b: u32) -> u32 {
+    (r << 16) | (g << 8) | b
+}
+
+fn channels(color: u32) -> [u32; 3] {
+    [(color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF]
+}
+
+/// Fully saturated color at `degrees` around the hue wheel.
+fn hue(degrees: u32) -> [u32; 3] {
+    let f = (degrees % 60) * 255 / 60;
+    match degrees / 60 {
+        0 => [255, f, 0],
+        1 => [255 - f, 255, 0],
+        2 => [0, 255, f],
+        3 => [0, 255 - f, 255],
+        4 => [f, 0, 255],
+        _ => [255, 0, 255 - f],
+    }
+}
+
+/// Rows run from pale through the pure hue to dark; the last row is grays.
+fn palette(col: usize, row: usize) -> u32 {
+    if row == SHADES - 1 {
+        let v = (col * 255 / (HUES - 1)) as u32;
+        return rgb(v, v, v);
+    }
+    let pure = (SHADES - 1) / 2;
+    let c = hue((col * 360 / HUES) as u32);
+    let mix = |v: u32| -> u32 {
+        if row < pure {
+            v + (255 - v) * (pure - row) as u32 / (pure + 1) as u32
+        } else {
+            v - v * (row - pure) as u32 / (pure + 1) as u32
+        }
+    };
+    rgb(mix(c[0]), mix(c[1]), mix(c[2]))
+}
+
+struct Picker {
+    color: u32,
+    /// 0 is the palette, 1 to 3 the R, G and B sliders.
+    focus: usize,
+    col: usize,
+    row: usize,
+}
+
+impl Picker {
+    fn draw_palette(&self) {
+        console::with_console(|c| {
+            for row in 0..SHADES {
+                for col in 0..HUES {
+                    c.fill_cells(PALETTE_X + col * CELL_W, PALETTE_Y + row, CELL_W - 1, 1, palette(col, row));
+                }
+            }
+        });
+    }
+
+    fn draw(&self) {
+        let fg = console::default_fg();
+        let marker = |on: bool| if on { ">" } else { " " };
+        let in_palette = self.focus == 0;
+        console::with_console(|c| {
+            for row in 0..SHADES {
+                c.draw_text_at_char_fg(DrawPos::Char(0, PALETTE_Y + row), marker(in_palette && row == self.row), LABEL_FG);
+            }
+            let mut under = alloc::string::String::new();
+            for col in 0..HUES {
+                let mark = if in_palette && col == self.col { '^' } else { ' ' };
+                for i in 0..CELL_W {
+                    under.push(if i == 0 { mark } else { ' ' });
+                }
+            }
+            c.draw_text_at_char_fg(DrawPos::Char(PALETTE_X, PALETTE_Y + SHADES), &under, LABEL_FG);
+
+            for (i, value) in channels(self.color).into_iter().enumerate() {
+                let filled = value as usize * BAR_W / 255;
+                let mut bar = alloc::string::String::new();
+                for j in 0..BAR_W {
+                    bar.push(if j < filled { '#' } else { '-' });
+                }
+                let y = SLIDERS_Y + i;
+                c.draw_text_at_char_fg(DrawPos::Char(0, y), marker(self.focus == i + 1), LABEL_FG);
+                c.draw_text_at_char_fg(DrawPos::Char(PALETTE_X, y), ["R", "G", "B"][i], fg);
+                c.draw_text_at_char_fg(DrawPos::Char(PALETTE_X + 2, y), &bar, SLIDER_FG[i]);
+                c.draw_text_at_char_fg(DrawPos::Char(PALETTE_X + 3 + BAR_W, y), &format!("{:>3}", value), fg);
+            }
+
+            c.fill_cells(PALETTE_X, PREVIEW_Y, 8, 2, self.color);
+            c.draw_text_at_char_fg(DrawPos::Char(PALETTE_X + 10, PREVIEW_Y), &format!("#{:06X}", self.color), fg);
+            c.draw_text_at_char_fg(
+                DrawPos::Char(PALETTE_X + 10, PREVIEW_Y + 1),
+                "The quick brown fox jumps over the lazy dog",
+                self.color,
+            );
+        });
+    }
+
+    fn step_slider(&mut self, delta: i32) {
+        let i = self.focus - 1;
+        let mut ch = channels(self.color);
+        ch[i] = (ch[i] as i32 + delta).clamp(0, 255) as u32;
+        self.color = rgb(ch[0], ch[1], ch[2]);
+    }
+
+    /// Applies one key. Some(result) ends the picker: the color on Enter, None on Esc.
+    fn key(&mut self, key: KeyEvent) -> Option<Option<u32>> {
+        match key {
+            KeyEvent::Enter => return Some(Some(self.color)),
+            KeyEvent::Escape => return Some(None),
+            KeyEvent::Tab => self.focus = (self.focus + 1) % 4,
+            _ if self.focus == 0 => {
+                match key {
+                    KeyEvent::Left => self.col = (self.col + HUES - 1) % HUES,
+                    KeyEvent::Right => self.col = (self.col + 1) % HUES,
+                    KeyEvent::Up => self.row = (self.row + SHADES - 1) % SHADES,
+                    KeyEvent::Down => self.row = (self.row + 1) % SHADES,
+                    _ => return None,
+                }
+                self.color = palette(self.col, self.row);
+            }
+            KeyEvent::Left => self.step_slider(-8),
+            KeyEvent::Right => self.step_slider(8),
+            KeyEvent::CtrlLeft => self.step_slider(-1),
+            KeyEvent::CtrlRight => self.step_slider(1),
+            KeyEvent::Up => self.focus -= 1,
+            KeyEvent::Down if self.focus < 3 => self.focus += 1,
+            _ => {}
+        }
+        None
+    }
+}
+
+/// `colorpick [text|bg|cursor]`: picks a color interactively and prints its
+/// hex value; with a target, also applies it like the matching `os` command.
+pub fn colorpick_cmd(args: &[&str]) -> commands::Status {
+    let a = COLORPICK_ARGS.check(args)?;
+    let target = a.word(0).map(|t| t.to_ascii_lowercase());
+    let (cols, rows) = console::size_chars();
+    if cols < MIN_COLS || rows < HELP_Y + 1 {
+        console::write_err(&format!("colorpick: needs at least {}x{} characters of screen", MIN_COLS, HELP_Y + 1));
+        return Err(1);
+    }
+
+    let start = match target.as_deref() {
+        Some("bg") => console::default_bg(),
+        Some("cursor") => console::with_console(|c| c.cursor_color()),
+        _ => console::default_fg(),
+    };
+    let mut picker = Picker { color: start, focus: 0, col: 0, row: (SHADES - 1) / 2 };
+
+    console::clear_screen();
+    console::with_console(|c| {
+        c.draw_text_at_char_fg(DrawPos::Char(0, 0), "Color picker", LABEL_FG);
+        c.draw_text_at_char_fg(
+            DrawPos::Char(0, HELP_Y),
+            "Arrows move, Tab switches palette/sliders, Ctrl+Left/Right fine-tunes, Enter picks, Esc cancels.",
+            console::default_fg(),
+        );
+        c.move_cursor_to(0, HELP_Y + 1);
+    });
+    picker.draw_palette();
+    picker.draw();
+    let picked = loop {
+        if let Some(result) = picker.key(keyboard::wait_event()) {
+            break result;
+        }
+        picker.draw();
+    };
+    console::clear_screen();
+
+    let Some(color) = picked else {
+        console::write_err("colorpick: cancelled");
+        return Err(1);
+    };
+    console::write(&format!("#{:06X}  ", color));
+    console::cwrite_line("sample", color, console::default_bg());
+    match target.as_deref() {
+        Some("text") => commands::handle_line(&format!("os text {:06X}", color)),
+        Some("bg") => commands::handle_line(&format!("os bg {:06X}", color)),
+        Some("cursor") => commands::handle_line(&format!("os cursor color {:06X}", color)),
+        _ => {}
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::{console, time, serial, wait, history, memory, OS_NAME, OS_VERSION};
+use crate::{console, glyphs, time, serial, wait, history, memory, OS_NAME, OS_VERSION};
 use crate::help::{BSOD_HEIGHT, BSOD_IMAGE, BSOD_WIDTH};
 use alloc::borrow::ToOwned;
 use alloc::string::ToString;
@@ -8,44 +8,104 @@ use crate::console::{
     FontKind,
 };
 use crate::theme_presets::{Preset, PRESETS};
+use crate::pager::Pager;
+use crate::args::{self, opt, req, Kind, Spec};
 use alloc::format;
 use heapless::{String as HString, Vec, LinearMap};
 use spin::Mutex;
 use raw_cpuid::CpuId;
 use core::fmt::Write;
-
-#[no_mangle]
-pub static mut TICKS: u64 = 0;
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
 
 const PRESET_COUNT: usize = PRESETS.len();
 
-static ALIASES: Mutex<LinearMap<HString<32>, HString<32>, 32>> =
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// Commands running inside one another through scripts and aliases.
+static COMMAND_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+static ALIASES: Mutex<LinearMap<HString<32>, alloc::string::String, 32>> =
     Mutex::new(LinearMap::new());
 
-pub fn add_alias(alias: &str, command: &str) {
-    let mut aliases = ALIASES.lock();
+/// Whether the alias table, the aliases being expanded, the fallbacks or
+/// the pipe input are locked, for the watchdog.
+pub fn busy() -> bool {
+    ALIASES.is_locked() || EXPANDING.is_locked() || FALLBACKS.is_locked() || PIPE_INPUT.is_locked()
+}
 
-    let alias_lower = alias.to_ascii_lowercase();
-    let command_lower = command.to_ascii_lowercase();
+/// Gets the words of a line no built-in command or alias matched. Returns
+/// None if the line is not its to run.
+pub type FallbackHandler = fn(&[&str]) -> Option<Status>;
+
+const MAX_FALLBACKS: usize = 8;
+
+static FALLBACKS: Mutex<Vec<(&'static str, FallbackHandler), MAX_FALLBACKS>> = Mutex::new(Vec::new());
+
+/// Adds a handler for unknown commands, tried in registration order before
+/// "Unknown command" is printed. Returns false if the name is taken or the
+/// table is full.
+pub fn register_fallback(name: &'static str, handler: FallbackHandler) -> bool {
+    let mut fallbacks = FALLBACKS.lock();
+    if fallbacks.iter().any(|(n, _)| *n == name) {
+        return false;
+    }
+    fallbacks.push((name, handler)).is_ok()
+}
+
+pub fn unregister_fallback(name: &str) {
+    FALLBACKS.lock().retain(|(n, _)| *n != name);
+}
+
+fn run_fallbacks(parts: &[&str]) -> Option<Status> {
+    // Copied out so a handler can run commands or register others.
+    let handlers: Vec<FallbackHandler, MAX_FALLBACKS> = FALLBACKS.lock().iter().map(|(_, h)| *h).collect();
+    handlers.iter().find_map(|h| h(parts))
+}
+
+/// Longest alias body; a body runs as one command line, so like any line it
+/// may chain commands with `&&` and `||`.
+const ALIAS_BODY_MAX: usize = 128;
+/// Aliases expanding inside one another, innermost last.
+const ALIAS_DEPTH: usize = 8;
+
+static EXPANDING: Mutex<Vec<HString<32>, ALIAS_DEPTH>> = Mutex::new(Vec::new());
+
+fn insert_alias(alias: &str, command: &str) -> Result<(), alloc::string::String> {
+    let mut aliases = ALIASES.lock();
 
     let mut alias_str: HString<32> = HString::new();
-    let mut command_str: HString<32> = HString::new();
+    if alias_str.push_str(alias).is_err() {
+        return Err("Alias too long (max 32 chars).".to_owned());
+    }
+    if command.len() > ALIAS_BODY_MAX {
+        return Err(format!("Alias body too long (max {} chars).", ALIAS_BODY_MAX));
+    }
 
-    if alias_str.push_str(&alias_lower).is_err() || command_str.push_str(&command_lower).is_err() {
-        console::write_line("Alias too long (max 32 chars).");
-        return;
+    if aliases.contains_key(&alias_str) {
+        return Err("Alias already exists.".to_owned());
     }
 
-    if aliases.contains_key(&alias_str) || aliases.values().any(|v| v == &alias_str) {
-        console::write_line("Alias already exists or conflicts.");
-        return;
+    if aliases.insert(alias_str, command.to_owned()).is_err() {
+        return Err("Too many aliases.".to_owned());
     }
+    Ok(())
+}
 
-    aliases.insert(alias_str.clone(), command_str.clone()).ok();
-    console::write_line(&format!("Alias added: {} -> {}", alias_lower, command_lower));
+pub fn add_alias(alias: &str, command: &str) -> Status {
+    let alias_lower = alias.to_ascii_lowercase();
+    match insert_alias(&alias_lower, command) {
+        Ok(()) => {
+            console::write_line(&format!("Alias added: {} -> {}", alias_lower, command));
+            Ok(())
+        }
+        Err(msg) => {
+            console::write_err(&msg);
+            Err(1)
+        }
+    }
 }
 
-pub fn remove_alias(alias: &str) {
+pub fn remove_alias(alias: &str) -> Status {
     let mut aliases = ALIASES.lock();
 
     let alias_lower = alias.to_ascii_lowercase();
@@ -54,11 +114,20 @@ pub fn remove_alias(alias: &str) {
 
     if aliases.remove(&alias_str).is_some() {
         console::write_line(&format!("Alias removed: {}", alias_lower));
+        Ok(())
     } else {
-        console::write_line("Alias not found.");
+        console::write_err("Alias not found.");
+        Err(1)
     }
 }
 
+/// Forgets aliases that were mid-expansion when the shell task was aborted.
+pub fn on_shell_restart() {
+    EXPANDING.lock().clear();
+    COMMAND_DEPTH.store(0, Ordering::Relaxed);
+    console::set_output_log(false);
+}
+
 pub fn list_aliases() {
     let aliases = ALIASES.lock();
     if aliases.is_empty() {
@@ -71,32 +140,112 @@ pub fn list_aliases() {
     }
 }
 
-pub fn resolve_alias(cmd: &str) -> HString<32> {
-    let aliases = ALIASES.lock();
+/// Aliases are kept across reboots in this file in the host folder, one
+/// `name<TAB>body` per line. Without a host folder they last until power-off.
+const ALIAS_FILE: &str = ".stratos-aliases";
+const ALIAS_FILE_MAX: usize = 8 * 1024;
 
-    let cmd_lower = cmd.to_ascii_lowercase();
-    let mut key: HString<32> = HString::new();
-    let _ = key.push_str(&cmd_lower);
+/// Writes the aliases to the host folder, if there is one. Called on the way
+/// to a reboot or shutdown someone asked for.
+pub fn save_aliases() {
+    if !crate::hostfs::attached() || crate::settings::frozen() {
+        return;
+    }
+    let mut text = alloc::string::String::new();
+    for (alias, body) in ALIASES.lock().iter() {
+        if !body.contains('\n') {
+            let _ = writeln!(text, "{}\t{}", alias, body);
+        }
+    }
+    if let Err(msg) = crate::hostfs::write_file(ALIAS_FILE, text.as_bytes()) {
+        crate::klog::warn(&format!("alias: could not save to host {}: {}", ALIAS_FILE, msg));
+    }
+}
 
-    if let Some(target) = aliases.get(&key) {
-        target.clone()
-    } else {
-        key
+/// Reads back the aliases a previous boot saved.
+pub fn restore_aliases() {
+    if !crate::hostfs::attached() || crate::settings::frozen() {
+        return;
+    }
+    let data = match crate::hostfs::read_file(ALIAS_FILE, ALIAS_FILE_MAX) {
+        Ok(data) => data,
+        Err(msg) => {
+            crate::klog::debug(&format!("alias: nothing restored from host {}: {}", ALIAS_FILE, msg));
+            return;
+        }
+    };
+    let mut restored = 0;
+    for line in core::str::from_utf8(&data).unwrap_or("").lines() {
+        let Some((alias, body)) = line.split_once('\t') else { continue; };
+        match insert_alias(&alias.to_ascii_lowercase(), body) {
+            Ok(()) => restored += 1,
+            Err(msg) => crate::klog::warn(&format!("alias: skipped saved alias {}: {}", alias, msg)),
+        }
     }
+    crate::klog::info(&format!("alias: restored {} from host {}", restored, ALIAS_FILE));
 }
 
-pub fn tick() {
-    unsafe { TICKS += 1; }
+fn alias_body(name: &str) -> Option<alloc::string::String> {
+    let mut key: HString<32> = HString::new();
+    key.push_str(&name.to_ascii_lowercase()).ok()?;
+    ALIASES.lock().get(&key).cloned()
 }
 
-pub fn wait_ticks(ticks: u64) {
-    let start = unsafe { TICKS };
-    while unsafe { TICKS } - start < ticks {
-        unsafe { x86::halt(); }
+/// The command word `cmd` stands for: the first word of its alias body, or
+/// `cmd` itself, lowercased.
+pub fn resolve_alias(cmd: &str) -> HString<32> {
+    let body = alias_body(cmd);
+    let word = body.as_deref().and_then(|b| b.split_whitespace().next()).unwrap_or(cmd);
+    let mut key: HString<32> = HString::new();
+    let _ = key.push_str(&word.to_ascii_lowercase());
+    key
+}
+
+/// Runs `parts` through the alias named by its first word: the body with
+/// the rest of the words appended, as a command line of its own. Returns
+/// None if `parts[0]` is no alias, or names a builtin it is already
+/// expanding, so `alias "help all" help` reaches the builtin `help`.
+///
+/// Any other alias met again while expanding is a loop (`a` runs `b` runs
+/// `a`); it is reported with the chain instead of recursing until the stack
+/// runs out, and so is nesting deeper than `ALIAS_DEPTH`.
+fn run_alias(parts: &[&str]) -> Option<Status> {
+    let body = alias_body(parts[0])?;
+    let mut name: HString<32> = HString::new();
+    let _ = name.push_str(&parts[0].to_ascii_lowercase());
+    {
+        let mut expanding = EXPANDING.lock();
+        if let Some(at) = expanding.iter().position(|n| *n == name) {
+            if find_command_info(&name).is_some() {
+                return None;
+            }
+            let mut chain = alloc::string::String::new();
+            for n in expanding[at..].iter() {
+                chain.push_str(n);
+                chain.push_str(" -> ");
+            }
+            chain.push_str(&name);
+            drop(expanding);
+            console::write_err(&format!("alias loop detected: {}", chain));
+            return Some(Err(1));
+        }
+        if expanding.push(name).is_err() {
+            drop(expanding);
+            console::write_err(&format!("{}: aliases nested more than {} deep", parts[0], ALIAS_DEPTH));
+            return Some(Err(1));
+        }
+    }
+    let mut line = body;
+    for word in &parts[1..] {
+        line.push(' ');
+        line.push_str(&quote(word));
     }
+    handle_line(&line);
+    EXPANDING.lock().pop();
+    Some(last_result())
 }
 
-pub fn echo(args: &[&str]) {
+pub fn echo(args: &[&str]) -> Status {
     let mut s = HString::<128>::new();
     for (i, word) in args.iter().enumerate() {
         if i > 0 {
@@ -104,10 +253,11 @@ pub fn echo(args: &[&str]) {
         }
         let _ = s.push_str(word);
     }
-    console::write_line(&s);
+    console::write_line(&glyphs::unescape(&s));
+    Ok(())
 }
 
-pub fn secho(args: &[&str]) {
+pub fn secho(args: &[&str]) -> Status {
     let mut s: HString<128> = HString::new();
 
     for (i, word) in args.iter().enumerate() {
@@ -116,14 +266,15 @@ pub fn secho(args: &[&str]) {
         }
 
         if s.push_str(word).is_err() {
-            serial::write("Error: message too long");
-            return;
+            console::write_err("secho: message too long");
+            return Err(1);
         }
     }
     serial::write(&s);
+    Ok(())
 }
 
-fn parse_rgb_hex(s: &str) -> Option<u32> {
+pub(crate) fn parse_rgb_hex(s: &str) -> Option<u32> {
     let h = s.trim();
     if h.len() == 3 {
         let mut buf = [0u8; 6];
@@ -141,73 +292,105 @@ fn parse_rgb_hex(s: &str) -> Option<u32> {
     None
 }
 
-const CURSOR_USAGE: &str = "Usage: cursor style underscore|line|block|hidden OR cursor blink none|pulse|fade OR cursor color <hex>";
+static CURSOR_STYLE_ARGS: Spec = Spec {
+    command: "os cursor style",
+    args: &[req("style", Kind::Choice(&["underscore", "line", "block", "hidden"]))],
+};
+static CURSOR_BLINK_ARGS: Spec = Spec {
+    command: "os cursor blink",
+    args: &[req("blink", Kind::Choice(&["none", "pulse", "fade"]))],
+};
+static CURSOR_COLOR_ARGS: Spec = Spec { command: "os cursor color", args: &[req("hex", Kind::Color)] };
+static TEXT_ARGS: Spec = Spec { command: "os text", args: &[req("hex", Kind::Color)] };
+static BG_ARGS: Spec = Spec { command: "os bg", args: &[req("hex", Kind::Color)] };
 const FONT_USAGE: &str = "Usage: os font vga8|default|terminus|spleen";
-const HUD_USAGE: &str = "Usage: os hud on|off";
-const TEXT_USAGE: &str = "Usage: os text <hex>";
-const BG_USAGE: &str = "Usage: os bg <hex>";
+const HUD_USAGE: &str = "Usage: os hud on|off | temp on [threshold C]|off | drift on [threshold s]|off | list | add <name> <left|center|right> [priority] <text> | remove <name> | color <name> <hex>|default | style [sep <char>|line|heavy|double|none] [pad 0-4]";
 const CMDHIST_USAGE: &str = "Usage: os cmdhistory clear|toggle";
 const THEME_USAGE: &str = "Usage: os theme list | os theme about <preset name> | os theme <preset name>";
-const TIME_USAGE: &str = "Usage: os time 12hr|24hr|sync|help";
+const POWER_USAGE: &str = "Usage: os power button shutdown|ignore|prompt | os power lid shutdown|ignore";
+const WATCHDOG_USAGE: &str = "Usage: os watchdog <seconds>|off";
+const TESTMODE_USAGE: &str = "Usage: os testmode on|off";
+const SERIALSHELL_USAGE: &str = "Usage: os serialshell on|off";
+const ACCESSIBILITY_USAGE: &str = "Usage: os accessibility colors protanopia|deuteranopia|off | os accessibility contrast high|normal";
+const HIGHLIGHT_USAGE: &str = "Usage: os highlight on|off";
+const OVERLAY_USAGE: &str = "Usage: os overlay [on|off]";
+const WRAP_USAGE: &str = "Usage: os wrap on|off";
+const ERRORS_USAGE: &str = "Usage: os errors color <hex>|default | serial on|off | log on|off";
+const MAGNIFIER_USAGE: &str = "Usage: os magnifier on|off  (F12 toggles)";
+const TIMER_USAGE: &str = "Usage: os timer [tickless on|off]  (tick rate is set with hz= on the kernel command line)";
+const LOG_USAGE: &str = "Usage: os log level debug|info|warn|error | os log serial on|off | os log e9 on|off";
+const APPS_USAGE: &str = "Usage: os apps output tagged|raw | os apps bare on|off";
+const TIME_USAGE: &str = "Usage: os time 12hr|24hr|sync|status|alarm|help";
+const ALARM_USAGE: &str = "Usage: os time alarm HH:MM <command> | os time alarm off";
 
 fn os_usage() {
-    console::write_line("Usage: os <font|cursor|hud|text|bg> ...");
+    console::write_err("Usage: os <font|cursor|hud|text|bg> ...");
     console::write_line("  font   default/vga8|terminus|spleen");
     console::write_line("  cursor style underscore|line|block|hidden");
     console::write_line("  cursor blink none|pulse|fade");
     console::write_line("  cursor color <hex>");
     console::write_line("  hud    on|off");
+    console::write_line("  hud    temp on [threshold C]|off");
+    console::write_line("  hud    drift on [threshold s]|off  (badge when the clock drifts from the RTC)");
+    console::write_line("  hud    list | add <name> <left|center|right> [priority] <text> | remove <name>");
+    console::write_line("  hud    color <name> <hex>|default  (color of one module)");
+    console::write_line("  hud    style [sep <char>|line|heavy|double|none] [pad 0-4]  (between groups)");
     console::write_line("  text   <hex>  (default text color)");
     console::write_line("  bg     <hex>  (default background, clears screen)");
     console::write_line("  cmdhistory clear|toggle");
-    console::write_line("  time   12hr|24hr|sync|help");
+    console::write_line("  time   12hr|24hr|sync|status|help");
+    console::write_line("  time   alarm HH:MM <command>|off");
+    console::write_line("  power  button shutdown|ignore|prompt");
+    console::write_line("  power  lid shutdown|ignore");
+    console::write_line("  apps   output tagged|raw | bare on|off  (run apps by name without `run`)");
+    console::write_line("  accessibility colors protanopia|deuteranopia|off | contrast high|normal");
+    console::write_line("  magnifier on|off  (2x zoom around the cursor, F12 toggles)");
+    console::write_line("  overlay [on|off]  (presents per second, time and area; F11 toggles)");
+    console::write_line("  highlight on|off  (color the command word: green if known, red if not)");
+    console::write_line("  wrap   on|off  (wrap help and paged text at words)");
+    console::write_line("  errors color <hex>|default | serial on|off | log on|off  (error output channel)");
+    console::write_line("  timer  tickless on|off  (experimental)");
+    console::write_line("  log    level debug|info|warn|error | serial on|off | e9 on|off");
+    console::write_line("  testmode on|off  (run commands received over serial)");
+    console::write_line("  serialshell on|off  (interactive shell on COM1, for a terminal)");
+    console::write_line("  watchdog <seconds>|off  (abort commands that never yield)");
     console::write_line("  theme  list | about <preset name> | <preset name> (apply, list, or describe presets)");
 }
 
-fn handle_cursor_args(args: &[&str]) -> Result<(), &'static str> {
-    if args.len() < 2 {
-        return Err(CURSOR_USAGE);
-    }
-
-    let mode = args[0];
-    let value = args[1];
-
-    if mode.eq_ignore_ascii_case("style") {
-        if value.eq_ignore_ascii_case("underscore") {
-            console::set_cursor_style(CursorStyle::Underscore);
-        } else if value.eq_ignore_ascii_case("line") {
-            console::set_cursor_style(CursorStyle::Line);
-        } else if value.eq_ignore_ascii_case("block") {
-            console::set_cursor_style(CursorStyle::Block);
-        } else if value.eq_ignore_ascii_case("hidden") {
-            console::set_cursor_style(CursorStyle::Hidden);
-        } else {
-            return Err(CURSOR_USAGE);
+fn os_cursor(args: &[&str]) -> Status {
+    match args.split_first() {
+        Some((mode, rest)) if mode.eq_ignore_ascii_case("style") => {
+            let a = CURSOR_STYLE_ARGS.check(rest)?;
+            let style = match a.word(0).unwrap_or("").to_ascii_lowercase().as_str() {
+                "underscore" => CursorStyle::Underscore,
+                "line" => CursorStyle::Line,
+                "block" => CursorStyle::Block,
+                _ => CursorStyle::Hidden,
+            };
+            console::set_cursor_style(style);
         }
-        Ok(())
-    } else if mode.eq_ignore_ascii_case("blink") {
-        if value.eq_ignore_ascii_case("none") {
-            console::set_cursor_blink(CursorBlink::None);
-        } else if value.eq_ignore_ascii_case("pulse") {
-            console::set_cursor_blink(CursorBlink::Pulse);
-        } else if value.eq_ignore_ascii_case("fade") {
-            console::set_cursor_blink(CursorBlink::Fade);
-        } else {
-            return Err(CURSOR_USAGE);
+        Some((mode, rest)) if mode.eq_ignore_ascii_case("blink") => {
+            let a = CURSOR_BLINK_ARGS.check(rest)?;
+            let blink = match a.word(0).unwrap_or("").to_ascii_lowercase().as_str() {
+                "none" => CursorBlink::None,
+                "pulse" => CursorBlink::Pulse,
+                _ => CursorBlink::Fade,
+            };
+            console::set_cursor_blink(blink);
         }
-        Ok(())
-    } else if mode.eq_ignore_ascii_case("color") {
-        match parse_rgb_hex(value) {
-            Some(v) if v <= 0xFFFFFF => {
-                console::set_cursor_color(v);
-                console::write_line(&format!("Cursor color set to #{:06X}.", v));
-                Ok(())
+        Some((mode, rest)) if mode.eq_ignore_ascii_case("color") => {
+            let v = CURSOR_COLOR_ARGS.check(rest)?.color(0).unwrap_or(0xFFFFFF);
+            console::set_cursor_color(v);
+            console::write_line(&format!("Cursor color set to #{:06X}.", v));
+        }
+        _ => {
+            for spec in [&CURSOR_STYLE_ARGS, &CURSOR_BLINK_ARGS, &CURSOR_COLOR_ARGS] {
+                console::write_err(&spec.usage());
             }
-            _ => Err("cursor color: invalid hex. Use 3 or 6 hex digits, e.g., FF00FF"),
+            return Err(2);
         }
-    } else {
-        Err(CURSOR_USAGE)
     }
+    Ok(())
 }
 
 fn handle_hud_args(args: &[&str]) -> Result<(), &'static str> {
@@ -222,6 +405,161 @@ fn handle_hud_args(args: &[&str]) -> Result<(), &'static str> {
             console::write_line("Terminal HUD disabled.");
             Ok(())
         }
+        Some(sub) if sub.eq_ignore_ascii_case("temp") => handle_hud_temp_args(&args[1..]),
+        Some(sub) if sub.eq_ignore_ascii_case("drift") => handle_hud_drift_args(&args[1..]),
+        Some(sub) if sub.eq_ignore_ascii_case("list") => {
+            let names = crate::thud::module_names();
+            console::write_line(&format!("{} of {} HUD modules:", names.len(), crate::thud::MAX_MODULES));
+            for name in names.iter() {
+                console::write_line(&format!("  {}", name));
+            }
+            Ok(())
+        }
+        Some(sub) if sub.eq_ignore_ascii_case("add") => handle_hud_add_args(&args[1..]),
+        Some(sub) if sub.eq_ignore_ascii_case("style") => handle_hud_style_args(&args[1..]),
+        Some(sub) if sub.eq_ignore_ascii_case("color") => {
+            let [name, v] = &args[1..] else {
+                return Err(HUD_USAGE);
+            };
+            let color = if v.eq_ignore_ascii_case("default") {
+                None
+            } else {
+                Some(parse_rgb_hex(v).filter(|&c| c <= 0xFFFFFF).ok_or(HUD_USAGE)?)
+            };
+            if !crate::thud::set_color(name, color) {
+                return Err("os hud color: no HUD module with that name");
+            }
+            match color {
+                Some(c) => console::write_line(&format!("HUD module '{}' drawn in #{:06X}.", name, c)),
+                None => console::write_line(&format!("HUD module '{}' back to its own color.", name)),
+            }
+            Ok(())
+        }
+        Some(sub) if sub.eq_ignore_ascii_case("remove") => {
+            let [name] = &args[1..] else {
+                return Err(HUD_USAGE);
+            };
+            if crate::thud::unregister(name) {
+                console::write_line(&format!("HUD module '{}' removed.", name));
+                Ok(())
+            } else {
+                Err("os hud remove: no HUD module with that name")
+            }
+        }
+        _ => Err(HUD_USAGE),
+    }
+}
+
+fn handle_hud_add_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::console::HudAlign;
+
+    let [name, align, rest @ ..] = args else {
+        return Err(HUD_USAGE);
+    };
+    let align = match *align {
+        a if a.eq_ignore_ascii_case("left") => HudAlign::Left,
+        a if a.eq_ignore_ascii_case("center") => HudAlign::Center,
+        a if a.eq_ignore_ascii_case("right") => HudAlign::Right,
+        _ => return Err(HUD_USAGE),
+    };
+    let (priority, words) = match rest.split_first().map(|(p, words)| (p.parse::<u8>(), words)) {
+        Some((Ok(p), words)) if !words.is_empty() => (p, words),
+        _ => (50, rest),
+    };
+    if words.is_empty() {
+        return Err(HUD_USAGE);
+    }
+    let text = join_name_parts(words);
+    crate::thudmodules::text::add(name, align, &text, priority)?;
+    console::write_line(&format!("HUD module '{}' added.", name));
+    Ok(())
+}
+
+fn handle_hud_style_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::thud;
+
+    let mut sep = thud::separator();
+    let mut pad = thud::padding();
+    let mut rest = args;
+    while let [what, v, more @ ..] = rest {
+        if what.eq_ignore_ascii_case("sep") {
+            sep = match v.to_ascii_lowercase().as_str() {
+                "none" => None,
+                "line" => Some(thud::DEFAULT_SEPARATOR),
+                "heavy" => Some('\u{2503}'),
+                "double" => Some('\u{2551}'),
+                _ => {
+                    let text = crate::glyphs::unescape(v);
+                    let mut chars = text.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if crate::glyphs::drawable(c) => Some(c),
+                        _ => return Err("os hud style: the separator must be one character the console can draw"),
+                    }
+                }
+            };
+        } else if what.eq_ignore_ascii_case("pad") {
+            pad = v.parse::<usize>().ok().filter(|&p| p <= thud::MAX_PADDING).ok_or("os hud style: pad must be 0-4")?;
+        } else {
+            return Err(HUD_USAGE);
+        }
+        rest = more;
+    }
+    if !rest.is_empty() {
+        return Err(HUD_USAGE);
+    }
+    thud::set_style(sep, pad);
+    let sep_text = sep.map_or("none".to_owned(), |c| format!("'{}'", c));
+    console::write_line(&format!("HUD separator {}, padding {}.", sep_text, pad));
+    Ok(())
+}
+
+fn handle_hud_temp_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::thudmodules::temp;
+
+    match args.first() {
+        Some(state) if state.eq_ignore_ascii_case("on") => {
+            if let Some(raw) = args.get(1) {
+                match raw.parse::<u32>() {
+                    Ok(v) if v > 0 && v < 150 => temp::set_threshold(v),
+                    _ => return Err("os hud temp: threshold must be 1-149 (degrees C)"),
+                }
+            }
+            temp::set_enabled(true);
+            console::write_line(&format!(
+                "Temperature HUD enabled (warns at {} C).",
+                temp::threshold()
+            ));
+            Ok(())
+        }
+        Some(state) if state.eq_ignore_ascii_case("off") => {
+            temp::set_enabled(false);
+            console::write_line("Temperature HUD disabled.");
+            Ok(())
+        }
+        _ => Err(HUD_USAGE),
+    }
+}
+
+fn handle_hud_drift_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::thudmodules::drift;
+
+    match args.first() {
+        Some(state) if state.eq_ignore_ascii_case("on") => {
+            if let Some(raw) = args.get(1) {
+                match raw.parse::<u64>() {
+                    Ok(v) if (1..=3600).contains(&v) => drift::set_threshold(v),
+                    _ => return Err("os hud drift: threshold must be 1-3600 (seconds)"),
+                }
+            }
+            drift::set_enabled(true);
+            console::write_line(&format!("Clock drift badge enabled (shown past {}s).", drift::threshold()));
+            Ok(())
+        }
+        Some(state) if state.eq_ignore_ascii_case("off") => {
+            drift::set_enabled(false);
+            console::write_line("Clock drift badge disabled.");
+            Ok(())
+        }
         _ => Err(HUD_USAGE),
     }
 }
@@ -252,11 +590,13 @@ fn handle_time_args(args: &[&str]) -> Result<(), &'static str> {
             if sub.eq_ignore_ascii_case("12hr")
                 || sub.eq_ignore_ascii_case("24hr")
                 || sub.eq_ignore_ascii_case("sync")
+                || sub.eq_ignore_ascii_case("status")
                 || sub.eq_ignore_ascii_case("help") =>
         {
-            time::time_cmd(&[sub]);
+            time::time_cmd(&[&sub.to_ascii_lowercase()]);
             Ok(())
         }
+        Some(sub) if sub.eq_ignore_ascii_case("alarm") => handle_alarm_args(&args[1..]),
         None => {
             time::time_cmd(&[]);
             Ok(())
@@ -265,6 +605,368 @@ fn handle_time_args(args: &[&str]) -> Result<(), &'static str> {
     }
 }
 
+fn handle_alarm_args(args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        [] => {
+            match time::alarm() {
+                Some((h, m, cmd)) => console::write_line(&format!("Alarm set for {:02}:{:02}: {}", h, m, cmd)),
+                None => console::write_line("No alarm set."),
+            }
+            Ok(())
+        }
+        [off] if off.eq_ignore_ascii_case("off") => {
+            console::write_line(if time::clear_alarm() { "Alarm cleared." } else { "No alarm set." });
+            Ok(())
+        }
+        [at, command @ ..] if !command.is_empty() => {
+            let (h, m) = at.split_once(':').ok_or(ALARM_USAGE)?;
+            let hour = h.parse::<u8>().map_err(|_| ALARM_USAGE)?;
+            let minute = m.parse::<u8>().map_err(|_| ALARM_USAGE)?;
+            time::set_alarm(hour, minute, &join_quoted(command))?;
+            console::write_line(&format!("Alarm set for {:02}:{:02} (RTC time).", hour, minute));
+            Ok(())
+        }
+        _ => Err(ALARM_USAGE),
+    }
+}
+
+fn handle_power_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::power::{set_button_policy, set_lid_policy, ButtonPolicy, LidPolicy};
+
+    match args {
+        [target, policy] if target.eq_ignore_ascii_case("button") => {
+            let (p, msg) = if policy.eq_ignore_ascii_case("shutdown") {
+                (ButtonPolicy::Shutdown, "Power button: shut down immediately.")
+            } else if policy.eq_ignore_ascii_case("ignore") {
+                (ButtonPolicy::Ignore, "Power button: ignored.")
+            } else if policy.eq_ignore_ascii_case("prompt") {
+                (ButtonPolicy::Prompt, "Power button: ask before shutting down.")
+            } else {
+                return Err(POWER_USAGE);
+            };
+            set_button_policy(p);
+            console::write_line(msg);
+            Ok(())
+        }
+        [target, policy] if target.eq_ignore_ascii_case("lid") => {
+            let (p, msg) = if policy.eq_ignore_ascii_case("shutdown") {
+                (LidPolicy::Shutdown, "Lid: shut down when closed.")
+            } else if policy.eq_ignore_ascii_case("ignore") {
+                (LidPolicy::Ignore, "Lid: closing it does nothing.")
+            } else {
+                return Err(POWER_USAGE);
+            };
+            set_lid_policy(p);
+            console::write_line(msg);
+            if !crate::power::has_lid() {
+                console::write_line("(The firmware describes no lid on this machine.)");
+            }
+            Ok(())
+        }
+        _ => Err(POWER_USAGE),
+    }
+}
+
+fn handle_watchdog_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::task::{set_watchdog_budget, watchdog_budget};
+
+    match args {
+        [] => {
+            match watchdog_budget() {
+                0 => console::write_line("Watchdog: off."),
+                secs => console::write_line(&format!("Watchdog: {}s budget.", secs)),
+            }
+            Ok(())
+        }
+        [v] if v.eq_ignore_ascii_case("off") => {
+            set_watchdog_budget(0);
+            console::write_line("Watchdog disabled.");
+            Ok(())
+        }
+        [v] => {
+            let secs: u64 = v.parse().map_err(|_| WATCHDOG_USAGE)?;
+            if secs == 0 {
+                return Err(WATCHDOG_USAGE);
+            }
+            set_watchdog_budget(secs);
+            console::write_line(&format!("Watchdog budget set to {}s.", secs));
+            Ok(())
+        }
+        _ => Err(WATCHDOG_USAGE),
+    }
+}
+
+fn handle_testmode_args(args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        [v] if v.eq_ignore_ascii_case("on") => {
+            crate::testmode::set_enabled(true);
+            console::write_line("Test mode on: reading commands from COM1.");
+            Ok(())
+        }
+        [v] if v.eq_ignore_ascii_case("off") => {
+            crate::testmode::set_enabled(false);
+            console::write_line("Test mode off.");
+            Ok(())
+        }
+        _ => Err(TESTMODE_USAGE),
+    }
+}
+
+fn handle_serialshell_args(args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        [v] if v.eq_ignore_ascii_case("on") => {
+            crate::serialshell::set_enabled(true);
+            if crate::testmode::enabled() {
+                console::write_line("Serial shell on; it stays quiet until test mode is switched off.");
+            } else {
+                console::write_line("Serial shell on COM1.");
+            }
+            Ok(())
+        }
+        [v] if v.eq_ignore_ascii_case("off") => {
+            crate::serialshell::set_enabled(false);
+            console::write_line("Serial shell off.");
+            Ok(())
+        }
+        _ => Err(SERIALSHELL_USAGE),
+    }
+}
+
+fn handle_accessibility_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::console::ColorFilter;
+
+    let (filter, high_contrast) = console::accessibility();
+    match args {
+        [] => {
+            console::write_line(&format!(
+                "Color filter: {}, contrast: {}",
+                filter.name(),
+                if high_contrast { "high" } else { "normal" }
+            ));
+            Ok(())
+        }
+        [what, v] if what.eq_ignore_ascii_case("colors") => {
+            let filter = ColorFilter::parse(v).ok_or(ACCESSIBILITY_USAGE)?;
+            console::set_accessibility(filter, high_contrast);
+            crate::settings::save();
+            console::write_line(&format!("Color filter: {}", filter.name()));
+            Ok(())
+        }
+        [what, v] if what.eq_ignore_ascii_case("contrast") => {
+            let on = if v.eq_ignore_ascii_case("high") {
+                true
+            } else if v.eq_ignore_ascii_case("normal") {
+                false
+            } else {
+                return Err(ACCESSIBILITY_USAGE);
+            };
+            console::set_accessibility(filter, on);
+            crate::settings::save();
+            console::write_line(if on { "High contrast on." } else { "High contrast off." });
+            Ok(())
+        }
+        _ => Err(ACCESSIBILITY_USAGE),
+    }
+}
+
+fn handle_magnifier_args(args: &[&str]) -> Result<(), &'static str> {
+    let on = match args {
+        [] => !console::magnifier(),
+        [v] if v.eq_ignore_ascii_case("on") => true,
+        [v] if v.eq_ignore_ascii_case("off") => false,
+        _ => return Err(MAGNIFIER_USAGE),
+    };
+    if !console::set_magnifier(on) {
+        return Err("Magnifier needs a back buffer, which could not be allocated.");
+    }
+    console::write_line(if on { "Magnifier on (F12 toggles)." } else { "Magnifier off." });
+    Ok(())
+}
+
+fn handle_overlay_args(args: &[&str]) -> Result<(), &'static str> {
+    let on = match args {
+        [] => !console::perf_overlay(),
+        [v] if v.eq_ignore_ascii_case("on") => true,
+        [v] if v.eq_ignore_ascii_case("off") => false,
+        _ => return Err(OVERLAY_USAGE),
+    };
+    if !console::set_perf_overlay(on) {
+        return Err("The overlay needs a back buffer, which could not be allocated.");
+    }
+    console::write_line(if on { "Present overlay on (F11 toggles)." } else { "Present overlay off." });
+    Ok(())
+}
+
+fn handle_highlight_args(args: &[&str]) -> Result<(), &'static str> {
+    let on = match args {
+        [v] if v.eq_ignore_ascii_case("on") => true,
+        [v] if v.eq_ignore_ascii_case("off") => false,
+        _ => return Err(HIGHLIGHT_USAGE),
+    };
+    crate::lineedit::set_highlight(on);
+    console::write_line(if on { "Command highlighting on." } else { "Command highlighting off." });
+    Ok(())
+}
+
+fn handle_wrap_args(args: &[&str]) -> Result<(), &'static str> {
+    let on = match args {
+        [v] if v.eq_ignore_ascii_case("on") => true,
+        [v] if v.eq_ignore_ascii_case("off") => false,
+        _ => return Err(WRAP_USAGE),
+    };
+    console::set_word_wrap(on);
+    console::write_line(if on { "Word wrap on for help and paged output." } else { "Word wrap off." });
+    Ok(())
+}
+
+fn handle_errors_args(args: &[&str]) -> Result<(), &'static str> {
+    let on_off = |v: &str| match v.to_ascii_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ERRORS_USAGE),
+    };
+    let (to_serial, to_log) = console::error_mirrors();
+    match args {
+        [] => {
+            let state = |on: bool| if on { "on" } else { "off" };
+            console::write_line(&format!(
+                "Errors: color #{:06X}, serial {}, log {}",
+                console::error_color(),
+                state(to_serial),
+                state(to_log)
+            ));
+        }
+        [what, v] if what.eq_ignore_ascii_case("color") => {
+            let color = if v.eq_ignore_ascii_case("default") {
+                console::DEFAULT_ERROR_COLOR
+            } else {
+                parse_rgb_hex(v).filter(|&c| c <= 0xFFFFFF).ok_or(ERRORS_USAGE)?
+            };
+            console::set_error_color(color);
+            console::cwrite_line(&format!("Error color set to #{:06X}.", color), color, console::default_bg());
+        }
+        [what, v] if what.eq_ignore_ascii_case("serial") => {
+            let on = on_off(v)?;
+            console::set_error_mirrors(on, to_log);
+            console::write_line(if on { "Errors copied to serial." } else { "Errors no longer copied to serial." });
+        }
+        [what, v] if what.eq_ignore_ascii_case("log") => {
+            let on = on_off(v)?;
+            console::set_error_mirrors(to_serial, on);
+            console::write_line(if on { "Errors copied to the kernel log." } else { "Errors no longer copied to the kernel log." });
+        }
+        _ => return Err(ERRORS_USAGE),
+    }
+    Ok(())
+}
+
+fn handle_timer_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::timer;
+
+    match args {
+        [] => {
+            console::write_line(&format!(
+                "Timer: {} Hz, tickless idle {}",
+                timer::frequency(),
+                if timer::tickless() { "on" } else { "off" }
+            ));
+            Ok(())
+        }
+        [what, v] if what.eq_ignore_ascii_case("tickless") => {
+            let on = if v.eq_ignore_ascii_case("on") {
+                true
+            } else if v.eq_ignore_ascii_case("off") {
+                false
+            } else {
+                return Err(TIMER_USAGE);
+            };
+            timer::set_tickless(on);
+            console::write_line(if on { "Tickless idle on (experimental)." } else { "Tickless idle off." });
+            Ok(())
+        }
+        _ => Err(TIMER_USAGE),
+    }
+}
+
+fn handle_log_args(args: &[&str]) -> Result<(), &'static str> {
+    use crate::klog;
+
+    match args {
+        [] => {
+            console::write_line(&format!(
+                "Log level: {} (serial mirror {}, port 0xE9 mirror {})",
+                klog::level().name(),
+                if klog::serial_mirror() { "on" } else { "off" },
+                if klog::e9_mirror() { "on" } else { "off" }
+            ));
+            Ok(())
+        }
+        [what, v] if what.eq_ignore_ascii_case("level") => {
+            let level = klog::Level::parse(v).ok_or(LOG_USAGE)?;
+            klog::set_level(level);
+            console::write_line(&format!("Showing log messages at {} and above.", level.name()));
+            Ok(())
+        }
+        [what, v] if what.eq_ignore_ascii_case("serial") => {
+            let on = if v.eq_ignore_ascii_case("on") {
+                true
+            } else if v.eq_ignore_ascii_case("off") {
+                false
+            } else {
+                return Err(LOG_USAGE);
+            };
+            klog::set_serial_mirror(on);
+            console::write_line(if on { "Log messages mirrored to serial." } else { "Log serial mirroring off." });
+            Ok(())
+        }
+        [what, v] if what.eq_ignore_ascii_case("e9") => {
+            let on = if v.eq_ignore_ascii_case("on") {
+                true
+            } else if v.eq_ignore_ascii_case("off") {
+                false
+            } else {
+                return Err(LOG_USAGE);
+            };
+            if !klog::set_e9_mirror(on) {
+                return Err("os log e9: no debug port at 0xE9 (QEMU needs -debugcon)");
+            }
+            console::write_line(if on { "Every log message is mirrored to port 0xE9." } else { "Port 0xE9 mirroring off." });
+            Ok(())
+        }
+        _ => Err(LOG_USAGE),
+    }
+}
+
+fn handle_apps_args(args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        [target, mode] if target.eq_ignore_ascii_case("output") => {
+            if mode.eq_ignore_ascii_case("tagged") {
+                crate::app::set_output_tagged(true);
+                console::write_line("App output: prefixed with the app name.");
+            } else if mode.eq_ignore_ascii_case("raw") {
+                crate::app::set_output_tagged(false);
+                console::write_line("App output: raw.");
+            } else {
+                return Err(APPS_USAGE);
+            }
+            Ok(())
+        }
+        [target, mode] if target.eq_ignore_ascii_case("bare") => {
+            if mode.eq_ignore_ascii_case("on") {
+                crate::app::set_bare_names(true);
+                console::write_line("Apps can be started by name alone.");
+            } else if mode.eq_ignore_ascii_case("off") {
+                crate::app::set_bare_names(false);
+                console::write_line("Apps start only through `run`.");
+            } else {
+                return Err(APPS_USAGE);
+            }
+            Ok(())
+        }
+        _ => Err(APPS_USAGE),
+    }
+}
+
 fn apply_preset(p: &Preset) {
     console::set_default_bg(p.bg);
     console::set_default_fg(p.fg);
@@ -272,6 +974,18 @@ fn apply_preset(p: &Preset) {
     console::set_font(p.font);
     console::set_cursor_style(p.cursor_style);
     console::set_cursor_blink(p.cursor_blink);
+    crate::events::publish(crate::events::THEME_CHANGED, p.name);
+}
+
+/// Applies the preset called `name`, for the boot config. False if there is none.
+pub fn apply_theme_named(name: &str) -> bool {
+    match PRESETS.iter().find(|p| p.name.eq_ignore_ascii_case(name)) {
+        Some(p) => {
+            apply_preset(p);
+            true
+        }
+        None => false,
+    }
 }
 
 fn join_name_parts(parts: &[&str]) -> HString<128> {
@@ -316,7 +1030,7 @@ fn handle_theme_args(args: &[&str]) -> Result<(), &'static str> {
             console::write_line(p.description);
             return Ok(());
         } else {
-            console::write_line("Preset not found. Use: os theme list");
+            console::write_err("Preset not found. Use: os theme list");
             return Err(THEME_USAGE);
         }
     }
@@ -327,7 +1041,7 @@ fn handle_theme_args(args: &[&str]) -> Result<(), &'static str> {
         console::write_line(&format!("Applied preset: {}", p.name));
         Ok(())
     } else {
-        console::write_line("Preset not found. Use: os theme list");
+        console::write_err("Preset not found. Use: os theme list");
         Err(THEME_USAGE)
     }
 }
@@ -354,99 +1068,77 @@ fn handle_font_args(args: &[&str]) -> Result<(), &'static str> {
     }
 }
 
-pub fn os_command(args: &[&str]) {
+pub fn os_command(args: &[&str]) -> Status {
     if args.is_empty() {
         os_usage();
-        return;
+        return Err(2);
     }
 
     let sub = args[0].to_ascii_lowercase();
-    match sub.as_str() {
-        "font" => {
-            if let Err(msg) = handle_font_args(&args[1..]) {
-                console::write_line(msg);
-            }
-        }
-        "cursor" => {
-            if let Err(msg) = handle_cursor_args(&args[1..]) {
-                console::write_line(msg);
-            }
-        }
-        "hud" => {
-            if let Err(msg) = handle_hud_args(&args[1..]) {
-                console::write_line(msg);
-            }
-        }
-        "theme" | "customization" => {
-            if let Err(msg) = handle_theme_args(&args[1..]) {
-                console::write_line(msg);
-            }
-        }
-        "cmdhistory" => {
-            if let Err(msg) = handle_cmdhistory_args(&args[1..]) {
-                console::write_line(msg);
-            }
-        }
-        "time" => {
-            if let Err(msg) = handle_time_args(&args[1..]) {
-                console::write_line(msg);
-            }
-        }
+    let result = match sub.as_str() {
+        "font" => handle_font_args(&args[1..]),
+        "cursor" => return os_cursor(&args[1..]),
+        "hud" => handle_hud_args(&args[1..]),
+        "theme" | "customization" => handle_theme_args(&args[1..]),
+        "cmdhistory" => handle_cmdhistory_args(&args[1..]),
+        "time" => handle_time_args(&args[1..]),
+        "power" => handle_power_args(&args[1..]),
+        "apps" => handle_apps_args(&args[1..]),
+        "testmode" => handle_testmode_args(&args[1..]),
+        "serialshell" => handle_serialshell_args(&args[1..]),
+        "accessibility" => handle_accessibility_args(&args[1..]),
+        "magnifier" => handle_magnifier_args(&args[1..]),
+        "overlay" => handle_overlay_args(&args[1..]),
+        "highlight" => handle_highlight_args(&args[1..]),
+        "errors" => handle_errors_args(&args[1..]),
+        "wrap" => handle_wrap_args(&args[1..]),
+        "timer" => handle_timer_args(&args[1..]),
+        "log" => handle_log_args(&args[1..]),
+        "watchdog" => handle_watchdog_args(&args[1..]),
         "text" => {
-            match args.get(1) {
-                Some(code) => match parse_rgb_hex(code) {
-                    Some(v) if v <= 0xFFFFFF => {
-                        console::set_default_fg(v);
-                        console::write_line(&format!("Default text color set to #{:06X}.", v));
-                    }
-                    _ => console::write_line("os text: invalid hex. Use 3 or 6 hex digits, e.g., FF0000"),
-                },
-                None => console::write_line(TEXT_USAGE),
-            }
+            let v = TEXT_ARGS.check(&args[1..])?.color(0).unwrap_or(0xFFFFFF);
+            console::set_default_fg(v);
+            console::write_line(&format!("Default text color set to #{:06X}.", v));
+            Ok(())
         }
         "bg" => {
-            match args.get(1) {
-                Some(code) => match parse_rgb_hex(code) {
-                    Some(v) if v <= 0xFFFFFF => {
-                        let prev = console::default_bg();
-                        console::set_default_bg(v);
-                        if v != prev {
-                            console::write_line(&format!("Default background set to #{:06X}. Screen cleared.", v));
-                        } else {
-                            console::write_line(&format!("Default background remains #{:06X}.", v));
-                        }
-                    }
-                    _ => console::write_line("os bg: invalid hex. Use 3 or 6 hex digits, e.g., 000000"),
-                },
-                None => console::write_line(BG_USAGE),
+            let v = BG_ARGS.check(&args[1..])?.color(0).unwrap_or(0);
+            let prev = console::default_bg();
+            console::set_default_bg(v);
+            if v != prev {
+                console::write_line(&format!("Default background set to #{:06X}. Screen cleared.", v));
+            } else {
+                console::write_line(&format!("Default background remains #{:06X}.", v));
             }
+            Ok(())
+        }
+        _ => {
+            console::write_err(&format!("os: unknown setting {}", args[0]));
+            os_usage();
+            return Err(2);
         }
-        "help" => os_usage(),
-        _ => os_usage(),
+    };
+    if let Err(msg) = result {
+        console::write_err(msg);
+        return Err(if msg.starts_with("Usage:") { 2 } else { 1 });
     }
+    Ok(())
 }
 
-pub fn cecho(args: &[&str]) {
-    if args.len() < 2 {
-        console::write_line("Usage: cecho <hex> <text>");
-        return;
-    }
+static CECHO_ARGS: Spec = Spec { command: "cecho", args: &[req("hex", Kind::Color), req("text", Kind::Rest)] };
 
-    let fg = match parse_rgb_hex(args[0]) {
-        Some(v) if v <= 0xFFFFFF => v,
-        _ => {
-            console::write_line("cecho: invalid hex. Use 3 or 6 hex digits, e.g., FF0000");
-            return;
-        }
-    };
+pub fn cecho(args: &[&str]) -> Status {
+    let a = CECHO_ARGS.check(args)?;
+    let fg = a.color(0).unwrap_or(0xFFFFFF);
 
     let mut s = HString::<128>::new();
-    for (i, word) in args[1..].iter().enumerate() {
+    for (i, word) in a.rest(1).iter().enumerate() {
         if i > 0 { let _ = s.push(' '); }
         let _ = s.push_str(word);
     }
 
-    console::cwrite_line(&s, fg, console::default_bg());
+    console::cwrite_line(&glyphs::unescape(&s), fg, console::default_bg());
+    Ok(())
 }
 
 fn bytobi(input: &str) -> Option<u32> {
@@ -502,71 +1194,365 @@ pub fn clear() {
     console::clear_screen();
 }
 
-pub fn help(args: &[&str]) {
-    if let Some(topic) = args.get(0) {
-        let topic = topic.to_ascii_lowercase();
-        let msg = match topic.as_str() {
-            "help" => "help shows available commands. Usage: help [command]",
-            "about" => "Prints info about StratOS and your hardware.",
-            "os" => "Changes system settings (font, cursor, HUD, colors, cmdhistory, time, themes). Usage: os <subcommand> ...",
-            "echo" => "Prints text to the console. Usage: echo <text>",
-            "cecho" => "Prints colored text. Usage: cecho <hex> <text> (hex in RGB, e.g., FF00FF)",
-            "secho" => "Writes text to the serial port. Usage: secho <text>",
-            "clear" => "Clears the screen.",
-            "uptime" => "Shows how long the system has been running since boot.",
-            "reboot" => "Restarts the device.",
-            "shutdown" => "Attempts to turn off the device.",
-            "meminfo" => "Shows memory statistics (total, reserved, free).",
-            "memtest" => "Runs the built-in memory test.",
-            "cpuinfo" => "Lists CPU vendor/brand/features if available.",
-            "fbinfo" => "Shows framebuffer dimensions, bpp, stride, and format.",
-            "version" => "Prints StratOS name and build version.",
-            "alias" => "Creates an alias. Usage: alias <command> <alias>",
-            "unalias" => "Removes an alias. Usage: unalias <alias>",
-            "aliases" => "Lists all defined aliases.",
-            "stratos" => "Displays the StratOS banner.",
-            _ => {
-                console::write_line("Unknown command for help.");
-                return;
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Category {
+    System,
+    Display,
+    Files,
+    Network,
+    Fun,
+}
+
+const CATEGORIES: &[(Category, &str, &str)] = &[
+    (Category::System, "system", "Power, memory, CPU, time, and settings"),
+    (Category::Display, "display", "Console output and the screen"),
+    (Category::Files, "files", "Files and storage"),
+    (Category::Network, "network", "Networking and serial links"),
+    (Category::Fun, "fun", "Toys and easter eggs"),
+];
+
+/// How a command went: `Err` carries its exit status, after the command has
+/// printed why it failed.
+pub type Status = Result<(), i32>;
+
+/// Runs a command with the words after its name.
+pub type Handler = fn(&[&str]) -> Status;
+
+/// One builtin command. `handle_command` dispatches through this table and
+/// `help` is generated from it, so adding a command is adding an entry.
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub handler: Handler,
+    pub category: Category,
+    pub summary: &'static str,
+    pub details: &'static str,
+    pub listed: bool,
+    /// When set, `help` prints a usage line derived from it.
+    pub args: Option<&'static Spec>,
+    /// Word counts accepted after the name, checked before the handler
+    /// runs. Commands with a spec check their own arguments.
+    pub min_args: usize,
+    pub max_args: usize,
+}
+
+impl CommandInfo {
+    const fn with_args(self, spec: &'static Spec) -> Self {
+        CommandInfo { args: Some(spec), ..self }
+    }
+
+    const fn with_arity(self, min_args: usize, max_args: usize) -> Self {
+        CommandInfo { min_args, max_args, ..self }
+    }
+
+    fn arity(&self) -> alloc::string::String {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match (self.min_args, self.max_args) {
+            (min, max) if min == max => format!("{} argument{}", min, plural(min)),
+            (min, usize::MAX) => format!("at least {} argument{}", min, plural(min)),
+            (min, max) => format!("{} to {} arguments", min, max),
+        }
+    }
+}
+
+const fn cmd(
+    name: &'static str,
+    handler: Handler,
+    category: Category,
+    summary: &'static str,
+    details: &'static str,
+) -> CommandInfo {
+    CommandInfo { name, handler, category, summary, details, listed: true, args: None, min_args: 0, max_args: usize::MAX }
+}
+
+const fn unlisted(
+    name: &'static str,
+    handler: Handler,
+    category: Category,
+    summary: &'static str,
+    details: &'static str,
+) -> CommandInfo {
+    CommandInfo { name, handler, category, summary, details, listed: false, args: None, min_args: 0, max_args: usize::MAX }
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    cmd("help", help, Category::System, "Show this help or per-command details",
+        "help shows command categories, or the commands in a category, or details about one command.").with_args(&HELP_ARGS),
+    cmd("about", about, Category::System, "Show StratOS build and system summary",
+        "Prints info about StratOS and your hardware. Usage: about [--full] (--full: paged report with SMBIOS, PCI, ACPI tables, settings and loaded modules)"),
+    cmd("os", os_command, Category::System, "System settings",
+        "Changes system settings (font, cursor, HUD, colors, cmdhistory, time, power, apps, accessibility, magnifier, overlay, highlight, wrap, errors, timer, log, watchdog, testmode, serialshell, themes). Usage: os <subcommand> ..."),
+    cmd("version", |_| { version(); Ok(()) }, Category::System, "Show OS version",
+        "Prints StratOS name and build version."),
+    cmd("uptime", |_| { uptime(); Ok(()) }, Category::System, "Show uptime since boot",
+        "Shows how long the system has been running since boot."),
+    cmd("date", time::date_cmd, Category::System, "Show the date, optionally formatted",
+        "Prints the date/time. Usage: date [+format] (%Y %m %d %H %M %S %j %A %%)"),
+    cmd("boottime", |_| { time::boottime_cmd(); Ok(()) }, Category::System, "Show when the system booted",
+        "Shows the wall-clock boot time, uptime, and whether the clock has been resynced since boot."),
+    cmd("since", time::since_cmd, Category::System, "Time elapsed since a clock time",
+        "Shows how long ago the most recent HH:MM was, and how that relates to boot. Usage: since <HH:MM>"),
+    cmd("cal", time::cal_cmd, Category::System, "Show a month calendar",
+        "Prints a month calendar with today highlighted. Usage: cal [month] [year]"),
+    cmd("reboot", reboot_cmd, Category::System, "Reboot the machine",
+        "Restarts the device after asking for confirmation. Usage: reboot [-y] (-y skips the question)").with_arity(0, 1),
+    cmd("shutdown", shutdown_cmd, Category::System, "Power down the machine",
+        "Attempts to turn off the device after asking for confirmation. Usage: shutdown [-y] (-y skips the question)").with_arity(0, 1),
+    unlisted("aml", crate::aml::aml_cmd, Category::System, "Evaluate an ACPI object",
+        "Evaluates a name from the DSDT and SSDTs, or calls a method with integer arguments, and prints the result. Usage: aml <path> [int...], e.g. aml \\_S5 or aml \\_SB.PCI0._STA").with_arity(1, 8),
+    cmd("s3check", |_| crate::acpi::s3check_cmd(), Category::System, "Check ACPI S3 support",
+        "Checks whether the firmware supports ACPI S3 (suspend-to-RAM) and prints the sleep type. The kernel cannot suspend yet, as it has no resume path, so the check fails even when the firmware is ready."),
+    cmd("nice", nice_cmd, Category::System, "Run a command at a priority",
+        "Runs a command with the given priority (0 most urgent to 4 lowest, default 2) for the shell and any task it starts, e.g. nice 4 run ticker &.").with_args(&NICE_ARGS),
+    cmd("timeit", timeit_cmd, Category::System, "Time a command",
+        "Runs a command and prints how long it took, to the microsecond when the TSC is calibrated and to the timer tick otherwise, e.g. timeit memtest. The command's exit status is kept.").with_args(&TIMEIT_ARGS),
+    cmd("time", timeit_cmd, Category::System, "Time a command",
+        "Same as timeit. Usage: time <command...>").with_args(&TIMEIT_ARGS),
+    cmd("watch", watch_cmd, Category::System, "Rerun a command every few seconds",
+        "Runs a command every N seconds (1-3600), drawing each run's output over the last one, until a key is pressed, e.g. watch 2 uptime.").with_args(&WATCH_ARGS),
+    cmd("renice", renice_cmd, Category::System, "Change a task's priority",
+        "Sets the priority of a running task (0 most urgent to 4 lowest; see ps for task ids).").with_args(&RENICE_ARGS),
+    cmd("schedstat", crate::task::schedstat_cmd, Category::System, "Show scheduler statistics",
+        "Shows per-task switch counts, CPU time, and max/average scheduling latency. Usage: schedstat [reset | trace on|off] (trace logs every context switch to dmesg)"),
+    cmd("kbd", crate::keyboard::kbd_cmd, Category::System, "Show or re-detect PS/2 devices",
+        "Shows the PS/2 controller state and whether a keyboard and mouse were detected. Usage: kbd [rescan | layout [name]] (rescan resets the controller and detects devices again; layout shows or sets the layout: us, uk, de, fr, dvorak, colemak or jp)"),
+    cmd("kbddebug", |_| { crate::keyboard::kbddebug_cmd(); Ok(()) }, Category::System, "Show raw keyboard input live",
+        "Prints each scancode byte as it arrives, the key event and character it decodes to, and which modifiers are held, until Esc. Ends with the keys pressed most."),
+    cmd("irqstats", |_| { crate::interrupts::irqstats_cmd(); Ok(()) }, Category::System, "Show interrupt counters",
+        "Shows how many times each interrupt fired, including spurious PIC IRQ 7/15 and local APIC spurious/error interrupts."),
+    cmd("events", |_| { crate::events::events_cmd(); Ok(()) }, Category::System, "List event subscriptions",
+        "Lists event bus topics (time.synced, theme.changed, app.exited, ...) and how many handlers listen to each."),
+    cmd("meminfo", meminfo, Category::System, "Show memory info",
+        "Shows memory statistics (total, reserved, free), kernel heap fragmentation, and quarantined frames. Usage: meminfo [--bad] (--bad: list bad frames)"),
+    cmd("memquarantine", memquarantine, Category::System, "Mark a RAM frame bad",
+        "Excludes the 4 KiB frame holding a physical address from the frame allocator and saves it in settings. Addresses take 0x for hex.").with_args(&MEMQUARANTINE_ARGS),
+    cmd("heapcompact", |_| { heapcompact(); Ok(()) }, Category::System, "Coalesce kernel heap free blocks",
+        "Merges any adjacent free blocks in the kernel heap and reports fragmentation with hints on why large allocations may fail."),
+    cmd("memtest", |_| mem_selftest(), Category::System, "Test the memory",
+        "Runs the built-in memory test."),
+    cmd("selftest", |_| crate::selftest::selftest_cmd(), Category::System, "Check each subsystem",
+        "Briefly exercises the allocator, RTC, timer, keyboard controller, serial port and framebuffer, then prints a PASS/FAIL table. Also runs at boot when the kernel command line has the selftest flag."),
+    cmd("cpuinfo", |_| { cpuinfo(); Ok(()) }, Category::System, "Show CPU info",
+        "Lists CPU vendor/brand/features if available."),
+    cmd("dmesg", crate::klog::dmesg_cmd, Category::System, "Show the kernel log",
+        "Shows the kernel log ring (boot messages, faults, watchdog events). Usage: dmesg [clear]"),
+    cmd("sensors", |_| { crate::sensors::sensors_cmd(); Ok(()) }, Category::System, "Show temperatures",
+        "Shows CPU package/core temperatures from the digital thermal sensor and the temperature of each ACPI thermal zone (its _TMP)."),
+    cmd("ps", |_| { crate::task::ps_cmd(); Ok(()) }, Category::System, "List tasks with CPU and memory use",
+        "Lists tasks with their app id, state, CPU time, and app heap usage."),
+    cmd("top", |_| { crate::task::top_cmd(); Ok(()) }, Category::System, "Live task view",
+        "Shows tasks with CPU% and memory, refreshing every second until a key is pressed."),
+    cmd("run", crate::script::run_cmd, Category::System, "Run a script or built-in app",
+        "Runs a script saved with script, or an app as its own task and prints its exit status. Usage: run <script> | run <app> [&] (no args lists apps)"),
+    cmd("script", crate::script::script_cmd, Category::System, "Write a script",
+        "Reads command lines until a line with just end and saves them as a RAM file that run executes line by line. Blank lines and # comments are skipped. Esc cancels.").with_args(&crate::script::SCRIPT_ARGS),
+    cmd("sleep", crate::time::sleep_cmd, Category::System, "Wait a while",
+        "Waits the given number of seconds, or milliseconds with an ms suffix, up to a day. Ctrl+C stops it early with status 130. Usage: sleep <seconds> | sleep <n>ms").with_arity(1, 1),
+    cmd("set", crate::env::set_cmd, Category::System, "Set a shell variable",
+        "Sets a variable that command lines can use as $NAME or ${NAME}; $? is the last exit status. Single quotes keep a $ as typed.").with_args(&crate::env::SET_ARGS),
+    cmd("unset", crate::env::unset_cmd, Category::System, "Remove a shell variable",
+        "Removes a variable set with set.").with_args(&crate::env::UNSET_ARGS),
+    cmd("env", crate::env::env_cmd, Category::System, "List shell variables",
+        "Lists the variables set with set.").with_arity(0, 0),
+    cmd("alias", alias_cmd, Category::System, "Create an alias",
+        "Creates an alias. The body may be several words or commands joined with &&, quoted so the && stays in it; words typed after the alias are added to the end. Usage: alias <command...> <alias>, e.g. alias \"clear && about\" fresh. With a host folder attached, aliases are saved to it on reboot or shutdown and restored at boot.").with_arity(2, usize::MAX),
+    cmd("unalias", unalias_cmd, Category::System, "Remove an alias",
+        "Removes an alias. Usage: unalias <alias>").with_arity(1, 1),
+    cmd("aliases", |_| { list_aliases(); Ok(()) }, Category::System, "List all aliases",
+        "Lists all defined aliases.").with_arity(0, 0),
+    unlisted("halt", halt_cmd, Category::System, "Halt the CPU",
+        "Stops the machine without powering off, after asking for confirmation. Usage: halt [yes-i-know] (yes-i-know skips the question)").with_arity(0, 1),
+    cmd("vmmap", |_| crate::paging::vmmap_cmd(), Category::System, "Dump page-table mappings",
+        "Pages through the active page tables as merged ranges with page size and flags (w writable, x executable, u user, g global, c cache disabled). Usage: vmmap"),
+    cmd("va2pa", crate::paging::va2pa_cmd, Category::System, "Translate a virtual address",
+        "Walks the page tables for one virtual address and prints the physical address, page size and flags. Addresses take 0x for hex.").with_args(&crate::paging::VA2PA_ARGS),
+    cmd("addr2sym", crate::symbols::addr2sym_cmd, Category::System, "Name the function at an address",
+        "Looks an address up in the kernel's symbol table and prints the function it falls in plus the offset, e.g. for a RIP from an exception dump. Usage: addr2sym <addr>").with_args(&crate::symbols::ADDR2SYM_ARGS),
+    cmd("peek", crate::peek::peek_cmd, Category::System, "Dump memory as hex",
+        "Prints memory as hex and ASCII, 16 bytes a line, through the pager. Addresses are virtual and must be mapped; with -p they are physical and must be RAM in the memory map. Reads 256 bytes unless a length (up to 65536) is given. Usage: peek [-p] <addr> [len]").with_arity(1, 3),
+    cmd("hexdump", crate::peek::peek_cmd, Category::System, "Dump memory as hex",
+        "Same as peek. Usage: hexdump [-p] <addr> [len]").with_arity(1, 3),
+    cmd("poke", crate::peek::poke_cmd, Category::System, "Write a device register",
+        "Writes one value of 1, 2 or 4 bytes (4 unless given) to an aligned address, virtual or with -p physical. Only a PCI device's memory BAR or the local APIC is written unless yes-i-know is given, since a stray write to RAM can bring the system down. Usage: poke [-p] [yes-i-know] <addr> <value> [1|2|4]").with_arity(2, 5),
+    cmd("calc", crate::calc::calc_cmd, Category::System, "Evaluate an expression",
+        "Evaluates integer or float math with C precedence and parentheses: + - * / % ** and the bit operators & | ^ ~ << >>. Numbers may be hex (0x), binary (0b) or octal (0o) and use _ between digits. Whole numbers are 64-bit and wrap; results print in decimal and hex. Quote expressions that use | & or >, e.g. calc \"0x1000 | 7\". Usage: calc <expr...>").with_arity(1, usize::MAX),
+    cmd("view", crate::view::view_cmd, Category::Display, "Show images from files",
+        "Shows netpbm (P5/P6) or raw RGB/RGBA images from RAM files full screen, one after another, for 5 seconds each or the -d delay. Any key skips to the next image; Esc or q stops. Usage: view [-d <seconds>] <file...>").with_arity(1, usize::MAX),
+    cmd("play", crate::gif::play_cmd, Category::Display, "Play an animated GIF",
+        "Plays a GIF from a RAM file full screen at its own frame delays, looping as the file asks. Interlaced GIFs are not supported. Any key stops it. Usage: play <file>").with_arity(1, 1),
+    cmd("memviz", memviz_cmd, Category::System, "Show memory as a grayscale image",
+        "Draws a region of memory full screen for a few seconds, one byte per gray pixel, to spot patterns or corruption in buffers. pgm or ppm also sends the image to COM1 as a binary netpbm file. Numbers take 0x for hex.").with_args(&MEMVIZ_ARGS),
+    unlisted("outb", outb_cmd, Category::System, "Write an I/O port",
+        "Writes a byte to an I/O port. Numbers take 0x for hex.").with_args(&OUTB_ARGS),
+    unlisted("inb", inb_cmd, Category::System, "Read an I/O port",
+        "Reads a byte from an I/O port; some ports change state when read.").with_args(&INB_ARGS),
+    unlisted("panic", panic_cmd, Category::System, "Trigger test faults",
+        "Triggers a kernel panic or CPU exception for testing, after asking for confirmation. Usage: panic [yes-i-know] <kind> (yes-i-know skips the question)"),
+    cmd("echo", echo, Category::Display, "Print text",
+        "Prints text to the console. Quoted text keeps its spacing as typed. \\u{2500}-style escapes give box drawing, blocks, arrows and a few symbols. Usage: echo <text>"),
+    cmd("cecho", cecho, Category::Display, "Print colored text",
+        "Prints colored text in an RGB hex color, e.g., FF00FF. Takes the same \\u{XXXX} escapes as echo.").with_args(&CECHO_ARGS),
+    cmd("colorpick", crate::colorpick::colorpick_cmd, Category::Display, "Pick a color interactively",
+        "Opens a palette and RGB sliders with a live preview, then prints the picked color as hex. Naming a target also applies it like os text, os bg or os cursor color.").with_args(&crate::colorpick::COLORPICK_ARGS),
+    cmd("clear", |_| { clear(); Ok(()) }, Category::Display, "Clear the screen",
+        "Clears the screen."),
+    unlisted("cls", |_| { clear(); Ok(()) }, Category::Display, "Clear the screen",
+        "Clears the screen."),
+    unlisted("fuzzparse", crate::fuzz::fuzzparse_cmd, Category::System, "Fuzz the command-line parser",
+        "Feeds random command lines through variable expansion, splitting, alias lookup, completion and argument checking without running any command. A line that panics is reported after the shell restarts. Usage: fuzzparse <iterations>").with_args(&crate::fuzz::FUZZPARSE_ARGS),
+    cmd("lastout", lastout_cmd, Category::System, "Show recent command output",
+        "Prints the last lines that commands wrote, without prompts, typed input or errors, and unbroken by the screen width; the whole record unless a count is given. Pipe it on to search it, e.g. lastout | grep error. Usage: lastout [n]").with_args(&LASTOUT_ARGS),
+    cmd("grep", crate::grep::grep_cmd, Category::System, "Filter lines by text",
+        "Prints the lines that contain the pattern, from the output piped into it (e.g. help | grep color) or, with nothing piped in, from recent screen output. -i ignores case, -v prints the lines that do not match. Status is 1 if nothing was printed. Usage: grep [-i] [-v] <pattern...>").with_arity(1, usize::MAX),
+    cmd("files", crate::ramfs::files_cmd, Category::Files, "List RAM files",
+        "Lists the files held in memory, with sizes. They are lost on reboot. Any command's output can go into one with > name, or >> name to append; > serial sends it to COM1 instead.").with_arity(0, 0),
+    cmd("cat", crate::ramfs::cat_cmd, Category::Files, "Print a RAM file",
+        "Prints a file from memory, such as one written with > name.").with_args(&crate::ramfs::CAT_ARGS),
+    cmd("rm", crate::ramfs::rm_cmd, Category::Files, "Delete a RAM file",
+        "Deletes a file from memory.").with_args(&crate::ramfs::RM_ARGS),
+    cmd("host", crate::hostfs::host_cmd, Category::Files, "Copy files to and from the host",
+        "Under QEMU with a virtio-9p shared folder (-virtfs local,path=DIR,mount_tag=host,security_model=none): host ls [dir] lists it, host get <path> [file] copies a host file into a RAM file, and host put <file> [path] copies a RAM file out. With no arguments, shows the shared folder."),
+    cmd("copy", crate::clipboard::copy_cmd, Category::Display, "Copy a command's output",
+        "Runs a command and puts its output on the clipboard. Usage: copy [--serial] <command> [args...] (--serial also sends it to COM1)"),
+    cmd("clipboard", crate::clipboard::clipboard_cmd, Category::Display, "Show the clipboard",
+        "Shows the clipboard in the pager. The last 8 copies are kept: list shows them, a number shows that one. Ctrl+Shift+V at the prompt picks one to paste. Usage: clipboard [clear | list | <n>]"),
+    cmd("benchmark", crate::bench::benchmark_cmd, Category::System, "Run performance benchmarks",
+        "Runs the CPU, memory, screen and disk benches (or one of them) and prints a result and score for each; 1000 is a fixed reference rate, so compare scores between builds on the same machine. A BENCH line with the scores also goes to COM1 for tracking regressions.").with_args(&crate::bench::BENCHMARK_ARGS),
+    cmd("gfxbench", |_| { crate::blit::gfxbench_cmd(); Ok(()) }, Category::Display, "Benchmark screen copy routines",
+        "Times full-screen present and scroll copies with each supported copy routine (slice, rep movsb, SSE2)."),
+    cmd("fbinfo", |_| { fbtst(); Ok(()) }, Category::Display, "Show framebuffer info",
+        "Shows framebuffer dimensions, bpp, stride, and format."),
+    cmd("secho", secho, Category::Network, "Write text to the serial port",
+        "Writes text to the serial port. Usage: secho <text>"),
+    cmd("stratos", |_| { funnybanner(); Ok(()) }, Category::Fun, "Show the StratOS banner",
+        "Displays the StratOS banner."),
+    cmd("fortune", |_| { crate::fortune::fortune_cmd(); Ok(()) }, Category::Fun, "Print a random quote",
+        "Prints a randomly chosen quote from the built-in collection."),
+    cmd("typetest", |_| { crate::typetest::typetest_cmd(); Ok(()) }, Category::Fun, "Measure your typing speed",
+        "Shows a random sentence and reports WPM and accuracy. Your best score is remembered."),
+    unlisted("make", makel, Category::Fun, "Make something",
+        "Try: make love"),
+    unlisted("c418", |_| { console::write_line("Droopy Likes Your Face"); Ok(()) }, Category::Fun, "Droopy",
+        "Droopy likes your face."),
+    unlisted("xyzzy", |_| { xyzzy(); Ok(()) }, Category::Fun, "Nothing happens",
+        "Twice as much happens."),
+];
+
+pub fn find_command_info(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+}
+
+/// True if `word` names a command, directly or through an alias.
+pub fn is_known(word: &str) -> bool {
+    alias_body(word).is_some() || find_command_info(word).is_some()
+}
+
+/// Subcommands of `os`, for completion.
+const OS_SUBCOMMANDS: &[&str] = &[
+    "accessibility", "apps", "bg", "cmdhistory", "cursor", "errors", "font", "help", "highlight", "hud", "log",
+    "magnifier", "overlay", "power", "serialshell", "testmode", "text", "theme", "time", "timer", "watchdog", "wrap",
+];
+
+/// Completion candidates for `prefix`, the word being typed, given the words
+/// before it on the line: command names and aliases for the first word,
+/// subcommands after `os`.
+pub fn complete(before: &[&str], prefix: &str) -> Vec<HString<32>, 64> {
+    let mut out: Vec<HString<32>, 64> = Vec::new();
+    let mut add = |name: &str| {
+        if name.len() >= prefix.len()
+            && name.is_char_boundary(prefix.len())
+            && name[..prefix.len()].eq_ignore_ascii_case(prefix)
+            && !out.iter().any(|n| n == name)
+        {
+            let mut s = HString::new();
+            if s.push_str(name).is_ok() {
+                let _ = out.push(s);
             }
-        };
-        console::write_line(msg);
-        return;
+        }
+    };
+    match before {
+        [] => {
+            for c in COMMANDS.iter().filter(|c| c.listed) {
+                add(c.name);
+            }
+            for alias in ALIASES.lock().keys() {
+                add(alias);
+            }
+        }
+        [cmd] if resolve_alias(cmd) == "os" => {
+            for sub in OS_SUBCOMMANDS {
+                add(sub);
+            }
+        }
+        _ => {}
+    }
+    out.sort_unstable();
+    out
+}
+
+fn help_category(category: Category, title: &str, blurb: &str) {
+    let mut pager = Pager::new();
+    pager.line(&format!("{} - {}", title, blurb));
+    let mut any = false;
+    for c in COMMANDS.iter().filter(|c| c.category == category && c.listed) {
+        pager.line(&format!("  {:<13} - {}", c.name, c.summary));
+        any = true;
     }
+    if !any {
+        pager.line("  (no commands in this category yet)");
+    }
+    pager.show();
+}
+
+static HELP_ARGS: Spec = Spec { command: "help", args: &[opt("category|command", Kind::Word)] };
+
+pub fn help(args: &[&str]) -> Status {
+    let a = HELP_ARGS.check(args)?;
+    if let Some(topic) = a.word(0) {
+        if let Some((cat, title, blurb)) = CATEGORIES.iter().find(|(_, t, _)| t.eq_ignore_ascii_case(topic)) {
+            help_category(*cat, title, blurb);
+            return Ok(());
+        }
+        if topic.eq_ignore_ascii_case("all") {
+            let mut pager = Pager::new();
+            for (cat, title, _) in CATEGORIES.iter() {
+                pager.line(&format!("{}:", title));
+                for c in COMMANDS.iter().filter(|c| c.category == *cat && c.listed) {
+                    pager.line(&format!("  {:<13} - {}", c.name, c.summary));
+                }
+            }
+            pager.show();
+            return Ok(());
+        }
+        match find_command_info(topic) {
+            Some(info) => {
+                console::write_wrapped(info.details);
+                if let Some(spec) = info.args {
+                    console::write_wrapped(&spec.usage());
+                }
+            }
+            None => {
+                console::write_err("Unknown command for help.");
+                return Err(1);
+            }
+        }
+        return Ok(());
+    }
+
+    console::write_line("\nCommand categories (type 'help <category>' or 'help <command>'):");
+    for (cat, title, blurb) in CATEGORIES.iter() {
+        let count = COMMANDS.iter().filter(|c| c.category == *cat && c.listed).count();
+        console::write_line(&format!("  {:<9} - {} ({})", title, blurb, count));
+    }
+    console::write_line("  all       - Every command on one list");
+    console::write_line("Ctrl+S pauses command output, Ctrl+Q resumes it.\n");
+    Ok(())
+}
 
-    console::write_line("\nAvailable commands (type 'help <command>' for details):");
-    console::write_line("  help          - Show this help or per-command details");
-    console::write_line("  about         - Show StratOS build and system summary");
-    console::write_line("  os ...        - System settings");
-    console::write_line("  echo <text>   - Print text");
-    console::write_line("  clear         - Clear the screen");
-    console::write_line("  uptime        - Show uptime since boot");
-    console::write_line("  reboot        - Reboot the machine");
-    console::write_line("  shutdown      - Power down the machine");
-    console::write_line("  meminfo       - Show memory info");
-    console::write_line("  memtest       - Test the memory");
-    console::write_line("  cpuinfo       - Show CPU info");
-    console::write_line("  fbinfo        - Show framebuffer info");
-    console::write_line("  version       - Show OS version");
-    console::write_line("  alias         - Create an alias");
-    console::write_line("  unalias       - Remove an alias");
-    console::write_line("  aliases       - List all aliases\n");
-}
-
-/*
-Unlisted commands (not shown in help summary):
-- cecho
-- secho
-- stratos
-- make
-- validate
-- c418
-- cls
-- halt
-- panic
-*/
-
-pub fn about() {
+pub fn about(args: &[&str]) -> Status {
+    if args.first().is_some_and(|a| a.eq_ignore_ascii_case("--full")) {
+        about_full();
+        return Ok(());
+    }
     console::write_line("StratOS Project Rejuvenescence");
     console::write_line(&format!("Version: {}", OS_VERSION));
     console::write_line("Built with Rust.");
@@ -595,24 +1581,137 @@ pub fn about() {
         stats.free / 1024
     ));
 
-    console::write_line("");
-    console::write_line("Time:");
-    time::time_cmd(&[]);
-    uptime();
+    console::write_line("");
+    console::write_line("Time:");
+    time::time_cmd(&[]);
+    uptime();
+    Ok(())
+}
+
+/// One paged diagnostic report: hardware, configuration and software state.
+fn about_full() {
+    use crate::{acpi, app, cmdline, pci, settings, smbios, task, thud};
+
+    let mut p = Pager::new();
+    p.line(&format!("StratOS Project Rejuvenescence {} - full report", OS_VERSION));
+    p.line("");
+
+    p.line("Hardware:");
+    let cpuid = CpuId::new();
+    match cpuid.get_processor_brand_string() {
+        Some(brand) => p.line(&format!("  CPU: {}", brand.as_str().trim())),
+        None => p.line("  CPU: Unknown"),
+    }
+    let stats = memory::system_stats();
+    p.line(&format!(
+        "  Memory: total {}, reserved {}, free {}",
+        format_bytes::<32>(stats.total),
+        format_bytes::<32>(stats.reserved),
+        format_bytes::<32>(stats.free)
+    ));
+    if let Some(fb) = console::display_buffer_stats() {
+        p.line(&format!(
+            "  Display: {}x{}, {} bytes/pixel, back buffer {}",
+            fb.width_px,
+            fb.height_px,
+            fb.bytes_per_pixel,
+            if fb.backbuffer_bytes > 0 { "yes" } else { "no (direct drawing)" }
+        ));
+    }
+    match smbios::info() {
+        Some(info) => {
+            p.line(&format!("  SMBIOS {}.{}", info.version.0, info.version.1));
+            p.line(&format!("    System: {} {}", info.system_vendor, info.product));
+            p.line(&format!("    BIOS: {} {} ({})", info.bios_vendor, info.bios_version, info.bios_date));
+        }
+        None => p.line("  SMBIOS: no entry point found"),
+    }
+    let tables = acpi::tables();
+    if tables.is_empty() {
+        p.line("  ACPI: not available");
+    } else {
+        let mut sigs: HString<256> = HString::new();
+        for t in tables.iter() {
+            let _ = write!(sigs, " {}", core::str::from_utf8(&t.signature).unwrap_or("????"));
+        }
+        p.line(&format!("  ACPI tables:{}", sigs));
+    }
+    let devices = pci::scan();
+    p.line(&format!("  PCI devices ({}):", devices.len()));
+    for d in devices.iter() {
+        p.line(&format!(
+            "    {:02x}:{:02x}.{} {:04x}:{:04x} [{:02x}{:02x}{:02x}] {}",
+            d.bus,
+            d.device,
+            d.function,
+            d.vendor_id,
+            d.device_id,
+            d.class,
+            d.subclass,
+            d.prog_if,
+            pci::class_name(d.class, d.subclass)
+        ));
+    }
+    p.line("");
+
+    p.line("Configuration:");
+    let line = cmdline::get();
+    p.line(&format!("  Kernel command line: {}", if line.is_empty() { "(none)" } else { line }));
+    let config = cmdline::config();
+    if config.is_empty() {
+        p.line("  Config file: (none)");
+    } else {
+        p.line("  Config file (stratos.cfg):");
+        for l in config.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            p.line(&format!("    {}", l));
+        }
+    }
+    let entries = settings::entries();
+    if entries.is_empty() {
+        p.line("  Settings: (none)");
+    } else {
+        p.line("  Settings:");
+        for (k, v) in entries.iter() {
+            p.line(&format!("    {} = {}", k, v));
+        }
+    }
+    p.line(&format!(
+        "  Timer: {} Hz{}, copy routine {:?}",
+        crate::timer::frequency(),
+        if crate::timer::tickless() { " (tickless idle)" } else { "" },
+        crate::blit::mode()
+    ));
+    p.line("");
+
+    p.line("Software:");
+    p.line(&format!("  Features: log={}", if cfg!(feature = "log") { "on" } else { "off" }));
+    let mut hud: HString<128> = HString::new();
+    for name in thud::module_names() {
+        let _ = write!(hud, " {}", name);
+    }
+    p.line(&format!("  HUD modules ({}):{}", if thud::is_enabled() { "shown" } else { "hidden" }, hud));
+    let mut apps: HString<128> = HString::new();
+    for name in app::builtin_names() {
+        let _ = write!(apps, " {}", name);
+    }
+    p.line(&format!("  Built-in apps:{}", apps));
+    p.line("  Tasks:");
+    for t in task::list() {
+        p.line(&format!("    {:>3} {}", t.id, t.name));
+    }
+    p.show();
 }
 
 pub fn uptime() {
-    unsafe {
-        let secs = TICKS / 100;
-        let mins = secs / 60;
-        let hours = mins / 60;
-        console::write_line(&format!(
-            "Uptime: {:02}:{:02}:{:02}",
-            hours,
-            mins % 60,
-            secs % 60
-        ));
-    }
+    let secs = crate::timer::seconds();
+    let mins = secs / 60;
+    let hours = mins / 60;
+    console::write_line(&format!(
+        "Uptime: {:02}:{:02}:{:02}",
+        hours,
+        mins % 60,
+        secs % 60
+    ));
 }
 
 pub fn version() {
@@ -622,39 +1721,127 @@ pub fn version() {
     console::write_line("Built with Rust.");
 }
 
+/// Arguments of commands that ask before acting: none asks `question`, and
+/// `skip` alone goes ahead without asking. Fails with the exit status to
+/// use if the command should not go ahead.
+fn confirmed(args: &[&str], skip: &str, question: &str, usage: &str) -> Status {
+    match args {
+        [] if console::prompt_yes_no(question) => Ok(()),
+        [] => {
+            console::write_line(&format!("Cancelled; {} skips the question.", skip));
+            Err(1)
+        }
+        [word] if word.eq_ignore_ascii_case(skip) => Ok(()),
+        _ => {
+            console::write_err(usage);
+            Err(2)
+        }
+    }
+}
+
+fn reboot_cmd(args: &[&str]) -> Status {
+    confirmed(args, "-y", "Reboot now?", "Usage: reboot [-y]")?;
+    save_state();
+    reboot();
+    Ok(())
+}
+
+fn shutdown_cmd(args: &[&str]) -> Status {
+    confirmed(args, "-y", "Shut down now?", "Usage: shutdown [-y]")?;
+    save_state();
+    shutdown()
+}
+
+/// Saves the aliases and settings before a reboot or shutdown. `reboot` and
+/// `shutdown` leave it to their callers, since the panic handler can't risk
+/// the locks it takes.
+pub fn save_state() {
+    save_aliases();
+    crate::settings::save();
+}
+
 pub fn reboot() {
     console::write_line("Attempting to reboot...");
-    wait_ticks(20);
+    wait::bms(200);
+
+    if let Some(fadt) = crate::acpi::fadt() {
+        if fadt.flags & crate::acpi::FADT_RESET_REG_SUP != 0 {
+            if let Some(reg) = fadt.reset_reg {
+                console::write_line("  Trying ACPI reset register...");
+                crate::acpi::write_register(&reg, fadt.reset_value);
+                wait::bms(100);
+            }
+        }
+    }
 
-    let ok = unsafe {
+    console::write_line("  Trying PCI reset (0xCF9)...");
+    unsafe {
+        x86::io::outb(0xCF9, 0x02);
+        x86::io::outb(0xCF9, 0x06);
+    }
+    wait::bms(100);
+
+    console::write_line("  Trying keyboard controller reset...");
+    unsafe {
+        wait::until_micros(10_000, || x86::io::inb(0x64) & 0x02 == 0);
         x86::io::outb(0x64, 0xFE);
-        true
-    };
+    }
+    wait::bms(100);
+
+    console::write_line("  Trying triple fault...");
+    wait::bms(200);
+    triple_fault();
+}
 
-    if !ok {
-        console::write_line("Something went wrong, attempting to restart the machine\n");
+/// `reboot` for the panic handler: no output, no ACPI and no timer waits, as
+/// whatever panicked may hold the console or ACPI locks with interrupts off.
+pub fn reset_raw() {
+    unsafe {
+        x86::io::outb(0xCF9, 0x02);
+        x86::io::outb(0xCF9, 0x06);
+        wait::micros(100_000);
+        wait::until_micros(10_000, || x86::io::inb(0x64) & 0x02 == 0);
+        x86::io::outb(0x64, 0xFE);
+    }
+    wait::micros(100_000);
+    triple_fault();
+}
+
+fn triple_fault() {
+    unsafe {
+        interrupts::disable();
+        let null_idt = DescriptorTablePointer {
+            base: VirtAddr::new(0),
+            limit: 0,
+        };
+        lidt(&null_idt);
+        asm!("int3", options(noreturn));
     }
 }
 
 pub fn shutdown() -> ! {
     console::write_line("Attempting to shut down...");
 
-    let ok = unsafe {
+    if let Err(why) = crate::acpi::enter_sleep_state(5) {
+        console::write_line(&format!("ACPI shutdown failed: {}. Trying emulator ports.", why));
+    }
+    // Last resort: QEMU, then Bochs and older QEMU, then VirtualBox.
+    unsafe {
         x86::io::outw(0x604, 0x2000);
-        true
-    };
-
-    if !ok {
-        console::write_line("\nSomething went wrong attempting to shut down the machine.");
-        console::write_line("Halting to allow for safe machine shutdown....\n");
+        x86::io::outw(0xB004, 0x2000);
+        x86::io::outw(0x4004, 0x3400);
     }
 
+    console::write_line("\nSomething went wrong attempting to shut down the machine.");
+    console::write_line("Halting to allow for safe machine shutdown....\n");
+
+    crate::task::release_watchdog();
     loop {
         unsafe { x86::halt(); }
     }
 }
 
-fn format_bytes<const N: usize>(bytes: usize) -> HString<N> {
+pub(crate) fn format_bytes<const N: usize>(bytes: usize) -> HString<N> {
     let mut s: HString<N> = HString::new();
     const KB: usize = 1024;
     const MB: usize = KB * 1024;
@@ -683,7 +1870,7 @@ fn funnybanner() {
  console::write_line("");
 }
 
-pub fn mem_selftest() {
+pub fn mem_selftest() -> Status {
     use crate::memory::{
         register_app, unregister_app, app_alloc, app_dealloc,
         app_stats, kalloc, kdealloc,
@@ -693,6 +1880,7 @@ pub fn mem_selftest() {
     const DUMMY_APP: u32 = 42;
 
     console::write_line("=== Memory self-test starting ===");
+    let mut failed = false;
 
     unsafe {
         let p = kalloc(128, 8);
@@ -702,8 +1890,13 @@ pub fn mem_selftest() {
             console::write_line("Kernel dealloc: success");
         } else {
             console::write_line("Kernel alloc FAILED");
+            failed = true;
         }
     }
+    if crate::keyboard::cancel_requested() {
+        console::write_line("=== Memory self-test cancelled ===");
+        return Err(130);
+    }
 
     if register_app(DUMMY_APP, 64 * 1024) {
         unsafe {
@@ -714,6 +1907,7 @@ pub fn mem_selftest() {
                 console::write_line("App dealloc: success");
             } else {
                 console::write_line("App alloc FAILED");
+                failed = true;
             }
         }
 
@@ -729,15 +1923,40 @@ pub fn mem_selftest() {
         unregister_app(DUMMY_APP);
     } else {
         console::write_line("App register FAILED");
+        failed = true;
     }
 
     console::write_line("=== Memory self-test complete ===");
+    if failed { Err(1) } else { Ok(()) }
+}
+
+fn print_bad_frames(bad: &crate::memory::BadFrames) {
+    for phys in bad.frames.iter() {
+        console::write_line(&format!("  {:#x}", phys));
+    }
+    if bad.count > bad.frames.len() {
+        console::write_line(&format!("  ...and {} more", bad.count - bad.frames.len()));
+    }
 }
 
-pub fn meminfo() {
+pub fn meminfo(args: &[&str]) -> Status {
     use crate::memory::memory_overview;
     use crate::console;
 
+    if args.first().is_some_and(|a| a.eq_ignore_ascii_case("--bad")) {
+        let bad = crate::memory::bad_frames();
+        if bad.tested_bytes > 0 {
+            console::write_line(&format!("Boot memory test covered {}.", format_bytes::<32>(bad.tested_bytes as usize)));
+        }
+        if bad.count == 0 {
+            console::write_line("No bad frames.");
+        } else {
+            console::write_line(&format!("{} bad frame(s), excluded from allocation:", bad.count));
+            print_bad_frames(&bad);
+        }
+        return Ok(());
+    }
+
     let mo = memory_overview();
 
     console::write_line(&format!(
@@ -756,6 +1975,16 @@ pub fn meminfo() {
         mo.kernel_heap.alloc_count,
         mo.kernel_heap.dealloc_count,
     ));
+    print_heap_fragmentation(&crate::memory::heap_fragmentation());
+
+    let bad = crate::memory::bad_frames();
+    if bad.tested_bytes > 0 || bad.count > 0 {
+        console::write_line("\nBad memory:");
+        if bad.tested_bytes > 0 {
+            console::write_line(&format!("  Tested at boot: {}", format_bytes::<32>(bad.tested_bytes as usize)));
+        }
+        console::write_line(&format!("  Quarantined frames: {} (meminfo --bad to list)", bad.count));
+    }
 
     console::write_line(&format!(
         "\nUser arena:\n  Total: {}\n  Free for new regions: {}",
@@ -775,15 +2004,23 @@ pub fn meminfo() {
             format_bytes::<32>(db.backbuffer_bytes),
             db.backbuffer_bytes,
         ));
+        if db.backbuffer_bytes == 0 {
+            console::write_line("  (no back buffer: drawing directly to the framebuffer)");
+        }
     } else {
         console::write_line("\nDisplay buffers:\n  Not initialized");
     }
 
     for e in mo.apps.iter().flatten() {
         let (id, st) = *e;
+        let owner = match crate::app::owner(id) {
+            Some((task, name)) => format!(" ({}, task {})", name, task),
+            None => alloc::string::String::new(),
+        };
         console::write_line(&format!(
-            "\nApp {}:\n  Total: {}\n  Used: {}\n  Free: {}\n  Peak: {}\n  Allocs: {}\n  Deallocs: {}",
+            "\nApp {}{}:\n  Total: {}\n  Used: {}\n  Free: {}\n  Peak: {}\n  Allocs: {}\n  Deallocs: {}",
             id,
+            owner,
             format_bytes::<32>(st.total),
             format_bytes::<32>(st.used),
             format_bytes::<32>(st.free),
@@ -792,6 +2029,176 @@ pub fn meminfo() {
             st.dealloc_count,
         ));
     }
+    Ok(())
+}
+
+fn print_heap_fragmentation(frag: &crate::memory::HeapFragmentation) {
+    use crate::memory::{FRAG_BUCKET_LIMITS, FRAG_BUCKETS};
+
+    console::write_line(&format!(
+        "  Largest free block: {} ({}% fragmented)",
+        format_bytes::<32>(frag.largest_free),
+        frag.percent(),
+    ));
+    let mut hist = alloc::string::String::new();
+    for (i, count) in frag.histogram.iter().enumerate() {
+        let label = if i < FRAG_BUCKETS - 1 {
+            format!("<{}", format_bytes::<32>(FRAG_BUCKET_LIMITS[i]))
+        } else {
+            format!(">={}", format_bytes::<32>(FRAG_BUCKET_LIMITS[FRAG_BUCKETS - 2]))
+        };
+        if !hist.is_empty() {
+            hist.push_str(", ");
+        }
+        hist.push_str(&format!("{}: {}", label, count));
+    }
+    console::write_line(&format!("  Free blocks: {} ({})", frag.free_blocks, hist));
+    if frag.unprobed_bytes > 0 {
+        console::write_line(&format!(
+            "  (plus {} in smaller blocks not counted)",
+            format_bytes::<32>(frag.unprobed_bytes)
+        ));
+    }
+}
+
+static MEMQUARANTINE_ARGS: Spec = Spec { command: "memquarantine", args: &[req("physical addr", args::ANY_INT)] };
+
+pub fn memquarantine(args: &[&str]) -> Status {
+    use crate::memory::Quarantine;
+
+    let phys = MEMQUARANTINE_ARGS.check(args)?.int(0).unwrap_or(0);
+    let frame = phys & !(crate::memory::FRAME_SIZE - 1);
+    let (result, saved) = crate::memory::quarantine(phys);
+    match result {
+        Quarantine::Removed => console::write_line(&format!("Frame {:#x} quarantined.", frame)),
+        Quarantine::InUse => console::write_line(&format!(
+            "Frame {:#x} is in use; it will be withheld once freed.", frame
+        )),
+        Quarantine::Unmanaged => console::write_line(&format!(
+            "Frame {:#x} is not managed by the frame allocator; recorded only.", frame
+        )),
+        Quarantine::AlreadyBad => {
+            console::write_line(&format!("Frame {:#x} is already quarantined.", frame));
+            return Ok(());
+        }
+    }
+    crate::klog::warn(&format!("memory: frame {:#x} quarantined", frame));
+    crate::settings::save();
+    if !saved {
+        console::write_line("Warning: the bad-frame list is too long to save in full.");
+    }
+    Ok(())
+}
+
+const PRIORITY: Kind = Kind::Int { min: 0, max: crate::task::LOWEST_PRIORITY as u64 };
+static NICE_ARGS: Spec = Spec { command: "nice", args: &[req("priority", PRIORITY), req("command", Kind::Rest)] };
+static RENICE_ARGS: Spec = Spec { command: "renice", args: &[req("task id", args::ANY_INT), req("priority", PRIORITY)] };
+
+/// `nice <priority> <command...>`: runs the command with the shell and any
+/// tasks it spawns at `priority`, then restores the shell's own priority.
+pub fn nice_cmd(args: &[&str]) -> Status {
+    let a = NICE_ARGS.check(args)?;
+    let priority = a.int(0).unwrap_or(0) as crate::task::Priority;
+    let rest = a.rest(1);
+    let me = crate::task::current_id();
+    let old = crate::task::priority(me).unwrap_or(crate::task::DEFAULT_PRIORITY);
+    crate::task::set_priority(me, priority);
+    let old_spawn = crate::task::set_spawn_priority(priority);
+    handle_line(&join_quoted(rest));
+    crate::task::set_spawn_priority(old_spawn);
+    crate::task::set_priority(me, old);
+    last_result()
+}
+
+static TIMEIT_ARGS: Spec = Spec { command: "timeit", args: &[req("command", Kind::Rest)] };
+
+/// `timeit <command...>`: runs the command and reports how long it took,
+/// timed with the TSC when it is calibrated and PIT ticks otherwise. The
+/// command's exit status is kept.
+pub fn timeit_cmd(args: &[&str]) -> Status {
+    let a = TIMEIT_ARGS.check(args)?;
+    let line = join_quoted(a.rest(0));
+    let hz = crate::timer::frequency() as u64;
+    let (ticks0, tsc0) = (crate::timer::ticks(), unsafe { core::arch::x86_64::_rdtsc() });
+    handle_line(&line);
+    let (ticks, cycles) = (crate::timer::ticks() - ticks0, unsafe { core::arch::x86_64::_rdtsc() } - tsc0);
+    let us = match wait::tsc_hz() {
+        0 => ticks * 1_000_000 / hz.max(1),
+        tsc_hz => (cycles as u128 * 1_000_000 / tsc_hz as u128) as u64,
+    };
+    console::write_line(&format!(
+        "real {}.{:06}s  ({} ticks at {} Hz, {} TSC cycles)",
+        us / 1_000_000,
+        us % 1_000_000,
+        ticks,
+        hz,
+        cycles
+    ));
+    last_result()
+}
+
+static WATCH_ARGS: Spec = Spec {
+    command: "watch",
+    args: &[req("seconds", Kind::Int { min: 1, max: 3600 }), req("command", Kind::Rest)],
+};
+
+/// `watch <seconds> <command...>`: reruns the command every few seconds,
+/// drawing each run over the last, until a key is pressed.
+pub fn watch_cmd(args: &[&str]) -> Status {
+    let a = WATCH_ARGS.check(args)?;
+    let secs = a.int(0).unwrap_or(1);
+    let line = join_quoted(a.rest(1));
+    console::write_line(&format!("Every {}s: {}  (press any key to stop)", secs, line));
+    console::save_position();
+    loop {
+        console::restore_position();
+        console::clear_to_end();
+        handle_line(&line);
+        let next = wait::Wait::sec(secs);
+        while !next.done() {
+            // Ctrl+C is left set so the watch ends with status 130.
+            if crate::keyboard::cancel_requested() || crate::keyboard::poll_event().is_some() {
+                return Ok(());
+            }
+            crate::idle::idle();
+        }
+    }
+}
+
+pub fn renice_cmd(args: &[&str]) -> Status {
+    let a = RENICE_ARGS.check(args)?;
+    let (tid, priority) = (a.int(0).unwrap_or(0) as crate::task::TaskId, a.int(1).unwrap_or(0) as crate::task::Priority);
+    if crate::task::set_priority(tid, priority) {
+        console::write_line(&format!("Task {} now runs at priority {}.", tid, priority));
+    } else {
+        console::write_err(&format!("renice: no task {}", tid));
+        return Err(1);
+    }
+    Ok(())
+}
+
+pub fn heapcompact() {
+    let frag = crate::memory::heap_fragmentation();
+    if frag.merged > 0 {
+        console::write_line(&format!("Merged {} adjacent free blocks.", frag.merged));
+    } else {
+        console::write_line("No adjacent free blocks to merge; the heap coalesces on every free.");
+    }
+    console::write_line("Kernel heap:");
+    print_heap_fragmentation(&frag);
+
+    if frag.free_blocks <= 1 {
+        console::write_line("Free space is contiguous.");
+    } else if frag.percent() >= 50 {
+        console::write_line(&format!(
+            "Free space is split by live allocations: requests over {} will fail even though {} is free.",
+            format_bytes::<32>(frag.largest_free),
+            format_bytes::<32>(frag.free),
+        ));
+        console::write_line("Stopping long-running apps or tasks releases their buffers and may rejoin free blocks.");
+    } else {
+        console::write_line("Fragmentation is moderate; most allocations will still find room.");
+    }
 }
 
 pub fn cpuinfo() {
@@ -823,23 +2230,108 @@ pub fn cpuinfo() {
     }
 }
 
-pub fn makel(args: &[&str]) {
+pub fn makel(args: &[&str]) -> Status {
     if args.len() == 1 && args[0] == "love" {
         console::write_line("Not war?");
     } else {
-        console::write_line("Unknown command: make");
+        console::write_err("Unknown command: make");
+        return Err(127);
+    }
+    Ok(())
+}
+
+pub fn halt_cmd(args: &[&str]) -> Status {
+    confirmed(args, "yes-i-know", "Halt the CPU? Only a reset brings it back.", "Usage: halt [yes-i-know]")?;
+    console::write_line("System halted.");
+    crate::task::release_watchdog();
+    loop {
+        unsafe { x86::halt(); }
+    }
+}
+
+/// Decimal, or hex with a 0x prefix.
+pub(crate) fn parse_num(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
     }
 }
 
-pub fn halt_cmd(args: &[&str]) {
-    if args.len() == 1 && args[0] == "yes-i-know" {
-        console::write_line("System halted.");
-        loop {
-            unsafe { x86::halt(); }
+const MEMVIZ_SIDE: Kind = Kind::Int { min: 1, max: 1024 };
+static MEMVIZ_ARGS: Spec = Spec {
+    command: "memviz",
+    args: &[
+        req("addr", args::ANY_INT),
+        req("width", MEMVIZ_SIDE),
+        req("height", MEMVIZ_SIDE),
+        opt("export", Kind::Choice(&["pgm", "ppm"])),
+    ],
+};
+const MEMVIZ_SECONDS: u64 = 5;
+
+/// `memviz <addr> <w> <h> [pgm|ppm]`: shows `w * h` bytes as grayscale
+/// pixels, one byte per pixel, and optionally sends them to COM1 as a
+/// binary netpbm image.
+pub fn memviz_cmd(args: &[&str]) -> Status {
+    let a = MEMVIZ_ARGS.check(args)?;
+    let (addr, w, h) = (a.int(0).unwrap_or(0), a.int(1).unwrap_or(1) as usize, a.int(2).unwrap_or(1) as usize);
+    let len = w * h;
+    // Refuse up front rather than fault halfway through an unmapped page.
+    let first_page = addr & !0xFFF;
+    let Some(end) = addr.checked_add(len as u64) else {
+        console::write_err("memviz: region wraps around the address space");
+        return Err(1);
+    };
+    let mut page = first_page;
+    while page < end {
+        if crate::paging::translate(page).is_none() {
+            console::write_err(&format!("memviz: {:#x} is not mapped", page));
+            return Err(1);
         }
-    } else {
-        console::write_line("Refusing to halt. Use: halt yes-i-know");
+        page += 0x1000;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+
+    if let Some(format) = a.word(3) {
+        let ppm = format.eq_ignore_ascii_case("ppm");
+        serial::write_raw(&format!("{}\n{} {}\n255\n", if ppm { "P6" } else { "P5" }, w, h));
+        if ppm {
+            for &b in bytes {
+                serial::write_bytes(&[b, b, b]);
+            }
+        } else {
+            serial::write_bytes(bytes);
+        }
+        console::write_line(&format!("Sent {}x{} {} image to COM1.", w, h, format.to_ascii_uppercase()));
+    }
+
+    let mut rgb = alloc::vec::Vec::with_capacity(len * 3);
+    for &b in bytes {
+        rgb.extend_from_slice(&[b, b, b]);
     }
+    console::showimage(&rgb, w, h, MEMVIZ_SECONDS);
+    Ok(())
+}
+
+const PORT: Kind = Kind::Int { min: 0, max: 0xFFFF };
+const BYTE: Kind = Kind::Int { min: 0, max: 0xFF };
+const I_KNOW: Kind = Kind::Choice(&["yes-i-know"]);
+static OUTB_ARGS: Spec = Spec { command: "outb", args: &[req("confirm", I_KNOW), req("port", PORT), req("value", BYTE)] };
+static INB_ARGS: Spec = Spec { command: "inb", args: &[req("confirm", I_KNOW), req("port", PORT)] };
+
+pub fn outb_cmd(args: &[&str]) -> Status {
+    let a = OUTB_ARGS.check(args)?;
+    let (port, value) = (a.int(1).unwrap_or(0) as u16, a.int(2).unwrap_or(0) as u8);
+    unsafe { x86::io::outb(port, value) };
+    console::write_line(&format!("outb {:#06x} <- {:#04x}", port, value));
+    Ok(())
+}
+
+pub fn inb_cmd(args: &[&str]) -> Status {
+    let port = INB_ARGS.check(args)?.int(1).unwrap_or(0) as u16;
+    let value = unsafe { x86::io::inb(port) };
+    console::write_line(&format!("inb {:#06x} -> {:#04x} ({})", port, value, value));
+    Ok(())
 }
 
 use x86_64::{
@@ -850,20 +2342,29 @@ use x86_64::{
 use core::arch::asm;
 use x86_64::structures::DescriptorTablePointer;
 
+const PANIC_KINDS: &[&str] = &["controlled", "int4", "badmem", "delidt", "nullidt", "int3", "int3andkill", "divby0", "ud"];
+const PANIC_USAGE: &str = "Usage: panic [yes-i-know] controlled|int4|badmem|delidt|nullidt|int3|int3andkill|divby0|ud";
+
 #[allow(unused_unsafe)]
 #[allow(static_mut_refs)]
-pub fn panic_cmd(args: &[&str]) {
-    match args {
-        ["yes-i-know", "controlled"] => {
+pub fn panic_cmd(args: &[&str]) -> Status {
+    let Some((&kind, skip)) = args.split_last().filter(|(k, _)| PANIC_KINDS.contains(k)) else {
+        console::write_err(PANIC_USAGE);
+        return Err(2);
+    };
+    let question = format!("Trigger `{}`? This may crash the kernel.", kind);
+    confirmed(skip, "yes-i-know", &question, PANIC_USAGE)?;
+    match kind {
+        "controlled" => {
             panic!("Kernel panic manually triggered from shell");
         }
 
-        ["yes-i-know", "int4"] => {
+        "int4" => {
             console::write_line("Testing INT4 response...");
             unsafe { asm!("int $4"); }
         }
 
-        ["yes-i-know", "badmem"] => {
+        "badmem" => {
             unsafe { interrupts::disable(); }
             console::write_line("Corrupting memory to trigger kernel panic...");
 
@@ -873,7 +2374,7 @@ pub fn panic_cmd(args: &[&str]) {
             }
         }
 
-        ["yes-i-know", "delidt"] => {
+        "delidt" => {
             unsafe {
                 interrupts::disable();
                 console::write_line("Deleting IDT then faulting...");
@@ -888,7 +2389,7 @@ pub fn panic_cmd(args: &[&str]) {
             }
         }
 
-        ["yes-i-know", "nullidt"] => {
+        "nullidt" => {
             unsafe {
                 console::write_line("Loading empty IDT then faulting...");
                 wait::bsec(1);
@@ -902,14 +2403,14 @@ pub fn panic_cmd(args: &[&str]) {
             }
         }
 
-        ["yes-i-know", "int3"] => {
+        "int3" => {
         unsafe {
             console::write_line("Testing INT3 response... (check serial)");
             asm!("int3");
         }
     }
 
-        ["yes-i-know", "int3andkill"] => {
+        "int3andkill" => {
             unsafe {
                 console::write_line("int3'ing to #UD");
                 wait::bms(400);
@@ -917,7 +2418,7 @@ pub fn panic_cmd(args: &[&str]) {
             }
         }
 
-        ["yes-i-know", "divby0"] => {
+        "divby0" => {
             unsafe {
                 console::write_line("Dividing by 0...");
                 wait::bms(400);
@@ -925,7 +2426,7 @@ pub fn panic_cmd(args: &[&str]) {
             }
         }
 
-        ["yes-i-know", "ud"] => {
+        "ud" => {
             unsafe {
                 console::write_line("Attempting to trigger #UD...");
                 wait::bms(400);
@@ -933,15 +2434,33 @@ pub fn panic_cmd(args: &[&str]) {
             }
         }
 
-        _ => {
-            console::write_line("Usage: panic yes-i-know [controlled|badmem|delidt|nullidt|int3|int3andkill|divby0|ud]");
-        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Exit status of the last command; 0 unless the command reported otherwise.
+pub fn set_status(code: i32) {
+    LAST_STATUS.store(code, Ordering::Relaxed);
+}
+
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+/// `last_status` as a handler's result, for commands that run others and
+/// pass on how they went.
+pub fn last_result() -> Status {
+    match last_status() {
+        0 => Ok(()),
+        code => Err(code),
     }
 }
 
 pub fn handle_command(input: &str) {
+    let words = tokenize(input);
     let mut parts: Vec<&str, 16> = Vec::new();
-    for word in input.split_whitespace() {
+    for word in words.iter() {
         let _ = parts.push(word);
     }
 
@@ -949,113 +2468,408 @@ pub fn handle_command(input: &str) {
         return;
     }
 
-    let command = resolve_alias(&parts[0]).to_ascii_lowercase();
-
-    match command.as_str() {
-        "echo" => echo(&parts[1..]),
-        "cecho" => cecho(&parts[1..]),
-        "secho" => secho(&parts[1..]),
-        "version" => version(),
-        "about" => about(),
-        "stratos" => funnybanner(),
-        "make" => makel(&parts[1..]),
-        "c418" => console::write_line("Droopy Likes Your Face"),
-        "clear" => clear(),
-        "cls" => clear(),
-        "help" => help(&parts[1..]),
-        "xyzzy" => {
-            console::showimage(&BSOD_IMAGE, BSOD_WIDTH, BSOD_HEIGHT, 10);
-            console::write_line("Twice as much happens");
-        }
-        "os" => os_command(&parts[1..]),
-        "uptime" => uptime(),
-        "reboot" => reboot(),
-        "fbinfo" => fbtst(),
-        "shutdown" => shutdown(),
-        "meminfo" => meminfo(),
-        "memtest" => mem_selftest(),
-        "cpuinfo" => cpuinfo(),
-        "halt" => halt_cmd(&parts[1..]),
-        "panic" => panic_cmd(&parts[1..]),
-        "alias" => {
-            if parts.len() == 3 {
-                add_alias(parts[2], parts[1]);
-            } else {
-                console::write_line("Usage: alias <command> <alias>");
+    // A Ctrl+C pressed at the prompt is not meant for this command, but one
+    // pressed between the commands of a script or alias is meant for the run.
+    if COMMAND_DEPTH.fetch_add(1, Ordering::Relaxed) == 0 {
+        crate::keyboard::clear_cancel();
+    }
+    let logging = console::set_output_log(true);
+    let result = run_parts(&parts);
+    COMMAND_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    console::set_output_log(logging);
+    console::break_output_log();
+    if crate::keyboard::clear_cancel() {
+        console::write_line("^C");
+        set_status(130);
+    } else {
+        set_status(result.err().unwrap_or(0));
+    }
+}
+
+fn run_parts(parts: &[&str]) -> Status {
+    if let Some(result) = run_alias(parts) {
+        return result;
+    }
+    let command = parts[0].to_ascii_lowercase();
+
+    let Some(info) = find_command_info(&command) else {
+        return run_fallbacks(parts).unwrap_or_else(|| {
+            console::write_err(&format!("Unknown command: {}", parts[0]));
+            Err(127)
+        });
+    };
+    let args = &parts[1..];
+    if !(info.min_args..=info.max_args).contains(&args.len()) {
+        console::write_err(&format!("{}: takes {}, got {}", info.name, info.arity(), args.len()));
+        console::write_wrapped(info.details);
+        return Err(2);
+    }
+    (info.handler)(args)
+}
+
+fn alias_cmd(args: &[&str]) -> Status {
+    let (alias, body) = args.split_last().unwrap_or((&"", &[]));
+    // The body is a command line, so `alias 'ls -l' ll` stays two words.
+    add_alias(alias, &body.join(" "))
+}
+
+fn unalias_cmd(args: &[&str]) -> Status {
+    remove_alias(args[0])
+}
+
+fn xyzzy() {
+    console::showimage(&BSOD_IMAGE, BSOD_WIDTH, BSOD_HEIGHT, 10);
+    console::write_line("Twice as much happens");
+}
+
+/// Where a segment's output goes instead of the screen: `> name` replaces a
+/// RAM file, `>> name` appends to it, and the name `serial` means COM1.
+struct Redirect {
+    /// As typed; expanded into one word when the segment runs.
+    target: alloc::string::String,
+    append: bool,
+}
+
+/// How a segment follows the one before it: `&&` runs it only after a
+/// success, `||` only after a failure, and `|` always, with the previous
+/// segment's output as its input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Joiner {
+    And,
+    Or,
+    Pipe,
+}
+
+struct Segment {
+    line: alloc::string::String,
+    redirect: Option<Redirect>,
+    joiner: Joiner,
+}
+
+fn finish_segment(current: &str, redirect_at: Option<(usize, bool)>, joiner: Joiner, result: &mut Vec<Segment, 16>) {
+    let (command, redirect) = match redirect_at {
+        Some((at, append)) => {
+            let target = current[at..].trim().into();
+            (&current[..at], Some(Redirect { target, append }))
+        }
+        None => (current, None),
+    };
+    let command = command.trim();
+    if command.is_empty() && redirect.is_none() {
+        return;
+    }
+    let _ = result.push(Segment { line: command.into(), redirect, joiner });
+}
+
+/// What a character is to the quoting rules.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quoted {
+    /// A quote or backslash doing its job; gone once the line is split into words.
+    Syntax,
+    /// Inside quotes or escaped, so never special.
+    Literal,
+    /// Outside any quoting: whitespace splits words here, and `&&`, `|`
+    /// and `>` mean what they do.
+    Plain,
+}
+
+/// Quote and backslash state while walking a line. Splitting into segments,
+/// splitting into words and spotting an unfinished line all go through this,
+/// so they cannot disagree about what is quoted.
+#[derive(Default)]
+struct Quoting {
+    single: bool,
+    double: bool,
+    escaped: bool,
+}
+
+impl Quoting {
+    fn step(&mut self, c: char, next: Option<char>) -> Quoted {
+        if self.escaped {
+            self.escaped = false;
+            return Quoted::Literal;
+        }
+        match c {
+            // \u{XXXX} is left for echo and cecho to decode.
+            '\\' if next == Some('u') => Quoted::Literal,
+            '\\' => {
+                self.escaped = true;
+                Quoted::Syntax
             }
+            '\'' if !self.double => {
+                self.single = !self.single;
+                Quoted::Syntax
+            }
+            '"' if !self.single => {
+                self.double = !self.double;
+                Quoted::Syntax
+            }
+            _ if self.single || self.double => Quoted::Literal,
+            _ => Quoted::Plain,
         }
-        "unalias" => {
-            if parts.len() == 2 {
-                remove_alias(parts[1]);
-            } else {
-                console::write_line("Usage: unalias <alias>");
+    }
+}
+
+/// Whether a typed line can run yet, or what it is still waiting for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Unfinished {
+    No,
+    /// A quote was opened and not closed.
+    Quote,
+    /// The line ends in a backslash, continuing it onto the next one.
+    Backslash,
+}
+
+pub fn unfinished(line: &str) -> Unfinished {
+    let mut q = Quoting::default();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        q.step(c, chars.peek().copied());
+    }
+    if q.escaped {
+        Unfinished::Backslash
+    } else if q.single || q.double {
+        Unfinished::Quote
+    } else {
+        Unfinished::No
+    }
+}
+
+/// Splits one segment into words. Quotes keep spaces inside a word and
+/// are dropped, a backslash takes the next character as-is, and `""` is
+/// an empty word. A `$` variable outside single quotes is put in as plain
+/// text of its word, never split or parsed again, so `set X "a && reboot"`
+/// then `echo $X` only prints it.
+pub fn tokenize(line: &str) -> alloc::vec::Vec<alloc::string::String> {
+    let mut words = alloc::vec::Vec::new();
+    let mut word = alloc::string::String::new();
+    let mut in_word = false;
+    let mut q = Quoting::default();
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '$' && !q.escaped && !q.single {
+            if let Some((value, used)) = crate::env::lookup(&line[i + 1..]) {
+                if !value.is_empty() {
+                    word.push_str(&value);
+                    in_word = true;
+                }
+                while chars.next_if(|&(at, _)| at <= i + used).is_some() {}
+                continue;
             }
         }
-        "aliases" => list_aliases(),
+        match q.step(c, chars.peek().map(|&(_, next)| next)) {
+            Quoted::Syntax => in_word = true,
+            Quoted::Plain if c.is_whitespace() => {
+                if in_word {
+                    words.push(core::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            _ => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
 
-        _ => console::write_line(&format!("Unknown command: {}", parts[0])),
+/// `word` written so `tokenize` gives it back unchanged, for commands that
+/// put words back together into a line to run later.
+pub fn quote(word: &str) -> alloc::string::String {
+    if word.is_empty() {
+        return "''".into();
     }
+    let mut out = alloc::string::String::with_capacity(word.len());
+    for c in word.chars() {
+        if c.is_whitespace() || matches!(c, '\'' | '"' | '\\' | '&' | '|' | '>' | '$') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
 }
 
-fn split_deuxand(line: &str) -> heapless::Vec<heapless::String<128>, 16> {
-    use heapless::{String as HString, Vec};
+/// Words joined into a line, each quoted as needed.
+pub fn join_quoted(words: &[&str]) -> alloc::string::String {
+    let mut line = alloc::string::String::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        line.push_str(&quote(word));
+    }
+    line
+}
 
-    let mut result: Vec<HString<128>, 16> = Vec::new();
-    let mut current = HString::<128>::new();
+/// Cuts a line at `&&`, `||` and `|` outside quotes and notes any `>`
+/// redirect. Quotes and backslashes stay in the segment text for
+/// `tokenize`.
+fn split_deuxand(line: &str) -> Vec<Segment, 16> {
+    let mut result: Vec<Segment, 16> = Vec::new();
+    let mut current = alloc::string::String::new();
+    let mut redirect_at: Option<(usize, bool)> = None;
+    let mut joiner = Joiner::And;
 
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut escaped = false;
+    let mut q = Quoting::default();
     let mut chars = line.chars().peekable();
 
     while let Some(c) = chars.next() {
-        if escaped {
-            let _ = current.push(c);
-            escaped = false;
+        if q.step(c, chars.peek().copied()) != Quoted::Plain {
+            current.push(c);
             continue;
         }
-
         match c {
-            '\\' => {
-                escaped = true;
-            }
-            '\'' if !in_double => in_single = !in_single,
-            '"' if !in_single => in_double = !in_double,
-            '&' if !in_single && !in_double => {
-                if chars.peek() == Some(&'&') {
+            '>' if redirect_at.is_none() => {
+                let append = chars.peek() == Some(&'>');
+                if append {
                     chars.next();
-                    let seg = current.trim();
-                    if !seg.is_empty() {
-                        let mut s = HString::<128>::new();
-                        let _ = s.push_str(seg);
-                        let _ = result.push(s);
-                    }
-                    current.clear();
-                    continue;
-                } else {
-                    let _ = current.push(c);
                 }
+                redirect_at = Some((current.len(), append));
+            }
+            '&' | '|' if chars.peek() == Some(&c) => {
+                chars.next();
+                finish_segment(&current, redirect_at.take(), joiner, &mut result);
+                current.clear();
+                joiner = if c == '&' { Joiner::And } else { Joiner::Or };
+            }
+            '|' => {
+                finish_segment(&current, redirect_at.take(), joiner, &mut result);
+                current.clear();
+                joiner = Joiner::Pipe;
             }
             other => {
-                let _ = current.push(other);
+                current.push(other);
+            }
+        }
+    }
+
+    finish_segment(&current, redirect_at, joiner, &mut result);
+    result
+}
+
+/// Runs `line` with its output captured off screen, then stores it.
+fn run_redirected(line: &str, redirect: &Redirect) {
+    let words = tokenize(&redirect.target);
+    let target = match words.as_slice() {
+        [one] => one.as_str(),
+        _ => "",
+    };
+    if line.is_empty() || target.is_empty() || target.contains(|c: char| c.is_ascii_whitespace() || c == '>') {
+        console::write_err("Usage: <command> > <file>|serial, or >> to append");
+        set_status(2);
+        return;
+    }
+    if !console::begin_redirect() {
+        console::write_err("redirect: too many nested captures");
+        set_status(1);
+        return;
+    }
+    handle_command(line);
+    let text = console::end_capture().unwrap_or_default();
+    if target.eq_ignore_ascii_case("serial") {
+        for l in text.lines() {
+            serial::write(l);
+        }
+    } else if let Err(msg) = crate::ramfs::write(target, text.as_bytes(), redirect.append) {
+        console::write_err(&format!("{}: {}", target, msg));
+        set_status(1);
+    }
+}
+
+/// Goes through everything `handle_line` does to pick the handlers for
+/// `input` and check their arguments, but runs nothing. For `fuzzparse`;
+/// returns how many segments the line split into.
+pub fn parse_dry_run(input: &str) -> usize {
+    let segments = split_deuxand(input);
+    for seg in segments.iter() {
+        let words = tokenize(&seg.line);
+        let mut parts: Vec<&str, 16> = Vec::new();
+        for word in words.iter() {
+            let _ = parts.push(word);
+        }
+        let Some((&first, args)) = parts.split_first() else { continue; };
+        let _ = resolve_alias(first);
+        let _ = complete(&parts[..parts.len() - 1], parts[parts.len() - 1]);
+        let Some(info) = find_command_info(first) else { continue; };
+        if !(info.min_args..=info.max_args).contains(&args.len()) {
+            continue;
+        }
+        if let Some(spec) = info.args {
+            if let Err(err) = spec.parse(args) {
+                let _ = spec.error_message(&err);
             }
         }
     }
+    segments.len()
+}
 
-    let seg = current.trim();
-    if !seg.is_empty() {
-        let mut s = HString::<128>::new();
-        let _ = s.push_str(seg);
-        let _ = result.push(s);
+static LASTOUT_ARGS: Spec = Spec {
+    command: "lastout",
+    args: &[opt("lines", Kind::Int { min: 1, max: console::OUTPUT_LOG_LINES as u64 })],
+};
+
+/// `lastout [n]`: the last `n` lines of command output, or all that are kept.
+fn lastout_cmd(args: &[&str]) -> Status {
+    let a = LASTOUT_ARGS.check(args)?;
+    // Keep this listing out of the record it prints.
+    console::set_output_log(false);
+    let lines = console::output_log();
+    let n = a.int(0).map_or(lines.len(), |n| n as usize);
+    for line in &lines[lines.len().saturating_sub(n)..] {
+        console::write_line(line);
     }
+    Ok(())
+}
 
-    result
+/// Output of the previous command in a `|` pipeline, for the next one.
+static PIPE_INPUT: Mutex<Option<alloc::string::String>> = Mutex::new(None);
+
+/// Takes what was piped into the running command, if anything was.
+pub fn piped_input() -> Option<alloc::string::String> {
+    PIPE_INPUT.lock().take()
 }
 
+/// Runs the commands of a line in turn. One after `&&` is skipped if the
+/// status so far is a failure, one after `||` if it is a success; skipping
+/// keeps the status, so `a && b || c` runs c when either a or b fails. A
+/// pipeline `a | b` is run or skipped as a whole, like one command. Ctrl+C
+/// (status 130) ends the whole line.
 pub fn handle_line(input: &str) {
-    let cmds = split_deuxand(input);
-    for cmd in cmds {
-        handle_command(&cmd);
+    let segments = split_deuxand(input);
+    let mut skipping = false;
+    let mut output = None;
+    for (i, seg) in segments.iter().enumerate() {
+        if seg.joiner != Joiner::Pipe {
+            let ok = last_status() == 0;
+            skipping = i > 0 && ok != (seg.joiner == Joiner::And);
+        }
+        if skipping {
+            continue;
+        }
+        if let Some(text) = output.take() {
+            *PIPE_INPUT.lock() = Some(text);
+        }
+        let pipes_on = segments.get(i + 1).is_some_and(|next| next.joiner == Joiner::Pipe);
+        if pipes_on && !console::begin_redirect() {
+            console::write_err("pipe: too many nested captures");
+            set_status(1);
+            skipping = true;
+            continue;
+        }
+        match &seg.redirect {
+            Some(redirect) => run_redirected(&seg.line, redirect),
+            None => handle_command(&seg.line),
+        }
+        if pipes_on {
+            output = Some(console::end_capture().unwrap_or_default());
+        }
+        PIPE_INPUT.lock().take();
+        if last_status() == 130 {
+            break;
+        }
     }
 }
-
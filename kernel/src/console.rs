@@ -2,16 +2,20 @@
 #![allow(unused_variables)]
 
 extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
 use bootloader_api::info::{FrameBufferInfo, PixelFormat};
 use bootloader_api::BootInfo;
-use core::mem::MaybeUninit;
-use core::ptr::{self, addr_of_mut};
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use spin::Mutex;
 use x86_64::instructions::interrupts;
 use crate::font::VGA8_FONT;
 use crate::font2::TERMINUS_FONT;
 use crate::font3::SPLEEN_FONT;
-use crate::wait;
+use crate::{blit, glyphs, idle, keyboard, memory, settings, wait};
+use crate::keyboard::KeyEvent;
 
 #[derive(Copy, Clone)]
 struct Font {
@@ -23,9 +27,10 @@ struct Font {
 
 impl Font {
     fn glyph(&self, c: char) -> &'static [u8] {
-        let code = c as u8;
-        let idx = if code < 0x20 || code > 0x7e { 0 } else { (code - 0x20) as usize };
-        (self.glyph)(idx as u8)
+        if (' '..='~').contains(&c) {
+            return (self.glyph)(c as u8 - 0x20);
+        }
+        glyphs::glyph(c, self.height).unwrap_or_else(|| (self.glyph)(0))
     }
 }
 
@@ -90,6 +95,8 @@ impl FontKind {
 pub struct Console {
     fb: &'static mut [u8],
     back_buffer: &'static mut [u8],
+    // No memory for a back buffer: `back_buffer` is the framebuffer itself and `fb` is empty.
+    direct: bool,
     info: FrameBufferInfo,
     font_kind: FontKind,
     width: usize,
@@ -100,6 +107,9 @@ pub struct Console {
     fg: u32,
     bg: u32,
     reserved_hud_rows: usize,
+    // Off-screen copy of the HUD rows, drawn into and then presented in one
+    // copy. None if there was no memory for it; the HUD then draws in place.
+    hud_strip: Option<&'static mut [u8]>,
     dirty: Option<(usize, usize, usize, usize)>,
     cursor_style: CursorStyle,
     cursor_blink: CursorBlink,
@@ -108,12 +118,37 @@ pub struct Console {
     cursor_color: u32,
     blink_timer: u16,
     cursor_saved: Option<CursorSave>,
+    // Set by `save_position`: cursor cell and `scrolled` at the time.
+    saved_position: Option<(usize, usize, u64)>,
+    // Lines scrolled off the top since boot, so a saved position can follow its text.
+    scrolled: u64,
+    glyph_cache: GlyphCache,
+    presents_this_tick: u8,
+    color_filter: ColorFilter,
+    high_contrast: bool,
+    magnifier: bool,
+    // Top-left of the back-buffer region shown by the magnifier at its last present.
+    magnifier_origin: Option<(usize, usize)>,
+    perf: PresentStats,
+    perf_overlay: bool,
+    // Last once-a-second sample, as shown by the overlay.
+    perf_text: String,
+}
+
+/// Framebuffer copies since the last sample, for the F11 overlay.
+#[derive(Default)]
+struct PresentStats {
+    presents: u32,
+    cycles: u64,
+    area: u64,
+    ticks: u8,
 }
 
 pub enum DrawPos {
     Char(usize, usize),
 }
 
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum HudAlign {
     Left,
     Center,
@@ -135,9 +170,100 @@ pub enum CursorBlink {
     Fade,
 }
 
-const MAX_BACKBUFFER_BYTES: usize = 32 * 1024 * 1024;
-static mut BACK_BUFFER_STORAGE: MaybeUninit<[u8; MAX_BACKBUFFER_BYTES]> = MaybeUninit::uninit();
-static mut SNAPSHOT_STORAGE: MaybeUninit<[u8; MAX_BACKBUFFER_BYTES]> = MaybeUninit::uninit();
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ColorFilter {
+    Off,
+    Protanopia,
+    Deuteranopia,
+}
+
+impl ColorFilter {
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorFilter::Off => "off",
+            ColorFilter::Protanopia => "protanopia",
+            ColorFilter::Deuteranopia => "deuteranopia",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<ColorFilter> {
+        [ColorFilter::Off, ColorFilter::Protanopia, ColorFilter::Deuteranopia]
+            .into_iter()
+            .find(|f| f.name().eq_ignore_ascii_case(s))
+    }
+
+    /// How the color looks with the deficiency (Vienot et al.), in thousandths.
+    fn simulation(self) -> [[i32; 3]; 3] {
+        match self {
+            ColorFilter::Off => [[1000, 0, 0], [0, 1000, 0], [0, 0, 1000]],
+            ColorFilter::Protanopia => [[567, 433, 0], [558, 442, 0], [0, 242, 758]],
+            ColorFilter::Deuteranopia => [[625, 375, 0], [700, 300, 0], [0, 300, 700]],
+        }
+    }
+
+    /// Daltonizes: the red/green difference lost to the deficiency is moved
+    /// into green and blue, where it can still be seen.
+    fn apply(self, color: u32) -> u32 {
+        if self == ColorFilter::Off {
+            return color;
+        }
+        let rgb = [((color >> 16) & 0xFF) as i32, ((color >> 8) & 0xFF) as i32, (color & 0xFF) as i32];
+        let m = self.simulation();
+        let sim: [i32; 3] = core::array::from_fn(|i| (m[i][0] * rgb[0] + m[i][1] * rgb[1] + m[i][2] * rgb[2]) / 1000);
+        let err = [rgb[0] - sim[0], rgb[1] - sim[1], rgb[2] - sim[2]];
+        let out = [
+            rgb[0],
+            rgb[1] + (700 * err[0]) / 1000 + err[1],
+            rgb[2] + (700 * err[0]) / 1000 + err[2],
+        ];
+        let c = |v: i32| v.clamp(0, 255) as u32;
+        (c(out[0]) << 16) | (c(out[1]) << 8) | c(out[2])
+    }
+}
+
+const SETTING_COLOR_FILTER: &str = "accessibility.colors";
+const SETTING_HIGH_CONTRAST: &str = "accessibility.contrast";
+
+// Printable ASCII glyphs, pre-rendered at the current font/scale in one fg/bg pair per
+// character, so drawing them is a few row copies instead of a per-pixel loop.
+const GLYPH_CACHE_FIRST: u32 = 0x20;
+const GLYPH_CACHE_SLOTS: usize = 95;
+
+struct GlyphCache {
+    buf: Option<&'static mut [u8]>,
+    glyph_bytes: usize,
+    tags: [Option<(u32, u32)>; GLYPH_CACHE_SLOTS],
+}
+
+impl GlyphCache {
+    const fn new() -> Self {
+        Self { buf: None, glyph_bytes: 0, tags: [None; GLYPH_CACHE_SLOTS] }
+    }
+
+    fn invalidate(&mut self) {
+        self.tags = [None; GLYPH_CACHE_SLOTS];
+    }
+
+    /// Makes room for glyphs of `glyph_bytes` each, dropping stale entries if the size changed.
+    fn ensure(&mut self, glyph_bytes: usize) -> bool {
+        if self.glyph_bytes != glyph_bytes {
+            self.invalidate();
+            self.glyph_bytes = glyph_bytes;
+        }
+        let needed = glyph_bytes * GLYPH_CACHE_SLOTS;
+        if self.buf.as_ref().is_some_and(|b| b.len() >= needed) {
+            return true;
+        }
+        if let Some(old) = self.buf.take() {
+            memory::free_buffer(old);
+        }
+        self.buf = memory::alloc_buffer(needed);
+        self.buf.is_some()
+    }
+}
+
+// Frame-backed copy of the screen used by `showimage`; allocated on first use.
+static IMAGE_SNAPSHOT: Mutex<Option<&'static mut [u8]>> = Mutex::new(None);
 // Snapshot of the pixels under the cursor so we can draw over existing text without losing it.
 const CURSOR_SNAPSHOT_MAX: usize = 8192;
 
@@ -150,16 +276,6 @@ struct CursorSave {
     data: [u8; CURSOR_SNAPSHOT_MAX],
 }
 
-fn alloc_back_buffer(len: usize) -> Option<&'static mut [u8]> {
-    if len > MAX_BACKBUFFER_BYTES {
-        return None;
-    }
-    unsafe {
-        let ptr = addr_of_mut!(BACK_BUFFER_STORAGE) as *mut u8;
-        Some(core::slice::from_raw_parts_mut(ptr, len))
-    }
-}
-
 #[derive(Copy, Clone)]
 pub struct DisplayBufferStats {
     pub framebuffer_bytes: usize,
@@ -229,14 +345,41 @@ impl Console {
         }
     }
 
+    /// Presents the dirty region, at most PRESENT_BUDGET times per 100 Hz tick.
+    /// Past that the region keeps growing and the next tick presents it once,
+    /// so floods of output cost one copy per tick instead of one per character.
     fn present(&mut self) {
+        if self.presents_this_tick >= PRESENT_BUDGET {
+            return;
+        }
+        self.presents_this_tick += 1;
+        self.flush();
+    }
+
+    /// Presents the dirty region now, ignoring the budget.
+    pub fn flush(&mut self) {
         if let Some((x0, y0, x1, y1)) = self.dirty.take() {
             self.present_rect(x0, y0, x1 - x0, y1 - y0);
         }
     }
 
     fn present_rect(&mut self, x: usize, y: usize, w: usize, h: usize) {
-        if w == 0 || h == 0 {
+        if self.direct || w == 0 || h == 0 {
+            return;
+        }
+        let t0 = unsafe { core::arch::x86_64::_rdtsc() };
+        self.copy_rect(x, y, w, h);
+        self.perf.presents += 1;
+        self.perf.cycles += unsafe { core::arch::x86_64::_rdtsc() } - t0;
+        self.perf.area += (w * h) as u64;
+        if self.perf_overlay {
+            self.draw_perf_overlay();
+        }
+    }
+
+    fn copy_rect(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        if self.magnifier {
+            self.present_magnified(x, y, w, h);
             return;
         }
         let max_x = self.info.width;
@@ -248,24 +391,139 @@ impl Console {
         let y1 = (y + h).min(max_y);
         let bpp = self.info.bytes_per_pixel;
         let stride = self.info.stride;
+        if x == 0 && x1 == max_x {
+            // Full-width rows are contiguous apart from stride padding; copy them in one go.
+            let off = y * stride * bpp;
+            let end = ((y1 - 1) * stride + x1) * bpp;
+            blit::copy(&mut self.fb[off..end], &self.back_buffer[off..end]);
+            return;
+        }
         for row in y..y1 {
             let off = (row * stride + x) * bpp;
             let len = (x1 - x) * bpp;
-            let src = &self.back_buffer[off..off + len];
-            let dst = &mut self.fb[off..off + len];
-            dst.copy_from_slice(src);
+            blit::copy(&mut self.fb[off..off + len], &self.back_buffer[off..off + len]);
         }
     }
 
-    fn present_full(&mut self) {
+    /// The half-size back-buffer region around the cursor that the magnifier shows.
+    fn magnifier_view(&self) -> (usize, usize, usize, usize) {
+        let vw = self.info.width / MAGNIFIER_ZOOM;
+        let vh = self.info.height / MAGNIFIER_ZOOM;
+        let (cx, cy) = match self.cursor_rect() {
+            Some((px, py, w, h)) => (px + w / 2, py + h / 2),
+            None => (self.cursor_x * self.char_w(), self.cursor_y * self.char_h()),
+        };
+        let vx = cx.saturating_sub(vw / 2).min(self.info.width - vw);
+        let vy = cy.saturating_sub(vh / 2).min(self.info.height - vh);
+        (vx, vy, vw, vh)
+    }
+
+    /// Draws the changed part of the magnified view into the framebuffer. The
+    /// back buffer keeps the normal screen, so output carries on underneath
+    /// and turning the magnifier off is just a full present.
+    fn present_magnified(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        let (vx, vy, vw, vh) = self.magnifier_view();
+        let (sx0, sy0, sx1, sy1) = if self.magnifier_origin != Some((vx, vy)) {
+            (vx, vy, vx + vw, vy + vh)
+        } else {
+            let sx0 = x.max(vx);
+            let sy0 = y.max(vy);
+            let sx1 = (x + w).min(vx + vw);
+            let sy1 = (y + h).min(vy + vh);
+            if sx0 >= sx1 || sy0 >= sy1 {
+                return;
+            }
+            (sx0, sy0, sx1, sy1)
+        };
+        self.magnifier_origin = Some((vx, vy));
+
+        let bpp = self.info.bytes_per_pixel;
+        let stride = self.info.stride;
+        let dx0 = (sx0 - vx) * MAGNIFIER_ZOOM;
+        let row_bytes = (sx1 - sx0) * MAGNIFIER_ZOOM * bpp;
+        for sy in sy0..sy1 {
+            let dy = (sy - vy) * MAGNIFIER_ZOOM;
+            let row_off = (dy * stride + dx0) * bpp;
+            for sx in sx0..sx1 {
+                let src = (sy * stride + sx) * bpp;
+                let dst = row_off + (sx - sx0) * MAGNIFIER_ZOOM * bpp;
+                for k in 0..MAGNIFIER_ZOOM {
+                    let d = dst + k * bpp;
+                    self.fb[d..d + bpp].copy_from_slice(&self.back_buffer[src..src + bpp]);
+                }
+            }
+            for k in 1..MAGNIFIER_ZOOM {
+                let copy_to = row_off + k * stride * bpp;
+                self.fb.copy_within(row_off..row_off + row_bytes, copy_to);
+            }
+        }
+    }
+
+    pub fn set_magnifier(&mut self, on: bool) -> bool {
+        if self.direct {
+            return false;
+        }
+        self.magnifier = on;
+        self.magnifier_origin = None;
+        self.present_full();
+        true
+    }
+
+    pub fn magnifier(&self) -> bool {
+        self.magnifier
+    }
+
+    /// Turns the present-rate overlay on or off. It is drawn straight into
+    /// the framebuffer after each present, so needs a back buffer.
+    pub fn set_perf_overlay(&mut self, on: bool) -> bool {
+        if self.direct {
+            return false;
+        }
+        self.perf_overlay = on;
+        self.present_full();
+        true
+    }
+
+    pub fn perf_overlay(&self) -> bool {
+        self.perf_overlay
+    }
+
+    /// Turns the last second's present counts into the overlay text.
+    fn sample_perf(&mut self) {
+        let p = core::mem::take(&mut self.perf);
+        let n = p.presents.max(1) as u64;
+        let us = match wait::tsc_hz() {
+            0 => 0,
+            hz => p.cycles / n * 1_000_000 / hz,
+        };
+        self.perf_text = alloc::format!(" {} presents/s  {} us avg  {} px avg ", p.presents, us, p.area / n);
+    }
+
+    fn draw_perf_overlay(&mut self) {
+        let len = self.perf_text.chars().count();
+        if len == 0 || len > self.width {
+            return;
+        }
+        let x0 = self.width - len;
+        let stride = self.info.stride;
+        let fb = core::mem::take(&mut self.fb);
+        let text = core::mem::take(&mut self.perf_text);
+        for (i, c) in text.chars().enumerate() {
+            self.draw_glyph_into(fb, stride, x0 + i, 0, c, PERF_OVERLAY_COLORS.0, PERF_OVERLAY_COLORS.1);
+        }
+        self.perf_text = text;
+        self.fb = fb;
+    }
+
+    pub(crate) fn present_full(&mut self) {
         self.present_rect(0, 0, self.info.width, self.info.height);
         self.dirty = None;
     }
 
     fn buffer_stats(&self) -> DisplayBufferStats {
         DisplayBufferStats {
-            framebuffer_bytes: self.fb.len(),
-            backbuffer_bytes: self.back_buffer.len(),
+            framebuffer_bytes: self.info.byte_len,
+            backbuffer_bytes: if self.direct { 0 } else { self.back_buffer.len() },
             width_px: self.info.width,
             height_px: self.info.height,
             stride_px: self.info.stride,
@@ -277,16 +535,25 @@ impl Console {
         let fb = boot.framebuffer.as_mut()?;
         let info = fb.info();
         let slice = fb.buffer_mut();
-        let back_buffer = alloc_back_buffer(slice.len())?;
-        back_buffer.copy_from_slice(slice);
+        let (fb, back_buffer, direct) = match memory::alloc_buffer(slice.len()) {
+            Some(back) => {
+                back.copy_from_slice(slice);
+                (slice, back, false)
+            }
+            None => {
+                crate::klog::warn("console: no memory for a back buffer, drawing directly");
+                (&mut [][..], slice, true)
+            }
+        };
         let font_kind = FontKind::Vga8;
         let scale = font_kind.default_scale();
         let font = font_kind.face();
         let width = info.width / (font.width * scale);
         let height = info.height / (font.height * scale);
         Some(Self {
-            fb: slice,
+            fb,
             back_buffer,
+            direct,
             info,
             font_kind,
             width,
@@ -294,9 +561,10 @@ impl Console {
             cursor_x: 0,
             cursor_y: 0,
             scale,
-            fg: 0xCCCCCC,
-            bg: 0x000000,
+            fg: DEFAULT_COLORS.0,
+            bg: DEFAULT_COLORS.1,
             reserved_hud_rows: 0,
+            hud_strip: None,
             dirty: None,
             cursor_style: CursorStyle::Line,
             cursor_blink: CursorBlink::Pulse,
@@ -305,6 +573,17 @@ impl Console {
             cursor_color: 0xFFFFFF,
             blink_timer: 0,
             cursor_saved: None,
+            saved_position: None,
+            scrolled: 0,
+            glyph_cache: GlyphCache::new(),
+            presents_this_tick: 0,
+            color_filter: ColorFilter::Off,
+            high_contrast: false,
+            magnifier: false,
+            magnifier_origin: None,
+            perf: PresentStats::default(),
+            perf_overlay: false,
+            perf_text: String::new(),
         })
     }
 
@@ -339,12 +618,54 @@ impl Console {
         }
     }
 
+    /// Writes patterns to the last pixel of the framebuffer, reads them back
+    /// and restores the original bytes.
+    fn framebuffer_readback(&mut self) -> bool {
+        let bpp = self.info.bytes_per_pixel;
+        let fb: &mut [u8] = if self.direct { &mut *self.back_buffer } else { &mut *self.fb };
+        let Some(off) = fb.len().checked_sub(bpp) else { return false; };
+        let mut saved = [0u8; 4];
+        let n = bpp.min(saved.len());
+        let px = fb[off..off + n].as_mut_ptr();
+        let mut ok = true;
+        unsafe {
+            for (i, b) in saved[..n].iter_mut().enumerate() {
+                *b = core::ptr::read_volatile(px.add(i));
+            }
+            for pattern in [0x5Au8, 0xA5] {
+                for i in 0..n {
+                    core::ptr::write_volatile(px.add(i), pattern);
+                }
+                for i in 0..n {
+                    if core::ptr::read_volatile(px.add(i)) != pattern {
+                        ok = false;
+                    }
+                }
+            }
+            for (i, &b) in saved[..n].iter().enumerate() {
+                core::ptr::write_volatile(px.add(i), b);
+            }
+        }
+        ok
+    }
+
     pub fn framebuffer_info(&self) -> &FrameBufferInfo {
         &self.info
     }
 
+    /// Accessibility remapping for text, fills and the cursor. Images are not
+    /// remapped. High contrast turns the current background black and
+    /// everything drawn on it white.
+    fn map_color(&self, color: u32) -> u32 {
+        if self.high_contrast {
+            return if color == self.bg { 0x000000 } else { 0xFFFFFF };
+        }
+        self.color_filter.apply(color)
+    }
+
     fn write_pixel_into(&self, buf: &mut [u8], buf_stride_px: usize, x: usize, y: usize, color: u32) {
         if x >= buf_stride_px || y >= self.info.height { return; }
+        let color = self.map_color(color);
         let bpp = self.info.bytes_per_pixel;
         let off = (y * buf_stride_px + x) * bpp;
         if off + bpp > buf.len() { return; }
@@ -388,6 +709,9 @@ impl Console {
         let base_px = x * font.width * s;
         let base_py = y * font.height * s;
         self.mark_dirty(base_px, base_py, font.width * s, font.height * s);
+        if self.draw_glyph_cached(base_px, base_py, c, color) {
+            return;
+        }
         for (row, bits) in glyph.iter().enumerate() {
             for col in 0..font.width {
                 let bit = (bits >> (7 - col)) & 1;
@@ -399,12 +723,50 @@ impl Console {
         }
     }
 
+    fn draw_glyph_cached(&mut self, px: usize, py: usize, c: char, fg: u32) -> bool {
+        let code = c as u32;
+        let gw = self.char_w();
+        let gh = self.char_h();
+        if !(GLYPH_CACHE_FIRST..GLYPH_CACHE_FIRST + GLYPH_CACHE_SLOTS as u32).contains(&code)
+            || px + gw > self.info.width
+            || py + gh > self.info.height
+        {
+            return false;
+        }
+        let bpp = self.info.bytes_per_pixel;
+        let glyph_bytes = gw * gh * bpp;
+        if !self.glyph_cache.ensure(glyph_bytes) {
+            return false;
+        }
+        let Some(buf) = self.glyph_cache.buf.take() else { return false; };
+
+        let slot = (code - GLYPH_CACHE_FIRST) as usize;
+        let cell = &mut buf[slot * glyph_bytes..(slot + 1) * glyph_bytes];
+        // A space looks the same in any foreground color, so share one entry.
+        let key = if c == ' ' { (self.bg, self.bg) } else { (fg, self.bg) };
+        if self.glyph_cache.tags[slot] != Some(key) {
+            self.draw_glyph_into(cell, gw, 0, 0, c, fg, self.bg);
+            self.glyph_cache.tags[slot] = Some(key);
+        }
+
+        let stride = self.info.stride;
+        let row_bytes = gw * bpp;
+        for row in 0..gh {
+            let off = ((py + row) * stride + px) * bpp;
+            self.back_buffer[off..off + row_bytes]
+                .copy_from_slice(&cell[row * row_bytes..(row + 1) * row_bytes]);
+        }
+        self.glyph_cache.buf = Some(buf);
+        true
+    }
+
     fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: u32) {
         self.mark_dirty(x, y, w, h);
         self.fill_rect_raw(x, y, w, h, color);
     }
 
     fn fill_rect_raw(&mut self, x: usize, y: usize, w: usize, h: usize, color: u32) {
+        let color = self.map_color(color);
         let bytes_per_pixel = self.info.bytes_per_pixel;
         let stride = self.info.stride;
         for dy in 0..h {
@@ -435,6 +797,11 @@ impl Console {
     }
 
     pub fn put_char(&mut self, c: char) {
+        if capture_char(c) {
+            return;
+        }
+        record_scrollback(c, self.cursor_x + 1 >= self.width);
+        record_output(c);
         if c == '\n' {
             self.newline();
             return;
@@ -520,7 +887,8 @@ impl Console {
             return;
         }
 
-        self.back_buffer.copy_within(shift..copy_bytes, 0);
+        blit::move_to_front(self.back_buffer, shift, copy_bytes);
+        self.scrolled += 1;
         self.mark_dirty(0, 0, self.info.width, visible_px);
         let clear_py = visible_px.saturating_sub(char_h_px);
         self.fill_rect(0, clear_py, self.info.width, char_h_px, self.bg);
@@ -548,7 +916,9 @@ impl Console {
         let font = self.font();
         let px = self.cursor_x * font.width * s;
         let py = self.cursor_y * font.height * s;
-        match self.cursor_style {
+        // High contrast always uses a solid block so the cursor is easy to find.
+        let style = if self.high_contrast { CursorStyle::Block } else { self.cursor_style };
+        match style {
             CursorStyle::Underscore => Some((px, py + (font.height - 1) * s, font.width * s, s)),
             CursorStyle::Line => Some((px, py, 2 * s, font.height * s)),
             CursorStyle::Block => Some((px, py, font.width * s, font.height * s)),
@@ -692,6 +1062,40 @@ impl Console {
         self.present();
     }
 
+    /// Remembers the cursor cell for `restore_position`, like ESC 7.
+    pub fn save_position(&mut self) {
+        self.saved_position = Some((self.cursor_x, self.cursor_y, self.scrolled));
+    }
+
+    /// Moves the cursor back to the saved cell, counting lines that have
+    /// scrolled since; a cell scrolled off the top becomes the top-left.
+    /// False if nothing was saved.
+    pub fn restore_position(&mut self) -> bool {
+        let Some((x, y, scrolled)) = self.saved_position else { return false; };
+        let up = self.scrolled - scrolled;
+        match (y as u64).checked_sub(up) {
+            Some(y) => self.move_cursor_to(x, y as usize),
+            None => self.move_cursor_to(0, 0),
+        }
+        true
+    }
+
+    /// Blanks from the cursor to the end of its row and every text row
+    /// below it, like ESC [J. The cursor stays put.
+    pub fn clear_to_end(&mut self) {
+        self.erase_cursor();
+        let (cw, ch) = (self.char_w(), self.char_h());
+        let rows = self.text_area_height();
+        if self.cursor_y < rows {
+            let x = self.cursor_x * cw;
+            self.fill_rect(x, self.cursor_y * ch, self.info.width.saturating_sub(x), ch, self.bg);
+            let below = (self.cursor_y + 1) * ch;
+            self.fill_rect(0, below, self.info.width, (rows * ch).saturating_sub(below), self.bg);
+        }
+        self.draw_cursor();
+        self.present();
+    }
+
     pub fn render_line_at(
         &mut self,
         origin_x: usize,
@@ -699,6 +1103,7 @@ impl Console {
         content: &str,
         prev_render_len: usize,
         cursor_offset: usize,
+        highlight: Option<(Range<usize>, u32)>,
     ) -> usize {
         self.erase_cursor();
         let max_x = self.width;
@@ -710,11 +1115,15 @@ impl Console {
         let y = origin_y.min(max_y - 1);
         let mut x = origin_x;
         let mut drawn = 0;
-        for ch in content.chars() {
+        for (i, ch) in content.chars().enumerate() {
             if x >= max_x {
                 break;
             }
-            self.draw_glyph(x, y, ch, self.fg);
+            let fg = match &highlight {
+                Some((range, fg)) if range.contains(&i) => *fg,
+                _ => self.fg,
+            };
+            self.draw_glyph(x, y, ch, fg);
             x += 1;
             drawn += 1;
         }
@@ -742,6 +1151,32 @@ impl Console {
     pub fn reserve_hud_rows(&mut self, rows: usize) {
         let rows = rows.min(self.height);
         self.reserved_hud_rows = rows;
+        self.resize_hud_strip();
+    }
+
+    /// First pixel row of the HUD. The strip runs from here to the bottom of
+    /// the screen, taking in any rows too short for a whole character.
+    fn hud_top(&self) -> usize {
+        self.text_area_height() * self.char_h()
+    }
+
+    fn hud_strip_len(&self) -> usize {
+        (self.info.height - self.hud_top()) * self.info.stride * self.info.bytes_per_pixel
+    }
+
+    /// Sizes the strip for the current font and HUD rows. Done here rather
+    /// than when drawing, since the HUD draws from the timer interrupt.
+    fn resize_hud_strip(&mut self) {
+        let len = if self.reserved_hud_rows == 0 { 0 } else { self.hud_strip_len() };
+        if self.hud_strip.as_ref().map_or(0, |s| s.len()) == len {
+            return;
+        }
+        if let Some(old) = self.hud_strip.take() {
+            memory::free_buffer(old);
+        }
+        if len > 0 {
+            self.hud_strip = memory::alloc_buffer(len);
+        }
     }
 
     fn text_area_height(&self) -> usize {
@@ -749,6 +1184,10 @@ impl Console {
     }
 
     pub fn draw_text_at_char(&mut self, pos: DrawPos, s: &str) {
+        self.draw_text_at_char_fg(pos, s, self.fg);
+    }
+
+    pub fn draw_text_at_char_fg(&mut self, pos: DrawPos, s: &str, fg: u32) {
         match pos {
             DrawPos::Char(x, y) => {
                 let old_x = self.cursor_x;
@@ -757,7 +1196,7 @@ impl Console {
                 self.erase_cursor();
                 let mut cx = x;
                 for ch in s.chars() {
-                    self.draw_glyph(cx, y, ch, self.fg);
+                    self.draw_glyph(cx, y, ch, fg);
                     cx += 1;
                 }
                 self.cursor_x = old_x;
@@ -769,30 +1208,71 @@ impl Console {
         self.present();
     }
 
+    /// Paints a block of character cells in a solid color, for swatches.
+    pub fn fill_cells(&mut self, x: usize, y: usize, w: usize, h: usize, color: u32) {
+        self.erase_cursor();
+        let (cw, ch) = (self.char_w(), self.char_h());
+        self.fill_rect(x * cw, y * ch, w * cw, h * ch, color);
+        self.draw_cursor();
+        self.present();
+    }
+
     pub fn hud_begin(&mut self) {
         if self.reserved_hud_rows == 0 {
             return;
         }
+        if let Some(strip) = self.hud_strip.take() {
+            let bpp = self.info.bytes_per_pixel;
+            self.write_pixel_into(strip, self.info.stride, 0, 0, self.bg);
+            let (first, rest) = strip.split_at_mut(bpp);
+            for px in rest.chunks_exact_mut(bpp) {
+                px.copy_from_slice(first);
+            }
+            self.hud_strip = Some(strip);
+            return;
+        }
         let hud_h_px = self.reserved_hud_rows * self.char_h();
         let start_y = self.info.height.saturating_sub(hud_h_px);
         self.fill_rect(0, start_y, self.info.width, hud_h_px, self.bg);
     }
 
+    /// Character columns available on the HUD row.
+    pub fn hud_cols(&self) -> usize {
+        match self.char_w() {
+            0 => 0,
+            w => self.info.width / w,
+        }
+    }
+
+    pub fn hud_align_x(&self, text_chars: usize, align: HudAlign) -> usize {
+        let char_w = self.char_w();
+        if char_w == 0 {
+            return 0;
+        }
+        let cols = self.info.width / char_w;
+        match align {
+            HudAlign::Left => 0,
+            HudAlign::Center => (cols / 2).saturating_sub(text_chars / 2),
+            HudAlign::Right => cols.saturating_sub(text_chars),
+        }
+    }
+
     pub fn hud_draw_text(&mut self, s: &str, fg: u32, align: HudAlign) {
         if self.reserved_hud_rows == 0 { return; }
-        let scale = self.scale;
-        let font = self.font();
-        let char_w = font.width * scale;
-        if char_w == 0 {
+        let x_char = self.hud_align_x(s.chars().count(), align);
+        self.hud_draw_text_at(x_char, s, fg);
+    }
+
+    pub fn hud_draw_text_at(&mut self, x_char: usize, s: &str, fg: u32) {
+        if self.reserved_hud_rows == 0 { return; }
+        if let Some(strip) = self.hud_strip.take() {
+            for (i, ch) in s.chars().enumerate() {
+                self.draw_glyph_into(strip, self.info.stride, x_char + i, 0, ch, fg, self.bg);
+            }
+            self.hud_strip = Some(strip);
             return;
         }
-        let text_chars = s.chars().count();
         let y_char = self.height.saturating_sub(self.reserved_hud_rows);
-        let x_char = match align {
-            HudAlign::Left => 0,
-            HudAlign::Center => ((self.info.width / char_w) / 2).saturating_sub(text_chars / 2),
-            HudAlign::Right => (self.info.width / char_w).saturating_sub(text_chars),
-        };
         let mut cx = x_char;
         for ch in s.chars() {
             self.draw_glyph(cx, y_char, ch, fg);
@@ -800,8 +1280,22 @@ impl Console {
         }
     }
 
+    /// Shows what was drawn since `hud_begin`. With a strip, nothing is
+    /// copied unless it differs from what is on screen.
     pub fn hud_present(&mut self) {
         if self.reserved_hud_rows == 0 { return; }
+        let Some(strip) = self.hud_strip.as_deref() else {
+            self.present();
+            return;
+        };
+        let top = self.hud_top();
+        let off = top * self.info.stride * self.info.bytes_per_pixel;
+        let shown = &mut self.back_buffer[off..off + strip.len()];
+        if shown == strip {
+            return;
+        }
+        blit::copy(shown, strip);
+        self.mark_dirty(0, top, self.info.width, self.info.height - top);
         self.present();
     }
 
@@ -834,6 +1328,10 @@ impl Console {
         self.present();
     }
 
+    pub fn cursor_color(&self) -> u32 {
+        self.cursor_color
+    }
+
     pub fn set_cursor_color(&mut self, color: u32) {
         self.cursor_color = color;
     }
@@ -844,6 +1342,7 @@ impl Console {
         }
         self.font_kind = kind;
         self.scale = kind.default_scale();
+        self.glyph_cache.invalidate();
         self.recompute_dimensions();
         self.clear();
     }
@@ -862,6 +1361,7 @@ impl Console {
             return;
         }
         self.bg = bg;
+        self.glyph_cache.invalidate();
         self.clear();
     }
 
@@ -871,15 +1371,49 @@ impl Console {
         self.bg = bg;
         self.cursor_color = fg;
         if bg_changed {
+            self.glyph_cache.invalidate();
             self.clear();
         }
     }
 
+    /// Changes the accessibility remapping and redraws the screen in it.
+    pub fn set_accessibility(&mut self, filter: ColorFilter, high_contrast: bool) {
+        if self.color_filter == filter && self.high_contrast == high_contrast {
+            return;
+        }
+        self.erase_cursor();
+        self.color_filter = filter;
+        self.high_contrast = high_contrast;
+        self.glyph_cache.invalidate();
+        self.clear();
+    }
+
+    pub fn accessibility(&self) -> (ColorFilter, bool) {
+        (self.color_filter, self.high_contrast)
+    }
+
     pub fn default_colors(&self) -> (u32, u32) {
         (self.fg, self.bg)
     }
 
+    /// Called at 100 Hz, so the blink periods below are in centiseconds.
     pub fn tick(&mut self) {
+        self.presents_this_tick = 0;
+        if keyboard::take_magnifier_toggle() {
+            let on = !self.magnifier;
+            self.set_magnifier(on);
+        }
+        if keyboard::take_perf_overlay_toggle() {
+            let on = !self.perf_overlay;
+            self.set_perf_overlay(on);
+        }
+        self.perf.ticks += 1;
+        if self.perf.ticks >= 100 {
+            self.sample_perf();
+            if self.perf_overlay {
+                self.draw_perf_overlay();
+            }
+        }
         match self.cursor_blink {
             CursorBlink::None => {
                 self.cursor_visible = true;
@@ -968,12 +1502,408 @@ impl Console {
 
 pub static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
 
+/// Text and background colors before any theme is applied.
+pub const DEFAULT_COLORS: (u32, u32) = (0xCCCCCC, 0x000000);
+
+const PRESENT_BUDGET: u8 = 4;
+const MAGNIFIER_ZOOM: usize = 2;
+const PERF_OVERLAY_COLORS: (u32, u32) = (0x000000, 0xFFD37F);
+
+const CAPTURE_LIMIT: usize = 16 * 1024;
+const CAPTURE_DEPTH: usize = 4;
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+struct Capture {
+    text: String,
+    /// Also keep what goes through `write_err`.
+    errors: bool,
+    /// Keep output from the screen and any outer capture, for redirection.
+    quiet: bool,
+}
+
+// Captures nest (e.g. `copy` run from test mode); every open one sees the output,
+// up to and including the innermost quiet one.
+static CAPTURE: Mutex<heapless::Vec<Capture, CAPTURE_DEPTH>> = Mutex::new(heapless::Vec::new());
+
+fn push_capture(errors: bool, quiet: bool) -> bool {
+    let ok = CAPTURE.lock().push(Capture { text: String::new(), errors, quiet }).is_ok();
+    CAPTURING.store(true, Ordering::Relaxed);
+    ok
+}
+
+/// Starts copying everything written through `put_char` into a buffer,
+/// errors included. Returns false if too many captures are already open.
+pub fn begin_capture() -> bool {
+    push_capture(true, false)
+}
+
+/// Like `begin_capture`, but leaves out the error channel, the way a pipe
+/// only takes stdout.
+pub fn begin_output_capture() -> bool {
+    push_capture(false, false)
+}
+
+/// Like `begin_output_capture`, but the output goes only to the buffer and
+/// not to the screen, for `>` redirection. Errors still show.
+pub fn begin_redirect() -> bool {
+    push_capture(false, true)
+}
+
+/// Whether normal output is currently going to a redirect instead of the screen.
+pub fn output_redirected() -> bool {
+    CAPTURING.load(Ordering::Relaxed) && CAPTURE.lock().iter().any(|c| c.quiet)
+}
+
+/// Stops the innermost capture and returns what was written since its `begin_capture`.
+pub fn end_capture() -> Option<String> {
+    let mut stack = CAPTURE.lock();
+    let out = stack.pop().map(|c| c.text);
+    CAPTURING.store(!stack.is_empty(), Ordering::Relaxed);
+    out
+}
+
+/// Hands `c` to the open captures, innermost first. Returns true if a quiet
+/// capture took it, so it must not be drawn.
+fn capture_char(c: char) -> bool {
+    if !CAPTURING.load(Ordering::Relaxed) {
+        return false;
+    }
+    let error = WRITING_ERROR.load(Ordering::Relaxed);
+    for cap in CAPTURE.lock().iter_mut().rev() {
+        if error && !cap.errors {
+            continue;
+        }
+        if cap.text.len() < CAPTURE_LIMIT {
+            cap.text.push(c);
+        }
+        if cap.quiet {
+            return true;
+        }
+    }
+    false
+}
+
+// Text of recent screen lines, for `grep` to search after the fact. Lines are
+// broken where the screen wraps them. The ring is fixed in size, so recording
+// a character never allocates, and the crash path can break its lock along
+// with the console's.
+const SCROLLBACK_LINES: usize = 200;
+/// Longer screen lines, on very wide screens, are cut.
+const SCROLLBACK_LINE_LEN: usize = 256;
+
+type ScreenLine = heapless::String<SCROLLBACK_LINE_LEN>;
+
+struct ScreenLines {
+    lines: heapless::Deque<ScreenLine, SCROLLBACK_LINES>,
+    current: ScreenLine,
+    /// Lines finished since boot; `lines` holds the last of them.
+    pushed: usize,
+    /// The typed lines of the command being run, as `pushed` counts.
+    command: Range<usize>,
+}
+
+impl ScreenLines {
+    fn finish_line(&mut self) {
+        let line = core::mem::take(&mut self.current);
+        if self.lines.is_full() {
+            self.lines.pop_front();
+        }
+        let _ = self.lines.push_back(line);
+        self.pushed += 1;
+    }
+}
+
+static SCROLLBACK: Mutex<ScreenLines> = Mutex::new(ScreenLines {
+    lines: heapless::Deque::new(),
+    current: heapless::String::new(),
+    pushed: 0,
+    command: 0..0,
+});
+
+/// `wraps` is whether `c` fills the screen line.
+fn record_scrollback(c: char, wraps: bool) {
+    let mut sb = SCROLLBACK.lock();
+    if c != '\n' {
+        let _ = sb.current.push(c);
+        if !wraps {
+            return;
+        }
+    }
+    sb.finish_line();
+}
+
+/// Records a line typed at a prompt, which is drawn in place rather than
+/// written, and ends it as Enter does on screen.
+pub fn record_input(line: &str) {
+    interrupts::without_interrupts(|| {
+        let mut sb = SCROLLBACK.lock();
+        for c in line.chars() {
+            let _ = sb.current.push(c);
+        }
+        sb.finish_line();
+    });
+}
+
+/// Marks where the shell prompt starts, so the command typed after it can be
+/// left out of `scrollback` while it runs.
+pub fn begin_command_lines() {
+    interrupts::without_interrupts(|| {
+        let mut sb = SCROLLBACK.lock();
+        let at = sb.pushed;
+        sb.command = at..at;
+    });
+}
+
+/// Marks the end of the command typed since `begin_command_lines`.
+pub fn end_command_lines() {
+    interrupts::without_interrupts(|| {
+        let mut sb = SCROLLBACK.lock();
+        sb.command.end = sb.pushed;
+    });
+}
+
+/// Whether scrollback, the output log or a capture is being written, for the watchdog.
+pub fn output_busy() -> bool {
+    SCROLLBACK.is_locked() || OUTPUT_LOG.is_locked() || CAPTURE.is_locked()
+}
+
+/// Recent screen lines, oldest first, including the one still being written
+/// but not the command line being run.
+pub fn scrollback() -> Vec<String> {
+    interrupts::without_interrupts(|| {
+        let sb = SCROLLBACK.lock();
+        let first = sb.pushed - sb.lines.len();
+        let mut lines: Vec<String> = sb
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !sb.command.contains(&(first + i)))
+            .map(|(_, line)| String::from(line.as_str()))
+            .collect();
+        if !sb.current.is_empty() {
+            lines.push(String::from(sb.current.as_str()));
+        }
+        lines
+    })
+}
+
+// Output of commands alone, for `lastout`: no prompt, typed input or errors,
+// and lines broken only at newlines, whatever the screen width.
+pub const OUTPUT_LOG_LINES: usize = 200;
+const OUTPUT_LOG_LINE_MAX: usize = 512;
+
+struct Scrollback {
+    lines: VecDeque<String>,
+    current: String,
+}
+
+static OUTPUT_LOG: Mutex<Scrollback> = Mutex::new(Scrollback { lines: VecDeque::new(), current: String::new() });
+static LOGGING_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+fn push_output_line(log: &mut Scrollback) {
+    let line = core::mem::take(&mut log.current);
+    if log.lines.len() == OUTPUT_LOG_LINES {
+        log.lines.pop_front();
+    }
+    log.lines.push_back(line);
+}
+
+fn record_output(c: char) {
+    if !LOGGING_OUTPUT.load(Ordering::Relaxed) || WRITING_ERROR.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut log = OUTPUT_LOG.lock();
+    if c == '\n' {
+        push_output_line(&mut log);
+    } else if log.current.len() < OUTPUT_LOG_LINE_MAX {
+        log.current.push(c);
+    }
+}
+
+/// Turns recording of command output on or off, returning the old setting.
+pub fn set_output_log(on: bool) -> bool {
+    LOGGING_OUTPUT.swap(on, Ordering::Relaxed)
+}
+
+/// Ends a line a command left unfinished, so the next one starts afresh.
+pub fn break_output_log() {
+    interrupts::without_interrupts(|| {
+        let mut log = OUTPUT_LOG.lock();
+        if !log.current.is_empty() {
+            push_output_line(&mut log);
+        }
+    })
+}
+
+/// Recent command output lines, oldest first.
+pub fn output_log() -> Vec<String> {
+    interrupts::without_interrupts(|| {
+        let log = OUTPUT_LOG.lock();
+        let mut lines: Vec<String> = log.lines.iter().cloned().collect();
+        if !log.current.is_empty() {
+            lines.push(log.current.clone());
+        }
+        lines
+    })
+}
+
+// Error channel: what commands print when they fail. It shares the screen
+// with normal output but has its own color, is skipped by output-only
+// captures and can be copied to serial or the kernel log.
+pub const DEFAULT_ERROR_COLOR: u32 = 0xFF5050;
+static ERROR_COLOR: AtomicU32 = AtomicU32::new(DEFAULT_ERROR_COLOR);
+static ERRORS_TO_SERIAL: AtomicBool = AtomicBool::new(false);
+static ERRORS_TO_LOG: AtomicBool = AtomicBool::new(false);
+static WRITING_ERROR: AtomicBool = AtomicBool::new(false);
+
+pub fn set_error_color(color: u32) {
+    ERROR_COLOR.store(color, Ordering::Relaxed);
+}
+
+pub fn error_color() -> u32 {
+    ERROR_COLOR.load(Ordering::Relaxed)
+}
+
+pub fn set_error_mirrors(serial: bool, log: bool) {
+    ERRORS_TO_SERIAL.store(serial, Ordering::Relaxed);
+    ERRORS_TO_LOG.store(log, Ordering::Relaxed);
+}
+
+/// Whether errors are copied to (serial, kernel log).
+pub fn error_mirrors() -> (bool, bool) {
+    (ERRORS_TO_SERIAL.load(Ordering::Relaxed), ERRORS_TO_LOG.load(Ordering::Relaxed))
+}
+
+/// write_line for the error channel.
+pub fn write_err(s: &str) {
+    wait_while_paused();
+    WRITING_ERROR.store(true, Ordering::Relaxed);
+    with_console(|c| {
+        let (_, bg) = c.default_colors();
+        c.cwrite_line(s, error_color(), bg);
+    });
+    WRITING_ERROR.store(false, Ordering::Relaxed);
+    let (to_serial, to_log) = error_mirrors();
+    if to_serial {
+        crate::serial::write(s);
+    }
+    if to_log {
+        crate::klog::try_log(crate::klog::Level::Warn, s);
+    }
+}
+
 pub fn init_console(boot: &'static mut BootInfo) {
     if let Some(console) = Console::from_boot_info(boot) {
+        let visible = if console.direct { &console.back_buffer } else { &console.fb };
+        PANIC_FB.call_once(|| PanicFramebuffer {
+            addr: visible.as_ptr() as usize,
+            len: visible.len(),
+            info: console.info,
+        });
         *CONSOLE.lock() = Some(console);
     }
 }
 
+// Where the visible framebuffer lives, kept outside the console lock so a
+// panic can still draw if the console itself is wedged.
+struct PanicFramebuffer {
+    addr: usize,
+    len: usize,
+    info: FrameBufferInfo,
+}
+
+static PANIC_FB: spin::Once<PanicFramebuffer> = spin::Once::new();
+static PANIC_ROW: AtomicUsize = AtomicUsize::new(0);
+const PANIC_SCALE: usize = 2;
+
+/// Draws a line of text straight into the framebuffer with the VGA font,
+/// bypassing `Console` entirely. Only for panics where the console is locked.
+pub fn panic_write_line(s: &str, fg: u32) {
+    let Some(pfb) = PANIC_FB.get() else { return; };
+    let info = pfb.info;
+    let bpp = info.bytes_per_pixel;
+    let glyph_w = FONT_VGA8.width * PANIC_SCALE;
+    let glyph_h = FONT_VGA8.height * PANIC_SCALE;
+    let rows = (info.height / glyph_h).max(1);
+    let row = PANIC_ROW.fetch_add(1, Ordering::Relaxed) % rows;
+    let fb = unsafe { core::slice::from_raw_parts_mut(pfb.addr as *mut u8, pfb.len) };
+
+    let put = |fb: &mut [u8], x: usize, y: usize, color: u32| {
+        let off = (y * info.stride + x) * bpp;
+        if x >= info.width || off + bpp > fb.len() {
+            return;
+        }
+        let (r, g, b) = ((color >> 16) as u8, (color >> 8) as u8, color as u8);
+        match (info.pixel_format, bpp) {
+            (PixelFormat::Rgb, 3 | 4) => { fb[off] = r; fb[off + 1] = g; fb[off + 2] = b; }
+            (PixelFormat::Bgr, 3 | 4) => { fb[off] = b; fb[off + 1] = g; fb[off + 2] = r; }
+            _ => {}
+        }
+    };
+
+    let chars: usize = info.width / glyph_w;
+    let mut text = s.chars();
+    for col in 0..chars {
+        let c = text.next().unwrap_or(' ');
+        let glyph = FONT_VGA8.glyph(c);
+        for (gy, bits) in glyph.iter().enumerate() {
+            for gx in 0..FONT_VGA8.width {
+                let color = if (bits >> (7 - gx)) & 1 == 1 { fg } else { 0x000000 };
+                for dy in 0..PANIC_SCALE {
+                    for dx in 0..PANIC_SCALE {
+                        let x = col * glyph_w + gx * PANIC_SCALE + dx;
+                        let y = row * glyph_h + gy * PANIC_SCALE + dy;
+                        put(fb, x, y, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Where a crash report could be shown.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CrashScreen {
+    /// Through the console as usual.
+    Console,
+    /// Straight into the framebuffer, because the console lock is held.
+    Framebuffer,
+    /// Nowhere: there is no console yet. Serial and port 0xE9 are all there is.
+    None,
+}
+
+/// Shows crash report lines by the best way left. Never waits for a lock,
+/// so panic and fault paths may call it at any point of boot.
+pub fn crash_lines(lines: &[(&str, u32)]) -> CrashScreen {
+    if PANIC_FB.get().is_none() {
+        return CrashScreen::None;
+    }
+    let shown = try_with_console(|c| {
+        for (line, fg) in lines {
+            c.cwrite_line(line, *fg, 0x000000);
+        }
+    });
+    if shown.is_some() {
+        return CrashScreen::Console;
+    }
+    for (line, fg) in lines {
+        panic_write_line(line, *fg);
+    }
+    CrashScreen::Framebuffer
+}
+
+/// Breaks the console locks. Only sound when their holder can never run again,
+/// e.g. it is the task being torn down after a fault or panic.
+pub unsafe fn force_unlock() {
+    if CONSOLE.is_locked() {
+        CONSOLE.force_unlock();
+    }
+    // Taken inside the console lock by every character written.
+    if SCROLLBACK.is_locked() {
+        SCROLLBACK.force_unlock();
+    }
+}
+
 pub fn with_console<F, R>(f: F) -> R
 where
     F: FnOnce(&mut Console) -> R,
@@ -985,15 +1915,160 @@ where
     })
 }
 
+/// Blocks task-context writers while output is paused with Ctrl+S, until Ctrl+Q.
+pub fn wait_while_paused() {
+    keyboard::pump();
+    while keyboard::output_paused() {
+        idle::idle();
+        keyboard::pump();
+    }
+}
+
+/// Writes `question` and " [y/N] " and waits for a key: y is yes; any other
+/// letter, Enter, Esc or Ctrl+C is no. Captured output (a pipe, a redirect,
+/// the serial shell) has nobody at the keyboard to answer, so it is no.
+pub fn prompt_yes_no(question: &str) -> bool {
+    if CAPTURING.load(Ordering::Relaxed) {
+        write_line(&alloc::format!("{} [y/N] n (nobody can answer while output is captured)", question));
+        return false;
+    }
+    write(question);
+    write(" [y/N] ");
+    let yes = loop {
+        match keyboard::poll_event() {
+            Some(KeyEvent::Char(c)) => break c.eq_ignore_ascii_case(&'y'),
+            Some(KeyEvent::Enter | KeyEvent::Escape) => break false,
+            Some(_) => {}
+            None if keyboard::cancel_requested() => break false,
+            None => idle::idle(),
+        }
+    };
+    write_line(if yes { "y" } else { "n" });
+    yes
+}
+
 pub fn write_line(s: &str) {
+    wait_while_paused();
     with_console(|c| c.write_line(s));
 }
 
+// Word wrapping for prose: help text and anything shown through the pager.
+// Plain write_line still breaks at the right edge wherever it lands.
+static WORD_WRAP: AtomicBool = AtomicBool::new(true);
+
+pub fn set_word_wrap(on: bool) {
+    WORD_WRAP.store(on, Ordering::Relaxed);
+}
+
+pub fn word_wrap() -> bool {
+    WORD_WRAP.load(Ordering::Relaxed)
+}
+
+/// Splits each line of `s` into pieces of at most `width` characters,
+/// breaking at spaces. Continuation lines are indented two past the line's
+/// own indent; runs of spaces inside a piece are kept so columns line up.
+pub fn wrap_words(s: &str, width: usize) -> Vec<String> {
+    let width = width.max(8);
+    let mut out = Vec::new();
+    for line in s.split('\n') {
+        if line.chars().count() <= width {
+            out.push(String::from(line));
+            continue;
+        }
+        let indent = line.chars().take_while(|&c| c == ' ').count().min(width / 2);
+        let hang = (indent + 2).min(width / 2);
+        let mut cur: String = core::iter::repeat_n(' ', indent).collect();
+        let mut cur_len = indent;
+        let mut fresh = true;
+        let mut rest = line.trim_start_matches(' ');
+        while !rest.is_empty() {
+            let word_at = rest.find(|c| c != ' ').unwrap_or(rest.len());
+            let gap = if fresh { 0 } else { word_at };
+            rest = &rest[word_at..];
+            let word_end = rest.find(' ').unwrap_or(rest.len());
+            let mut word = &rest[..word_end];
+            rest = &rest[word_end..];
+            if word.is_empty() {
+                break;
+            }
+            let mut word_len = word.chars().count();
+            let gap = if !fresh && cur_len + gap + word_len > width {
+                out.push(core::mem::take(&mut cur));
+                cur.extend(core::iter::repeat_n(' ', hang));
+                cur_len = hang;
+                0
+            } else {
+                gap
+            };
+            // Longer than a whole line: hard-break it.
+            while cur_len + gap + word_len > width {
+                let fit = width - cur_len;
+                let split = word.char_indices().nth(fit).map_or(word.len(), |(i, _)| i);
+                cur.push_str(&word[..split]);
+                out.push(core::mem::take(&mut cur));
+                cur.extend(core::iter::repeat_n(' ', hang));
+                cur_len = hang;
+                word = &word[split..];
+                word_len -= fit;
+            }
+            cur.extend(core::iter::repeat_n(' ', gap));
+            cur.push_str(word);
+            cur_len += gap + word_len;
+            fresh = false;
+        }
+        out.push(cur);
+    }
+    out
+}
+
+/// write_line for prose: wrapped at words to the console width while word
+/// wrap is on.
+pub fn write_wrapped(s: &str) {
+    if !word_wrap() {
+        write_line(s);
+        return;
+    }
+    let (cols, _) = size_chars();
+    for line in wrap_words(s, cols) {
+        write_line(&line);
+    }
+}
+
+/// Like `with_console`, but gives up instead of spinning if the console is
+/// already locked (e.g. a fault inside console code). Used by fault paths,
+/// which may never see another tick, so the output is presented right away.
+pub fn try_with_console<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut Console) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut guard = CONSOLE.try_lock()?;
+        guard.as_mut().map(|c| {
+            let r = f(c);
+            c.flush();
+            r
+        })
+    })
+}
+
 pub fn clear_screen() {
     with_console(|c| c.clear());
 }
 
+pub fn save_position() {
+    with_console(|c| c.save_position());
+}
+
+pub fn restore_position() -> bool {
+    with_console(|c| c.restore_position())
+}
+
+pub fn clear_to_end() {
+    with_console(|c| c.clear_to_end());
+}
+
 pub fn cwrite_line(s: &str, fg: u32, bg: u32) {
+    wait_while_paused();
     with_console(|c| c.cwrite_line(s, fg, bg));
 }
 
@@ -1002,10 +2077,12 @@ pub fn cput_char(c: char, fg: u32, bg: u32) {
 }
 
 pub fn cwrite(s: &str, fg: u32, bg: u32) {
+    wait_while_paused();
     with_console(|c| c.cwrite(s, fg, bg));
 }
 
 pub fn write(s: &str) {
+    wait_while_paused();
     with_console(|c| c.write(s));
 }
 
@@ -1045,6 +2122,43 @@ pub fn default_colors() -> (u32, u32) {
     with_console(|c| c.default_colors())
 }
 
+/// Applies and records the accessibility modes in settings.
+pub fn set_accessibility(filter: ColorFilter, high_contrast: bool) {
+    with_console(|c| c.set_accessibility(filter, high_contrast));
+    settings::set(SETTING_COLOR_FILTER, filter.name());
+    settings::set(SETTING_HIGH_CONTRAST, if high_contrast { "high" } else { "normal" });
+}
+
+/// False if there is no back buffer to magnify from.
+pub fn set_magnifier(on: bool) -> bool {
+    with_console(|c| c.set_magnifier(on))
+}
+
+pub fn magnifier() -> bool {
+    with_console(|c| c.magnifier())
+}
+
+pub fn set_perf_overlay(on: bool) -> bool {
+    with_console(|c| c.set_perf_overlay(on))
+}
+
+pub fn perf_overlay() -> bool {
+    with_console(|c| c.perf_overlay())
+}
+
+pub fn accessibility() -> (ColorFilter, bool) {
+    with_console(|c| c.accessibility())
+}
+
+/// Re-applies accessibility modes saved in settings.
+pub fn apply_saved_accessibility() {
+    let filter = settings::get(SETTING_COLOR_FILTER)
+        .and_then(|v| ColorFilter::parse(&v))
+        .unwrap_or(ColorFilter::Off);
+    let high_contrast = settings::get(SETTING_HIGH_CONTRAST).is_some_and(|v| v == "high");
+    with_console(|c| c.set_accessibility(filter, high_contrast));
+}
+
 pub fn default_fg() -> u32 {
     default_colors().0
 }
@@ -1063,14 +2177,20 @@ pub fn render_line_at(
     content: &str,
     prev_render_len: usize,
     cursor_offset: usize,
+    highlight: Option<(Range<usize>, u32)>,
 ) -> usize {
-    with_console(|c| c.render_line_at(origin_x, origin_y, content, prev_render_len, cursor_offset))
+    with_console(|c| c.render_line_at(origin_x, origin_y, content, prev_render_len, cursor_offset, highlight))
 }
 
 pub fn tick() {
     with_console(|c| c.tick());
 }
 
+/// None when there is no console to test.
+pub fn framebuffer_readback_test() -> Option<bool> {
+    interrupts::without_interrupts(|| CONSOLE.lock().as_mut().map(|c| c.framebuffer_readback()))
+}
+
 pub fn display_buffer_stats() -> Option<DisplayBufferStats> {
     interrupts::without_interrupts(|| {
         let lock = CONSOLE.lock();
@@ -1101,56 +2221,104 @@ fn infer_image_dims(image: &[u8], fb_w: usize, fb_h: usize) -> Option<(usize, us
 }
 
 pub fn showimage(image: &[u8], width: usize, height: usize, seconds: u64) {
-    let (snapshot_len, prev_style, prev_visible, prev_blink) = interrupts::without_interrupts(|| {
-        let mut lock = CONSOLE.lock();
-        let con = lock.as_mut().expect("Console not init");
-        let len = con.back_buffer.len();
-        if len > MAX_BACKBUFFER_BYTES {
-            return (0, con.cursor_style, con.cursor_visible, con.cursor_blink);
-        }
-        let prev_cursor_style = con.cursor_style;
-        let prev_cursor_visible = con.cursor_visible;
-        let prev_cursor_blink = con.cursor_blink;
-
-        con.erase_cursor();
-        con.cursor_style = CursorStyle::Hidden;
-        con.cursor_visible = false;
-        con.cursor_blink = CursorBlink::None;
-
-        let snap_ptr = addr_of_mut!(SNAPSHOT_STORAGE) as *mut u8;
-        unsafe { ptr::copy_nonoverlapping(con.back_buffer.as_ptr(), snap_ptr, len); }
-        let (w, h, channels) = if width.saturating_mul(height).saturating_mul(4) == image.len() {
-            (width, height, 4)
-        } else if width.saturating_mul(height).saturating_mul(3) == image.len() {
-            (width, height, 3)
-        } else {
-            infer_image_dims(image, con.info.width, con.info.height).unwrap_or((0, 0, 0))
-        };
-        if w == 0 || h == 0 || channels == 0 {
-            return (0, prev_cursor_style, prev_cursor_visible, prev_cursor_blink);
+    slideshow(&[(image, width, height)], seconds * 1000);
+}
+
+/// Shows each image full screen for `delay_ms`, then puts the screen back.
+/// A key skips to the next image; Esc or q ends the show.
+pub fn slideshow(images: &[(&[u8], usize, usize)], delay_ms: u64) {
+    let Some(mut screen) = FullScreen::begin() else { return; };
+    for &(image, width, height) in images {
+        if !screen.draw(image, width, height) {
+            continue;
         }
-        con.blit_image_scaled(image, w, h, channels);
-        (len, prev_cursor_style, prev_cursor_visible, prev_cursor_blink)
-    });
+        let key = keyboard::wait_event_timeout(delay_ms);
+        if keyboard::cancel_requested() || matches!(key, Some(KeyEvent::Escape | KeyEvent::Char('q' | 'Q'))) {
+            break;
+        }
+    }
+}
 
-    if snapshot_len == 0 {
-        return;
+/// The screen handed over to full-screen images, with the cursor hidden.
+/// Dropping it puts the text screen and cursor back.
+pub struct FullScreen {
+    snapshot_len: usize,
+    prev_style: CursorStyle,
+    prev_visible: bool,
+    prev_blink: CursorBlink,
+}
+
+impl FullScreen {
+    /// None if there is no memory for a copy of the text screen.
+    pub fn begin() -> Option<Self> {
+        interrupts::without_interrupts(|| {
+            let mut lock = CONSOLE.lock();
+            let con = lock.as_mut()?;
+            let len = con.back_buffer.len();
+            let mut snapshot = IMAGE_SNAPSHOT.lock();
+            if snapshot.as_ref().is_none_or(|s| s.len() < len) {
+                if let Some(old) = snapshot.take() {
+                    memory::free_buffer(old);
+                }
+                *snapshot = memory::alloc_buffer(len);
+            }
+            let snap = snapshot.as_mut()?;
+            let screen = FullScreen {
+                snapshot_len: len,
+                prev_style: con.cursor_style,
+                prev_visible: con.cursor_visible,
+                prev_blink: con.cursor_blink,
+            };
+
+            con.erase_cursor();
+            con.cursor_style = CursorStyle::Hidden;
+            con.cursor_visible = false;
+            con.cursor_blink = CursorBlink::None;
+
+            snap[..len].copy_from_slice(con.back_buffer);
+            Some(screen)
+        })
     }
 
-    wait::bsec(seconds);
+    /// Draws RGB or RGBA pixels centered and scaled down to fit. If the size
+    /// does not match the data, it is guessed from the screen's aspect ratio.
+    /// False if no size fits.
+    pub fn draw(&mut self, image: &[u8], width: usize, height: usize) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut lock = CONSOLE.lock();
+            let Some(con) = lock.as_mut() else { return false; };
+            let (w, h, channels) = if width.saturating_mul(height).saturating_mul(4) == image.len() {
+                (width, height, 4)
+            } else if width.saturating_mul(height).saturating_mul(3) == image.len() {
+                (width, height, 3)
+            } else {
+                infer_image_dims(image, con.info.width, con.info.height).unwrap_or((0, 0, 0))
+            };
+            if w == 0 || h == 0 || channels == 0 {
+                return false;
+            }
+            con.blit_image_scaled(image, w, h, channels);
+            true
+        })
+    }
+}
 
-    interrupts::without_interrupts(|| {
-        let mut lock = CONSOLE.lock();
-        if let Some(con) = lock.as_mut() {
-            let len = core::cmp::min(snapshot_len, con.back_buffer.len());
-            let snap_ptr = core::ptr::addr_of!(SNAPSHOT_STORAGE) as *const u8;
-            unsafe { ptr::copy_nonoverlapping(snap_ptr, con.back_buffer.as_mut_ptr(), len); }
-            con.cursor_style = prev_style;
-            con.cursor_visible = prev_visible;
-            con.cursor_blink = prev_blink;
-            con.cursor_intensity = 255;
-            con.draw_cursor();
-            con.present_full();
-        }
-    });
+impl Drop for FullScreen {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| {
+            let mut lock = CONSOLE.lock();
+            if let Some(con) = lock.as_mut() {
+                if let Some(snap) = IMAGE_SNAPSHOT.lock().as_ref() {
+                    let len = self.snapshot_len.min(con.back_buffer.len()).min(snap.len());
+                    con.back_buffer[..len].copy_from_slice(&snap[..len]);
+                }
+                con.cursor_style = self.prev_style;
+                con.cursor_visible = self.prev_visible;
+                con.cursor_blink = self.prev_blink;
+                con.cursor_intensity = 255;
+                con.draw_cursor();
+                con.present_full();
+            }
+        });
+    }
 }
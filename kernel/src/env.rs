@@ -0,0 +1,130 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use heapless::String as HString;
+use spin::Mutex;
+use crate::args::{req, Kind, Spec};
+use crate::{commands, console};
+
+// Shell variables. Each command of a line expands `$NAME` and `${NAME}`
+// as it splits into words, just before it runs, so `$?` sees the command
+// before it. A value is always plain text inside one word: its spaces,
+// quotes and `&&` are never parsed again. Nothing is expanded inside single
+// quotes or after a backslash; `$?` is the last exit status. Unset names
+// expand to nothing.
+
+const NAME_LEN: usize = 32;
+const MAX_VARS: usize = 64;
+const VALUE_LEN: usize = 128;
+
+static VARS: Mutex<Vec<(HString<NAME_LEN>, String)>> = Mutex::new(Vec::new());
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.len() <= NAME_LEN
+}
+
+pub fn set(name: &str, value: &str) -> Result<(), &'static str> {
+    if !valid_name(name) {
+        return Err("names are letters, digits and _, not starting with a digit");
+    }
+    if value.len() > VALUE_LEN {
+        return Err("value too long");
+    }
+    let mut vars = VARS.lock();
+    if let Some((_, v)) = vars.iter_mut().find(|(n, _)| n == name) {
+        *v = String::from(value);
+        return Ok(());
+    }
+    if vars.len() >= MAX_VARS {
+        return Err("too many variables");
+    }
+    let mut n = HString::new();
+    let _ = n.push_str(name);
+    vars.push((n, String::from(value)));
+    Ok(())
+}
+
+/// Whether the variable table's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    VARS.is_locked()
+}
+
+pub fn get(name: &str) -> Option<String> {
+    VARS.lock().iter().find(|(n, _)| n == name).map(|(_, v)| v.clone())
+}
+
+pub fn unset(name: &str) -> bool {
+    let mut vars = VARS.lock();
+    let before = vars.len();
+    vars.retain(|(n, _)| n != name);
+    vars.len() != before
+}
+
+/// The value of the variable named at the start of `rest`, the text just
+/// after a `$`, and how many bytes of `rest` the name took. None if no name
+/// follows, so the `$` stays as typed.
+pub fn lookup(rest: &str) -> Option<(String, usize)> {
+    let (name, used) = if rest.starts_with('?') {
+        ("?", 1)
+    } else if let Some(body) = rest.strip_prefix('{') {
+        let end = body.find('}')?;
+        (&body[..end], end + 2)
+    } else {
+        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        (&rest[..end], end)
+    };
+    if name.is_empty() {
+        return None;
+    }
+    let value = if name == "?" {
+        format!("{}", commands::last_status())
+    } else {
+        get(name).unwrap_or_default()
+    };
+    Some((value, used))
+}
+
+pub static SET_ARGS: Spec = Spec { command: "set", args: &[req("name", Kind::Word), req("value", Kind::Rest)] };
+pub static UNSET_ARGS: Spec = Spec { command: "unset", args: &[req("name", Kind::Word)] };
+
+pub fn set_cmd(args: &[&str]) -> commands::Status {
+    let a = SET_ARGS.check(args)?;
+    let name = a.word(0).unwrap_or("");
+    let mut value = String::new();
+    for (i, word) in a.rest(1).iter().enumerate() {
+        if i > 0 {
+            value.push(' ');
+        }
+        value.push_str(word);
+    }
+    if let Err(msg) = set(name, &value) {
+        console::write_err(&format!("set: {}", msg));
+        return Err(1);
+    }
+    Ok(())
+}
+
+pub fn unset_cmd(args: &[&str]) -> commands::Status {
+    let a = UNSET_ARGS.check(args)?;
+    let name = a.word(0).unwrap_or("");
+    if !unset(name) {
+        console::write_err(&format!("unset: {} is not set", name));
+        return Err(1);
+    }
+    Ok(())
+}
+
+pub fn env_cmd(_args: &[&str]) -> commands::Status {
+    let vars = VARS.lock().clone();
+    if vars.is_empty() {
+        console::write_line("No variables set. Try: set NAME value");
+        return Ok(());
+    }
+    for (name, value) in vars.iter() {
+        console::write_line(&format!("{}={}", name, value));
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use heapless::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::pager::Pager;
+
+/// The RTC was read into the wall clock (boot or `os time sync`).
+pub const TIME_SYNCED: &str = "time.synced";
+/// A theme preset was applied; data is the preset name.
+pub const THEME_CHANGED: &str = "theme.changed";
+/// An app finished; data is `<id> <status>`. Subsystems holding per-app
+/// state free it here.
+pub const APP_EXITED: &str = "app.exited";
+
+/// Subscribing to this receives every topic.
+pub const ALL: &str = "*";
+
+const MAX_SUBSCRIBERS: usize = 16;
+
+pub struct Event<'a> {
+    pub topic: &'a str,
+    pub data: &'a str,
+}
+
+pub type Handler = fn(&Event);
+
+struct Subscriber {
+    topic: &'static str,
+    handler: Handler,
+}
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber, MAX_SUBSCRIBERS>> = Mutex::new(Vec::new());
+
+/// Calls `handler` for every event published on `topic` (or every topic for
+/// `ALL`). Returns false if the subscriber table is full.
+pub fn subscribe(topic: &'static str, handler: Handler) -> bool {
+    interrupts::without_interrupts(|| SUBSCRIBERS.lock().push(Subscriber { topic, handler }).is_ok())
+}
+
+/// Delivers an event synchronously on the caller's task. Handlers run
+/// outside the table lock, so they may publish or subscribe themselves, but
+/// they should only record state or flag work, not block.
+pub fn publish(topic: &str, data: &str) {
+    let handlers: Vec<Handler, MAX_SUBSCRIBERS> = interrupts::without_interrupts(|| {
+        SUBSCRIBERS
+            .lock()
+            .iter()
+            .filter(|s| s.topic == ALL || s.topic == topic)
+            .map(|s| s.handler)
+            .collect()
+    });
+    let event = Event { topic, data };
+    for handler in handlers.iter() {
+        handler(&event);
+    }
+}
+
+/// `events`: lists subscriptions by topic.
+pub fn events_cmd() {
+    let mut topics: Vec<(&'static str, usize), MAX_SUBSCRIBERS> = Vec::new();
+    interrupts::without_interrupts(|| {
+        for s in SUBSCRIBERS.lock().iter() {
+            match topics.iter_mut().find(|(t, _)| *t == s.topic) {
+                Some((_, n)) => *n += 1,
+                None => {
+                    let _ = topics.push((s.topic, 1));
+                }
+            }
+        }
+    });
+    let mut pager = Pager::new();
+    if topics.is_empty() {
+        pager.line("(no subscriptions)");
+    }
+    for (topic, n) in topics.iter() {
+        pager.line(&alloc::format!("{:<20} {} subscriber(s)", topic, n));
+    }
+    pager.show();
+}
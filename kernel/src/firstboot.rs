@@ -0,0 +1,116 @@
+use alloc::format;
+use pc_keyboard::KeyCode;
+use crate::keyboard::{self, Layout};
+use crate::{cmdline, console, hostfs, safemode, settings, testmode, time};
+
+// The first boot with a host folder but no settings file in it asks for a
+// few key presses to work out the keyboard layout, and for a time format,
+// then saves both. Without a host folder there is nowhere to save and no way
+// to tell a first boot, so nothing is asked. The `nosetup` flag skips it for
+// automated boots; safe mode and test mode never ask.
+
+/// How long each question waits before setup gives up until the next boot.
+const ANSWER_TIMEOUT_MS: u64 = 30_000;
+
+/// Why setup stopped early.
+enum Stop {
+    /// Esc: keep what was chosen so far and do not ask again.
+    Skipped,
+    /// No key in time, or Ctrl+C: probably nobody there, so ask next boot.
+    NoAnswer,
+}
+
+/// Asks until a key `pick` accepts is pressed.
+fn ask<T>(prompt: &str, pick: impl Fn(KeyCode) -> Option<T>) -> Result<T, Stop> {
+    loop {
+        console::write(prompt);
+        let key = keyboard::wait_keycode(ANSWER_TIMEOUT_MS);
+        console::write_line("");
+        match key {
+            None => return Err(Stop::NoAnswer),
+            Some(KeyCode::Escape) => return Err(Stop::Skipped),
+            Some(code) => match pick(code) {
+                Some(answer) => return Ok(answer),
+                None => console::write_line("That key does not fit any layout StratOS knows; try again."),
+            },
+        }
+    }
+}
+
+/// Key codes name places on a US keyboard, so where a few letters turn up
+/// gives the layout away: on AZERTY the key printed Q is at US A, on Dvorak
+/// at US X, and so on.
+fn guess_layout() -> Result<Layout, Stop> {
+    enum Row {
+        Qwerty,
+        Known(Layout),
+    }
+    let row = ask("Press the key labelled Q: ", |k| match k {
+        KeyCode::Q => Some(Row::Qwerty),
+        KeyCode::A => Some(Row::Known(Layout::Fr)),
+        KeyCode::X => Some(Row::Known(Layout::Dvorak)),
+        _ => None,
+    })?;
+    if let Row::Known(layout) = row {
+        return Ok(layout);
+    }
+    let row = ask("Press the key labelled Y: ", |k| match k {
+        KeyCode::Y => Some(Row::Qwerty),
+        KeyCode::Z => Some(Row::Known(Layout::De)),
+        KeyCode::O => Some(Row::Known(Layout::Colemak)),
+        _ => None,
+    })?;
+    if let Row::Known(layout) = row {
+        return Ok(layout);
+    }
+    ask("Press the key with @ on it (no need to hold Shift): ", |k| match k {
+        KeyCode::Key2 => Some(Layout::Us),
+        KeyCode::Oem3 => Some(Layout::Uk),
+        KeyCode::Oem4 => Some(Layout::Jp),
+        _ => None,
+    })
+}
+
+fn setup() -> Result<(), Stop> {
+    let layout = guess_layout()?;
+    keyboard::set_layout(layout);
+    console::write_line(&format!("Keyboard layout: {}", layout.name()));
+    let is_24 = ask("Press 1 for 12-hour time (3:00 PM) or 2 for 24-hour time (15:00): ", |k| match k {
+        KeyCode::Key1 => Some(false),
+        KeyCode::Key2 => Some(true),
+        _ => None,
+    })?;
+    time::set_24h(is_24);
+    console::write_line(if is_24 { "Time format: 24-hour" } else { "Time format: 12-hour" });
+    Ok(())
+}
+
+/// Runs setup if this looks like a first boot. `settings_found` is whether
+/// `settings::load` read a settings file.
+pub fn run(settings_found: bool) {
+    if settings_found
+        || !hostfs::attached()
+        || cmdline::has_flag("nosetup")
+        || safemode::active()
+        || testmode::enabled()
+        || !keyboard::ps2_status().keyboard
+    {
+        return;
+    }
+    console::write_line("First boot: a few key presses to set up the keyboard and clock.");
+    console::write_line("Esc skips; `kbd layout` and `os time` change these later.");
+    match setup() {
+        Ok(()) => {}
+        Err(Stop::Skipped) => console::write_line("Setup skipped."),
+        Err(Stop::NoAnswer) => {
+            console::write_line("No answer; setup will ask again next boot.");
+            return;
+        }
+    }
+    if settings::save() {
+        console::write_line("Settings saved to the host folder.");
+    } else {
+        console::write_err("setup: could not save settings to the host folder");
+    }
+    console::write_line("");
+}
@@ -0,0 +1,31 @@
+use crate::{console, rng};
+
+const QUOTE_COLOR: u32 = 0xFFD37F;
+const AUTHOR_COLOR: u32 = 0x7FB8FF;
+
+const FORTUNES: &[(&str, &str)] = &[
+    ("Simplicity is prerequisite for reliability.", "Edsger W. Dijkstra"),
+    ("Premature optimization is the root of all evil.", "Donald Knuth"),
+    ("Talk is cheap. Show me the code.", "Linus Torvalds"),
+    ("There are two ways of constructing a software design: one way is to make it so simple that there are obviously no deficiencies.", "C. A. R. Hoare"),
+    ("Any sufficiently advanced technology is indistinguishable from magic.", "Arthur C. Clarke"),
+    ("The best way to predict the future is to invent it.", "Alan Kay"),
+    ("Programs must be written for people to read, and only incidentally for machines to execute.", "Harold Abelson"),
+    ("It works on my machine.", "Every developer, eventually"),
+    ("UNIX is simple. It just takes a genius to understand its simplicity.", "Dennis Ritchie"),
+    ("First, solve the problem. Then, write the code.", "John Johnson"),
+    ("Weeks of coding can save you hours of planning.", "Unknown"),
+    ("A triple fault is just the CPU's way of saying 'let's start over'.", "StratOS boot log"),
+    ("Deleted code is debugged code.", "Jeff Sickel"),
+    ("Make it work, make it right, make it fast.", "Kent Beck"),
+    ("The cheapest, fastest, and most reliable components are those that aren't there.", "Gordon Bell"),
+    ("In theory there is no difference between theory and practice. In practice there is.", "Jan L. A. van de Snepscheut"),
+];
+
+pub fn fortune_cmd() {
+    let idx = rng::below(FORTUNES.len() as u64) as usize;
+    let (quote, author) = FORTUNES[idx];
+    let bg = console::default_bg();
+    console::cwrite_line(quote, QUOTE_COLOR, bg);
+    console::cwrite_line(&alloc::format!("    -- {}", author), AUTHOR_COLOR, bg);
+}
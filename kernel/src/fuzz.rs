@@ -0,0 +1,79 @@
+use alloc::format;
+use alloc::string::String;
+use heapless::Vec;
+use spin::Mutex;
+use crate::args::{req, Kind, Spec};
+use crate::{commands, console, glyphs, keyboard, rng, serial};
+
+// `fuzzparse` throws random command lines at the parser. A panic kills the
+// shell task, so the line being parsed is kept here and reported by
+// `on_shell_restart` once the supervisor has brought the shell back.
+
+const MAX_LEN: usize = 192;
+/// Bytes the parser treats specially, picked more often than chance would.
+const SPECIAL: &[u8] = b" \t'\"\\&|>$?{}u#";
+/// Start of a few multi-byte UTF-8 sequences, some left unfinished.
+const ODD: &[&[u8]] = &[b"\xC3\xA9", b"\xE2\x94\x80", b"\xF0\x9F\x98\x80", b"\xE2", b"\xFF", b"\\u{2500}", b"&&", b"||", b">>"];
+
+static CASE: Mutex<Option<Vec<u8, MAX_LEN>>> = Mutex::new(None);
+
+pub static FUZZPARSE_ARGS: Spec = Spec {
+    command: "fuzzparse",
+    args: &[req("iterations", Kind::Int { min: 1, max: 10_000_000 })],
+};
+
+fn random_case() -> Vec<u8, MAX_LEN> {
+    let mut case = Vec::new();
+    let len = rng::below(MAX_LEN as u64) as usize;
+    while case.len() < len {
+        let bytes: &[u8] = match rng::below(8) {
+            0..=2 => &[b'a' + rng::below(26) as u8],
+            3..=5 => core::slice::from_ref(&SPECIAL[rng::below(SPECIAL.len() as u64) as usize]),
+            6 => ODD[rng::below(ODD.len() as u64) as usize],
+            _ => &[rng::next_u64() as u8],
+        };
+        if case.extend_from_slice(bytes).is_err() {
+            break;
+        }
+    }
+    case
+}
+
+/// Shows a case with anything unprintable as \xNN, so it can be retyped.
+fn printable(case: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in case {
+        if b.is_ascii_graphic() || b == b' ' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("\\x{:02X}", b));
+        }
+    }
+    out
+}
+
+pub fn fuzzparse_cmd(args: &[&str]) -> commands::Status {
+    let a = FUZZPARSE_ARGS.check(args)?;
+    let iterations = a.int(0).unwrap_or(1);
+    let mut segments = 0;
+    let mut done = 0;
+    while done < iterations && !keyboard::cancel_requested() {
+        done += 1;
+        let case = random_case();
+        *CASE.lock() = Some(case.clone());
+        let line = String::from_utf8_lossy(&case);
+        segments += commands::parse_dry_run(&line);
+        let _ = glyphs::unescape(&line);
+        CASE.lock().take();
+    }
+    console::write_line(&format!("fuzzparse: {} lines, {} segments, no panics.", done, segments));
+    Ok(())
+}
+
+/// Reports the line that was being parsed if the shell died mid-run.
+pub fn on_shell_restart() {
+    let Some(case) = CASE.lock().take() else { return; };
+    let shown = printable(&case);
+    serial::write(&format!("fuzzparse: crashing input: {}", shown));
+    console::write_err(&format!("fuzzparse: the shell died parsing: {}", shown));
+}
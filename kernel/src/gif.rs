@@ -0,0 +1,458 @@
+use alloc::boxed::Box;
+use alloc::format;
+use crate::{console, keyboard, memory, ramfs, timer};
+
+// GIF playback. The decoder handles GIF87a and GIF89a with global and local
+// color tables, transparency and all four disposal methods, but not
+// interlaced frames. Frames are composed onto a canvas the size of the
+// logical screen, which `play` hands to the console one frame at a time.
+
+const MAX_SIDE: usize = 4096;
+const MAX_CODES: usize = 4096;
+/// What browsers use for frames that ask for no delay at all.
+const DEFAULT_DELAY_CS: u16 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Disposal {
+    Keep,
+    Background,
+    Previous,
+}
+
+/// Frame buffer memory, given back on drop.
+struct Buffer(&'static mut [u8]);
+
+impl Buffer {
+    fn new(len: usize) -> Result<Self, &'static str> {
+        memory::alloc_buffer(len).map(Buffer).ok_or("not enough memory")
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        memory::free_buffer(core::mem::take(&mut self.0));
+    }
+}
+
+/// LZW string table. A code's string is its prefix code's string followed
+/// by its suffix byte; `first` caches the string's first byte.
+struct Lzw {
+    prefix: [u16; MAX_CODES],
+    suffix: [u8; MAX_CODES],
+    first: [u8; MAX_CODES],
+    stack: [u8; MAX_CODES],
+}
+
+/// Reads codes LSB-first from a chain of data sub-blocks.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    block_left: usize,
+    acc: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn read(&mut self, size: u32) -> Option<u16> {
+        while self.count < size {
+            if self.block_left == 0 {
+                self.block_left = *self.data.get(self.pos)? as usize;
+                self.pos += 1;
+                if self.block_left == 0 {
+                    return None;
+                }
+            }
+            self.acc |= (*self.data.get(self.pos)? as u32) << self.count;
+            self.pos += 1;
+            self.block_left -= 1;
+            self.count += 8;
+        }
+        let code = (self.acc & ((1 << size) - 1)) as u16;
+        self.acc >>= size;
+        self.count -= size;
+        Some(code)
+    }
+}
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    first_frame: usize,
+    pub width: usize,
+    pub height: usize,
+    global: Option<(usize, usize)>,
+    background: [u8; 3],
+    /// Times to play the animation; 0 is forever. None without a NETSCAPE
+    /// extension, which means once.
+    pub loops: Option<u16>,
+    canvas: Buffer,
+    indices: Buffer,
+    saved: Option<Buffer>,
+    pending: Disposal,
+    last_rect: (usize, usize, usize, usize),
+    lzw: Box<Lzw>,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, &'static str> {
+        if !data.starts_with(b"GIF87a") && !data.starts_with(b"GIF89a") {
+            return Err("not a GIF file");
+        }
+        let header = data.get(6..13).ok_or("truncated header")?;
+        let width = u16::from_le_bytes([header[0], header[1]]) as usize;
+        let height = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let flags = header[4];
+        if width == 0 || height == 0 || width > MAX_SIDE || height > MAX_SIDE {
+            return Err("bad screen size");
+        }
+        let mut pos = 13;
+        let mut global = None;
+        if flags & 0x80 != 0 {
+            let len = 3 << ((flags & 7) + 1);
+            data.get(pos..pos + len).ok_or("truncated color table")?;
+            global = Some((pos, len / 3));
+            pos += len;
+        }
+        let mut background = [0; 3];
+        if let Some((table, colors)) = global {
+            let index = header[5] as usize;
+            if index < colors {
+                background.copy_from_slice(&data[table + index * 3..table + index * 3 + 3]);
+            }
+        }
+        let mut decoder = Decoder {
+            data,
+            pos,
+            first_frame: pos,
+            width,
+            height,
+            global,
+            background,
+            loops: None,
+            canvas: Buffer::new(width * height * 3)?,
+            indices: Buffer::new(width * height)?,
+            saved: None,
+            pending: Disposal::Keep,
+            last_rect: (0, 0, 0, 0),
+            lzw: Box::new(Lzw {
+                prefix: [0; MAX_CODES],
+                suffix: [0; MAX_CODES],
+                first: [0; MAX_CODES],
+                stack: [0; MAX_CODES],
+            }),
+        };
+        decoder.rewind();
+        Ok(decoder)
+    }
+
+    /// The composed frame as RGB pixels, `width * height * 3` bytes.
+    pub fn canvas(&self) -> &[u8] {
+        &self.canvas.0[..self.width * self.height * 3]
+    }
+
+    /// Goes back to the first frame with a cleared canvas.
+    pub fn rewind(&mut self) {
+        self.pos = self.first_frame;
+        self.pending = Disposal::Keep;
+        let bg = self.background;
+        for px in self.canvas.0[..self.width * self.height * 3].chunks_exact_mut(3) {
+            px.copy_from_slice(&bg);
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        let b = *self.data.get(self.pos).ok_or("unexpected end of file")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let data = self.data;
+        let out = data.get(self.pos..self.pos + len).ok_or("unexpected end of file")?;
+        self.pos += len;
+        Ok(out)
+    }
+
+    fn skip_sub_blocks(&mut self) -> Result<(), &'static str> {
+        loop {
+            let len = self.byte()? as usize;
+            if len == 0 {
+                return Ok(());
+            }
+            self.bytes(len)?;
+        }
+    }
+
+    /// Undoes the last frame as its disposal method asks.
+    fn dispose(&mut self) {
+        let (x, y, w, h) = self.last_rect;
+        match self.pending {
+            Disposal::Keep => {}
+            Disposal::Background => {
+                let bg = self.background;
+                for row in y..y + h {
+                    let start = (row * self.width + x) * 3;
+                    for px in self.canvas.0[start..start + w * 3].chunks_exact_mut(3) {
+                        px.copy_from_slice(&bg);
+                    }
+                }
+            }
+            Disposal::Previous => {
+                if let Some(saved) = &self.saved {
+                    let len = self.width * self.height * 3;
+                    self.canvas.0[..len].copy_from_slice(&saved.0[..len]);
+                }
+            }
+        }
+        self.pending = Disposal::Keep;
+    }
+
+    /// Composes the next frame onto the canvas and returns its delay in
+    /// hundredths of a second, or None after the last frame.
+    pub fn next_frame(&mut self) -> Result<Option<u16>, &'static str> {
+        self.dispose();
+        let mut delay = 0;
+        let mut transparent = None;
+        let mut disposal = Disposal::Keep;
+        loop {
+            match self.byte()? {
+                0x3B => return Ok(None),
+                0x21 => match self.byte()? {
+                    0xF9 => {
+                        let block = self.bytes(6)?;
+                        if block[0] != 4 {
+                            return Err("bad graphic control block");
+                        }
+                        disposal = match (block[1] >> 2) & 7 {
+                            2 => Disposal::Background,
+                            3 => Disposal::Previous,
+                            _ => Disposal::Keep,
+                        };
+                        delay = u16::from_le_bytes([block[2], block[3]]);
+                        transparent = (block[1] & 1 != 0).then_some(block[4]);
+                    }
+                    0xFF => {
+                        let len = self.byte()? as usize;
+                        let id = self.bytes(len)?;
+                        if id == b"NETSCAPE2.0" {
+                            let sub = self.bytes(4)?;
+                            if sub[0] == 3 && sub[1] == 1 {
+                                self.loops = Some(u16::from_le_bytes([sub[2], sub[3]]));
+                            }
+                        }
+                        self.skip_sub_blocks()?;
+                        continue;
+                    }
+                    _ => {
+                        self.skip_sub_blocks()?;
+                        continue;
+                    }
+                },
+                0x2C => {
+                    self.draw_image(transparent, disposal)?;
+                    return Ok(Some(if delay == 0 { DEFAULT_DELAY_CS } else { delay }));
+                }
+                _ => return Err("corrupt block"),
+            }
+        }
+    }
+
+    fn draw_image(&mut self, transparent: Option<u8>, disposal: Disposal) -> Result<(), &'static str> {
+        let desc = self.bytes(9)?;
+        let x = u16::from_le_bytes([desc[0], desc[1]]) as usize;
+        let y = u16::from_le_bytes([desc[2], desc[3]]) as usize;
+        let w = u16::from_le_bytes([desc[4], desc[5]]) as usize;
+        let h = u16::from_le_bytes([desc[6], desc[7]]) as usize;
+        let flags = desc[8];
+        if flags & 0x40 != 0 {
+            return Err("interlaced GIFs are not supported");
+        }
+        let table = if flags & 0x80 != 0 {
+            let len = 3 << ((flags & 7) + 1);
+            let at = self.pos;
+            self.bytes(len)?;
+            (at, len / 3)
+        } else {
+            self.global.ok_or("no color table")?
+        };
+        let min_size = self.byte()? as u32;
+        if !(2..=8).contains(&min_size) {
+            return Err("bad LZW code size");
+        }
+        let pixels = w * h;
+        if pixels > self.width * self.height {
+            return Err("frame larger than the screen");
+        }
+        let decoded = self.decode_lzw(min_size, pixels)?;
+        self.skip_sub_blocks()?;
+
+        if disposal == Disposal::Previous {
+            if self.saved.is_none() {
+                self.saved = Some(Buffer::new(self.width * self.height * 3)?);
+            }
+            let len = self.width * self.height * 3;
+            if let Some(saved) = &mut self.saved {
+                saved.0[..len].copy_from_slice(&self.canvas.0[..len]);
+            }
+        }
+        // Frames may stick out of the logical screen; those pixels are dropped.
+        let (table_at, colors) = table;
+        let palette = &self.data[table_at..table_at + colors * 3];
+        for row in 0..h.min(self.height.saturating_sub(y)) {
+            for col in 0..w.min(self.width.saturating_sub(x)) {
+                let i = row * w + col;
+                if i >= decoded {
+                    break;
+                }
+                let index = self.indices.0[i];
+                if Some(index) == transparent || index as usize >= colors {
+                    continue;
+                }
+                let out = ((y + row) * self.width + x + col) * 3;
+                let color = index as usize * 3;
+                self.canvas.0[out..out + 3].copy_from_slice(&palette[color..color + 3]);
+            }
+        }
+        self.pending = disposal;
+        self.last_rect = (
+            x.min(self.width),
+            y.min(self.height),
+            w.min(self.width.saturating_sub(x)),
+            h.min(self.height.saturating_sub(y)),
+        );
+        Ok(())
+    }
+
+    /// Decodes up to `pixels` color indices into `indices`, returning how
+    /// many there were. Leaves `pos` somewhere inside the image data, which
+    /// the caller skips.
+    fn decode_lzw(&mut self, min_size: u32, pixels: usize) -> Result<usize, &'static str> {
+        let start = self.pos;
+        let clear = 1u16 << min_size;
+        let end = clear + 1;
+        let lzw = &mut *self.lzw;
+        for code in 0..clear {
+            lzw.suffix[code as usize] = code as u8;
+            lzw.first[code as usize] = code as u8;
+        }
+        let out = &mut self.indices.0[..pixels];
+        let mut bits = Bits { data: self.data, pos: start, block_left: 0, acc: 0, count: 0 };
+        let mut size = min_size + 1;
+        let mut next = end + 1;
+        let mut prev: Option<u16> = None;
+        let mut written = 0;
+        while written < pixels {
+            let Some(code) = bits.read(size) else { break; };
+            if code == clear {
+                size = min_size + 1;
+                next = end + 1;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let emit = if code < next {
+                if let Some(p) = prev {
+                    if (next as usize) < MAX_CODES {
+                        lzw.prefix[next as usize] = p;
+                        lzw.suffix[next as usize] = lzw.first[code as usize];
+                        lzw.first[next as usize] = lzw.first[p as usize];
+                        next += 1;
+                    }
+                }
+                code
+            } else if code == next && (next as usize) < MAX_CODES {
+                let p = prev.ok_or("corrupt image data")?;
+                lzw.prefix[next as usize] = p;
+                lzw.suffix[next as usize] = lzw.first[p as usize];
+                lzw.first[next as usize] = lzw.first[p as usize];
+                next += 1;
+                code
+            } else {
+                return Err("corrupt image data");
+            };
+            // Walk the string back to front onto the stack, then copy it out.
+            let mut depth = 0;
+            let mut c = emit;
+            while c > end {
+                lzw.stack[depth] = lzw.suffix[c as usize];
+                depth += 1;
+                c = lzw.prefix[c as usize];
+            }
+            lzw.stack[depth] = lzw.suffix[c as usize];
+            depth += 1;
+            for &b in lzw.stack[..depth].iter().rev() {
+                if written == pixels {
+                    break;
+                }
+                out[written] = b;
+                written += 1;
+            }
+            if next as usize == 1 << size && size < 12 {
+                size += 1;
+            }
+            prev = Some(code);
+        }
+        self.pos = start;
+        Ok(written)
+    }
+}
+
+/// `play <file>`: plays a GIF from a RAM file at its own frame delays,
+/// looping as the file asks. Any key stops it.
+pub fn play_cmd(args: &[&str]) -> crate::commands::Status {
+    let name = args[0];
+    let Some(data) = ramfs::read(name) else {
+        console::write_err(&format!("play: no file {}", name));
+        return Err(1);
+    };
+    let mut decoder = match Decoder::new(&data) {
+        Ok(d) => d,
+        Err(msg) => {
+            console::write_err(&format!("play: {}: {}", name, msg));
+            return Err(1);
+        }
+    };
+    let Some(mut screen) = console::FullScreen::begin() else {
+        console::write_err("play: not enough memory");
+        return Err(1);
+    };
+    let mut played = 0u32;
+    let error = 'show: loop {
+        let mut frames = 0;
+        // Deadlines run from the previous frame's, so decode and draw time
+        // does not add up over a long animation.
+        let mut deadline = timer::ticks();
+        loop {
+            let delay = match decoder.next_frame() {
+                Ok(Some(delay)) => delay,
+                Ok(None) => break,
+                Err(msg) => break 'show Some(msg),
+            };
+            frames += 1;
+            screen.draw(decoder.canvas(), decoder.width, decoder.height);
+            deadline += delay as u64 * timer::frequency() as u64 / 100;
+            let left = deadline.saturating_sub(timer::ticks()) * 1000 / timer::frequency() as u64;
+            if keyboard::wait_event_timeout(left).is_some() || keyboard::cancel_requested() {
+                break 'show None;
+            }
+        }
+        played += 1;
+        if frames == 0 {
+            break Some("no frames");
+        }
+        match decoder.loops {
+            Some(0) => {}
+            Some(n) if played <= n as u32 => {}
+            _ => break None,
+        }
+        decoder.rewind();
+    };
+    drop(screen);
+    if let Some(msg) = error {
+        console::write_err(&format!("play: {}: {}", name, msg));
+        return Err(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use alloc::string::String;
+
+// Glyphs beyond printable ASCII. The bitmap fonts stop at 0x7E, so box
+// drawing, block elements and a few symbols live here instead: box drawing
+// is built from line weights at compile time for both cell heights, the rest
+// are 8-row bitmaps that get each row doubled in the 16-row fonts.
+
+const NONE: u8 = 0;
+const LIGHT: u8 = 1;
+const HEAVY: u8 = 2;
+const DOUBLE: u8 = 3;
+
+/// Column of a light vertical line.
+const MID_COL: usize = 3;
+
+/// Line weight of each arm: up, down, left, right.
+const BOX: [(char, [u8; 4]); 48] = [
+    ('\u{2500}', [NONE, NONE, LIGHT, LIGHT]),
+    ('\u{2501}', [NONE, NONE, HEAVY, HEAVY]),
+    ('\u{2502}', [LIGHT, LIGHT, NONE, NONE]),
+    ('\u{2503}', [HEAVY, HEAVY, NONE, NONE]),
+    ('\u{250C}', [NONE, LIGHT, NONE, LIGHT]),
+    ('\u{250F}', [NONE, HEAVY, NONE, HEAVY]),
+    ('\u{2510}', [NONE, LIGHT, LIGHT, NONE]),
+    ('\u{2513}', [NONE, HEAVY, HEAVY, NONE]),
+    ('\u{2514}', [LIGHT, NONE, NONE, LIGHT]),
+    ('\u{2517}', [HEAVY, NONE, NONE, HEAVY]),
+    ('\u{2518}', [LIGHT, NONE, LIGHT, NONE]),
+    ('\u{251B}', [HEAVY, NONE, HEAVY, NONE]),
+    ('\u{251C}', [LIGHT, LIGHT, NONE, LIGHT]),
+    ('\u{2523}', [HEAVY, HEAVY, NONE, HEAVY]),
+    ('\u{2524}', [LIGHT, LIGHT, LIGHT, NONE]),
+    ('\u{252B}', [HEAVY, HEAVY, HEAVY, NONE]),
+    ('\u{252C}', [NONE, LIGHT, LIGHT, LIGHT]),
+    ('\u{2533}', [NONE, HEAVY, HEAVY, HEAVY]),
+    ('\u{2534}', [LIGHT, NONE, LIGHT, LIGHT]),
+    ('\u{253B}', [HEAVY, NONE, HEAVY, HEAVY]),
+    ('\u{253C}', [LIGHT, LIGHT, LIGHT, LIGHT]),
+    ('\u{254B}', [HEAVY, HEAVY, HEAVY, HEAVY]),
+    ('\u{2550}', [NONE, NONE, DOUBLE, DOUBLE]),
+    ('\u{2551}', [DOUBLE, DOUBLE, NONE, NONE]),
+    ('\u{2554}', [NONE, DOUBLE, NONE, DOUBLE]),
+    ('\u{2557}', [NONE, DOUBLE, DOUBLE, NONE]),
+    ('\u{255A}', [DOUBLE, NONE, NONE, DOUBLE]),
+    ('\u{255D}', [DOUBLE, NONE, DOUBLE, NONE]),
+    ('\u{2560}', [DOUBLE, DOUBLE, NONE, DOUBLE]),
+    ('\u{2563}', [DOUBLE, DOUBLE, DOUBLE, NONE]),
+    ('\u{2566}', [NONE, DOUBLE, DOUBLE, DOUBLE]),
+    ('\u{2569}', [DOUBLE, NONE, DOUBLE, DOUBLE]),
+    ('\u{256C}', [DOUBLE, DOUBLE, DOUBLE, DOUBLE]),
+    // Rounded corners are drawn square; the cells are too small to tell.
+    ('\u{256D}', [NONE, LIGHT, NONE, LIGHT]),
+    ('\u{256E}', [NONE, LIGHT, LIGHT, NONE]),
+    ('\u{256F}', [LIGHT, NONE, LIGHT, NONE]),
+    ('\u{2570}', [LIGHT, NONE, NONE, LIGHT]),
+    ('\u{2574}', [NONE, NONE, LIGHT, NONE]),
+    ('\u{2575}', [LIGHT, NONE, NONE, NONE]),
+    ('\u{2576}', [NONE, NONE, NONE, LIGHT]),
+    ('\u{2577}', [NONE, LIGHT, NONE, NONE]),
+    ('\u{2578}', [NONE, NONE, HEAVY, NONE]),
+    ('\u{2579}', [HEAVY, NONE, NONE, NONE]),
+    ('\u{257A}', [NONE, NONE, NONE, HEAVY]),
+    ('\u{257B}', [NONE, HEAVY, NONE, NONE]),
+    ('\u{257C}', [NONE, NONE, LIGHT, HEAVY]),
+    ('\u{257D}', [LIGHT, HEAVY, NONE, NONE]),
+    ('\u{257E}', [NONE, NONE, HEAVY, LIGHT]),
+];
+
+const SHAPES: [(char, [u8; 8]); 25] = [
+    ('\u{00B0}', [0x38, 0x6C, 0x38, 0x00, 0x00, 0x00, 0x00, 0x00]), // °
+    ('\u{00B7}', [0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00]), // ·
+    ('\u{00D7}', [0x00, 0x66, 0x3C, 0x18, 0x3C, 0x66, 0x00, 0x00]), // ×
+    ('\u{2022}', [0x00, 0x00, 0x18, 0x3C, 0x3C, 0x18, 0x00, 0x00]), // •
+    ('\u{2190}', [0x00, 0x10, 0x30, 0x7E, 0x30, 0x10, 0x00, 0x00]), // ←
+    ('\u{2191}', [0x18, 0x3C, 0x7E, 0x18, 0x18, 0x18, 0x18, 0x00]), // ↑
+    ('\u{2192}', [0x00, 0x08, 0x0C, 0x7E, 0x0C, 0x08, 0x00, 0x00]), // →
+    ('\u{2193}', [0x18, 0x18, 0x18, 0x18, 0x7E, 0x3C, 0x18, 0x00]), // ↓
+    ('\u{2580}', [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00]), // ▀
+    ('\u{2584}', [0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]), // ▄
+    ('\u{2588}', [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]), // █
+    ('\u{258C}', [0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0]), // ▌
+    ('\u{2590}', [0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F]), // ▐
+    ('\u{2591}', [0x88, 0x22, 0x88, 0x22, 0x88, 0x22, 0x88, 0x22]), // ░
+    ('\u{2592}', [0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55]), // ▒
+    ('\u{2593}', [0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD]), // ▓
+    ('\u{25A0}', [0x00, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x00]), // ■
+    ('\u{25A1}', [0x00, 0x7E, 0x42, 0x42, 0x42, 0x42, 0x7E, 0x00]), // □
+    ('\u{25B2}', [0x00, 0x18, 0x18, 0x3C, 0x3C, 0x7E, 0x7E, 0x00]), // ▲
+    ('\u{25BA}', [0x00, 0x60, 0x78, 0x7E, 0x78, 0x60, 0x00, 0x00]), // ►
+    ('\u{25BC}', [0x00, 0x7E, 0x7E, 0x3C, 0x3C, 0x18, 0x18, 0x00]), // ▼
+    ('\u{25C4}', [0x00, 0x06, 0x1E, 0x7E, 0x1E, 0x06, 0x00, 0x00]), // ◄
+    ('\u{25C6}', [0x00, 0x18, 0x3C, 0x7E, 0x3C, 0x18, 0x00, 0x00]), // ◆
+    ('\u{25CF}', [0x00, 0x3C, 0x7E, 0x7E, 0x7E, 0x7E, 0x3C, 0x00]), // ●
+    ('\u{2713}', [0x00, 0x03, 0x06, 0x0C, 0xD8, 0x70, 0x20, 0x00]), // ✓
+];
+
+/// Rows or columns covered by a line of `weight` centred on `center`.
+const fn span(weight: u8, center: usize) -> (usize, usize) {
+    match weight {
+        HEAVY => (center, center + 1),
+        DOUBLE => (center - 1, center + 1),
+        _ => (center, center),
+    }
+}
+
+/// Combined span of two opposite arms, or just the centre if neither is there.
+const fn band(a: u8, b: u8, center: usize) -> (usize, usize) {
+    let (alo, ahi) = span(a, center);
+    let (blo, bhi) = span(b, center);
+    match (a, b) {
+        (NONE, NONE) => (center, center),
+        (NONE, _) => (blo, bhi),
+        (_, NONE) => (alo, ahi),
+        _ => (if alo < blo { alo } else { blo }, if ahi > bhi { ahi } else { bhi }),
+    }
+}
+
+/// Whether `pos` carries a stroke of a line of `weight`; doubles skip the middle.
+const fn on_line(weight: u8, center: usize, pos: usize) -> bool {
+    let (lo, hi) = span(weight, center);
+    weight != NONE && pos >= lo && pos <= hi && !(weight == DOUBLE && pos == center)
+}
+
+const fn box_glyph<const H: usize>(arms: [u8; 4]) -> [u8; H] {
+    let [up, down, left, right] = arms;
+    let mid = H / 2 - 1;
+    let (vlo, vhi) = band(up, down, MID_COL);
+    let (hlo, hhi) = band(left, right, mid);
+    let mut rows = [0u8; H];
+    let mut y = 0;
+    while y < H {
+        let mut x = 0;
+        while x < 8 {
+            // Each stroke of a double line stops at the near side of the
+            // crossing line when it turns that way, so corners stay open.
+            let up_end = if up == DOUBLE && (if x < MID_COL { left } else { right }) != NONE { hlo } else { hhi };
+            let down_start = if down == DOUBLE && (if x < MID_COL { left } else { right }) != NONE { hhi } else { hlo };
+            let left_end = if left == DOUBLE && (if y < mid { up } else { down }) != NONE { vlo } else { vhi };
+            let right_start = if right == DOUBLE && (if y < mid { up } else { down }) != NONE { vhi } else { vlo };
+            let set = (on_line(up, MID_COL, x) && y <= up_end)
+                || (on_line(down, MID_COL, x) && y >= down_start)
+                || (on_line(left, mid, y) && x <= left_end)
+                || (on_line(right, mid, y) && x >= right_start);
+            if set {
+                rows[y] |= 0x80 >> x;
+            }
+            x += 1;
+        }
+        y += 1;
+    }
+    rows
+}
+
+const fn box_table<const H: usize>() -> [[u8; H]; BOX.len()] {
+    let mut out = [[0u8; H]; BOX.len()];
+    let mut i = 0;
+    while i < BOX.len() {
+        out[i] = box_glyph::<H>(BOX[i].1);
+        i += 1;
+    }
+    out
+}
+
+const fn tall_table() -> [[u8; 16]; SHAPES.len()] {
+    let mut out = [[0u8; 16]; SHAPES.len()];
+    let mut i = 0;
+    while i < SHAPES.len() {
+        let mut row = 0;
+        while row < 16 {
+            out[i][row] = SHAPES[i].1[row / 2];
+            row += 1;
+        }
+        i += 1;
+    }
+    out
+}
+
+static BOX_8: [[u8; 8]; BOX.len()] = box_table::<8>();
+static BOX_16: [[u8; 16]; BOX.len()] = box_table::<16>();
+static SHAPES_16: [[u8; 16]; SHAPES.len()] = tall_table();
+
+/// Bitmap for a non-ASCII character in a font `height` rows tall (8 or 16).
+pub fn glyph(c: char, height: usize) -> Option<&'static [u8]> {
+    if let Some(i) = BOX.iter().position(|&(ch, _)| ch == c) {
+        return Some(if height >= 16 { &BOX_16[i] } else { &BOX_8[i] });
+    }
+    let i = SHAPES.iter().position(|&(ch, _)| ch == c)?;
+    Some(if height >= 16 { &SHAPES_16[i] } else { &SHAPES[i].1 })
+}
+
+/// Whether the console can draw `c`.
+pub fn drawable(c: char) -> bool {
+    (' '..='~').contains(&c) || glyph(c, 8).is_some()
+}
+
+/// Decodes a `\u{XXXX}` escape at the start of `s`, returning the character
+/// and the escape's length in bytes. Code points the console cannot draw are
+/// refused so they stay visible as typed.
+pub fn escape_at(s: &str) -> Option<(char, usize)> {
+    let body = s.strip_prefix("\\u{")?;
+    let close = body.find('}')?;
+    let hex = &body[..close];
+    if hex.is_empty() || hex.len() > 6 {
+        return None;
+    }
+    let c = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+    drawable(c).then_some((c, close + 4))
+}
+
+/// `s` with every drawable `\u{XXXX}` escape replaced by its character.
+pub fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(at) = rest.find('\\') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        match escape_at(rest) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
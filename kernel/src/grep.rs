@@ -0,0 +1,61 @@
+use alloc::string::String;
+use crate::{commands, console};
+
+// `grep` filters lines by a plain substring, no regular expressions. Its
+// input is whatever was piped into it, or the recent screen output when it
+// runs on its own.
+
+const GREP_USAGE: &str = "Usage: grep [-i] [-v] <pattern...>";
+
+fn contains(line: &str, pattern: &str, ignore_case: bool) -> bool {
+    if !ignore_case {
+        return line.contains(pattern);
+    }
+    let (hay, needle) = (line.as_bytes(), pattern.as_bytes());
+    needle.is_empty() || hay.windows(needle.len()).any(|w| w.eq_ignore_ascii_case(needle))
+}
+
+pub fn grep_cmd(args: &[&str]) -> commands::Status {
+    let mut ignore_case = false;
+    let mut invert = false;
+    let mut words = args;
+    while let [flag, rest @ ..] = words {
+        let Some(letters) = flag.strip_prefix('-').filter(|l| !l.is_empty()) else { break; };
+        for l in letters.chars() {
+            match l {
+                'i' => ignore_case = true,
+                'v' => invert = true,
+                _ => {
+                    console::write_err(GREP_USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        words = rest;
+    }
+    if words.is_empty() {
+        console::write_err(GREP_USAGE);
+        return Err(2);
+    }
+    let mut pattern = String::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            pattern.push(' ');
+        }
+        pattern.push_str(word);
+    }
+
+    let lines = match commands::piped_input() {
+        Some(text) => text.lines().map(String::from).collect(),
+        None => console::scrollback(),
+    };
+    let mut matched = false;
+    for line in lines.iter().filter(|l| contains(l, &pattern, ignore_case) != invert) {
+        console::write_line(line);
+        matched = true;
+    }
+    if !matched {
+        return Err(1);
+    }
+    Ok(())
+}
@@ -45,6 +45,11 @@ pub fn clear() {
     *history = Vec::new();
 }
 
+/// Whether the history's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    HISTORY.is_locked()
+}
+
 pub fn is_enabled() -> bool {
     *ENABLED.lock()
 }
@@ -0,0 +1,485 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::args::{opt, req, Kind, Spec};
+use crate::virtio::{Device, Dma, Queue, DESC_F_NEXT, DESC_F_WRITE, PAGE};
+use crate::{commands, console, klog, ramfs, wait};
+
+// A folder shared by the host over virtio-9p, e.g. QEMU's
+// `-virtfs local,path=DIR,mount_tag=host,security_model=none`. There is no
+// VFS to mount it into, so `host` copies files between it and the RAM files,
+// where `run`, `view`, `cat` and the rest can use them. Speaks 9P2000.L, one
+// request at a time.
+
+/// Transitional virtio-9p.
+const DEVICE_ID: u16 = 0x1009;
+const F_MOUNT_TAG: u32 = 1;
+const REQUESTQ: u16 = 0;
+
+const BUF_PAGES: usize = 2;
+const MSIZE: usize = BUF_PAGES * PAGE;
+/// Room for the Rread and Twrite headers around their data.
+const IO_HEADER: usize = 24;
+const CHUNK: usize = MSIZE - IO_HEADER;
+const REPLY_TIMEOUT_US: u64 = 2_000_000;
+const NO_ANSWER: &str = "host did not answer";
+const MAX_WALK: usize = 16;
+
+const NOTAG: u16 = 0xFFFF;
+const NOFID: u32 = !0;
+const ROOT_FID: u32 = 0;
+const FILE_FID: u32 = 1;
+
+// Request types; each reply is the request's type plus one.
+const RLERROR: u8 = 7;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TREADDIR: u8 = 40;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+
+// Linux open flags, which 9P2000.L passes through.
+const O_RDONLY: u32 = 0;
+const O_WRONLY: u32 = 1;
+const O_CREAT: u32 = 0o100;
+const O_TRUNC: u32 = 0o1000;
+const O_DIRECTORY: u32 = 0o200000;
+const DT_DIR: u8 = 4;
+const QID_LEN: usize = 13;
+
+static CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+fn errno_message(errno: u32) -> &'static str {
+    match errno {
+        1 | 13 => "permission denied",
+        2 => "no such file or directory",
+        17 => "file exists",
+        20 => "not a directory",
+        21 => "is a directory",
+        28 => "host disk full",
+        36 => "name too long",
+        _ => "host error",
+    }
+}
+
+/// Lays out a request after the size, type and tag fields.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    overflow: bool,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, b: &[u8]) {
+        match self.buf.get_mut(self.pos..self.pos + b.len()) {
+            Some(dst) => dst.copy_from_slice(b),
+            None => self.overflow = true,
+        }
+        self.pos += b.len();
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u16(s.len() as u16);
+        self.bytes(s.as_bytes());
+    }
+}
+
+/// Reads the fields of a reply.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], &'static str> {
+        let b = self.buf.get(self.pos..self.pos + n).ok_or("short reply from host")?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap_or_default()))
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap_or_default()))
+    }
+
+    fn u64(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap_or_default()))
+    }
+
+    fn str(&mut self) -> Result<&'a str, &'static str> {
+        let len = self.u16()? as usize;
+        core::str::from_utf8(self.bytes(len)?).map_err(|_| "host sent a name that is not UTF-8")
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+}
+
+struct Client {
+    dev: Device,
+    queue: Queue,
+    req: Dma,
+    resp: Dma,
+    tag: String,
+}
+
+impl Client {
+    /// Sends one request and waits for its reply, which is returned past the
+    /// header. An Rlerror becomes its errno's message.
+    fn call(&mut self, ty: u8, build: impl FnOnce(&mut Writer)) -> Result<Reader<'_>, &'static str> {
+        let mut w = Writer { buf: self.req.bytes(MSIZE), pos: 7, overflow: false };
+        build(&mut w);
+        if w.overflow {
+            return Err("request too long");
+        }
+        let len = w.pos;
+        w.buf[..4].copy_from_slice(&(len as u32).to_le_bytes());
+        w.buf[4] = ty;
+        let tag = if ty == TVERSION { NOTAG } else { 1 };
+        w.buf[5..7].copy_from_slice(&tag.to_le_bytes());
+
+        self.queue.set_desc(0, self.req.phys, len as u32, DESC_F_NEXT, 1);
+        self.queue.set_desc(1, self.resp.phys, MSIZE as u32, DESC_F_WRITE, 0);
+        self.queue.offer(0);
+        self.dev.notify(&self.queue);
+        let queue = &mut self.queue;
+        if !wait::until_micros(REPLY_TIMEOUT_US, || queue.take_used().is_some()) {
+            return Err(NO_ANSWER);
+        }
+
+        let buf = self.resp.bytes(MSIZE);
+        let mut r = Reader { buf, pos: 0 };
+        let size = (r.u32()? as usize).min(MSIZE);
+        let reply = r.u8()?;
+        r.u16()?;
+        r.buf = &r.buf[..size];
+        if reply == RLERROR {
+            return Err(errno_message(r.u32()?));
+        }
+        if reply != ty + 1 {
+            return Err("unexpected reply from host");
+        }
+        Ok(r)
+    }
+
+    fn attach(&mut self) -> Result<(), &'static str> {
+        let mut r = self.call(TVERSION, |w| {
+            w.u32(MSIZE as u32);
+            w.str("9P2000.L");
+        })?;
+        r.u32()?;
+        if r.str()? != "9P2000.L" {
+            return Err("host does not speak 9P2000.L");
+        }
+        self.call(TATTACH, |w| {
+            w.u32(ROOT_FID);
+            w.u32(NOFID);
+            w.str("stratos");
+            w.str("");
+            w.u32(NOFID);
+        })?;
+        Ok(())
+    }
+
+    /// Points FILE_FID at `path`, relative to the shared folder.
+    fn walk(&mut self, path: &str) -> Result<(), &'static str> {
+        let mut names: heapless::Vec<&str, MAX_WALK> = heapless::Vec::new();
+        for name in path.split('/').filter(|n| !n.is_empty() && *n != ".") {
+            names.push(name).map_err(|_| "path too deep")?;
+        }
+        let mut r = self.call(TWALK, |w| {
+            w.u32(ROOT_FID);
+            w.u32(FILE_FID);
+            w.u16(names.len() as u16);
+            for n in names.iter() {
+                w.str(n);
+            }
+        })?;
+        // A walk that stops early succeeds but leaves the new fid unset.
+        if r.u16()? as usize != names.len() {
+            return Err(errno_message(2));
+        }
+        Ok(())
+    }
+
+    fn clunk(&mut self, fid: u32) {
+        let _ = self.call(TCLUNK, |w| w.u32(fid));
+    }
+
+    fn open(&mut self, flags: u32) -> Result<(), &'static str> {
+        self.call(TLOPEN, |w| {
+            w.u32(FILE_FID);
+            w.u32(flags);
+        })?;
+        Ok(())
+    }
+
+    /// Walks to `path`, runs `f` with FILE_FID pointing at it, then lets the
+    /// fid go whatever happened.
+    fn with_file<T>(&mut self, path: &str, f: impl FnOnce(&mut Self) -> Result<T, &'static str>) -> Result<T, &'static str> {
+        self.walk(path)?;
+        let result = f(self);
+        self.clunk(FILE_FID);
+        result
+    }
+
+    fn list(&mut self, dir: &str) -> Result<(), &'static str> {
+        self.with_file(dir, |c| {
+            c.open(O_RDONLY | O_DIRECTORY)?;
+            let mut offset = 0;
+            loop {
+                let mut r = c.call(TREADDIR, |w| {
+                    w.u32(FILE_FID);
+                    w.u64(offset);
+                    w.u32(CHUNK as u32);
+                })?;
+                if r.u32()? == 0 {
+                    return Ok(());
+                }
+                while !r.at_end() {
+                    r.bytes(QID_LEN)?;
+                    offset = r.u64()?;
+                    let kind = r.u8()?;
+                    let name = r.str()?;
+                    if name == "." || name == ".." {
+                        continue;
+                    }
+                    let slash = if kind == DT_DIR { "/" } else { "" };
+                    console::write_line(&format!("{}{}", name, slash));
+                }
+            }
+        })
+    }
+
+    /// Reads `path` from the start, handing each piece to `sink` with its
+    /// offset. Returns the bytes read.
+    fn read(&mut self, path: &str, mut sink: impl FnMut(usize, &[u8]) -> Result<(), &'static str>) -> Result<usize, &'static str> {
+        self.with_file(path, |c| {
+            c.open(O_RDONLY)?;
+            let mut total = 0;
+            loop {
+                let mut r = c.call(TREAD, |w| {
+                    w.u32(FILE_FID);
+                    w.u64(total as u64);
+                    w.u32(CHUNK as u32);
+                })?;
+                let count = r.u32()? as usize;
+                if count == 0 {
+                    return Ok(total);
+                }
+                sink(total, r.bytes(count)?)?;
+                total += count;
+            }
+        })
+    }
+
+    /// Copies `path` into the RAM file `name`. Returns the bytes copied.
+    fn get(&mut self, path: &str, name: &str) -> Result<usize, &'static str> {
+        let total = self.read(path, |at, bytes| ramfs::write(name, bytes, at != 0))?;
+        if total == 0 {
+            ramfs::write(name, &[], false)?;
+        }
+        Ok(total)
+    }
+
+    /// Writes `data` to `path`, replacing the file if there is one.
+    fn put(&mut self, data: &[u8], path: &str) -> Result<(), &'static str> {
+        let opened = self.walk(path).and_then(|_| self.open(O_WRONLY | O_TRUNC));
+        if opened.is_err() {
+            self.clunk(FILE_FID);
+            let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+            self.walk(dir)?;
+            let created = self.call(TLCREATE, |w| {
+                w.u32(FILE_FID);
+                w.str(name);
+                w.u32(O_WRONLY | O_CREAT | O_TRUNC);
+                w.u32(0o644);
+                w.u32(0);
+            });
+            if let Err(msg) = created {
+                self.clunk(FILE_FID);
+                return Err(msg);
+            }
+        }
+        let mut result = Ok(());
+        let mut offset = 0;
+        for chunk in data.chunks(CHUNK) {
+            let written = self.call(TWRITE, |w| {
+                w.u32(FILE_FID);
+                w.u64(offset as u64);
+                w.u32(chunk.len() as u32);
+                w.bytes(chunk);
+            }).and_then(|mut r| r.u32());
+            match written {
+                Ok(n) if n as usize == chunk.len() => offset += chunk.len(),
+                Ok(_) => result = Err(errno_message(28)),
+                Err(msg) => result = Err(msg),
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        self.clunk(FILE_FID);
+        result
+    }
+}
+
+fn bring_up(dev: Device) -> Result<Client, &'static str> {
+    let features = dev.begin(F_MOUNT_TAG);
+    let queue = dev.queue(REQUESTQ).ok_or("no request queue")?;
+    let req = Dma::alloc(BUF_PAGES).ok_or("out of memory")?;
+    let resp = Dma::alloc(BUF_PAGES).ok_or("out of memory")?;
+    let mut tag = String::new();
+    if features & F_MOUNT_TAG != 0 {
+        let len = dev.config_read16(0);
+        for i in 0..len.min(64) {
+            tag.push(dev.config_read8(2 + i) as char);
+        }
+    }
+    dev.ready();
+    let mut client = Client { dev, queue, req, resp, tag };
+    client.attach()?;
+    Ok(client)
+}
+
+/// Looks for a virtio-9p device and attaches to its shared folder.
+pub fn init() {
+    let dev = match Device::find(DEVICE_ID) {
+        Ok(Some(dev)) => dev,
+        Ok(None) => return,
+        Err(msg) => {
+            klog::warn(&format!("hostfs: {}", msg));
+            return;
+        }
+    };
+    match bring_up(dev) {
+        Ok(client) => {
+            klog::info(&format!("hostfs: attached to host folder '{}'", client.tag));
+            *CLIENT.lock() = Some(client);
+        }
+        Err(msg) => {
+            dev.fail();
+            klog::warn(&format!("hostfs: {}", msg));
+        }
+    }
+}
+
+/// Runs `f` on the client, dropping it if the host stops answering.
+fn with_client<T>(f: impl FnOnce(&mut Client) -> Result<T, &'static str>) -> Result<T, &'static str> {
+    let mut lock = CLIENT.lock();
+    let client = lock.as_mut().ok_or("no shared folder")?;
+    let result = f(client);
+    if result.as_ref().err() == Some(&NO_ANSWER) {
+        *lock = None;
+    }
+    result
+}
+
+/// Whether a request to the host is in flight, for the watchdog.
+pub fn busy() -> bool {
+    CLIENT.is_locked()
+}
+
+pub fn attached() -> bool {
+    CLIENT.lock().is_some()
+}
+
+/// Reads a whole file from the shared folder, refusing ones over `max` bytes.
+pub fn read_file(path: &str, max: usize) -> Result<Vec<u8>, &'static str> {
+    with_client(|c| {
+        let mut data = Vec::new();
+        c.read(path, |_, bytes| {
+            if data.len() + bytes.len() > max {
+                return Err("file too large");
+            }
+            data.extend_from_slice(bytes);
+            Ok(())
+        })?;
+        Ok(data)
+    })
+}
+
+/// Writes `data` to a file in the shared folder, replacing what was there.
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), &'static str> {
+    with_client(|c| c.put(data, path))
+}
+
+pub static HOST_LS_ARGS: Spec = Spec { command: "host ls", args: &[opt("dir", Kind::Path)] };
+pub static HOST_GET_ARGS: Spec = Spec { command: "host get", args: &[req("path", Kind::Path), opt("file", Kind::Path)] };
+pub static HOST_PUT_ARGS: Spec = Spec { command: "host put", args: &[req("file", Kind::Path), opt("path", Kind::Path)] };
+
+const HOST_USAGE: &str = "Usage: host [ls [dir] | get <path> [file] | put <file> [path]]";
+
+/// `host`: status, or `ls`, `get` and `put` against the shared folder.
+pub fn host_cmd(args: &[&str]) -> commands::Status {
+    let mut lock = CLIENT.lock();
+    let Some(client) = lock.as_mut() else {
+        console::write_err("host: no shared folder. Start QEMU with -virtfs local,path=DIR,mount_tag=host,security_model=none");
+        return Err(1);
+    };
+    let Some((sub, rest)) = args.split_first() else {
+        console::write_line(&format!("Host folder '{}' over virtio-9p at I/O {:#x}.", client.tag, client.dev.io));
+        return Ok(());
+    };
+    let result = match sub.to_ascii_lowercase().as_str() {
+        "ls" => {
+            let a = HOST_LS_ARGS.check(rest)?;
+            client.list(a.word(0).unwrap_or(""))
+        }
+        "get" => {
+            let a = HOST_GET_ARGS.check(rest)?;
+            let path = a.word(0).unwrap_or("");
+            let name = a.word(1).unwrap_or_else(|| path.rsplit('/').next().unwrap_or(path));
+            client.get(path, name).map(|n| console::write_line(&format!("Copied {} bytes to {}.", n, name)))
+        }
+        "put" => {
+            let a = HOST_PUT_ARGS.check(rest)?;
+            let name = a.word(0).unwrap_or("");
+            let path = a.word(1).unwrap_or(name);
+            match ramfs::read(name) {
+                Some(data) => client
+                    .put(&data, path)
+                    .map(|_| console::write_line(&format!("Copied {} bytes to host {}.", data.len(), path))),
+                None => Err("no such RAM file"),
+            }
+        }
+        _ => {
+            console::write_err(HOST_USAGE);
+            return Err(2);
+        }
+    };
+    if let Err(msg) = result {
+        console::write_err(&format!("host {}: {}", sub, msg));
+        // The request is still out, so later replies would not line up.
+        if msg == NO_ANSWER {
+            *lock = None;
+        }
+        return Err(1);
+    }
+    Ok(())
+}
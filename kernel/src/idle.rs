@@ -0,0 +1,52 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use crate::{task, timer};
+
+static IN_IDLE: AtomicBool = AtomicBool::new(false);
+static IDLE_TICKS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static WINDOW_TICKS: AtomicU64 = AtomicU64::new(0);
+static WINDOW_IDLE: AtomicU64 = AtomicU64::new(0);
+static LAST_IDLE_PERCENT: AtomicU8 = AtomicU8::new(100);
+static TICK_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Lets other tasks run, then halts until the next interrupt unless a tick
+/// already went by while they ran. Interrupts are always enabled first so a
+/// caller that forgot to re-enable them cannot wedge the CPU.
+pub fn idle() {
+    let epoch = TICK_EPOCH.load(Ordering::Relaxed);
+    if task::yield_now() && TICK_EPOCH.load(Ordering::Relaxed) != epoch {
+        return;
+    }
+    IN_IDLE.store(true, Ordering::Relaxed);
+    timer::halt();
+    IN_IDLE.store(false, Ordering::Relaxed);
+}
+
+/// Called from the timer interrupt; attributes `n` ticks (more than one after
+/// a tickless sleep) to idle or busy time.
+pub fn on_tick(n: u64) {
+    TICK_EPOCH.fetch_add(1, Ordering::Relaxed);
+    if IN_IDLE.load(Ordering::Relaxed) {
+        IDLE_TICKS_TOTAL.fetch_add(n, Ordering::Relaxed);
+        WINDOW_IDLE.fetch_add(n, Ordering::Relaxed);
+    } else {
+        task::account_tick();
+    }
+    let window = WINDOW_TICKS.fetch_add(n, Ordering::Relaxed) + n;
+    if window >= timer::frequency() as u64 {
+        let idle = WINDOW_IDLE.swap(0, Ordering::Relaxed);
+        WINDOW_TICKS.store(0, Ordering::Relaxed);
+        let pct = (idle * 100 / window).min(100) as u8;
+        LAST_IDLE_PERCENT.store(pct, Ordering::Relaxed);
+    }
+}
+
+/// Idle share of the last full second, 0-100.
+pub fn idle_percent() -> u8 {
+    LAST_IDLE_PERCENT.load(Ordering::Relaxed)
+}
+
+pub fn idle_ticks() -> u64 {
+    IDLE_TICKS_TOTAL.load(Ordering::Relaxed)
+}
@@ -7,15 +7,69 @@ use x86_64::{
     registers::control::Cr2,
     structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
 };
-use crate::{console, timer, serial};
+use crate::{app, console, klog, serial, task, timer};
+use crate::klog::Level;
 
-use alloc::format;
-
-use alloc::string::String;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use heapless::String as HString;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+// Local APIC vectors. The kernel drives the PIC, but firmware may leave the
+// LAPIC enabled, and its spurious vector resets to 0xFF.
+const LAPIC_ERROR_VECTOR: usize = 0xFE;
+const LAPIC_SPURIOUS_VECTOR: usize = 0xFF;
+const IA32_APIC_BASE: u32 = 0x1B;
+const LAPIC_EOI: u64 = 0xB0;
+const LAPIC_ESR: u64 = 0x280;
+
+#[derive(Copy, Clone)]
+pub enum Irq {
+    Timer,
+    Keyboard,
+    Rtc,
+    AcpiSci,
+    PicSpurious7,
+    PicSpurious15,
+    Pic7,
+    Pic15,
+    LapicSpurious,
+    LapicError,
+}
+
+const IRQ_NAMES: [&str; 10] = [
+    "timer (IRQ 0)",
+    "keyboard (IRQ 1)",
+    "rtc (IRQ 8)",
+    "acpi sci",
+    "pic spurious IRQ 7",
+    "pic spurious IRQ 15",
+    "pic IRQ 7",
+    "pic IRQ 15",
+    "lapic spurious",
+    "lapic error",
+];
+
+static IRQ_COUNTS: [AtomicU64; 10] = [const { AtomicU64::new(0) }; 10];
+static LAST_LAPIC_ESR: AtomicU32 = AtomicU32::new(0);
+
+pub fn count(irq: Irq) {
+    IRQ_COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// `irqstats`: how often each interrupt source fired, spurious ones included.
+pub fn irqstats_cmd() {
+    console::write_line("Interrupt                 Count");
+    for (name, n) in IRQ_NAMES.iter().zip(IRQ_COUNTS.iter()) {
+        console::write_line(&alloc::format!("{:<24} {:>8}", name, n.load(Ordering::Relaxed)));
+    }
+    let esr = LAST_LAPIC_ESR.load(Ordering::Relaxed);
+    if esr != 0 {
+        console::write_line(&alloc::format!("Last LAPIC error status: {:#x}", esr));
+    }
+}
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -46,6 +100,15 @@ lazy_static! {
         idt.virtualization.set_handler_fn(exc_default);
 
         idt[32].set_handler_fn(timer::timer_interrupt_handler);
+        idt[33].set_handler_fn(keyboard_handler);
+        idt[32 + 7].set_handler_fn(pic_irq7_handler);
+        idt[32 + 15].set_handler_fn(pic_irq15_handler);
+        idt[LAPIC_ERROR_VECTOR].set_handler_fn(lapic_error_handler);
+        idt[LAPIC_SPURIOUS_VECTOR].set_handler_fn(lapic_spurious_handler);
+        idt[32 + crate::time::RTC_IRQ as usize].set_handler_fn(rtc_handler);
+        if let Some(irq) = crate::acpi::sci_irq() {
+            idt[32 + irq as usize].set_handler_fn(acpi_sci_handler);
+        }
 
         idt
     };
@@ -55,31 +118,90 @@ pub fn init_idt() {
     IDT.load();
 }
 
-fn print_line(msg: &str) {
-    console::cwrite_line(msg, 0xFFFFFF, 0x000000);
+const TITLE_FG: u32 = 0xFF0000;
+const TEXT_FG: u32 = 0xFFFFFF;
+const APP_FAULT_FG: u32 = 0xFFD37F;
+
+type Line = HString<160>;
+
+fn frame_line(frame: &InterruptStackFrame) -> Line {
+    let mut s = Line::new();
+    let _ = write!(
+        s,
+        "RIP {:#x}  CS {:#x}  RFLAGS {:#x}  RSP {:#x}  SS {:#x}",
+        frame.instruction_pointer.as_u64(),
+        frame.code_segment,
+        frame.cpu_flags,
+        frame.stack_pointer.as_u64(),
+        frame.stack_segment
+    );
+    s
 }
 
-fn strip_ascii_whitespace(s: &str) -> String {
-    let mut result = String::with_capacity(s.len());
-    for b in s.bytes() {
-        if !b.is_ascii_whitespace() {
-            let _ = result.write_char(b as char);
+/// Reports an exception everywhere that can still be reached: serial first
+/// (lock-free), then the klog ring, then the screen: the console, or the
+/// framebuffer directly if the console was locked when we faulted, or port
+/// 0xE9 if there is no console yet. Formats without the heap for the same reason.
+fn report(level: Level, title: &str, lines: &[&str]) {
+    serial::emergency_write(title);
+    for l in lines {
+        serial::emergency_write(l);
+    }
+
+    let mut summary = Line::new();
+    let _ = summary.push_str(title);
+    if let Some(first) = lines.first() {
+        let _ = write!(summary, ": {}", first);
+    }
+    klog::try_log(level, &summary);
+
+    let mut shown: heapless::Vec<(&str, u32), 8> = heapless::Vec::new();
+    let _ = shown.push((title, TITLE_FG));
+    for l in lines {
+        let _ = shown.push((l, TEXT_FG));
+    }
+    if console::crash_lines(&shown) == console::CrashScreen::None {
+        klog::e9_emergency_write(title);
+        for l in lines {
+            klog::e9_emergency_write(l);
         }
     }
-    result
 }
 
-fn print_err(msg: &str) {
-    console::cwrite_line(msg, 0xFF0000, 0x000000);
+/// Ends the faulting app, or the supervised task; anything else cannot be recovered.
+fn recover_or_halt() -> ! {
+    // Each app runs on its own task, so a fault in one ends only that app.
+    // Its exit hooks release the app heap and cleanup hooks; the task is reaped.
+    if let Some(id) = task::try_current_app() {
+        let mut msg = Line::new();
+        let _ = match app::try_name(id) {
+            Some(name) => write!(msg, "App '{}' (id {}) crashed and was terminated.", name, id),
+            None => write!(msg, "App {} crashed and was terminated.", id),
+        };
+        serial::emergency_write(&msg);
+        klog::try_log(Level::Warn, &msg);
+        // As below: a held console lock can only belong to the faulting task.
+        unsafe { console::force_unlock(); }
+        console::try_with_console(|c| c.cwrite_line(&msg, APP_FAULT_FG, 0x000000));
+        task::exit(task::STATUS_FAULT);
+    }
+    if task::current_is_supervised() {
+        report(Level::Warn, "Aborting the command and restarting the shell.", &[]);
+        // Console code runs with interrupts off and never yields, so if the
+        // lock is held here it is held by the task we are about to end.
+        unsafe { console::force_unlock(); }
+        task::exit(task::STATUS_FAULT);
+    }
+    report(Level::Error, "Fault outside a supervised task, halting.", &[&crate::time::crash_stamp()]);
+    loop { hlt(); }
 }
 
 macro_rules! simple_exc {
     ($name:ident, $msg:expr) => {
         extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
-            print_err("CPU EXCEPTION");
-            print_line(concat!($msg, " detected, halting..."));
-            print_line(&format!("{:#?}", stack_frame));
-            loop { hlt(); }
+            let frame = frame_line(&stack_frame);
+            report(Level::Error, "CPU EXCEPTION", &[concat!($msg, " detected"), &frame]);
+            recover_or_halt();
         }
     };
 }
@@ -95,11 +217,11 @@ simple_exc!(exc_default, "Unknown/Reserved Exception");
 macro_rules! errcode_exc {
     ($name:ident, $msg:expr) => {
         extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, _error_code: u64) {
-            print_err("CPU EXCEPTION");
-            print_line(concat!($msg, " detected, halting..."));
-            print_line(&format!("{:#?}", stack_frame));
-            print_line(&format!("Error code: {:#x}", _error_code));
-            loop { hlt(); }
+            let frame = frame_line(&stack_frame);
+            let mut code = Line::new();
+            let _ = write!(code, "Error code: {:#x}", _error_code);
+            report(Level::Error, "CPU EXCEPTION", &[concat!($msg, " detected"), &code, &frame]);
+            recover_or_halt();
         }
     };
 }
@@ -114,19 +236,17 @@ extern "x86-interrupt" fn exc_page_fault(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
-    let addr = Cr2::read();
-    print_err("PAGE FAULT");
-    {
-        print_line(&format!("Accessed address: {:?}", addr));
-        print_line(&format!("Error code: {:?}", error_code));
-        print_line(&format!("{:#?}", stack_frame));
-    }
-    loop { hlt(); }
+    let mut addr = Line::new();
+    let _ = write!(addr, "Accessed address: {:?}", Cr2::read());
+    let mut code = Line::new();
+    let _ = write!(code, "Error code: {:?}", error_code);
+    let frame = frame_line(&stack_frame);
+    report(Level::Error, "PAGE FAULT", &[&addr, &code, &frame]);
+    recover_or_halt();
 }
 
 extern "x86-interrupt" fn exc_machine_check(_stack_frame: InterruptStackFrame) -> ! {
-    console::cwrite_line("MACHINE CHECK", 0xFF0000, 0x000000);
-    console::cwrite_line("Fatal hardware error, halting.", 0xFFFFFF, 0x000000);
+    report(Level::Error, "MACHINE CHECK", &["Fatal hardware error, halting."]);
     loop { x86_64::instructions::hlt(); }
 }
 
@@ -134,21 +254,86 @@ extern "x86-interrupt" fn exc_double_fault(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
-    print_err("DOUBLE FAULT");
-    print_line("CPU failed to deliver exceptions correctly, halting.");
-    print_line(&format!("{:#?}", stack_frame));
+    let frame = frame_line(&stack_frame);
+    report(Level::Error, "DOUBLE FAULT", &["CPU failed to deliver exceptions correctly, halting.", &frame, &crate::time::crash_stamp()]);
     loop { hlt(); }
 }
 
 extern "x86-interrupt" fn exc_breakpoint(stack_frame: InterruptStackFrame) {
-    serial::write("INT3 detected");
-    
-    let formatted = format!("Stack frame: {:#?}", stack_frame);
-    let compact = strip_ascii_whitespace(&formatted);
-    serial::write(&compact);
+    let frame = frame_line(&stack_frame);
+    serial::emergency_write("INT3 detected");
+    serial::emergency_write(&frame);
+    klog::try_log(Level::Debug, "INT3 breakpoint");
 }
 
 extern "x86-interrupt" fn exc_overflow(stack_frame: InterruptStackFrame) {
-    print_line("INT4 (#OF) detected!");
-    print_line(&format!("Stack frame: {:#?}", stack_frame));
-}
\ No newline at end of file
+    let frame = frame_line(&stack_frame);
+    report(Level::Warn, "INT4 (#OF) detected!", &[&frame]);
+}
+
+// Buffers the scancode for the next poll and wakes the CPU out of `hlt`.
+extern "x86-interrupt" fn keyboard_handler(_stack_frame: InterruptStackFrame) {
+    count(Irq::Keyboard);
+    crate::keyboard::on_irq();
+    crate::pic::end_of_interrupt(1);
+}
+
+// Nothing is wired to IRQ 7 or 15; these exist so spurious interrupts are
+// counted rather than landing in an unhandled vector.
+extern "x86-interrupt" fn pic_irq7_handler(_stack_frame: InterruptStackFrame) {
+    if crate::pic::is_spurious(7) {
+        count(Irq::PicSpurious7);
+        return;
+    }
+    count(Irq::Pic7);
+    crate::pic::end_of_interrupt(7);
+}
+
+extern "x86-interrupt" fn pic_irq15_handler(_stack_frame: InterruptStackFrame) {
+    if crate::pic::is_spurious(15) {
+        count(Irq::PicSpurious15);
+        crate::pic::end_of_interrupt_master();
+        return;
+    }
+    count(Irq::Pic15);
+    crate::pic::end_of_interrupt(15);
+}
+
+fn lapic_register(offset: u64) -> Option<*mut u32> {
+    let base = unsafe { x86::msr::rdmsr(IA32_APIC_BASE) } & 0xF_FFFF_F000;
+    // The physical memory mapping may not reach MMIO holes; check before touching it.
+    let virt = crate::memory::phys_to_virt(base + offset)?;
+    crate::paging::translate(virt)?;
+    Some(virt as *mut u32)
+}
+
+// Spurious LAPIC interrupts must not be acknowledged.
+extern "x86-interrupt" fn lapic_spurious_handler(_stack_frame: InterruptStackFrame) {
+    count(Irq::LapicSpurious);
+}
+
+extern "x86-interrupt" fn lapic_error_handler(_stack_frame: InterruptStackFrame) {
+    count(Irq::LapicError);
+    if let (Some(esr), Some(eoi)) = (lapic_register(LAPIC_ESR), lapic_register(LAPIC_EOI)) {
+        unsafe {
+            // The ESR latches on a write before it is read.
+            core::ptr::write_volatile(esr, 0);
+            LAST_LAPIC_ESR.store(core::ptr::read_volatile(esr), Ordering::Relaxed);
+            core::ptr::write_volatile(eoi, 0);
+        }
+    }
+}
+
+extern "x86-interrupt" fn rtc_handler(_stack_frame: InterruptStackFrame) {
+    count(Irq::Rtc);
+    crate::time::handle_rtc_irq();
+    crate::pic::end_of_interrupt(crate::time::RTC_IRQ);
+}
+
+extern "x86-interrupt" fn acpi_sci_handler(_stack_frame: InterruptStackFrame) {
+    count(Irq::AcpiSci);
+    crate::acpi::handle_sci();
+    if let Some(irq) = crate::acpi::sci_irq() {
+        crate::pic::end_of_interrupt(irq);
+    }
+}
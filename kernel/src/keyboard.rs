@@ -1,8 +1,11 @@
 use pc_keyboard::{
-    layouts::Us104Key, DecodedKey, HandleControl, Keyboard as PcKeyboard, KeyCode,
-    KeyEvent as PcKeyEvent, KeyState, ScancodeSet1,
+    layouts::{AnyLayout, Azerty, Colemak, De105Key, Dvorak104Key, Jis109Key, Uk105Key, Us104Key},
+    DecodedKey, HandleControl, Keyboard as PcKeyboard, KeyCode, KeyEvent as PcKeyEvent, KeyState, ScancodeSet1,
 };
-use x86_64::instructions::port::Port;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use heapless::{Deque, String as HString};
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
 
 pub enum KeyEvent {
     Char(char),
@@ -16,26 +19,93 @@ pub enum KeyEvent {
     Right,
     CtrlLeft,
     CtrlRight,
+    Escape,
+    Tab,
+    /// Ctrl+Shift+V
+    PastePicker,
+    /// Text pasted into a terminal, line breaks as `\n`.
+    Paste(alloc::string::String),
 }
 
+/// What the keys print. Scancodes name positions on a US keyboard; the
+/// layout turns them into characters.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    Us,
+    Uk,
+    De,
+    Fr,
+    Dvorak,
+    Colemak,
+    Jp,
+}
+
+impl Layout {
+    /// In `repr` order, so a stored number indexes it.
+    pub const ALL: [Layout; 7] =
+        [Layout::Us, Layout::Uk, Layout::De, Layout::Fr, Layout::Dvorak, Layout::Colemak, Layout::Jp];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::De => "de",
+            Layout::Fr => "fr",
+            Layout::Dvorak => "dvorak",
+            Layout::Colemak => "colemak",
+            Layout::Jp => "jp",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Layout> {
+        Self::ALL.iter().copied().find(|l| l.name().eq_ignore_ascii_case(s))
+    }
+
+    const fn decoder(self) -> AnyLayout {
+        match self {
+            Layout::Us => AnyLayout::Us104Key(Us104Key),
+            Layout::Uk => AnyLayout::Uk105Key(Uk105Key),
+            Layout::De => AnyLayout::De105Key(De105Key),
+            Layout::Fr => AnyLayout::Azerty(Azerty),
+            Layout::Dvorak => AnyLayout::Dvorak104Key(Dvorak104Key),
+            Layout::Colemak => AnyLayout::Colemak(Colemak),
+            Layout::Jp => AnyLayout::Jis109Key(Jis109Key),
+        }
+    }
+}
+
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us as u8);
+const SETTING_LAYOUT: &str = "keyboard.layout";
+
 pub struct KeyboardState {
-    kb: PcKeyboard<Us104Key, ScancodeSet1>,
+    kb: PcKeyboard<AnyLayout, ScancodeSet1>,
     data: Port<u8>,
     status: Port<u8>,
 }
 
 impl KeyboardState {
-    fn new() -> Self {
+    const fn new(layout: Layout) -> Self {
         Self {
-            kb: PcKeyboard::new(ScancodeSet1::new(), Us104Key, HandleControl::Ignore),
+            kb: PcKeyboard::new(ScancodeSet1::new(), layout.decoder(), HandleControl::Ignore),
             data: Port::new(0x60),
             status: Port::new(0x64),
         }
     }
 
+    fn status_empty(&mut self) -> bool {
+        let status: u8 = unsafe { self.status.read() };
+        status & 1 == 0 && SCANCODES.lock().is_empty()
+    }
+
+    /// Scancodes buffered by the IRQ come first; the port is still polled in
+    /// case IRQ 1 is not being delivered. Callers have interrupts disabled.
     fn read_scancode(&mut self) -> Option<u8> {
+        if let Some(sc) = SCANCODES.lock().pop_front() {
+            return Some(sc);
+        }
         let status: u8 = unsafe { self.status.read() };
-        if status & 1 == 0 {
+        if status & 1 == 0 || status & STATUS_AUX_DATA != 0 {
             return None;
         }
         let sc: u8 = unsafe { self.data.read() };
@@ -49,7 +119,7 @@ pub struct Keyboard {
 }
 
 impl Keyboard {
-    pub fn new() -> Self { Self { inner: KeyboardState::new(), ctrl_down: false } }
+    pub const fn new(layout: Layout) -> Self { Self { inner: KeyboardState::new(layout), ctrl_down: false } }
 
     fn update_ctrl_state(&mut self, evt: &PcKeyEvent) {
         if matches!(evt.code, KeyCode::LControl | KeyCode::RControl) {
@@ -67,10 +137,24 @@ impl Keyboard {
                 self.update_ctrl_state(&evt);
                 if let Some(key) = self.inner.kb.process_keyevent(evt) {
                     match key {
+                        // Flow control is handled here so it works whoever is polling.
+                        DecodedKey::Unicode('s' | 'S') if self.ctrl_down => {
+                            OUTPUT_PAUSED.store(true, Ordering::Relaxed);
+                            None
+                        }
+                        DecodedKey::Unicode('q' | 'Q') if self.ctrl_down => {
+                            OUTPUT_PAUSED.store(false, Ordering::Relaxed);
+                            None
+                        }
+                        DecodedKey::Unicode('V') if self.ctrl_down => Some(KeyEvent::PastePicker),
+                        // Already seen by `on_irq` as an interrupt request.
+                        DecodedKey::Unicode('c' | 'C') if self.ctrl_down => None,
                         DecodedKey::Unicode(c) => match c {
                             '\n' | '\r' => Some(KeyEvent::Enter),
                             '\x08' => Some(self.translate_backspace()),
                             '\u{7f}' => Some(KeyEvent::Delete),
+                            '\u{1b}' => Some(KeyEvent::Escape),
+                            '\t' => Some(KeyEvent::Tab),
                             _ => Some(KeyEvent::Char(c)),
                         },
                         DecodedKey::RawKey(k) => {
@@ -78,6 +162,15 @@ impl Keyboard {
                                 KeyCode::Return => Some(KeyEvent::Enter),
                                 KeyCode::Backspace => Some(self.translate_backspace()),
                                 KeyCode::Delete => Some(KeyEvent::Delete),
+                                KeyCode::Escape => Some(KeyEvent::Escape),
+                                KeyCode::F12 => {
+                                    MAGNIFIER_TOGGLE.store(true, Ordering::Relaxed);
+                                    None
+                                }
+                                KeyCode::F11 => {
+                                    PERF_OVERLAY_TOGGLE.store(true, Ordering::Relaxed);
+                                    None
+                                }
                                 KeyCode::ArrowUp => Some(KeyEvent::Up),
                                 KeyCode::ArrowDown => Some(KeyEvent::Down),
                                 KeyCode::ArrowLeft => {
@@ -96,3 +189,580 @@ impl Keyboard {
     }
 }
 
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new(Layout::Us));
+// Keys read by `pump` while output was being written, waiting for `poll_event`.
+static PENDING: Mutex<Deque<KeyEvent, 16>> = Mutex::new(Deque::new());
+static OUTPUT_PAUSED: AtomicBool = AtomicBool::new(false);
+// Typeahead: raw scancodes read by the IRQ handler while nobody is polling,
+// e.g. during a slow command, so keys typed meanwhile reach the next prompt.
+pub const TYPEAHEAD_SIZE: usize = 128;
+static SCANCODES: Mutex<Deque<u8, TYPEAHEAD_SIZE>> = Mutex::new(Deque::new());
+static TYPEAHEAD_DROPPED: AtomicU64 = AtomicU64::new(0);
+// Set while the controller is being reset, so the IRQ leaves replies alone.
+static CONTROLLER_BUSY: AtomicBool = AtomicBool::new(false);
+// Set while `kbddebug` reads scancodes itself, so output does not pump them away.
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+// F12 toggles the magnifier; the console picks this up on its next tick.
+static MAGNIFIER_TOGGLE: AtomicBool = AtomicBool::new(false);
+// F11 toggles the present-rate overlay, the same way.
+static PERF_OVERLAY_TOGGLE: AtomicBool = AtomicBool::new(false);
+// Ctrl+C is spotted by the IRQ handler itself, straight from scancodes, so
+// a command can notice it without polling the keyboard and eating typeahead.
+// The flag stays set until the shell clears it when the command returns.
+static IRQ_CTRL_DOWN: AtomicBool = AtomicBool::new(false);
+static CANCEL_REQUESTED: AtomicBool = AtomicBool::new(false);
+const PUMP_LIMIT: usize = 16;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+const STATUS_AUX_DATA: u8 = 0x20;
+// Set 1 make and break codes; right Ctrl is the same code after an 0xE0 prefix.
+const SC_CTRL: u8 = 0x1D;
+const SC_CTRL_BREAK: u8 = 0x9D;
+const SC_C: u8 = 0x2E;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_PORT2: u8 = 0xA7;
+const CMD_ENABLE_PORT2: u8 = 0xA8;
+const CMD_TEST_PORT2: u8 = 0xA9;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_PORT1: u8 = 0xAB;
+const CMD_DISABLE_PORT1: u8 = 0xAD;
+const CMD_ENABLE_PORT1: u8 = 0xAE;
+const CMD_WRITE_PORT2: u8 = 0xD4;
+
+const CONFIG_PORT1_IRQ: u8 = 0x01;
+const CONFIG_PORT2_IRQ: u8 = 0x02;
+const CONFIG_PORT2_CLOCK_OFF: u8 = 0x20;
+const CONFIG_TRANSLATE: u8 = 0x40;
+
+const DEV_RESET: u8 = 0xFF;
+const DEV_SET_SCANCODE: u8 = 0xF0;
+const DEV_ENABLE_SCANNING: u8 = 0xF4;
+const DEV_ACK: u8 = 0xFA;
+const DEV_SELF_TEST_OK: u8 = 0xAA;
+
+const IO_TIMEOUT_US: u64 = 50_000;
+// Devices answer a reset only after their self-test, which can take half a second.
+const RESET_TIMEOUT_US: u64 = 750_000;
+
+#[derive(Copy, Clone, Default)]
+pub struct Ps2Status {
+    pub controller: bool,
+    pub self_test_passed: bool,
+    pub dual_channel: bool,
+    pub keyboard: bool,
+    pub mouse: bool,
+    /// Translation to scancode set 1 had to be switched on.
+    pub was_untranslated: bool,
+}
+
+static PS2_STATUS: Mutex<Ps2Status> = Mutex::new(Ps2Status {
+    controller: false,
+    self_test_passed: false,
+    dual_channel: false,
+    keyboard: false,
+    mouse: false,
+    was_untranslated: false,
+});
+
+fn status_reg() -> u8 {
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+fn write_command(cmd: u8) -> bool {
+    if !crate::wait::until_micros(IO_TIMEOUT_US, || status_reg() & STATUS_INPUT_FULL == 0) {
+        return false;
+    }
+    unsafe { Port::<u8>::new(STATUS_PORT).write(cmd) };
+    true
+}
+
+fn write_data(byte: u8) -> bool {
+    if !crate::wait::until_micros(IO_TIMEOUT_US, || status_reg() & STATUS_INPUT_FULL == 0) {
+        return false;
+    }
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    true
+}
+
+fn read_data(timeout_us: u64) -> Option<u8> {
+    if !crate::wait::until_micros(timeout_us, || status_reg() & STATUS_OUTPUT_FULL != 0) {
+        return None;
+    }
+    Some(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+fn flush_output() {
+    for _ in 0..64 {
+        if status_reg() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+fn command_with_reply(cmd: u8) -> Option<u8> {
+    if !write_command(cmd) {
+        return None;
+    }
+    read_data(IO_TIMEOUT_US)
+}
+
+/// Sends a byte to a device and waits for its ACK.
+fn device_command(port2: bool, byte: u8) -> bool {
+    if port2 && !write_command(CMD_WRITE_PORT2) {
+        return false;
+    }
+    write_data(byte) && read_data(IO_TIMEOUT_US) == Some(DEV_ACK)
+}
+
+fn reset_device(port2: bool) -> bool {
+    device_command(port2, DEV_RESET) && read_data(RESET_TIMEOUT_US) == Some(DEV_SELF_TEST_OK)
+}
+
+/// Brings the 8042 and its devices into a known state instead of trusting
+/// whatever firmware left behind: self-test, translation on (the decoder
+/// expects set 1), keyboard reset to scancode set 2 and scanning enabled.
+/// A mouse is detected but its port is left disabled, since its bytes would
+/// be read as scancodes.
+fn init_controller() -> Ps2Status {
+    let mut st = Ps2Status::default();
+    if !controller_present() {
+        return st;
+    }
+    st.controller = true;
+
+    write_command(CMD_DISABLE_PORT1);
+    write_command(CMD_DISABLE_PORT2);
+    flush_output();
+
+    let Some(mut config) = command_with_reply(CMD_READ_CONFIG) else {
+        return st;
+    };
+    st.was_untranslated = config & CONFIG_TRANSLATE == 0;
+    config &= !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ);
+    config |= CONFIG_TRANSLATE;
+    write_command(CMD_WRITE_CONFIG);
+    write_data(config);
+
+    // Some controllers reset their configuration during the self-test.
+    st.self_test_passed = command_with_reply(CMD_SELF_TEST) == Some(0x55);
+    write_command(CMD_WRITE_CONFIG);
+    write_data(config);
+
+    write_command(CMD_ENABLE_PORT2);
+    st.dual_channel = command_with_reply(CMD_READ_CONFIG).is_some_and(|c| c & CONFIG_PORT2_CLOCK_OFF == 0);
+    write_command(CMD_DISABLE_PORT2);
+
+    let port1_ok = command_with_reply(CMD_TEST_PORT1) == Some(0x00);
+    let port2_ok = st.dual_channel && command_with_reply(CMD_TEST_PORT2) == Some(0x00);
+
+    if port2_ok {
+        write_command(CMD_ENABLE_PORT2);
+        st.mouse = reset_device(true);
+        flush_output();
+        write_command(CMD_DISABLE_PORT2);
+    }
+
+    if port1_ok {
+        write_command(CMD_ENABLE_PORT1);
+        st.keyboard = reset_device(false);
+        flush_output();
+        if st.keyboard {
+            let _ = device_command(false, DEV_SET_SCANCODE) && device_command(false, 0x02);
+            device_command(false, DEV_ENABLE_SCANNING);
+        }
+    }
+
+    config |= CONFIG_PORT1_IRQ;
+    write_command(CMD_WRITE_CONFIG);
+    write_data(config);
+    flush_output();
+    st
+}
+
+/// Initializes the PS/2 controller. Also used by `kbd rescan`; holding the
+/// keyboard lock keeps pollers away while devices are reset.
+pub fn init() -> Ps2Status {
+    let mut kb = KEYBOARD.lock();
+    CONTROLLER_BUSY.store(true, Ordering::Relaxed);
+    let st = init_controller();
+    CONTROLLER_BUSY.store(false, Ordering::Relaxed);
+    *kb = Keyboard::new(layout());
+    drop(kb);
+    interrupts::without_interrupts(|| {
+        PENDING.lock().clear();
+        SCANCODES.lock().clear();
+        *PS2_STATUS.lock() = st;
+    });
+    st
+}
+
+pub fn layout() -> Layout {
+    Layout::ALL[LAYOUT.load(Ordering::Relaxed) as usize]
+}
+
+/// Switches the layout and keeps it in settings. Keys half-way through a
+/// multi-byte scancode are dropped.
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        KEYBOARD.lock().inner.kb = PcKeyboard::new(ScancodeSet1::new(), layout.decoder(), HandleControl::Ignore);
+    });
+    crate::settings::set(SETTING_LAYOUT, layout.name());
+}
+
+/// Re-applies the layout saved in settings.
+pub fn apply_saved_layout() {
+    if let Some(layout) = crate::settings::get(SETTING_LAYOUT).and_then(|v| Layout::parse(&v)) {
+        set_layout(layout);
+    }
+}
+
+/// IRQ 1: moves the byte out of the controller into the typeahead buffer.
+/// When the buffer is full the newest key is dropped and counted.
+pub fn on_irq() {
+    if CONTROLLER_BUSY.load(Ordering::Relaxed) {
+        return;
+    }
+    let status = status_reg();
+    if status & STATUS_OUTPUT_FULL == 0 || status & STATUS_AUX_DATA != 0 {
+        return;
+    }
+    let sc = unsafe { Port::<u8>::new(DATA_PORT).read() };
+    match sc {
+        SC_CTRL => IRQ_CTRL_DOWN.store(true, Ordering::Relaxed),
+        SC_CTRL_BREAK => IRQ_CTRL_DOWN.store(false, Ordering::Relaxed),
+        SC_C if IRQ_CTRL_DOWN.load(Ordering::Relaxed) => CANCEL_REQUESTED.store(true, Ordering::Relaxed),
+        _ => {}
+    }
+    // Everyone else takes this lock with interrupts off, so it is free here.
+    if SCANCODES.lock().push_back(sc).is_err() {
+        TYPEAHEAD_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Scancodes lost because the typeahead buffer was full.
+pub fn typeahead_dropped() -> u64 {
+    TYPEAHEAD_DROPPED.load(Ordering::Relaxed)
+}
+
+pub fn ps2_status() -> Ps2Status {
+    interrupts::without_interrupts(|| *PS2_STATUS.lock())
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b { "yes" } else { "no" }
+}
+
+fn print_status(st: &Ps2Status) {
+    use crate::console::write_line;
+    write_line(&alloc::format!("PS/2 controller: {}", if st.controller { "present" } else { "not found" }));
+    if !st.controller {
+        return;
+    }
+    write_line(&alloc::format!("  Self-test passed: {}", yes_no(st.self_test_passed)));
+    write_line(&alloc::format!("  Second port:      {}", yes_no(st.dual_channel)));
+    write_line(&alloc::format!("  Keyboard:         {}", if st.keyboard { "present" } else { "not detected" }));
+    write_line(&alloc::format!("  Mouse:            {}", if st.mouse { "present (no driver)" } else { "not detected" }));
+    if st.was_untranslated {
+        write_line("  Firmware left translation off; it was switched on.");
+    }
+    let (queued, dropped) = (interrupts::without_interrupts(|| SCANCODES.lock().len()), typeahead_dropped());
+    write_line(&alloc::format!("  Typeahead:        {} of {} scancodes queued, {} dropped", queued, TYPEAHEAD_SIZE, dropped));
+}
+
+const KBD_USAGE: &str = "Usage: kbd [rescan | layout [us|uk|de|fr|dvorak|colemak|jp]]";
+
+/// `kbd [rescan | layout [name]]`: shows PS/2 state, resets the controller and
+/// re-detects devices, or shows or changes the layout.
+pub fn kbd_cmd(args: &[&str]) -> crate::commands::Status {
+    use crate::console::write_line;
+    match args {
+        [] => {
+            print_status(&ps2_status());
+            write_line(&alloc::format!("  Layout:           {}", layout().name()));
+        }
+        [sub] if sub.eq_ignore_ascii_case("rescan") => {
+            let st = init();
+            crate::klog::info(&alloc::format!("ps2: rescan, keyboard={} mouse={}", st.keyboard, st.mouse));
+            print_status(&st);
+        }
+        [sub] if sub.eq_ignore_ascii_case("layout") => {
+            let mut names: HString<64> = HString::new();
+            for l in Layout::ALL {
+                let _ = names.push(' ');
+                let _ = names.push_str(l.name());
+            }
+            write_line(&alloc::format!("Layout: {} (available:{})", layout().name(), names));
+        }
+        [sub, name] if sub.eq_ignore_ascii_case("layout") => match Layout::parse(name) {
+            Some(l) => {
+                set_layout(l);
+                crate::settings::save();
+                write_line(&alloc::format!("Keyboard layout: {}", l.name()));
+            }
+            None => {
+                crate::console::write_err(KBD_USAGE);
+                return Err(2);
+            }
+        },
+        _ => {
+            crate::console::write_err(KBD_USAGE);
+            return Err(2);
+        }
+    }
+    Ok(())
+}
+
+/// One scancode byte and what it decoded to, if it completed a key event.
+struct RawKey {
+    scancode: u8,
+    event: Option<PcKeyEvent>,
+    decoded: Option<DecodedKey>,
+}
+
+/// Next scancode with its decoding, skipping flow control and the F12 hook.
+fn poll_raw() -> Option<RawKey> {
+    interrupts::without_interrupts(|| {
+        let mut kb = KEYBOARD.lock();
+        let scancode = kb.inner.read_scancode()?;
+        let event = kb.inner.kb.add_byte(scancode).ok().flatten();
+        let decoded = event.clone().and_then(|ev| {
+            kb.update_ctrl_state(&ev);
+            kb.inner.kb.process_keyevent(ev)
+        });
+        Some(RawKey { scancode, event, decoded })
+    })
+}
+
+#[derive(Default)]
+struct Modifiers {
+    lshift: bool,
+    rshift: bool,
+    lctrl: bool,
+    rctrl: bool,
+    alt: bool,
+    altgr: bool,
+    capslock: bool,
+    numlock: bool,
+}
+
+impl Modifiers {
+    fn update(&mut self, ev: &PcKeyEvent) {
+        let down = ev.state != KeyState::Up;
+        match ev.code {
+            KeyCode::LShift => self.lshift = down,
+            KeyCode::RShift => self.rshift = down,
+            KeyCode::LControl => self.lctrl = down,
+            KeyCode::RControl => self.rctrl = down,
+            KeyCode::LAlt => self.alt = down,
+            KeyCode::RAltGr => self.altgr = down,
+            KeyCode::CapsLock if ev.state == KeyState::Down => self.capslock = !self.capslock,
+            KeyCode::NumpadLock if ev.state == KeyState::Down => self.numlock = !self.numlock,
+            _ => {}
+        }
+    }
+
+    fn describe(&self) -> HString<48> {
+        let mut s = HString::new();
+        let flags = [
+            (self.lshift, "LSh"),
+            (self.rshift, "RSh"),
+            (self.lctrl, "LCt"),
+            (self.rctrl, "RCt"),
+            (self.alt, "Alt"),
+            (self.altgr, "AGr"),
+            (self.capslock, "Caps"),
+            (self.numlock, "Num"),
+        ];
+        for (on, name) in flags {
+            if on {
+                let _ = s.push_str(name);
+                let _ = s.push(' ');
+            }
+        }
+        if s.is_empty() {
+            let _ = s.push('-');
+        }
+        s
+    }
+}
+
+/// `kbddebug`: prints every scancode byte as it arrives with the key event
+/// and character it decodes to and the modifier state, until Esc. On exit
+/// lists the keys pressed most.
+pub fn kbddebug_cmd() {
+    use crate::console::write_line;
+    use alloc::format;
+
+    write_line("Scancodes live (set 1 as seen after translation). Press Esc to stop.");
+    write_line(&format!("{:<6}{:<20}{:<16}{}", "Byte", "Event", "Decoded", "Modifiers"));
+    let mut mods = Modifiers::default();
+    let mut presses: heapless::LinearMap<KeyCode, u32, 64> = heapless::LinearMap::new();
+    let dropped_before = typeahead_dropped();
+    interrupts::without_interrupts(|| PENDING.lock().clear());
+    RAW_MODE.store(true, Ordering::Relaxed);
+    loop {
+        let Some(raw) = poll_raw() else {
+            crate::idle::idle();
+            continue;
+        };
+        let event = match &raw.event {
+            Some(ev) => {
+                mods.update(ev);
+                if ev.state == KeyState::Down {
+                    match presses.get_mut(&ev.code) {
+                        Some(n) => *n += 1,
+                        None => {
+                            let _ = presses.insert(ev.code, 1);
+                        }
+                    }
+                }
+                format!("{:?} {:?}", ev.code, ev.state)
+            }
+            // Prefix bytes (0xE0, 0xE1) wait for the rest of the sequence.
+            None => alloc::string::String::from("..."),
+        };
+        let decoded = match raw.decoded {
+            Some(DecodedKey::Unicode(c)) if c.is_control() => format!("U+{:04X}", c as u32),
+            Some(DecodedKey::Unicode(c)) => format!("'{}'", c),
+            Some(DecodedKey::RawKey(k)) => format!("{:?}", k),
+            None => alloc::string::String::from("-"),
+        };
+        write_line(&format!("{:02X}    {:<20}{:<16}{}", raw.scancode, event, decoded, mods.describe()));
+        let escape = matches!(&raw.event, Some(ev) if ev.code == KeyCode::Escape && ev.state == KeyState::Down);
+        if escape {
+            break;
+        }
+    }
+    RAW_MODE.store(false, Ordering::Relaxed);
+
+    let mut top: heapless::Vec<(KeyCode, u32), 64> = presses.iter().map(|(k, n)| (*k, *n)).collect();
+    top.sort_unstable_by_key(|&(_, n)| core::cmp::Reverse(n));
+    let mut line = alloc::string::String::from("Most pressed:");
+    for (k, n) in top.iter().take(8) {
+        line.push_str(&format!(" {:?} x{}", k, n));
+    }
+    write_line(&line);
+    let dropped = typeahead_dropped() - dropped_before;
+    if dropped > 0 {
+        write_line(&format!("{} scancodes were dropped because the typeahead buffer was full.", dropped));
+    }
+}
+
+/// A floating bus reads back as 0xFF when there is no 8042 controller.
+pub fn controller_present() -> bool {
+    let mut status: Port<u8> = Port::new(0x64);
+    interrupts::without_interrupts(|| unsafe { status.read() } != 0xFF)
+}
+
+pub fn poll_event() -> Option<KeyEvent> {
+    interrupts::without_interrupts(|| {
+        PENDING.lock().pop_front().or_else(|| KEYBOARD.lock().poll_event())
+    })
+}
+
+/// Reads whatever the controller has into the pending queue, so Ctrl+S and
+/// Ctrl+Q take effect while a command is busy writing output.
+pub fn pump() {
+    if RAW_MODE.load(Ordering::Relaxed) {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let mut kb = KEYBOARD.lock();
+        let mut pending = PENDING.lock();
+        for _ in 0..PUMP_LIMIT {
+            if kb.inner.status_empty() {
+                break;
+            }
+            if let Some(evt) = kb.poll_event() {
+                let _ = pending.push_back(evt);
+            }
+        }
+    });
+}
+
+pub fn take_magnifier_toggle() -> bool {
+    MAGNIFIER_TOGGLE.swap(false, Ordering::Relaxed)
+}
+
+pub fn take_perf_overlay_toggle() -> bool {
+    PERF_OVERLAY_TOGGLE.swap(false, Ordering::Relaxed)
+}
+
+/// Whether Ctrl+C was pressed since the running command started.
+/// Long-running commands check this to stop early.
+pub fn cancel_requested() -> bool {
+    CANCEL_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Forgets a Ctrl+C, returning whether there was one.
+pub fn clear_cancel() -> bool {
+    CANCEL_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// True between Ctrl+S and Ctrl+Q.
+pub fn output_paused() -> bool {
+    OUTPUT_PAUSED.load(Ordering::Relaxed)
+}
+
+/// Waits up to `ms` milliseconds for a key other than a modifier to go down
+/// and returns where it is, whatever the layout prints on it. None on timeout
+/// or Ctrl+C.
+pub fn wait_keycode(ms: u64) -> Option<KeyCode> {
+    let start = crate::timer::ticks();
+    let ticks = ms * crate::timer::frequency() as u64 / 1000;
+    interrupts::without_interrupts(|| PENDING.lock().clear());
+    RAW_MODE.store(true, Ordering::Relaxed);
+    let code = loop {
+        if let Some(RawKey { event: Some(ev), .. }) = poll_raw() {
+            let modifier = matches!(
+                ev.code,
+                KeyCode::LShift | KeyCode::RShift | KeyCode::LControl | KeyCode::RControl | KeyCode::LAlt | KeyCode::RAltGr
+            );
+            if ev.state == KeyState::Down && !modifier {
+                break Some(ev.code);
+            }
+            continue;
+        }
+        if cancel_requested() || crate::timer::ticks() - start >= ticks {
+            break None;
+        }
+        crate::timer::request_wakeup(start + ticks);
+        crate::idle::idle();
+    };
+    RAW_MODE.store(false, Ordering::Relaxed);
+    code
+}
+
+/// Blocks (halting between polls) until a key event arrives.
+pub fn wait_event() -> KeyEvent {
+    loop {
+        if let Some(evt) = poll_event() {
+            return evt;
+        }
+        crate::idle::idle();
+    }
+}
+
+/// Like `wait_event`, but gives up after `ms` milliseconds, or straight away
+/// on Ctrl+C.
+pub fn wait_event_timeout(ms: u64) -> Option<KeyEvent> {
+    let start = crate::timer::ticks();
+    let ticks = ms * crate::timer::frequency() as u64 / 1000;
+    loop {
+        if let Some(evt) = poll_event() {
+            return Some(evt);
+        }
+        if cancel_requested() || crate::timer::ticks() - start >= ticks {
+            return None;
+        }
+        crate::timer::request_wakeup(start + ticks);
+        crate::idle::idle();
+    }
+}
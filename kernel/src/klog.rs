@@ -0,0 +1,249 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use heapless::{Deque, String as HString};
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+use crate::pager::Pager;
+use crate::{cmdline, console, events, serial, timer};
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Level> {
+        [Level::Debug, Level::Info, Level::Warn, Level::Error]
+            .into_iter()
+            .find(|l| l.name().eq_ignore_ascii_case(s))
+    }
+
+    fn from_u8(v: u8) -> Level {
+        match v {
+            0 => Level::Debug,
+            1 => Level::Info,
+            2 => Level::Warn,
+            _ => Level::Error,
+        }
+    }
+
+    fn color(self) -> u32 {
+        match self {
+            Level::Debug => 0x8080A0,
+            Level::Info => 0x7FB8FF,
+            Level::Warn => 0xFFD37F,
+            Level::Error => 0xFF5050,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+const CAPACITY: usize = 128;
+const MSG_LEN: usize = 120;
+
+struct Entry {
+    tick: u64,
+    level: Level,
+    msg: HString<MSG_LEN>,
+}
+
+static RING: Mutex<Deque<Entry, CAPACITY>> = Mutex::new(Deque::new());
+// Everything is kept in the ring; these only control what is echoed as it happens.
+// Warnings already print their own user-facing message, so only errors are echoed by default.
+static EMIT_LEVEL: AtomicU8 = AtomicU8::new(Level::Error as u8);
+static SERIAL_MIRROR: AtomicBool = AtomicBool::new(true);
+
+// Bochs and QEMU (`-debugcon stdio`) print whatever is written to this port.
+// It needs no setup, so it carries every entry at any level from the first
+// one on, before serial or the console are up.
+const E9_PORT: u16 = 0xE9;
+static E9_MIRROR: AtomicBool = AtomicBool::new(false);
+
+/// Applies `loglevel=`, `logserial=` and `loge9=` from the kernel command line.
+pub fn init() {
+    if let Some(level) = cmdline::value("loglevel").and_then(Level::parse) {
+        set_level(level);
+    }
+    match cmdline::value("logserial") {
+        Some("off") => set_serial_mirror(false),
+        Some("on") => set_serial_mirror(true),
+        _ => {}
+    }
+    if cmdline::value("loge9") == Some("on") && !set_e9_mirror(true) {
+        warn("klog: loge9=on, but there is no debug port at 0xE9");
+    }
+    events::subscribe(events::ALL, log_event);
+}
+
+pub fn set_level(level: Level) {
+    EMIT_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    Level::from_u8(EMIT_LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn set_serial_mirror(on: bool) {
+    SERIAL_MIRROR.store(on, Ordering::Relaxed);
+}
+
+pub fn serial_mirror() -> bool {
+    SERIAL_MIRROR.load(Ordering::Relaxed)
+}
+
+/// The port reads back 0xE9 when an emulator provides it.
+fn e9_present() -> bool {
+    unsafe { Port::<u8>::new(E9_PORT).read() == 0xE9 }
+}
+
+/// Mirrors every entry to port 0xE9. Returns false, leaving it off, if the
+/// port is not there.
+pub fn set_e9_mirror(on: bool) -> bool {
+    if on && !e9_present() {
+        return false;
+    }
+    E9_MIRROR.store(on, Ordering::Relaxed);
+    true
+}
+
+pub fn e9_mirror() -> bool {
+    E9_MIRROR.load(Ordering::Relaxed)
+}
+
+/// Takes no lock, so fault paths can use it too.
+fn e9_write(line: &str) {
+    let mut port = Port::<u8>::new(E9_PORT);
+    for b in line.bytes().chain(core::iter::once(b'\n')) {
+        unsafe { port.write(b) };
+    }
+}
+
+/// Writes a line to port 0xE9 if it is there, mirrored or not. For crash
+/// reports from before the console is up.
+pub fn e9_emergency_write(line: &str) {
+    if e9_mirror() || e9_present() {
+        e9_write(line);
+    }
+}
+
+fn make_entry(level: Level, msg: &str) -> Entry {
+    let mut s = HString::new();
+    for ch in msg.chars() {
+        if s.push(ch).is_err() {
+            break;
+        }
+    }
+    Entry { tick: timer::ticks(), level, msg: s }
+}
+
+fn push(ring: &mut Deque<Entry, CAPACITY>, entry: Entry) {
+    if ring.is_full() {
+        ring.pop_front();
+    }
+    let _ = ring.push_back(entry);
+}
+
+fn format_entry(e: &Entry) -> HString<160> {
+    let hz = timer::frequency() as u64;
+    let mut line = HString::new();
+    let _ = write!(
+        line,
+        "[{:>5}.{:02}] {:<5} {}",
+        e.tick / hz,
+        (e.tick % hz) * 100 / hz,
+        e.level.name(),
+        e.msg
+    );
+    line
+}
+
+pub fn log(level: Level, msg: &str) {
+    let entry = make_entry(level, msg);
+    if e9_mirror() {
+        e9_write(&format_entry(&entry));
+    }
+    if level >= self::level() {
+        if serial_mirror() {
+            serial::write(&format_entry(&entry));
+        }
+        // Best effort: logging from inside console code must not deadlock.
+        console::try_with_console(|c| {
+            let (_, bg) = c.default_colors();
+            let mut line: HString<160> = HString::new();
+            let _ = write!(line, "[{}] {}", level.name(), msg);
+            c.cwrite_line(&line, level.color(), bg);
+        });
+    }
+    interrupts::without_interrupts(|| push(&mut RING.lock(), entry));
+}
+
+/// Records without waiting for the lock, for exception and panic paths that
+/// may have interrupted a logger. Returns false if the entry was dropped.
+pub fn try_log(level: Level, msg: &str) -> bool {
+    let entry = make_entry(level, msg);
+    if e9_mirror() {
+        e9_write(&format_entry(&entry));
+    }
+    match RING.try_lock() {
+        Some(mut ring) => {
+            push(&mut ring, entry);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn debug(msg: &str) {
+    log(Level::Debug, msg);
+}
+
+pub fn info(msg: &str) {
+    log(Level::Info, msg);
+}
+
+pub fn warn(msg: &str) {
+    log(Level::Warn, msg);
+}
+
+/// Records every event on the bus, for tracing who reacted to what.
+fn log_event(event: &events::Event) {
+    debug(&alloc::format!("event: {} {}", event.topic, event.data));
+}
+
+/// Whether the ring's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    RING.is_locked()
+}
+
+pub fn dmesg_cmd(args: &[&str]) -> crate::commands::Status {
+    if args.first().is_some_and(|a| a.eq_ignore_ascii_case("clear")) {
+        interrupts::without_interrupts(|| RING.lock().clear());
+        console::write_line("Kernel log cleared.");
+        return Ok(());
+    }
+
+    let mut pager = Pager::new();
+    let count = interrupts::without_interrupts(|| {
+        let ring = RING.lock();
+        for e in ring.iter() {
+            pager.line(&format_entry(e));
+        }
+        ring.len()
+    });
+    if count == 0 {
+        pager.line("(kernel log is empty)");
+    }
+    pager.show();
+    Ok(())
+}
@@ -0,0 +1,876 @@
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String as Text;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::{String, Vec};
+use crate::keyboard::{self, KeyEvent};
+use crate::{commands, console, glyphs, history, serial};
+use crate::commands::Unfinished;
+
+pub const LINE_LEN: usize = 128;
+/// Longest command that can be built up from continued lines.
+const MAX_COMMAND: usize = 1024;
+
+const KNOWN_FG: u32 = 0x9CFF7F;
+const UNKNOWN_FG: u32 = 0xFF5050;
+
+static HIGHLIGHT: AtomicBool = AtomicBool::new(true);
+
+pub fn set_highlight(on: bool) {
+    HIGHLIGHT.store(on, Ordering::Relaxed);
+}
+
+pub fn highlight() -> bool {
+    HIGHLIGHT.load(Ordering::Relaxed)
+}
+
+/// Character range of the command word and whether it names a command or
+/// alias. None when highlighting is off or the line is blank.
+fn command_word(line: &str) -> Option<(Range<usize>, bool)> {
+    if !highlight() {
+        return None;
+    }
+    let start = line.chars().take_while(|c| c.is_ascii_whitespace()).count();
+    let word = line.trim_start().split_ascii_whitespace().next()?;
+    let range = start..start + word.chars().count();
+    Some((range, commands::is_known(word)))
+}
+
+/// Where keys come from.
+pub trait Input {
+    fn poll_key(&mut self) -> Option<KeyEvent>;
+}
+
+/// Where the prompt and the line being edited are drawn.
+pub trait Output {
+    /// Prints the prompt; input starts right after it.
+    fn prompt(&mut self);
+    /// Prints the `... ` prompt for the next line of an unfinished command.
+    fn more_prompt(&mut self);
+    /// Redraws the whole input line with the cursor `cursor` characters in.
+    fn render(&mut self, line: &str, cursor: usize);
+    /// Moves the cursor without changing the text.
+    fn move_cursor(&mut self, line: &str, cursor: usize);
+    /// Ends the input line `line` when Enter is pressed.
+    fn submit(&mut self, line: &str);
+    fn newline(&mut self);
+    fn write_line(&mut self, s: &str);
+}
+
+/// One editable input line plus its place in the shared command history.
+pub struct LineEditor {
+    line: String<LINE_LEN>,
+    draft_line: String<LINE_LEN>,
+    history_index: Option<usize>,
+    cursor_pos: usize,
+    cycle: Option<Cycle>,
+    more: Continuation,
+    /// Lines of a paste still to be entered, and what followed its last line
+    /// break, which becomes the input line once they are in.
+    pasted: VecDeque<Text>,
+    paste_tail: String<LINE_LEN>,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            draft_line: String::new(),
+            history_index: None,
+            cursor_pos: 0,
+            cycle: None,
+            more: Continuation::new(),
+            pasted: VecDeque::new(),
+            paste_tail: String::new(),
+        }
+    }
+
+    /// Prompt and current line again, after something else wrote to the output.
+    pub fn redraw(&self, out: &mut dyn Output) {
+        if self.more.is_pending() {
+            out.more_prompt();
+        } else {
+            out.prompt();
+        }
+        out.render(&self.line, self.cursor_pos);
+    }
+
+    /// Applies one key. Returns the finished command on Enter; the caller
+    /// runs it and prints the next prompt. A line ending in `\` or inside an
+    /// open quote asks for another line instead, and Esc drops the command.
+    pub fn feed(&mut self, key: KeyEvent, out: &mut dyn Output) -> Option<Text> {
+        if !matches!(key, KeyEvent::Tab) {
+            self.cycle = None;
+        }
+        match key {
+            KeyEvent::Char(ch) => {
+                if insert_char_at(&mut self.line, self.cursor_pos, ch) {
+                    self.cursor_pos += 1;
+                    if ch == '}' {
+                        self.expand_escape();
+                    }
+                    out.render(&self.line, self.cursor_pos);
+                }
+                self.history_index = None;
+            }
+            KeyEvent::CtrlBackspace => {
+                if delete_prev_word(&mut self.line, &mut self.cursor_pos) {
+                    out.render(&self.line, self.cursor_pos);
+                }
+                self.history_index = None;
+            }
+            KeyEvent::Backspace => {
+                if self.cursor_pos > 0 && remove_char_at(&mut self.line, self.cursor_pos - 1) {
+                    self.cursor_pos -= 1;
+                    out.render(&self.line, self.cursor_pos);
+                }
+                self.history_index = None;
+            }
+            KeyEvent::Delete => {
+                if remove_char_at(&mut self.line, self.cursor_pos) {
+                    out.render(&self.line, self.cursor_pos);
+                }
+                self.history_index = None;
+            }
+            KeyEvent::Left => {
+                if self.cursor_pos > 0 {
+                    self.cursor_pos -= 1;
+                    out.move_cursor(&self.line, self.cursor_pos);
+                }
+            }
+            KeyEvent::Right => {
+                if self.cursor_pos < self.line.chars().count() {
+                    self.cursor_pos += 1;
+                    out.move_cursor(&self.line, self.cursor_pos);
+                }
+            }
+            KeyEvent::CtrlLeft => {
+                if move_cursor_word_left(&self.line, &mut self.cursor_pos) {
+                    out.move_cursor(&self.line, self.cursor_pos);
+                }
+            }
+            KeyEvent::CtrlRight => {
+                if move_cursor_word_right(&self.line, &mut self.cursor_pos) {
+                    out.move_cursor(&self.line, self.cursor_pos);
+                }
+            }
+            KeyEvent::Up => self.history_up(out),
+            KeyEvent::Down => self.history_down(out),
+            KeyEvent::Tab => {
+                self.complete(out);
+                self.history_index = None;
+            }
+            KeyEvent::PastePicker => {
+                let text = crate::clipboard::pick();
+                self.redraw(out);
+                if let Some(text) = text {
+                    return self.paste(&crate::clipboard::flatten(&text), out);
+                }
+            }
+            KeyEvent::Paste(text) => return self.paste(&text, out),
+            KeyEvent::Enter => {
+                out.submit(&self.line);
+                let line = core::mem::take(&mut self.line);
+                self.draft_line.clear();
+                self.history_index = None;
+                self.cursor_pos = 0;
+                return self.push_line(&line, out);
+            }
+            KeyEvent::Escape => {
+                if self.more.is_pending() {
+                    self.more.clear();
+                    self.line.clear();
+                    self.cursor_pos = 0;
+                    out.newline();
+                    out.prompt();
+                }
+            }
+        }
+        None
+    }
+
+    fn push_line(&mut self, line: &str, out: &mut dyn Output) -> Option<Text> {
+        match self.more.push(line) {
+            Ok(Some(command)) => return Some(command),
+            Ok(None) => out.more_prompt(),
+            Err(msg) => {
+                out.write_line(msg);
+                out.prompt();
+            }
+        }
+        None
+    }
+
+    /// Puts `text` in at the cursor. Each line break in it is an Enter, so
+    /// pasted lines run one by one as if typed; the first command is
+    /// returned like from `feed` and the rest come from `next_pasted`. A
+    /// paste that doesn't fit is refused whole rather than cut short.
+    fn paste(&mut self, text: &str, out: &mut dyn Output) -> Option<Text> {
+        self.history_index = None;
+        let before: Text = self.line.chars().take(self.cursor_pos).collect();
+        let after: Text = self.line.chars().skip(self.cursor_pos).collect();
+        let whole = format!("{}{}{}", before, text, after);
+        let tail = whole.rsplit('\n').next().unwrap_or("");
+        let refused = if text.len() > MAX_COMMAND {
+            Some(format!("Paste refused: longer than {} bytes.", MAX_COMMAND))
+        } else if tail.len() > LINE_LEN {
+            Some(format!("Paste refused: it would leave a line longer than {} bytes.", LINE_LEN))
+        } else {
+            None
+        };
+        if let Some(msg) = refused {
+            out.newline();
+            out.write_line(&msg);
+            self.redraw(out);
+            return None;
+        }
+        let Some((lines, tail)) = whole.rsplit_once('\n') else {
+            self.line.clear();
+            let _ = self.line.push_str(&whole);
+            self.cursor_pos = before.chars().count() + text.chars().count();
+            out.render(&self.line, self.cursor_pos);
+            return None;
+        };
+        self.pasted = lines.split('\n').map(Text::from).collect();
+        self.paste_tail.clear();
+        let _ = self.paste_tail.push_str(tail);
+        self.line.clear();
+        self.cursor_pos = 0;
+        self.next_pasted(out)
+    }
+
+    /// Enters the remaining lines of a paste until one finishes a command,
+    /// and returns it. Callers run it, redraw, and call this again before
+    /// reading more keys.
+    pub fn next_pasted(&mut self, out: &mut dyn Output) -> Option<Text> {
+        while let Some(line) = self.pasted.pop_front() {
+            out.render(&line, line.chars().count());
+            out.submit(&line);
+            let command = self.push_line(&line, out);
+            if self.pasted.is_empty() {
+                self.line = core::mem::take(&mut self.paste_tail);
+                self.cursor_pos = self.line.chars().count();
+                if command.is_none() {
+                    out.render(&self.line, self.cursor_pos);
+                }
+            }
+            if command.is_some() {
+                return command;
+            }
+        }
+        None
+    }
+
+    /// Turns a `\u{XXXX}` escape just closed before the cursor into its
+    /// character, since the keyboard itself only produces ASCII.
+    fn expand_escape(&mut self) {
+        let before: String<LINE_LEN> = self.line.chars().take(self.cursor_pos).collect();
+        let Some(start) = before.rfind("\\u{") else { return; };
+        let Some((ch, len)) = glyphs::escape_at(&before[start..]) else { return; };
+        if start + len != before.len() {
+            return;
+        }
+        let first = before[..start].chars().count();
+        let escape_chars = before[start..].chars().count();
+        for _ in 0..escape_chars {
+            remove_char_at(&mut self.line, first);
+        }
+        insert_char_at(&mut self.line, first, ch);
+        self.cursor_pos = first + 1;
+    }
+
+    fn history_up(&mut self, out: &mut dyn Output) {
+        let hist_len = history::len();
+        if hist_len == 0 {
+            return;
+        }
+        if self.history_index.is_none() {
+            self.draft_line.clear();
+            let _ = self.draft_line.push_str(&self.line);
+        }
+        let new_idx = self
+            .history_index
+            .map(|i| i.saturating_sub(1))
+            .unwrap_or_else(|| hist_len.saturating_sub(1));
+        if let Some(new_line) = history::entry(new_idx) {
+            self.history_index = Some(new_idx);
+            self.set_line(&new_line, out);
+        } else {
+            self.history_index = None;
+        }
+    }
+
+    fn history_down(&mut self, out: &mut dyn Output) {
+        let hist_len = history::len();
+        let Some(idx) = self.history_index else {
+            return;
+        };
+        if idx + 1 < hist_len {
+            if let Some(new_line) = history::entry(idx + 1) {
+                self.history_index = Some(idx + 1);
+                self.set_line(&new_line, out);
+                return;
+            }
+        }
+        self.history_index = None;
+        let draft = self.draft_line.clone();
+        self.set_line(&draft, out);
+    }
+
+    fn set_line(&mut self, new_content: &str, out: &mut dyn Output) {
+        self.line.clear();
+        for ch in new_content.chars() {
+            if self.line.push(ch).is_err() {
+                break;
+            }
+        }
+        self.cursor_pos = self.line.chars().count();
+        out.render(&self.line, self.cursor_pos);
+    }
+
+    /// Completes the word before the cursor: command names and aliases for
+    /// the first word, `os` subcommands after `os`. A unique match is filled
+    /// in; otherwise the common prefix is, and when there is nothing left to
+    /// add the candidates are listed. Further presses cycle through them.
+    fn complete(&mut self, out: &mut dyn Output) {
+        if let Some(cycle) = self.cycle.as_mut() {
+            let name = cycle.matches[cycle.next].clone();
+            cycle.next = (cycle.next + 1) % cycle.matches.len();
+            let start = cycle.start;
+            while self.cursor_pos > start && remove_char_at(&mut self.line, start) {
+                self.cursor_pos -= 1;
+            }
+            for ch in name.chars() {
+                if !insert_char_at(&mut self.line, self.cursor_pos, ch) {
+                    break;
+                }
+                self.cursor_pos += 1;
+            }
+            out.render(&self.line, self.cursor_pos);
+            return;
+        }
+
+        let before: String<LINE_LEN> = self.line.chars().take(self.cursor_pos).collect();
+        let start = before.rfind(|c: char| c.is_ascii_whitespace()).map_or(0, |i| i + 1);
+        let prefix = &before[start..];
+        let words: Vec<&str, 8> = before[..start].split_ascii_whitespace().take(8).collect();
+        let matches = commands::complete(&words, prefix);
+        let Some(first) = matches.first() else {
+            return;
+        };
+        let common = matches.iter().fold(first.len(), |n, m| {
+            first.bytes().zip(m.bytes()).take(n).take_while(|(a, b)| a.eq_ignore_ascii_case(b)).count()
+        });
+        let mut insert: String<32> = String::new();
+        let _ = insert.push_str(&first[prefix.len().min(common)..common]);
+        let at_word_end = self.line.chars().nth(self.cursor_pos).is_none_or(|c| c.is_ascii_whitespace());
+        if matches.len() == 1 && at_word_end {
+            let _ = insert.push(' ');
+        }
+        if matches.len() > 1 {
+            self.cycle = Some(Cycle { start: before[..start].chars().count(), next: 0, matches: matches.clone() });
+        }
+        if insert.is_empty() {
+            if matches.len() > 1 {
+                out.newline();
+                let names: Vec<&str, 64> = matches.iter().map(|m| m.as_str()).collect();
+                out.write_line(&names.join("  "));
+                self.redraw(out);
+            }
+            return;
+        }
+        for ch in insert.chars() {
+            if !insert_char_at(&mut self.line, self.cursor_pos, ch) {
+                break;
+            }
+            self.cursor_pos += 1;
+        }
+        out.render(&self.line, self.cursor_pos);
+    }
+}
+
+/// Candidates left from an ambiguous Tab, taken in turn by the next presses.
+struct Cycle {
+    /// Character index where the word being completed starts.
+    start: usize,
+    next: usize,
+    matches: Vec<String<32>, 64>,
+}
+
+/// Reads one line from the keyboard after `prompt`, for commands that ask
+/// for input. None if Esc was pressed.
+pub fn read_line(prompt: &'static str) -> Option<Text> {
+    let mut out = ConsoleOutput::with_prompt(prompt);
+    let mut editor = LineEditor::new();
+    out.prompt();
+    loop {
+        match keyboard::wait_event() {
+            KeyEvent::Escape => {
+                out.newline();
+                return None;
+            }
+            key => {
+                if let Some(line) = editor.feed(key, &mut out) {
+                    return Some(line);
+                }
+            }
+        }
+    }
+}
+
+/// The PS/2 keyboard.
+pub struct KeyboardInput;
+
+impl Input for KeyboardInput {
+    fn poll_key(&mut self) -> Option<KeyEvent> {
+        keyboard::poll_event()
+    }
+}
+
+/// The framebuffer console: the line is redrawn in place after the prompt.
+pub struct ConsoleOutput {
+    origin: (usize, usize),
+    rendered_len: usize,
+    prompt: &'static str,
+    // Continued lines do not start with a command, so nothing is highlighted.
+    continuing: bool,
+}
+
+impl ConsoleOutput {
+    pub const fn new() -> Self {
+        Self::with_prompt(">")
+    }
+
+    pub const fn with_prompt(prompt: &'static str) -> Self {
+        Self { origin: (0, 0), rendered_len: 0, prompt, continuing: false }
+    }
+
+    fn draw_prompt(&mut self, prompt: &str) {
+        self.origin = console::with_console(|c| {
+            let (_, bg) = c.default_colors();
+            c.cwrite(prompt, c.cursor_color(), bg);
+            c.cursor_position()
+        });
+        self.rendered_len = 0;
+    }
+}
+
+impl Output for ConsoleOutput {
+    fn prompt(&mut self) {
+        self.continuing = false;
+        self.draw_prompt(self.prompt);
+    }
+
+    fn more_prompt(&mut self) {
+        self.continuing = true;
+        self.draw_prompt("... ");
+    }
+
+    fn render(&mut self, line: &str, cursor: usize) {
+        let highlight = command_word(line).filter(|_| !self.continuing).map(|(range, known)| (range, if known { KNOWN_FG } else { UNKNOWN_FG }));
+        let (x, y) = self.origin;
+        self.rendered_len = console::render_line_at(x, y, line, self.rendered_len, cursor, highlight);
+    }
+
+    fn move_cursor(&mut self, _line: &str, cursor: usize) {
+        let (x, y) = self.origin;
+        console::with_console(|c| c.move_cursor_to(x.saturating_add(cursor), y));
+    }
+
+    fn submit(&mut self, line: &str) {
+        console::record_input(line);
+        self.newline();
+    }
+
+    fn newline(&mut self) {
+        console::with_console(|c| c.newline());
+    }
+
+    fn write_line(&mut self, s: &str) {
+        console::write_line(s);
+    }
+}
+
+/// Bytes from COM1, with the VT100 escape sequences terminals send for
+/// arrows and Delete.
+///
+/// Pasted text is gathered and handed over whole as `KeyEvent::Paste`, for
+/// the editor to enter line by line. A paste is known by the terminal's
+/// bracketed paste markers or, from terminals that send none, by more input
+/// arriving right behind a line break, faster than anyone types.
+pub struct SerialInput {
+    escape: Vec<u8, 8>,
+    after_cr: bool,
+    paste: Paste,
+    /// The paste so far, kept to one byte over `MAX_COMMAND` so the editor
+    /// can tell it was too long.
+    pasted: Text,
+    lookahead: Option<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Paste {
+    None,
+    /// Between ESC [200~ and ESC [201~.
+    Bracketed,
+    /// Guessed from timing; over when the input goes quiet.
+    Guessed,
+}
+
+/// How long input may pause and still count as one paste. Several byte
+/// times at 115200 baud, far below the gap between keystrokes.
+const PASTE_GAP_US: u64 = 2_000;
+
+impl SerialInput {
+    pub const fn new() -> Self {
+        Self {
+            escape: Vec::new(),
+            after_cr: false,
+            paste: Paste::None,
+            pasted: Text::new(),
+            lookahead: None,
+        }
+    }
+
+    /// Waits up to PASTE_GAP_US for another byte and keeps it for later.
+    fn byte_follows(&mut self) -> bool {
+        if self.lookahead.is_some() {
+            return true;
+        }
+        let mut next = None;
+        crate::wait::until_micros(PASTE_GAP_US, || {
+            next = serial::try_read();
+            next.is_some()
+        });
+        self.lookahead = next;
+        next.is_some()
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        if let Some(byte) = self.lookahead.take().or_else(serial::try_read) {
+            return Some(byte);
+        }
+        if self.paste == Paste::Guessed && self.byte_follows() {
+            return self.lookahead.take();
+        }
+        None
+    }
+
+    fn end_paste(&mut self) -> Option<KeyEvent> {
+        self.paste = Paste::None;
+        let mut text = core::mem::take(&mut self.pasted);
+        // A trailing line break is left for the user's own Enter.
+        if text.len() <= MAX_COMMAND && text.ends_with('\n') {
+            text.pop();
+        }
+        (!text.is_empty()).then_some(KeyEvent::Paste(text))
+    }
+
+    fn pasted(&mut self, ch: char) -> Option<KeyEvent> {
+        if self.pasted.len() <= MAX_COMMAND {
+            self.pasted.push(ch);
+        }
+        None
+    }
+
+    /// A line break: Enter, unless it is part of a paste.
+    fn line_break(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.paste == Paste::None {
+            let mut follows = self.byte_follows();
+            // Terminals that send CR LF for Enter are not pasting.
+            if follows && byte == b'\r' && self.lookahead == Some(b'\n') {
+                self.lookahead = None;
+                follows = self.byte_follows();
+            }
+            if !follows {
+                return Some(KeyEvent::Enter);
+            }
+            self.paste = Paste::Guessed;
+        }
+        self.pasted('\n')
+    }
+
+    fn decode(&mut self, byte: u8) -> Option<KeyEvent> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        if !self.escape.is_empty() {
+            return self.decode_escape(byte);
+        }
+        let pasting = self.paste != Paste::None;
+        match byte {
+            0x1b => {
+                let _ = self.escape.push(byte);
+                None
+            }
+            // CR LF is one Enter.
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => self.line_break(byte),
+            b'\t' if pasting => self.pasted(' '),
+            b'\t' => Some(KeyEvent::Tab),
+            0x20..=0x7e if pasting => self.pasted(byte as char),
+            _ if pasting => None,
+            0x08 | 0x7f => Some(KeyEvent::Backspace),
+            // Ctrl+W
+            0x17 => Some(KeyEvent::CtrlBackspace),
+            0x20..=0x7e => Some(KeyEvent::Char(byte as char)),
+            _ => None,
+        }
+    }
+
+    fn decode_escape(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.escape.len() == 1 && byte != b'[' {
+            self.escape.clear();
+            return Some(KeyEvent::Escape);
+        }
+        if self.escape.push(byte).is_err() {
+            self.escape.clear();
+            return None;
+        }
+        if self.escape.len() < 3 || !(0x40..=0x7e).contains(&byte) {
+            return None;
+        }
+        let key = match &self.escape[2..] {
+            b"A" => Some(KeyEvent::Up),
+            b"B" => Some(KeyEvent::Down),
+            b"C" => Some(KeyEvent::Right),
+            b"D" => Some(KeyEvent::Left),
+            b"1;5C" => Some(KeyEvent::CtrlRight),
+            b"1;5D" => Some(KeyEvent::CtrlLeft),
+            b"3~" => Some(KeyEvent::Delete),
+            b"200~" => {
+                self.paste = Paste::Bracketed;
+                None
+            }
+            b"201~" => {
+                self.escape.clear();
+                return self.end_paste();
+            }
+            _ => None,
+        };
+        self.escape.clear();
+        // Keys inside a paste are text that happened to be escapes; drop them.
+        key.filter(|_| self.paste == Paste::None)
+    }
+}
+
+impl Input for SerialInput {
+    fn poll_key(&mut self) -> Option<KeyEvent> {
+        while let Some(byte) = self.next_byte() {
+            if let Some(key) = self.decode(byte) {
+                return Some(key);
+            }
+        }
+        // The input went quiet, so a guessed paste is over.
+        if self.paste == Paste::Guessed {
+            return self.end_paste();
+        }
+        None
+    }
+}
+
+/// A terminal on COM1: the line is redrawn with a carriage return and an
+/// erase-to-end-of-line, then the cursor is stepped back into place.
+pub struct SerialOutput;
+
+impl Output for SerialOutput {
+    fn prompt(&mut self) {
+        // Asks the terminal to mark pastes; repeated in case it was reset.
+        serial::write_raw("\x1b[?2004h> ");
+    }
+
+    fn more_prompt(&mut self) {
+        serial::write_raw("... ");
+    }
+
+    fn render(&mut self, line: &str, cursor: usize) {
+        match command_word(line) {
+            Some((range, known)) => {
+                // Word ranges are in characters; the line may not be ASCII.
+                let at = |i: usize| line.char_indices().nth(i).map_or(line.len(), |(b, _)| b);
+                let (start, end) = (at(range.start), at(range.end));
+                let color = if known { 32 } else { 31 };
+                serial::write_raw(&format!(
+                    "\r> {}\x1b[{}m{}\x1b[0m{}\x1b[K",
+                    &line[..start],
+                    color,
+                    &line[start..end],
+                    &line[end..]
+                ));
+            }
+            None => serial::write_raw(&format!("\r> {}\x1b[K", line)),
+        }
+        self.move_cursor(line, cursor);
+    }
+
+    fn move_cursor(&mut self, _line: &str, cursor: usize) {
+        // Column after "> " plus the cursor offset.
+        serial::write_raw(&format!("\r\x1b[{}C", cursor + 2));
+    }
+
+    fn submit(&mut self, _line: &str) {
+        self.newline();
+    }
+
+    fn newline(&mut self) {
+        serial::write_raw("\r\n");
+    }
+
+    fn write_line(&mut self, s: &str) {
+        serial::write(s);
+    }
+}
+
+fn insert_char_at(line: &mut String<LINE_LEN>, idx: usize, ch: char) -> bool {
+    let len = line.chars().count();
+    if idx > len {
+        return false;
+    }
+    let mut new_line = String::<LINE_LEN>::new();
+    let mut inserted = false;
+    for (i, existing) in line.chars().enumerate() {
+        if i == idx {
+            if new_line.push(ch).is_err() { return false; }
+            inserted = true;
+        }
+        if new_line.push(existing).is_err() { return false; }
+    }
+    if !inserted && new_line.push(ch).is_err() {
+        return false;
+    }
+    *line = new_line;
+    true
+}
+
+fn remove_char_at(line: &mut String<LINE_LEN>, idx: usize) -> bool {
+    let len = line.chars().count();
+    if idx >= len {
+        return false;
+    }
+    let mut new_line = String::<LINE_LEN>::new();
+    for (i, ch) in line.chars().enumerate() {
+        if i == idx {
+            continue;
+        }
+        if new_line.push(ch).is_err() {
+            return false;
+        }
+    }
+    *line = new_line;
+    true
+}
+
+fn delete_prev_word(line: &mut String<LINE_LEN>, cursor_pos: &mut usize) -> bool {
+    if *cursor_pos == 0 {
+        return false;
+    }
+    let mut chars = Vec::<char, LINE_LEN>::new();
+    for ch in line.chars() {
+        let _ = chars.push(ch);
+    }
+    let mut idx = (*cursor_pos).min(chars.len());
+    while idx > 0 && chars[idx - 1].is_ascii_whitespace() {
+        idx -= 1;
+    }
+    while idx > 0 && !chars[idx - 1].is_ascii_whitespace() {
+        idx -= 1;
+    }
+    if idx == *cursor_pos {
+        return false;
+    }
+    let remove_count = *cursor_pos - idx;
+    for _ in 0..remove_count {
+        chars.remove(idx);
+    }
+    line.clear();
+    for ch in chars.iter() {
+        let _ = line.push(*ch);
+    }
+    *cursor_pos = idx;
+    true
+}
+
+fn move_cursor_word_left(line: &String<LINE_LEN>, cursor_pos: &mut usize) -> bool {
+    if *cursor_pos == 0 {
+        return false;
+    }
+    let chars: Vec<char, LINE_LEN> = line.chars().collect();
+    let mut idx = (*cursor_pos).min(chars.len());
+    while idx > 0 && chars[idx - 1].is_ascii_whitespace() {
+        idx -= 1;
+    }
+    while idx > 0 && !chars[idx - 1].is_ascii_whitespace() {
+        idx -= 1;
+    }
+    if idx == *cursor_pos {
+        return false;
+    }
+    *cursor_pos = idx;
+    true
+}
+
+fn move_cursor_word_right(line: &String<LINE_LEN>, cursor_pos: &mut usize) -> bool {
+    let chars: Vec<char, LINE_LEN> = line.chars().collect();
+    if *cursor_pos >= chars.len() {
+        return false;
+    }
+    let mut idx = *cursor_pos;
+    while idx < chars.len() && !chars[idx].is_ascii_whitespace() {
+        idx += 1;
+    }
+    while idx < chars.len() && chars[idx].is_ascii_whitespace() {
+        idx += 1;
+    }
+    if idx == *cursor_pos {
+        return false;
+    }
+    *cursor_pos = idx;
+    true
+}
+
+/// Joins lines that end in `\` or stop inside a quote into one command.
+pub struct Continuation {
+    text: Text,
+    pending: bool,
+}
+
+impl Continuation {
+    pub const fn new() -> Self {
+        Self { text: Text::new(), pending: false }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.pending = false;
+    }
+
+    /// Adds the next line. Returns the whole command once it is complete,
+    /// or None while more lines are needed.
+    pub fn push(&mut self, line: &str) -> Result<Option<Text>, &'static str> {
+        if self.text.len() + line.len() + 1 > MAX_COMMAND {
+            self.clear();
+            return Err("Command too long; dropped.");
+        }
+        self.text.push_str(line);
+        self.pending = true;
+        match commands::unfinished(&self.text) {
+            Unfinished::No => {
+                self.pending = false;
+                Ok(Some(core::mem::take(&mut self.text)))
+            }
+            // The backslash and line break vanish, like in other shells.
+            Unfinished::Backslash => {
+                self.text.pop();
+                Ok(None)
+            }
+            // Words are split on whitespace anyway, so the break is a space.
+            Unfinished::Quote => {
+                self.text.push(' ');
+                Ok(None)
+            }
+        }
+    }
+}
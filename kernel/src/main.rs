@@ -24,17 +24,65 @@ mod serial;
 mod time;
 mod thud;
 mod wait;
+mod sensors;
+mod idle;
+mod acpi;
+mod power;
+mod pager;
+mod rng;
+mod fortune;
+mod settings;
+mod typetest;
+mod task;
+mod app;
+mod cmdline;
+mod klog;
+mod testmode;
+mod blit;
+mod selftest;
+mod clipboard;
+mod pci;
+mod smbios;
+mod paging;
+mod events;
+mod safemode;
+mod args;
+mod lineedit;
+mod serialshell;
+mod colorpick;
+mod glyphs;
+mod ramfs;
+mod bench;
+mod env;
+mod script;
+mod view;
+mod virtio;
+mod virtio_console;
+mod hostfs;
+mod symbols;
+mod gif;
+mod grep;
+mod fuzz;
+mod aml;
+mod calc;
+mod peek;
+mod firstboot;
 mod thudmodules {
     pub mod tin;
     pub mod min;
     pub mod utin;
+    pub mod temp;
+    pub mod drift;
+    pub mod cpu;
+    pub mod text;
 }
 
-use bootloader_api::{config::BootloaderConfig, entry_point, BootInfo};
+use alloc::format;
+use bootloader_api::{config::{BootloaderConfig, Mapping}, entry_point, BootInfo};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
 use console::{init_console, with_console};
-use keyboard::Keyboard;
-use heapless::{String, Vec};
+use lineedit::{ConsoleOutput, Input, KeyboardInput, LineEditor, Output};
 use x86_64::instructions::interrupts as cpu_intr;
 
 pub const OS_NAME: &str = "StratOS";
@@ -42,7 +90,8 @@ pub const OS_VERSION: &str = "1.A015.92.251130.EXENUS@cfc8a";
 const SHOWSPLASH: bool = true;
 
 static BOOTLOADER_CONFIG: BootloaderConfig = {
-    let cfg = BootloaderConfig::new_default();
+    let mut cfg = BootloaderConfig::new_default();
+    cfg.mappings.physical_memory = Some(Mapping::Dynamic);
     cfg
 };
 
@@ -50,27 +99,65 @@ entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     serial::write("Hello from kernel!");
+    cmdline::init(boot_info);
+    symbols::init(boot_info);
+    klog::init();
+    safemode::init();
     memory::init_memory(boot_info);
+    smbios::init(boot_info);
+    memory::boot_memtest();
+    klog::info(&format!("memory: {} KiB usable", memory::system_stats().total / 1024));
+    blit::init();
+    klog::info(&format!("blit: using {:?} copies", blit::mode()));
+    acpi::init(boot_info.rsdp_addr.into_option());
+    klog::info(if acpi::is_available() { "acpi: tables found" } else { "acpi: not available" });
+    pci::init();
+    virtio_console::init();
+    serial::init();
+    hostfs::init();
+    let settings_found = settings::load();
+    memory::apply_saved_quarantine();
 
     init_console(boot_info);
+    console::apply_saved_accessibility();
+    if let Some(name) = cmdline::value("theme").filter(|_| !safemode::active()) {
+        if !commands::apply_theme_named(name) {
+            klog::warn(&format!("config: no theme preset named {}", name));
+        }
+    }
     with_console(|c| c.reserve_hud_rows(1));
     thud::init();
     thudmodules::utin::init();
+    thudmodules::cpu::init();
     thudmodules::min::init();
     thudmodules::tin::init();
+    thudmodules::temp::init();
+    thudmodules::drift::init();
 
     interrupts::init_idt();
     pic::init_pic();
     timer::init_pit();
+    power::init();
     cpu_intr::enable();
     time::init_time();
+    time::init_rtc_irq();
+    rng::init();
     wait::init();
+    klog::info(&format!("wait: TSC calibrated at {} MHz", wait::tsc_hz() / 1_000_000));
+    let ps2 = keyboard::init();
+    klog::info(&format!("ps2: controller={} keyboard={} mouse={}", ps2.controller, ps2.keyboard, ps2.mouse));
+    keyboard::apply_saved_layout();
+    time::apply_saved_format();
+    task::init();
+    app::init();
+    testmode::init();
+    serialshell::init();
 
-    if SHOWSPLASH {
-    boot_splash::show();
+    if SHOWSPLASH && !safemode::active() && boot_splash::show() {
+        safemode::enter();
     }
 
-    let mut input_origin = with_console(|c| {
+    with_console(|c| {
         c.clear();
         c.write_line("==================================================\n");
         c.write_line(OS_NAME);
@@ -81,288 +168,136 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         c.write_line("");
         c.write_line("==================================================\n");
         c.newline();
-        c.put_char('>');
-        c.cursor_position()
     });
+    safemode::apply();
+    commands::restore_aliases();
+    firstboot::run(settings_found);
+    selftest::boot_check();
 
-    let mut kbd = Keyboard::new();
-    let mut line = String::<128>::new();
-    let mut draft_line = String::<128>::new();
-    let mut history_index: Option<usize> = None;
-    let mut cursor_pos: usize = 0;
-    let mut rendered_len: usize = 0;
+    supervise_shell()
+}
+
+const SHELL_STACK_SIZE: usize = 64 * 1024;
 
+/// Keeps a shell task alive. The watchdog ends the shell on a CPU fault or a
+/// runaway command; history, aliases and settings live in statics, so a fresh
+/// shell picks up where the old one left off.
+fn supervise_shell() -> ! {
     loop {
-        if let Some(evt) = kbd.poll_event() {
-            match evt {
-                keyboard::KeyEvent::Char(ch) => {
-                    if insert_char_at(&mut line, cursor_pos, ch) {
-                        cursor_pos += 1;
-                        redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len);
-                    }
-                    history_index = None;
-                }
-                keyboard::KeyEvent::CtrlBackspace => {
-                    if delete_prev_word(&mut line, &mut cursor_pos) {
-                        redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len);
-                    }
-                    history_index = None;
-                }
-                keyboard::KeyEvent::Backspace => {
-                    if cursor_pos > 0 && remove_char_at(&mut line, cursor_pos - 1) {
-                        cursor_pos -= 1;
-                        redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len);
-                    }
-                    history_index = None;
-                }
-                keyboard::KeyEvent::Delete => {
-                    if remove_char_at(&mut line, cursor_pos) {
-                        redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len);
-                    }
-                    history_index = None;
-                }
-                keyboard::KeyEvent::Left => {
-                    if cursor_pos > 0 {
-                        cursor_pos -= 1;
-                        with_console(|c| {
-                            let target_x = input_origin.0.saturating_add(cursor_pos);
-                            c.move_cursor_to(target_x, input_origin.1);
-                        });
-                    }
-                }
-                keyboard::KeyEvent::Right => {
-                    let len = line.chars().count();
-                    if cursor_pos < len {
-                        cursor_pos += 1;
-                        with_console(|c| {
-                            let target_x = input_origin.0.saturating_add(cursor_pos);
-                            c.move_cursor_to(target_x, input_origin.1);
-                        });
-                    }
-                }
-                keyboard::KeyEvent::CtrlLeft => {
-                    if move_cursor_word_left(&line, &mut cursor_pos) {
-                        with_console(|c| {
-                            let target_x = input_origin.0.saturating_add(cursor_pos);
-                            c.move_cursor_to(target_x, input_origin.1);
-                        });
-                    }
-                }
-                keyboard::KeyEvent::CtrlRight => {
-                    if move_cursor_word_right(&line, &mut cursor_pos) {
-                        with_console(|c| {
-                            let target_x = input_origin.0.saturating_add(cursor_pos);
-                            c.move_cursor_to(target_x, input_origin.1);
-                        });
-                    }
-                }
-                keyboard::KeyEvent::Up => {
-                    let hist_len = history::len();
-                    if hist_len == 0 {
-                        continue;
-                    }
-                    if history_index.is_none() {
-                        draft_line.clear();
-                        let _ = draft_line.push_str(&line);
-                    }
-                    let new_idx = history_index
-                        .map(|i| i.saturating_sub(1))
-                        .unwrap_or_else(|| hist_len.saturating_sub(1));
-                    if let Some(new_line) = history::entry(new_idx) {
-                        history_index = Some(new_idx);
-                        set_input_line(&mut line, &new_line, &mut cursor_pos, input_origin, &mut rendered_len);
-                    } else {
-                        history_index = None;
-                    }
-                }
-                keyboard::KeyEvent::Down => {
-                    let hist_len = history::len();
-                    if hist_len == 0 {
-                        continue;
-                    }
-                    if let Some(idx) = history_index {
-                        if idx + 1 < hist_len {
-                            let new_idx = idx + 1;
-                            if let Some(new_line) = history::entry(new_idx) {
-                                history_index = Some(new_idx);
-                                set_input_line(&mut line, &new_line, &mut cursor_pos, input_origin, &mut rendered_len);
-                            } else {
-                                history_index = None;
-                                set_input_line(&mut line, &draft_line, &mut cursor_pos, input_origin, &mut rendered_len);
-                            }
-                        } else {
-                            history_index = None;
-                            set_input_line(&mut line, &draft_line, &mut cursor_pos, input_origin, &mut rendered_len);
-                        }
-                    }
-                }
-                keyboard::KeyEvent::Enter => {
-                    with_console(|c| c.newline());
-                    commands::handle_line(&line);
-                    history::push(&line);
-                    line.clear();
-                    draft_line.clear();
-                    history_index = None;
-                    cursor_pos = 0;
-                    rendered_len = 0;
-                    input_origin = with_console(|c| {
-                        c.put_char('>');
-                        c.cursor_position()
-                    });
-                }
+        let shell = task::spawn_with_stack("shell", None, shell_main, 0, SHELL_STACK_SIZE)
+            .expect("could not start the shell task");
+        task::set_priority(shell, task::SHELL_PRIORITY);
+        task::supervise(shell);
+        let mut status = task::STATUS_FAULT;
+        while let Some(state) = task::state(shell) {
+            if let task::TaskState::Exited(code) = state {
+                status = code;
+                break;
             }
+            idle::idle();
         }
+        klog::warn(&format!("shell: restarted after exit status {}", status));
+        testmode::on_shell_restart(status);
+        commands::on_shell_restart();
+        script::on_shell_restart();
+        fuzz::on_shell_restart();
+        console::cwrite_line(
+            "Shell restarted; history and settings were kept.",
+            0xFFD37F,
+            console::default_bg(),
+        );
     }
 }
 
-fn insert_char_at(line: &mut String<128>, idx: usize, ch: char) -> bool {
-    let len = line.chars().count();
-    if idx > len {
-        return false;
-    }
-    let mut new_line = String::<128>::new();
-    let mut inserted = false;
-    for (i, existing) in line.chars().enumerate() {
-        if i == idx {
-            if new_line.push(ch).is_err() { return false; }
-            inserted = true;
-        }
-        if new_line.push(existing).is_err() { return false; }
-    }
-    if !inserted && new_line.push(ch).is_err() {
-        return false;
-    }
-    *line = new_line;
-    true
-}
+fn shell_main(_: usize) -> i32 {
+    let mut input = KeyboardInput;
+    let mut out = ConsoleOutput::new();
+    let mut editor = LineEditor::new();
+    console::begin_command_lines();
+    out.prompt();
 
-fn remove_char_at(line: &mut String<128>, idx: usize) -> bool {
-    let len = line.chars().count();
-    if idx >= len {
-        return false;
-    }
-    let mut new_line = String::<128>::new();
-    for (i, ch) in line.chars().enumerate() {
-        if i == idx {
-            continue;
-        }
-        if new_line.push(ch).is_err() {
-            return false;
+    loop {
+        let mut interrupted = power::poll_button();
+        interrupted |= power::poll_lid();
+        interrupted |= app::report_exits();
+        interrupted |= testmode::poll();
+        interrupted |= serialshell::poll();
+        interrupted |= time::poll_alarm();
+        time::poll_drift();
+        if interrupted {
+            console::begin_command_lines();
+            editor.redraw(&mut out);
         }
-    }
-    *line = new_line;
-    true
-}
 
-fn delete_prev_word(line: &mut String<128>, cursor_pos: &mut usize) -> bool {
-    if *cursor_pos == 0 {
-        return false;
-    }
-    let mut chars = Vec::<char, 128>::new();
-    for ch in line.chars() {
-        let _ = chars.push(ch);
-    }
-    let mut idx = (*cursor_pos).min(chars.len());
-    while idx > 0 && chars[idx - 1].is_ascii_whitespace() {
-        idx -= 1;
-    }
-    while idx > 0 && !chars[idx - 1].is_ascii_whitespace() {
-        idx -= 1;
-    }
-    if idx == *cursor_pos {
-        return false;
-    }
-    let remove_count = *cursor_pos - idx;
-    for _ in 0..remove_count {
-        chars.remove(idx);
-    }
-    line.clear();
-    for ch in chars.iter() {
-        let _ = line.push(*ch);
+        if let Some(key) = input.poll_key() {
+            if let Some(line) = editor.feed(key, &mut out) {
+                console::end_command_lines();
+                commands::handle_line(&line);
+                history::push(&line);
+                console::begin_command_lines();
+                out.prompt();
+            }
+        } else {
+            idle::idle();
+        }
     }
-    *cursor_pos = idx;
-    true
 }
 
-fn move_cursor_word_left(line: &String<128>, cursor_pos: &mut usize) -> bool {
-    if *cursor_pos == 0 {
-        return false;
-    }
-    let chars: Vec<char, 128> = line.chars().collect();
-    let mut idx = (*cursor_pos).min(chars.len());
-    while idx > 0 && chars[idx - 1].is_ascii_whitespace() {
-        idx -= 1;
-    }
-    while idx > 0 && !chars[idx - 1].is_ascii_whitespace() {
-        idx -= 1;
-    }
-    if idx == *cursor_pos {
-        return false;
-    }
-    *cursor_pos = idx;
-    true
-}
+// Set while the panic handler runs, so a panic inside it stops at once
+// instead of recursing.
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
-fn move_cursor_word_right(line: &String<128>, cursor_pos: &mut usize) -> bool {
-    let chars: Vec<char, 128> = line.chars().collect();
-    if *cursor_pos >= chars.len() {
-        return false;
-    }
-    let mut idx = *cursor_pos;
-    while idx < chars.len() && !chars[idx].is_ascii_whitespace() {
-        idx += 1;
-    }
-    while idx < chars.len() && chars[idx].is_ascii_whitespace() {
-        idx += 1;
-    }
-    if idx == *cursor_pos {
-        return false;
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let msg = alloc_str(info);
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        serial::emergency_write("=== PANIC WHILE HANDLING A PANIC ===");
+        serial::emergency_write(&msg);
+        klog::e9_emergency_write(&msg);
+        loop {
+            unsafe { x86::halt(); }
+        }
     }
-    *cursor_pos = idx;
-    true
-}
+    serial::emergency_write("=== KERNEL PANIC ===");
+    serial::emergency_write(&msg);
+    let stamp = time::crash_stamp();
+    serial::emergency_write(&stamp);
+    klog::try_log(klog::Level::Error, &msg);
 
-fn set_input_line(
-    line: &mut String<128>,
-    new_content: &str,
-    cursor_pos: &mut usize,
-    origin: (usize, usize),
-    rendered_len: &mut usize,
-) {
-    line.clear();
-    for ch in new_content.chars() {
-        if line.push(ch).is_err() {
-            break;
+    let supervised = task::current_is_supervised();
+    let title = if supervised { "=== SHELL PANIC ===" } else { "=== KERNEL PANIC ===" };
+    let lines = [("", 0xFFFFFF), (title, 0xFF0000), (msg.as_str(), 0xFFFF8F), (stamp.as_str(), 0xFFFFFF)];
+    match console::crash_lines(&lines) {
+        console::CrashScreen::Console => {}
+        // We panicked inside console code, which still holds the lock.
+        console::CrashScreen::Framebuffer => unsafe { console::force_unlock() },
+        console::CrashScreen::None => {
+            for (line, _) in &lines[1..] {
+                klog::e9_emergency_write(line);
+            }
+            // Nothing is running that could be recovered, and a reboot
+            // would most likely panic the same way again.
+            serial::emergency_write("Panic before the console was up, halting.");
+            klog::e9_emergency_write("Panic before the console was up, halting.");
+            loop {
+                unsafe { x86::halt(); }
+            }
         }
     }
-    *cursor_pos = line.chars().count();
-    redraw_input_line(line, *cursor_pos, origin, rendered_len);
-}
 
-fn redraw_input_line(
-    line: &String<128>,
-    cursor_pos: usize,
-    origin: (usize, usize),
-    rendered_len: &mut usize,
-) {
-    let new_len = console::render_line_at(origin.0, origin.1, line.as_str(), *rendered_len, cursor_pos);
-    *rendered_len = new_len;
-}
+    if supervised {
+        PANICKING.store(false, Ordering::Relaxed);
+        task::exit(task::STATUS_FAULT);
+    }
 
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
     with_console(|c| {
-        c.write_line("");
-        c.cwrite_line("=== KERNEL PANIC ===", 0xFF0000, 0x000000);
-        let msg = alloc_str(info);
-        c.cwrite_line(&msg, 0xFFFF8F, 0x000000);
         c.write_line("");
         c.cwrite_line("Attempting to fix via reboot...", 0x0047AB, 0x000000);
     });
 
-    crate::commands::wait_ticks(300);
+    crate::wait::bms(3000);
 
-    crate::commands::reboot();
+    crate::commands::reset_raw();
 
     with_console(|c| {
         c.write_line("Reboot failed! Halting...");
@@ -1,10 +1,12 @@
 use core::alloc::{Layout, GlobalAlloc};
 use core::mem::MaybeUninit;
-use core::ptr::{addr_of_mut, null_mut, NonNull};
+use core::ptr::{self, addr_of_mut, null_mut, NonNull};
+use core::sync::atomic::{AtomicU64, Ordering};
 use bootloader_api::info::{BootInfo, MemoryRegionKind};
 use crate::console;
 use linked_list_allocator::{Heap, LockedHeap};
 use spin::Mutex;
+use x86_64::instructions::interrupts;
 
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
@@ -62,6 +64,11 @@ pub unsafe fn kdealloc(ptr: *mut u8, size: usize, align: usize) {
     c.dealloc_count += 1;
 }
 
+/// True while the kernel heap lock is held, e.g. by code an interrupt landed in.
+pub fn heap_busy() -> bool {
+    ALLOCATOR.is_locked()
+}
+
 pub fn heap_stats() -> HeapStats {
     let allocator = ALLOCATOR.lock();
     let used = allocator.used();
@@ -74,6 +81,91 @@ pub fn heap_stats() -> HeapStats {
     *c
 }
 
+// Free blocks are sized in multiples of the allocator's hole header alignment.
+const HOLE_UNIT: usize = core::mem::align_of::<usize>();
+const MAX_PROBED_BLOCKS: usize = 64;
+pub const FRAG_BUCKETS: usize = 6;
+/// Upper bounds of the histogram buckets; the last bucket takes everything larger.
+pub const FRAG_BUCKET_LIMITS: [usize; FRAG_BUCKETS - 1] = [64, 256, 1024, 4096, 16 * 1024];
+
+#[derive(Copy, Clone, Default)]
+pub struct HeapFragmentation {
+    pub free: usize,
+    pub free_blocks: usize,
+    pub largest_free: usize,
+    pub histogram: [usize; FRAG_BUCKETS],
+    /// Bytes in blocks beyond the first MAX_PROBED_BLOCKS, not in the histogram.
+    pub unprobed_bytes: usize,
+    /// Free blocks that were directly adjacent and got merged when released.
+    pub merged: usize,
+}
+
+impl HeapFragmentation {
+    /// Share of free memory unusable by a single allocation, in percent.
+    pub fn percent(&self) -> usize {
+        (self.largest_free * 100).checked_div(self.free).map_or(0, |share| 100 - share)
+    }
+}
+
+fn probe_layout(units: usize) -> Layout {
+    Layout::from_size_align(units * HOLE_UNIT, HOLE_UNIT).unwrap()
+}
+
+/// Takes the largest free block out of `heap`, found by binary search on the
+/// allocation size. The allocator has no public free-list walk, so this is how
+/// free blocks are enumerated: claiming a block whole leaves the next largest.
+fn take_largest(heap: &mut Heap) -> Option<(NonNull<u8>, usize)> {
+    let (mut lo, mut hi) = (0, heap.free() / HOLE_UNIT);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        match heap.allocate_first_fit(probe_layout(mid)) {
+            Ok(p) => {
+                unsafe { heap.deallocate(p, probe_layout(mid)) };
+                lo = mid;
+            }
+            Err(()) => hi = mid - 1,
+        }
+    }
+    if lo == 0 {
+        return None;
+    }
+    let p = heap.allocate_first_fit(probe_layout(lo)).ok()?;
+    Some((p, lo * HOLE_UNIT))
+}
+
+/// Enumerates the kernel heap's free blocks by claiming them largest first,
+/// then hands them all back. Freeing coalesces neighbours, so any adjacent
+/// free blocks come back as one; the allocator merges on every free anyway,
+/// so that count is normally zero.
+pub fn heap_fragmentation() -> HeapFragmentation {
+    interrupts::without_interrupts(|| {
+        let mut heap = ALLOCATOR.lock();
+        let mut frag = HeapFragmentation { free: heap.free(), ..Default::default() };
+        let mut blocks: heapless::Vec<(NonNull<u8>, usize), MAX_PROBED_BLOCKS> = heapless::Vec::new();
+        while !blocks.is_full() {
+            let Some((p, size)) = take_largest(&mut heap) else {
+                break;
+            };
+            frag.largest_free = frag.largest_free.max(size);
+            let bucket = FRAG_BUCKET_LIMITS.iter().position(|&lim| size < lim).unwrap_or(FRAG_BUCKETS - 1);
+            frag.histogram[bucket] += 1;
+            frag.free_blocks += 1;
+            let _ = blocks.push((p, size));
+        }
+        frag.unprobed_bytes = heap.free();
+
+        blocks.sort_unstable_by_key(|(p, _)| p.as_ptr() as usize);
+        frag.merged = blocks
+            .windows(2)
+            .filter(|w| w[0].0.as_ptr() as usize + w[0].1 == w[1].0.as_ptr() as usize)
+            .count();
+        for (p, size) in blocks {
+            unsafe { heap.deallocate(p, probe_layout(size / HOLE_UNIT)) };
+        }
+        frag
+    })
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct SystemStats {
     pub reserved: usize,
@@ -82,12 +174,326 @@ pub struct SystemStats {
 }
 
 static mut TOTAL_RAM: usize = 0;
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Translates a physical address through the bootloader's full physical memory mapping.
+pub fn phys_to_virt(phys: u64) -> Option<u64> {
+    let offset = PHYS_OFFSET.load(Ordering::Relaxed);
+    if offset == u64::MAX {
+        return None;
+    }
+    offset.checked_add(phys)
+}
+
+pub const FRAME_SIZE: u64 = 4096;
+const MAX_FRAME_REGIONS: usize = 32;
+const MAX_FREED_RUNS: usize = 16;
+pub const MAX_BAD_FRAMES: usize = 64;
+// Leave real-mode memory alone; firmware and the AP trampoline area live there.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// Hands out physically contiguous runs of frames from the bootloader's usable
+/// regions. Each region is consumed from the front; freed runs are kept for
+/// reuse by later requests that fit.
+struct FrameAllocator {
+    // Usable memory as the bootloader reported it, for telling in-use frames from unmanaged ones.
+    bounds: heapless::Vec<(u64, u64), MAX_FRAME_REGIONS>,
+    regions: heapless::Vec<(u64, u64), MAX_FRAME_REGIONS>,
+    freed: heapless::Vec<(u64, usize), MAX_FREED_RUNS>,
+    allocated_frames: usize,
+    // Frames that failed a memory test; the first MAX_BAD_FRAMES are listed.
+    bad: heapless::Vec<u64, MAX_BAD_FRAMES>,
+    bad_count: usize,
+}
+
+static FRAMES: Mutex<FrameAllocator> = Mutex::new(FrameAllocator {
+    bounds: heapless::Vec::new(),
+    regions: heapless::Vec::new(),
+    freed: heapless::Vec::new(),
+    allocated_frames: 0,
+    bad: heapless::Vec::new(),
+    bad_count: 0,
+});
+
+impl FrameAllocator {
+    /// Cuts `phys` out of whichever region holds it so it is never handed out.
+    fn exclude_frame(&mut self, phys: u64) {
+        let Some(i) = self.regions.iter().position(|&(s, e)| (s..e).contains(&phys)) else {
+            return;
+        };
+        let (start, end) = self.regions[i];
+        let tail = (phys + FRAME_SIZE, end);
+        self.regions[i] = (start, phys);
+        if tail.1 > tail.0 && self.regions.push(tail).is_err() {
+            // No slot left for the tail: keep the larger half and lose the other.
+            if tail.1 - tail.0 > phys - start {
+                self.regions[i] = tail;
+            }
+        }
+    }
+
+    /// Cuts `phys` out of a freed run, splitting the run around it.
+    fn exclude_freed(&mut self, phys: u64) -> bool {
+        let Some(i) = self
+            .freed
+            .iter()
+            .position(|&(p, n)| (p..p + n as u64 * FRAME_SIZE).contains(&phys))
+        else {
+            return false;
+        };
+        let (start, count) = self.freed.swap_remove(i);
+        self.push_freed_excluding_bad(start, count);
+        true
+    }
+
+    /// Returns a run to the freed list, leaving out any bad frames in it.
+    fn push_freed_excluding_bad(&mut self, phys: u64, count: usize) {
+        let mut run_start = phys;
+        let end = phys + count as u64 * FRAME_SIZE;
+        let mut frame = phys;
+        while frame <= end {
+            if frame == end || self.is_bad(frame) {
+                if frame > run_start {
+                    // If the list is full the run is leaked rather than risking a double hand-out.
+                    let _ = self.freed.push((run_start, ((frame - run_start) / FRAME_SIZE) as usize));
+                }
+                run_start = frame + FRAME_SIZE;
+            }
+            frame += FRAME_SIZE;
+        }
+    }
+
+    fn is_bad(&self, phys: u64) -> bool {
+        self.bad.contains(&phys)
+    }
+
+    fn mark_bad(&mut self, phys: u64) -> Quarantine {
+        if self.is_bad(phys) {
+            return Quarantine::AlreadyBad;
+        }
+        let managed = self.bounds.iter().any(|&(s, e)| (s..e).contains(&phys));
+        let free = self.regions.iter().any(|&(s, e)| (s..e).contains(&phys));
+        self.exclude_frame(phys);
+        self.bad_count += 1;
+        let _ = self.bad.push(phys);
+        if free || self.exclude_freed(phys) {
+            Quarantine::Removed
+        } else if managed {
+            Quarantine::InUse
+        } else {
+            Quarantine::Unmanaged
+        }
+    }
+}
+
+pub enum Quarantine {
+    /// The frame was free and will never be handed out.
+    Removed,
+    /// The frame is allocated; it is withheld once its owner frees it.
+    InUse,
+    /// Not memory the frame allocator manages (kernel image, firmware, ...); only recorded.
+    Unmanaged,
+    AlreadyBad,
+}
+
+fn init_frames(boot_info: &BootInfo) {
+    let mut f = FRAMES.lock();
+    for r in boot_info.memory_regions.iter().filter(|r| r.kind == MemoryRegionKind::Usable) {
+        let start = (r.start.max(LOW_MEMORY_END) + FRAME_SIZE - 1) & !(FRAME_SIZE - 1);
+        let end = r.end & !(FRAME_SIZE - 1);
+        if end > start && f.regions.push((start, end)).is_err() {
+            break;
+        }
+    }
+    f.bounds = f.regions.clone();
+}
+
+/// Allocates `count` physically contiguous frames and returns the first frame's address.
+pub fn alloc_frames(count: usize) -> Option<u64> {
+    if count == 0 {
+        return None;
+    }
+    let mut f = FRAMES.lock();
+    if let Some(i) = f.freed.iter().position(|&(_, n)| n >= count) {
+        let (phys, n) = f.freed[i];
+        if n == count {
+            f.freed.swap_remove(i);
+        } else {
+            f.freed[i] = (phys + count as u64 * FRAME_SIZE, n - count);
+        }
+        f.allocated_frames += count;
+        return Some(phys);
+    }
+    let bytes = count as u64 * FRAME_SIZE;
+    let region = f.regions.iter_mut().find(|(start, end)| end - start >= bytes)?;
+    let phys = region.0;
+    region.0 += bytes;
+    f.allocated_frames += count;
+    Some(phys)
+}
+
+pub fn free_frames(phys: u64, count: usize) {
+    let mut f = FRAMES.lock();
+    f.allocated_frames = f.allocated_frames.saturating_sub(count);
+    f.push_freed_excluding_bad(phys, count);
+}
+
+/// Allocates a zeroed, frame-backed buffer of at least `bytes`, reachable
+/// through the physical memory mapping.
+pub fn alloc_buffer(bytes: usize) -> Option<&'static mut [u8]> {
+    let frames = (bytes as u64).div_ceil(FRAME_SIZE) as usize;
+    let phys = alloc_frames(frames)?;
+    let Some(virt) = phys_to_virt(phys) else {
+        free_frames(phys, frames);
+        return None;
+    };
+    unsafe {
+        ptr::write_bytes(virt as *mut u8, 0, bytes);
+        Some(core::slice::from_raw_parts_mut(virt as *mut u8, bytes))
+    }
+}
+
+/// Returns a buffer from `alloc_buffer`. The slice must not be used afterwards.
+pub fn free_buffer(buf: &'static mut [u8]) {
+    let frames = (buf.len() as u64).div_ceil(FRAME_SIZE) as usize;
+    let offset = PHYS_OFFSET.load(Ordering::Relaxed);
+    free_frames(buf.as_ptr() as u64 - offset, frames);
+}
+
+static MEMTEST_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `pattern(i)` to every word, then reads it all back.
+unsafe fn pattern_pass(words: *mut u64, count: usize, pattern: impl Fn(usize) -> u64) -> bool {
+    for i in 0..count {
+        ptr::write_volatile(words.add(i), pattern(i));
+    }
+    (0..count).all(|i| ptr::read_volatile(words.add(i)) == pattern(i))
+}
+
+/// Walking ones and walking zeros within one frame.
+unsafe fn test_frame_bits(virt: u64) -> bool {
+    let words = virt as *mut u64;
+    let count = FRAME_SIZE as usize / 8;
+    pattern_pass(words, count, |i| 1u64 << (i % 64))
+        && pattern_pass(words, count, |i| !(1u64 << (i % 64)))
+}
+
+/// `memtest=boot`: pattern-tests every frame the frame allocator owns before
+/// anything is allocated from it. Address-in-address covers a whole region
+/// before verifying, so aliased address lines show up; walking ones/zeros run
+/// per frame. Failing frames are cut out of the allocator.
+pub fn boot_memtest() {
+    if crate::cmdline::value("memtest") != Some("boot") {
+        return;
+    }
+    let offset = PHYS_OFFSET.load(Ordering::Relaxed);
+    if offset == u64::MAX {
+        crate::klog::warn("memtest: no physical memory mapping, skipped");
+        return;
+    }
+    let mut f = FRAMES.lock();
+    let regions = f.regions.clone();
+    let mut tested = 0u64;
+    for &(start, end) in regions.iter() {
+        crate::serial::write(&alloc::format!("memtest: {:#x}-{:#x}", start, end));
+        let words = (start + offset) as *mut u64;
+        let count = ((end - start) / 8) as usize;
+        unsafe {
+            for i in 0..count {
+                ptr::write_volatile(words.add(i), start + i as u64 * 8);
+            }
+        }
+        let mut frame = start;
+        while frame < end {
+            let virt = frame + offset;
+            let addr_ok = unsafe {
+                let w = virt as *const u64;
+                (0..FRAME_SIZE / 8).all(|i| ptr::read_volatile(w.add(i as usize)) == frame + i * 8)
+            };
+            if !addr_ok || !unsafe { test_frame_bits(virt) } {
+                let _ = f.mark_bad(frame);
+                crate::serial::write(&alloc::format!("memtest: bad frame {:#x}", frame));
+            }
+            frame += FRAME_SIZE;
+        }
+        tested += end - start;
+    }
+    MEMTEST_BYTES.store(tested, Ordering::Relaxed);
+    let bad = f.bad_count;
+    drop(f);
+    let msg = alloc::format!("memtest: {} MiB tested, {} bad frames", tested / (1024 * 1024), bad);
+    if bad > 0 {
+        crate::klog::warn(&msg);
+    } else {
+        crate::klog::info(&msg);
+    }
+}
+
+const SETTING_BAD_FRAMES: &str = "memory.bad";
+
+/// Quarantines the frame holding `phys` and saves the bad-frame list.
+/// Returns what happened to the frame and whether the whole list fit in settings.
+pub fn quarantine(phys: u64) -> (Quarantine, bool) {
+    let frame = phys & !(FRAME_SIZE - 1);
+    let result = interrupts::without_interrupts(|| FRAMES.lock().mark_bad(frame));
+    (result, save_bad_frames())
+}
+
+/// Stores bad frame numbers in settings as space-separated hex, as many as fit.
+fn save_bad_frames() -> bool {
+    use core::fmt::Write;
+    let frames = bad_frames().frames;
+    let mut value: heapless::String<{ crate::settings::MAX_VALUE }> = heapless::String::new();
+    let mut all = true;
+    for phys in frames.iter() {
+        let mut item: heapless::String<20> = heapless::String::new();
+        let sep = if value.is_empty() { "" } else { " " };
+        let _ = write!(item, "{}{:x}", sep, phys / FRAME_SIZE);
+        if value.push_str(&item).is_err() {
+            all = false;
+            break;
+        }
+    }
+    crate::settings::set(SETTING_BAD_FRAMES, &value) && all
+}
+
+/// Re-applies the quarantine list saved in settings.
+pub fn apply_saved_quarantine() {
+    let Some(saved) = crate::settings::get(SETTING_BAD_FRAMES) else {
+        return;
+    };
+    let mut f = FRAMES.lock();
+    for frame in saved.split_whitespace().filter_map(|v| u64::from_str_radix(v, 16).ok()) {
+        f.mark_bad(frame * FRAME_SIZE);
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct BadFrames {
+    /// Bytes covered by the boot memory test; 0 if it did not run.
+    pub tested_bytes: u64,
+    pub count: usize,
+    pub frames: heapless::Vec<u64, MAX_BAD_FRAMES>,
+}
+
+pub fn bad_frames() -> BadFrames {
+    let f = FRAMES.lock();
+    BadFrames {
+        tested_bytes: MEMTEST_BYTES.load(Ordering::Relaxed),
+        count: f.bad_count,
+        frames: f.bad.clone(),
+    }
+}
+
+pub fn frames_allocated_bytes() -> usize {
+    FRAMES.lock().allocated_frames * FRAME_SIZE as usize
+}
 
 pub fn system_stats() -> SystemStats {
     let total = get_total_ram();
-    // Reserved tracks memory the OS takes exclusively (kernel heap, static data, etc.).
+    // Reserved tracks memory the OS takes exclusively (kernel heap, static data, frame allocations, etc.).
     // The user arena is space meant to be given to apps, so we leave it out of "reserved".
-    let reserved = HEAP_SIZE;
+    let reserved = HEAP_SIZE + frames_allocated_bytes();
     let free = total.saturating_sub(reserved);
     SystemStats { reserved, free, total }
 }
@@ -96,6 +502,40 @@ fn get_total_ram() -> usize {
     unsafe { TOTAL_RAM }
 }
 
+const MAX_RAM_RANGES: usize = 128;
+
+/// Physical ranges the memory map says are RAM, adjacent ones merged. Reads
+/// outside them could land on device registers.
+static RAM_RANGES: Mutex<heapless::Vec<(u64, u64), MAX_RAM_RANGES>> = Mutex::new(heapless::Vec::new());
+
+/// RAM as opposed to reserved space: ours, the bootloader's, and the
+/// firmware's code, data and ACPI tables (UEFI types 1-7, 9, 10; E820 3, 4).
+fn is_ram(kind: MemoryRegionKind) -> bool {
+    match kind {
+        MemoryRegionKind::Usable | MemoryRegionKind::Bootloader => true,
+        MemoryRegionKind::UnknownUefi(t) => matches!(t, 1..=7 | 9 | 10),
+        MemoryRegionKind::UnknownBios(t) => matches!(t, 3 | 4),
+        _ => false,
+    }
+}
+
+fn record_ram_ranges(boot_info: &BootInfo) {
+    let mut ranges = RAM_RANGES.lock();
+    for r in boot_info.memory_regions.iter().filter(|r| is_ram(r.kind)) {
+        if let Some(last) = ranges.last_mut().filter(|l| l.1 == r.start) {
+            last.1 = r.end;
+        } else if ranges.push((r.start, r.end)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Whether all of `start..start + len` is RAM according to the memory map.
+pub fn is_ram_range(start: u64, len: u64) -> bool {
+    let Some(end) = start.checked_add(len) else { return false; };
+    RAM_RANGES.lock().iter().any(|&(s, e)| s <= start && end <= e)
+}
+
 pub fn init_memory(boot_info: &BootInfo) {
     let total: usize = boot_info
         .memory_regions
@@ -104,6 +544,11 @@ pub fn init_memory(boot_info: &BootInfo) {
         .map(|r| (r.end - r.start) as usize)
         .sum();
     unsafe { TOTAL_RAM = total; }
+    record_ram_ranges(boot_info);
+    if let Some(offset) = boot_info.physical_memory_offset.into_option() {
+        PHYS_OFFSET.store(offset, Ordering::Relaxed);
+        init_frames(boot_info);
+    }
     unsafe { init_heap(); }
     init_user_arena();
 }
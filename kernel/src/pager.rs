@@ -0,0 +1,74 @@
+#![allow(dead_code)]
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::{console, keyboard};
+use crate::keyboard::KeyEvent;
+
+const MORE_PROMPT: &str = "-- More -- (Space: page, Enter: line, q: quit)";
+
+pub struct Pager {
+    lines: Vec<String>,
+}
+
+impl Pager {
+    pub fn new() -> Self {
+        Self { lines: Vec::new() }
+    }
+
+    pub fn line(&mut self, s: &str) {
+        for part in s.split('\n') {
+            self.lines.push(String::from(part));
+        }
+    }
+
+    pub fn show(self) {
+        let (cols, rows) = console::size_chars();
+        let page = rows.saturating_sub(1).max(1);
+        let cols = cols.max(1);
+
+        let lines = if console::word_wrap() {
+            self.lines.iter().flat_map(|l| console::wrap_words(l, cols)).collect()
+        } else {
+            self.lines
+        };
+
+        let mut used = 0;
+        let mut budget = page;
+        for line in lines.iter() {
+            let height = line.chars().count().div_ceil(cols).max(1);
+            if used + height > budget && !console::output_redirected() {
+                match wait_for_more() {
+                    More::Page => budget = page,
+                    More::Line => budget = 1,
+                    More::Quit => return,
+                }
+                used = 0;
+            }
+            console::write_line(line);
+            used += height;
+        }
+    }
+}
+
+enum More {
+    Page,
+    Line,
+    Quit,
+}
+
+fn wait_for_more() -> More {
+    let (fg, bg) = console::default_colors();
+    console::cwrite(MORE_PROMPT, bg, fg);
+    let choice = loop {
+        match keyboard::wait_event() {
+            KeyEvent::Char(' ') => break More::Page,
+            KeyEvent::Enter | KeyEvent::Down => break More::Line,
+            KeyEvent::Char('q') | KeyEvent::Char('Q') | KeyEvent::Escape => break More::Quit,
+            _ => {}
+        }
+    };
+    let (_, y) = console::with_console(|c| c.cursor_position());
+    console::render_line_at(0, y, "", MORE_PROMPT.len(), 0, None);
+    choice
+}
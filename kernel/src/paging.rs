@@ -0,0 +1,208 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use heapless::String as HString;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::VirtAddr;
+use crate::args::{self, req, Spec};
+use crate::pager::Pager;
+use crate::{commands, console, memory};
+
+// Bytes covered by one entry at each level, from the PML4 down.
+const ENTRY_SIZE: [u64; 4] = [1 << 39, 1 << 30, 1 << 21, 1 << 12];
+
+pub struct Translation {
+    pub phys: u64,
+    /// Size of the page the address lives in.
+    pub page_size: u64,
+    /// Effective flags: writable/user only if every level allows it, NX if any level sets it.
+    pub flags: PageTableFlags,
+}
+
+fn table_at(phys: u64) -> Option<&'static PageTable> {
+    let virt = memory::phys_to_virt(phys)?;
+    Some(unsafe { &*(virt as *const PageTable) })
+}
+
+fn active_pml4() -> Option<&'static PageTable> {
+    let (frame, _) = Cr3::read();
+    table_at(frame.start_address().as_u64())
+}
+
+fn combine(parent: PageTableFlags, entry: PageTableFlags) -> PageTableFlags {
+    let inherited = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let mut flags = entry & !inherited | (entry & parent & inherited);
+    if parent.contains(PageTableFlags::NO_EXECUTE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+fn is_leaf(level: usize, flags: PageTableFlags) -> bool {
+    level == 3 || (level > 0 && flags.contains(PageTableFlags::HUGE_PAGE))
+}
+
+/// Walks the active page tables for `virt`. None if it is non-canonical or unmapped.
+pub fn translate(virt: u64) -> Option<Translation> {
+    let addr = VirtAddr::try_new(virt).ok()?;
+    let indices = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    let mut table = active_pml4()?;
+    let mut flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    for (level, idx) in indices.into_iter().enumerate() {
+        let entry = &table[idx];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        flags = combine(flags, entry.flags());
+        if is_leaf(level, entry.flags()) {
+            let page_size = ENTRY_SIZE[level];
+            return Some(Translation {
+                phys: entry.addr().as_u64() + (virt & (page_size - 1)),
+                page_size,
+                flags,
+            });
+        }
+        table = table_at(entry.addr().as_u64())?;
+    }
+    None
+}
+
+/// Adjacent leaf mappings with contiguous physical memory and identical
+/// flags and page size are merged into one range.
+struct Range {
+    virt: u64,
+    phys: u64,
+    len: u64,
+    page_size: u64,
+    flags: PageTableFlags,
+}
+
+struct Dump {
+    pager: Pager,
+    current: Option<Range>,
+    ranges: usize,
+    mapped: u64,
+}
+
+impl Dump {
+    fn leaf(&mut self, virt: u64, phys: u64, page_size: u64, flags: PageTableFlags) {
+        self.mapped += page_size;
+        if let Some(r) = &mut self.current {
+            if r.virt.wrapping_add(r.len) == virt
+                && r.phys + r.len == phys
+                && r.page_size == page_size
+                && r.flags == flags
+            {
+                r.len += page_size;
+                return;
+            }
+        }
+        self.flush();
+        self.current = Some(Range { virt, phys, len: page_size, page_size, flags });
+    }
+
+    fn flush(&mut self) {
+        let Some(r) = self.current.take() else {
+            return;
+        };
+        self.ranges += 1;
+        self.pager.line(&format!(
+            "{:#018x}-{:#018x} -> {:#012x}  {:>4} x{:<6} {}",
+            r.virt,
+            r.virt + r.len - 1,
+            r.phys,
+            size_name(r.page_size),
+            r.len / r.page_size,
+            flag_string(r.flags)
+        ));
+    }
+}
+
+fn walk(dump: &mut Dump, table: &PageTable, level: usize, base: u64, parent: PageTableFlags) {
+    for (i, entry) in table.iter().enumerate() {
+        let entry_flags = entry.flags();
+        if !entry_flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let mut virt = base + i as u64 * ENTRY_SIZE[level];
+        // The upper half of the address space is sign-extended.
+        if level == 0 && i >= 256 {
+            virt |= 0xFFFF_0000_0000_0000;
+        }
+        let flags = combine(parent, entry_flags);
+        if is_leaf(level, entry_flags) {
+            dump.leaf(virt, entry.addr().as_u64(), ENTRY_SIZE[level], flags);
+        } else if let Some(next) = table_at(entry.addr().as_u64()) {
+            walk(dump, next, level + 1, virt, flags);
+        }
+    }
+}
+
+fn size_name(size: u64) -> &'static str {
+    match size {
+        0x1000 => "4K",
+        0x20_0000 => "2M",
+        _ => "1G",
+    }
+}
+
+/// r, w, x, u(ser)/k(ernel), g(lobal), c(ache disabled), each `-` when off.
+fn flag_string(flags: PageTableFlags) -> HString<8> {
+    let mut s = HString::new();
+    let bits = [
+        ('r', true),
+        ('w', flags.contains(PageTableFlags::WRITABLE)),
+        ('x', !flags.contains(PageTableFlags::NO_EXECUTE)),
+    ];
+    for (c, on) in bits {
+        let _ = s.push(if on { c } else { '-' });
+    }
+    let _ = s.push(if flags.contains(PageTableFlags::USER_ACCESSIBLE) { 'u' } else { 'k' });
+    let _ = s.push(if flags.contains(PageTableFlags::GLOBAL) { 'g' } else { '-' });
+    let _ = s.push(if flags.contains(PageTableFlags::NO_CACHE) { 'c' } else { '-' });
+    s
+}
+
+/// `vmmap`: pages through every mapping in the active address space.
+pub fn vmmap_cmd() -> commands::Status {
+    let Some(pml4) = active_pml4() else {
+        console::write_err("vmmap: physical memory is not mapped, cannot read page tables");
+        return Err(1);
+    };
+    let mut dump = Dump { pager: Pager::new(), current: None, ranges: 0, mapped: 0 };
+    dump.pager.line(&format!("{:<41}{:<14}{:<13}{}", "Virtual", "Physical", "Pages", "Flags"));
+    let root = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    walk(&mut dump, pml4, 0, 0, root);
+    dump.flush();
+    dump.pager.line(&format!(
+        "{} ranges, {} MiB mapped. Flags: r w x, u/k user/kernel, g global, c cache disabled.",
+        dump.ranges,
+        dump.mapped / (1024 * 1024)
+    ));
+    dump.pager.show();
+    Ok(())
+}
+
+pub static VA2PA_ARGS: Spec = Spec { command: "va2pa", args: &[req("addr", args::ANY_INT)] };
+
+/// `va2pa <addr>`: translates one virtual address.
+pub fn va2pa_cmd(args: &[&str]) -> commands::Status {
+    let virt = VA2PA_ARGS.check(args)?.int(0).unwrap_or(0);
+    if VirtAddr::try_new(virt).is_err() {
+        console::write_err(&format!("{:#x} is not a canonical address.", virt));
+        return Err(1);
+    }
+    let Some(t) = translate(virt) else {
+        console::write_err(&format!("{:#018x} is not mapped.", virt));
+        return Err(1);
+    };
+    console::write_line(&format!(
+        "{:#018x} -> {:#x} ({} page, {})",
+        virt,
+        t.phys,
+        size_name(t.page_size),
+        flag_string(t.flags)
+    ));
+    Ok(())
+}
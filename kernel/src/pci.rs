@@ -0,0 +1,193 @@
+use heapless::Vec;
+use spin::Once;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+const MAX_DEVICES: usize = 64;
+const MAX_MEMORY_BARS: usize = 128;
+
+/// Every device's memory BARs, sized once at boot: sizing turns a device's
+/// memory decoding off for a moment, which a running driver wouldn't expect.
+static MEMORY_BARS: Once<Vec<(u64, u64), MAX_MEMORY_BARS>> = Once::new();
+
+#[derive(Copy, Clone)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+/// Configuration mechanism #1.
+pub fn config_read32(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    let address = 0x8000_0000u32
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xFC);
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address);
+        Port::<u32>::new(CONFIG_DATA).read()
+    }
+}
+
+pub fn config_write32(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    let address = 0x8000_0000u32
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xFC);
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address);
+        Port::<u32>::new(CONFIG_DATA).write(value);
+    }
+}
+
+/// Sets the I/O space and bus master bits in the command register, so the
+/// device answers its ports and may reach memory for DMA.
+pub fn enable_io_and_bus_master(dev: &PciDevice) {
+    let reg = config_read32(dev.bus, dev.device, dev.function, 0x04);
+    // The upper half is the status register, whose bits clear on a 1 write.
+    let command = (reg & 0xFFFF) | 0x1 | 0x4;
+    config_write32(dev.bus, dev.device, dev.function, 0x04, command);
+}
+
+/// The memory ranges a device decodes, as (base, size). Each BAR is sized
+/// by writing all ones to it with memory decoding off for the moment.
+fn memory_bars(dev: &PciDevice) -> Vec<(u64, u64), 6> {
+    let mut bars = Vec::new();
+    // Bridges have two BARs, ordinary devices six.
+    let header_type = (config_read32(dev.bus, dev.device, dev.function, 0x0C) >> 16) as u8 & 0x7F;
+    let count = match header_type {
+        0 => 6,
+        1 => 2,
+        _ => return bars,
+    };
+    let read = |offset: u8| config_read32(dev.bus, dev.device, dev.function, offset);
+    let write = |offset: u8, value: u32| config_write32(dev.bus, dev.device, dev.function, offset, value);
+    interrupts::without_interrupts(|| {
+        let command = read(0x04) & 0xFFFF;
+        write(0x04, command & !0x2);
+        let mut i = 0;
+        while i < count {
+            let offset = 0x10 + 4 * i as u8;
+            let low = read(offset);
+            if low & 1 != 0 {
+                i += 1;
+                continue;
+            }
+            let is_64 = (low >> 1) & 0x3 == 2 && i + 1 < count;
+            write(offset, !0);
+            let size_low = read(offset) & !0xF;
+            write(offset, low);
+            let (base, mask) = if is_64 {
+                let high = read(offset + 4);
+                write(offset + 4, !0);
+                let size_high = read(offset + 4);
+                write(offset + 4, high);
+                ((high as u64) << 32 | (low & !0xF) as u64, (size_high as u64) << 32 | size_low as u64)
+            } else {
+                ((low & !0xF) as u64, 0xFFFF_FFFF_0000_0000 | size_low as u64)
+            };
+            // An unimplemented BAR reads back as zero.
+            let implemented = if is_64 { mask != 0 } else { size_low != 0 };
+            if implemented && base != 0 {
+                let size = (!mask).wrapping_add(1);
+                let _ = bars.push((base, size));
+            }
+            i += if is_64 { 2 } else { 1 };
+        }
+        write(0x04, command);
+    });
+    bars
+}
+
+fn probe(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+    let id = config_read32(bus, device, function, 0x00);
+    let vendor_id = id as u16;
+    if vendor_id == 0xFFFF {
+        return None;
+    }
+    let class = config_read32(bus, device, function, 0x08);
+    Some(PciDevice {
+        bus,
+        device,
+        function,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+    })
+}
+
+/// Sizes the memory BARs of every device. Call before any PCI driver starts.
+pub fn init() {
+    MEMORY_BARS.call_once(|| {
+        let mut all = Vec::new();
+        for dev in scan().iter() {
+            for &bar in memory_bars(dev).iter() {
+                let _ = all.push(bar);
+            }
+        }
+        all
+    });
+}
+
+/// The memory ranges of all devices as (base, size), as found by `init`.
+pub fn memory_ranges() -> &'static [(u64, u64)] {
+    MEMORY_BARS.get().map(|bars| bars.as_slice()).unwrap_or(&[])
+}
+
+/// Brute-force scan of every bus/device/function.
+pub fn scan() -> Vec<PciDevice, MAX_DEVICES> {
+    let mut found = Vec::new();
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let Some(first) = probe(bus, device, 0) else { continue; };
+            let _ = found.push(first);
+            let header_type = (config_read32(bus, device, 0, 0x0C) >> 16) as u8;
+            if header_type & 0x80 == 0 {
+                continue;
+            }
+            for function in 1..8u8 {
+                if let Some(dev) = probe(bus, device, function) {
+                    let _ = found.push(dev);
+                }
+            }
+        }
+    }
+    found
+}
+
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "Storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus controller",
+        (0x0C, _) => "Serial bus controller",
+        _ => "Device",
+    }
+}
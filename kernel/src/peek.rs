@@ -0,0 +1,172 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+use x86_64::structures::paging::PageTableFlags;
+use crate::args::{self, opt, req, Kind, Spec};
+use crate::pager::Pager;
+use crate::{commands, console, memory, paging, pci};
+
+// `peek` (or `hexdump`) shows memory as hex and ASCII, `poke` writes one
+// value. Addresses are virtual unless -p makes them physical. Nothing is read
+// unless it is known to be safe: virtual pages must be mapped, cached and
+// backed by RAM, physical ranges must be RAM in the memory map, so a typo
+// cannot read a device register. Writes are the reverse: they go to device registers (a PCI
+// memory BAR or the local APIC) and anywhere else only with yes-i-know.
+
+const DEFAULT_LEN: u64 = 256;
+const MAX_LEN: u64 = 64 * 1024;
+const PER_LINE: usize = 16;
+
+pub static PEEK_ARGS: Spec = Spec {
+    command: "peek",
+    args: &[req("addr", args::ANY_INT), opt("len", Kind::Int { min: 1, max: MAX_LEN })],
+};
+
+/// Checks that every page of the virtual range can be read: mapped, cached,
+/// and backed by RAM in the memory map, since a cached mapping can still
+/// point at a device.
+fn check_virtual(start: u64, len: u64) -> Result<(), String> {
+    let mut addr = start;
+    let end = start.checked_add(len).ok_or_else(|| String::from("range wraps around"))?;
+    while addr < end {
+        let Some(t) = paging::translate(addr) else {
+            return Err(format!("{:#x} is not mapped", addr));
+        };
+        if t.flags.contains(PageTableFlags::NO_CACHE) {
+            return Err(format!("{:#x} is uncached, probably device memory", addr));
+        }
+        // On to the start of the next page.
+        let next = (addr | (t.page_size - 1)).saturating_add(1);
+        if !memory::is_ram_range(t.phys, next.min(end) - addr) {
+            return Err(format!("{:#x} maps {:#x}, which is not RAM in the memory map", addr, t.phys));
+        }
+        addr = next;
+    }
+    Ok(())
+}
+
+fn check_physical(start: u64, len: u64) -> Result<u64, String> {
+    if !memory::is_ram_range(start, len) {
+        return Err(format!("{:#x}..{:#x} is not all RAM in the memory map", start, start.saturating_add(len)));
+    }
+    memory::phys_to_virt(start).ok_or_else(|| String::from("physical memory is not mapped"))
+}
+
+fn dump_line(pager: &mut Pager, shown: u64, bytes: &[u8]) {
+    let mut line = format!("{:016x}  ", shown);
+    for i in 0..PER_LINE {
+        match bytes.get(i) {
+            Some(b) => {
+                let _ = write!(line, "{:02x} ", b);
+            }
+            None => line.push_str("   "),
+        }
+        if i == PER_LINE / 2 - 1 {
+            line.push(' ');
+        }
+    }
+    line.push_str(" |");
+    line.extend(bytes.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }));
+    line.push('|');
+    pager.line(&line);
+}
+
+/// `peek [-p] <addr> [len]`: hex and ASCII dump, 256 bytes unless told.
+pub fn peek_cmd(args: &[&str]) -> commands::Status {
+    let (physical, rest) = match args.split_first() {
+        Some((flag, rest)) if flag.eq_ignore_ascii_case("-p") => (true, rest),
+        _ => (false, args),
+    };
+    let a = PEEK_ARGS.check(rest)?;
+    let Some(start) = a.int(0) else { return Ok(()); };
+    let len = a.int(1).unwrap_or(DEFAULT_LEN);
+
+    let checked = if physical { check_physical(start, len) } else { check_virtual(start, len).map(|_| start) };
+    let virt = match checked {
+        Ok(virt) => virt,
+        Err(msg) => {
+            console::write_err(&format!("peek: {}", msg));
+            return Err(1);
+        }
+    };
+
+    let mut pager = Pager::new();
+    let mut line = [0u8; PER_LINE];
+    let mut offset = 0;
+    while offset < len {
+        let n = (len - offset).min(PER_LINE as u64) as usize;
+        for (i, b) in line[..n].iter_mut().enumerate() {
+            *b = unsafe { core::ptr::read_volatile((virt + offset + i as u64) as *const u8) };
+        }
+        dump_line(&mut pager, start + offset, &line[..n]);
+        offset += n as u64;
+    }
+    pager.show();
+    Ok(())
+}
+
+pub static POKE_ARGS: Spec = Spec {
+    command: "poke",
+    args: &[req("addr", args::ANY_INT), req("value", args::ANY_INT), opt("width", Kind::Choice(&["1", "2", "4"]))],
+};
+
+const IA32_APIC_BASE: u32 = 0x1B;
+
+/// Whether `phys..phys + len` lies in a device's registers: a PCI memory BAR
+/// or the local APIC page.
+fn is_register_range(phys: u64, len: u64) -> bool {
+    let inside = |base: u64, size: u64| base <= phys && phys + len <= base.saturating_add(size);
+    let apic = unsafe { x86::msr::rdmsr(IA32_APIC_BASE) } & 0xF_FFFF_F000;
+    inside(apic, 0x1000)
+        || pci::memory_ranges().iter().any(|&(base, size)| inside(base, size))
+}
+
+/// `poke [-p] [yes-i-know] <addr> <value> [1|2|4]`: one aligned write, 4 bytes
+/// wide unless told.
+pub fn poke_cmd(args: &[&str]) -> commands::Status {
+    let physical = args.iter().any(|a| a.eq_ignore_ascii_case("-p"));
+    let force = args.contains(&"yes-i-know");
+    let rest: heapless::Vec<&str, 8> =
+        args.iter().copied().filter(|a| !a.eq_ignore_ascii_case("-p") && *a != "yes-i-know").collect();
+    let a = POKE_ARGS.check(&rest)?;
+    let (Some(addr), Some(value)) = (a.int(0), a.int(1)) else { return Ok(()); };
+    let width: u64 = a.word(2).and_then(|w| w.parse().ok()).unwrap_or(4);
+
+    let fail = |msg: String| -> commands::Status {
+        console::write_err(&format!("poke: {}", msg));
+        Err(1)
+    };
+    if value >> (width * 8) != 0 {
+        return fail(format!("{:#x} does not fit in {} bytes", value, width));
+    }
+    if addr % width != 0 {
+        return fail(format!("{:#x} is not aligned to {} bytes", addr, width));
+    }
+    let virt = if physical {
+        match memory::phys_to_virt(addr) {
+            Some(v) => v,
+            None => return fail(String::from("physical memory is not mapped")),
+        }
+    } else {
+        addr
+    };
+    let Some(t) = paging::translate(virt) else {
+        return fail(format!("{:#x} is not mapped", virt));
+    };
+    if !t.flags.contains(PageTableFlags::WRITABLE) {
+        return fail(format!("{:#x} is mapped read-only", virt));
+    }
+    if !force && !is_register_range(t.phys, width) {
+        return fail(format!("{:#x} is not a device register; add yes-i-know to write it anyway", t.phys));
+    }
+
+    unsafe {
+        match width {
+            1 => core::ptr::write_volatile(virt as *mut u8, value as u8),
+            2 => core::ptr::write_volatile(virt as *mut u16, value as u16),
+            _ => core::ptr::write_volatile(virt as *mut u32, value as u32),
+        }
+    }
+    console::write_line(&format!("Wrote {:#x} ({} bytes) to {:#x}.", value, width, addr));
+    Ok(())
+}
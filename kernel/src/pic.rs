@@ -31,7 +31,55 @@ pub fn init_pic() {
         pic1_data.write(ICW4_8086);
         pic2_data.write(ICW4_8086);
 
-        pic1_data.write(0b1111_1110);
+        pic1_data.write(0b1111_1100);
         pic2_data.write(0xFF);
     }
 }
+
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// In-service registers of both PICs, slave in the high byte.
+pub fn read_isr() -> u16 {
+    unsafe {
+        Port::<u8>::new(PIC1_CMD).write(OCW3_READ_ISR);
+        Port::<u8>::new(PIC2_CMD).write(OCW3_READ_ISR);
+        ((Port::<u8>::new(PIC2_CMD).read() as u16) << 8) | Port::<u8>::new(PIC1_CMD).read() as u16
+    }
+}
+
+/// A spurious IRQ 7 or 15 is raised when a request goes away before the CPU
+/// acknowledges it; the matching in-service bit is then clear. Spurious IRQ 7
+/// needs no EOI; spurious IRQ 15 still needs one for the master's cascade line.
+pub fn is_spurious(irq: u8) -> bool {
+    read_isr() & (1 << irq) == 0
+}
+
+pub fn end_of_interrupt_master() {
+    unsafe { Port::<u8>::new(PIC1_CMD).write(0x20) };
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            Port::<u8>::new(PIC2_CMD).write(0x20);
+        }
+        Port::<u8>::new(PIC1_CMD).write(0x20);
+    }
+}
+
+pub fn unmask(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            let mut pic2_data = Port::<u8>::new(PIC2_DATA);
+            let mask: u8 = pic2_data.read();
+            pic2_data.write(mask & !(1 << (irq - 8)));
+            let mut pic1_data = Port::<u8>::new(PIC1_DATA);
+            let mask: u8 = pic1_data.read();
+            pic1_data.write(mask & !(1 << 2));
+        } else {
+            let mut pic1_data = Port::<u8>::new(PIC1_DATA);
+            let mask: u8 = pic1_data.read();
+            pic1_data.write(mask & !(1 << irq));
+        }
+    }
+}
@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Once;
+use crate::{acpi, aml, commands, console, klog};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ButtonPolicy {
+    Shutdown,
+    Ignore,
+    Prompt,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum LidPolicy {
+    Ignore,
+    Shutdown,
+}
+
+static BUTTON_POLICY: AtomicU8 = AtomicU8::new(ButtonPolicy::Prompt as u8);
+static LID_POLICY: AtomicU8 = AtomicU8::new(LidPolicy::Ignore as u8);
+/// The lid device, if the firmware describes one.
+static LID: Once<Option<String>> = Once::new();
+
+pub fn init() {
+    if !acpi::enable_events() {
+        return;
+    }
+    let lid = LID.call_once(|| aml::find_device("PNP0C0D"));
+    if let Some(path) = lid {
+        klog::info(&format!("power: lid at {}", path));
+    }
+    if let Some(irq) = acpi::sci_irq() {
+        crate::pic::unmask(irq);
+    }
+}
+
+pub fn set_button_policy(policy: ButtonPolicy) {
+    BUTTON_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn button_policy() -> ButtonPolicy {
+    match BUTTON_POLICY.load(Ordering::Relaxed) {
+        0 => ButtonPolicy::Shutdown,
+        1 => ButtonPolicy::Ignore,
+        _ => ButtonPolicy::Prompt,
+    }
+}
+
+pub fn set_lid_policy(policy: LidPolicy) {
+    LID_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn lid_policy() -> LidPolicy {
+    match LID_POLICY.load(Ordering::Relaxed) {
+        1 => LidPolicy::Shutdown,
+        _ => LidPolicy::Ignore,
+    }
+}
+
+pub fn has_lid() -> bool {
+    LID.get().is_some_and(|l| l.is_some())
+}
+
+/// Runs pending GPE handlers and acts on a lid notification among them.
+/// Returns true if anything was printed.
+pub fn poll_lid() -> bool {
+    let notified = acpi::dispatch_gpes();
+    let Some(Some(lid)) = LID.get() else { return false; };
+    if !notified.iter().any(|(dev, _)| dev == lid) {
+        return false;
+    }
+    let open = match aml::evaluate(&format!("{}._LID", lid), &[]).and_then(|v| v.int()) {
+        Ok(v) => v != 0,
+        Err(e) => {
+            klog::warn(&format!("power: {}._LID failed: {}", lid, e.describe()));
+            return false;
+        }
+    };
+    klog::info(if open { "power: lid opened" } else { "power: lid closed" });
+    if open || lid_policy() == LidPolicy::Ignore {
+        return false;
+    }
+    console::write_line("");
+    console::write_line("Lid closed.");
+    commands::save_state();
+    commands::shutdown();
+}
+
+/// Acts on a pending power button press. Returns true if anything was printed.
+pub fn poll_button() -> bool {
+    if !acpi::take_power_button_event() {
+        return false;
+    }
+    match button_policy() {
+        ButtonPolicy::Ignore => false,
+        ButtonPolicy::Shutdown => {
+            console::write_line("");
+            console::write_line("Power button pressed.");
+            commands::save_state();
+            commands::shutdown();
+        }
+        ButtonPolicy::Prompt => {
+            console::write_line("");
+            if console::prompt_yes_no("Power button pressed. Shut down now?") {
+                commands::save_state();
+                commands::shutdown();
+            }
+            console::write_line("Shutdown cancelled.");
+            true
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use alloc::format;
+use alloc::vec::Vec;
+use heapless::String as HString;
+use spin::Mutex;
+use crate::args::{req, Kind, Spec};
+use crate::{commands, console};
+
+// Named in-memory files. There is no writable storage yet, so this is where
+// redirected output and anything else that needs a file goes; it is gone on
+// reboot.
+
+pub const NAME_LEN: usize = 32;
+const MAX_FILES: usize = 32;
+const MAX_FILE_SIZE: usize = 256 * 1024;
+
+struct File {
+    name: HString<NAME_LEN>,
+    data: Vec<u8>,
+}
+
+static FILES: Mutex<Vec<File>> = Mutex::new(Vec::new());
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= NAME_LEN
+        && name.chars().all(|c| c.is_ascii_graphic() && !matches!(c, '/' | '>' | '&' | '|'))
+}
+
+/// Whether the file table's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    FILES.is_locked()
+}
+
+/// Replaces or appends to the file `name`, creating it if needed.
+pub fn write(name: &str, bytes: &[u8], append: bool) -> Result<(), &'static str> {
+    if !valid_name(name) {
+        return Err("bad file name");
+    }
+    let mut files = FILES.lock();
+    let index = match files.iter().position(|f| f.name == name) {
+        Some(i) => i,
+        None => {
+            if files.len() >= MAX_FILES {
+                return Err("too many files");
+            }
+            let mut n = HString::new();
+            let _ = n.push_str(name);
+            files.push(File { name: n, data: Vec::new() });
+            files.len() - 1
+        }
+    };
+    let file = &mut files[index];
+    let base = if append { file.data.len() } else { 0 };
+    if base + bytes.len() > MAX_FILE_SIZE {
+        return Err("file too large");
+    }
+    if !append {
+        file.data.clear();
+    }
+    file.data.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// A copy of the file's contents.
+pub fn read(name: &str) -> Option<Vec<u8>> {
+    FILES.lock().iter().find(|f| f.name == name).map(|f| f.data.clone())
+}
+
+pub fn remove(name: &str) -> bool {
+    let mut files = FILES.lock();
+    let before = files.len();
+    files.retain(|f| f.name != name);
+    files.len() != before
+}
+
+/// Names and sizes, in creation order.
+pub fn list() -> Vec<(HString<NAME_LEN>, usize)> {
+    FILES.lock().iter().map(|f| (f.name.clone(), f.data.len())).collect()
+}
+
+pub static CAT_ARGS: Spec = Spec { command: "cat", args: &[req("file", Kind::Path)] };
+pub static RM_ARGS: Spec = Spec { command: "rm", args: &[req("file", Kind::Path)] };
+
+pub fn cat_cmd(args: &[&str]) -> commands::Status {
+    let a = CAT_ARGS.check(args)?;
+    let name = a.word(0).unwrap_or("");
+    let Some(data) = read(name) else {
+        console::write_err(&format!("cat: no file {}", name));
+        return Err(1);
+    };
+    let text = alloc::string::String::from_utf8_lossy(&data);
+    for line in text.strip_suffix('\n').unwrap_or(&text).split('\n') {
+        console::write_line(line);
+    }
+    Ok(())
+}
+
+pub fn rm_cmd(args: &[&str]) -> commands::Status {
+    let a = RM_ARGS.check(args)?;
+    let name = a.word(0).unwrap_or("");
+    if !remove(name) {
+        console::write_err(&format!("rm: no file {}", name));
+        return Err(1);
+    }
+    Ok(())
+}
+
+pub fn files_cmd(_args: &[&str]) -> commands::Status {
+    let files = list();
+    if files.is_empty() {
+        console::write_line("No files. Redirect output into one with: <command> > <name>");
+        return Ok(());
+    }
+    let total: usize = files.iter().map(|(_, size)| size).sum();
+    for (name, size) in files.iter() {
+        console::write_line(&format!("  {:<32} {:>7} bytes", name, size));
+    }
+    console::write_line(&format!("{} files, {} bytes in RAM", files.len(), total));
+    Ok(())
+}
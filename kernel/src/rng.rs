@@ -0,0 +1,53 @@
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use raw_cpuid::CpuId;
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+fn hardware_seed() -> u64 {
+    let has_rdrand = CpuId::new()
+        .get_feature_info()
+        .map(|f| f.has_rdrand())
+        .unwrap_or(false);
+    if has_rdrand {
+        for _ in 0..10 {
+            let mut v: u64 = 0;
+            if unsafe { core::arch::x86_64::_rdrand64_step(&mut v) } == 1 {
+                return v;
+            }
+        }
+    }
+    let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+    tsc ^ crate::timer::ticks().rotate_left(32) ^ 0x9E37_79B9_7F4A_7C15
+}
+
+pub fn init() {
+    let mut seed = hardware_seed();
+    if seed == 0 {
+        seed = 0x2545_F491_4F6C_DD1D;
+    }
+    STATE.store(seed, Ordering::Relaxed);
+}
+
+/// xorshift64* — fast and good enough for picking quotes and fuzz inputs, not for secrets.
+pub fn next_u64() -> u64 {
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        init();
+        x = STATE.load(Ordering::Relaxed);
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    STATE.store(x, Ordering::Relaxed);
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+/// Uniform-ish value in `0..bound` (returns 0 when `bound` is 0).
+pub fn below(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    next_u64() % bound
+}
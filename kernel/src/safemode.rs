@@ -0,0 +1,50 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::{cmdline, console, klog, settings, thud};
+
+// Safe mode boots with everything a bad configuration could break switched
+// off: no splash, no HUD, default colors and accessibility modes, and saved
+// settings neither read nor written. Logging is raised to debug so the
+// cause can be found with `dmesg`.
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Checks for the `safemode` flag. Runs before anything reads settings.
+pub fn init() {
+    if cmdline::has_flag("safemode") {
+        enter();
+    }
+}
+
+/// Switches safe mode on, e.g. from the splash hotkey.
+pub fn enter() {
+    if ACTIVE.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    settings::set_frozen(true);
+    klog::set_level(klog::Level::Debug);
+    klog::warn("safemode: active");
+}
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Undoes anything the boot path set up before safe mode was requested and
+/// tells the user how to get out.
+pub fn apply() {
+    if !active() {
+        return;
+    }
+    thud::disable();
+    let (fg, bg) = console::DEFAULT_COLORS;
+    console::with_console(|c| {
+        c.set_accessibility(console::ColorFilter::Off, false);
+        c.set_default_colors(fg, bg);
+    });
+    console::cwrite_line(
+        "Safe mode: splash, HUD, themes and saved settings are off; logging is at debug.",
+        0xFFD37F,
+        bg,
+    );
+    console::cwrite_line("Reboot without `safemode` to return to normal.", 0xFFD37F, bg);
+}
@@ -0,0 +1,105 @@
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::args::{req, Kind, Spec};
+use crate::{commands, console, lineedit, ramfs};
+
+// Scripts are RAM files holding one command line per line, so they can also
+// be written with `>>` and inspected with cat. `run <name>` prefers a script
+// over a built-in app of the same name. Blank lines and lines starting with #
+// are skipped; the script's status is that of its last command. Ctrl+C
+// (status 130) stops the script, and any script running it.
+
+const MAX_DEPTH: usize = 8;
+
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+pub static SCRIPT_ARGS: Spec = Spec { command: "script", args: &[req("name", Kind::Path)] };
+
+/// `script <name>`: reads lines until `end`, then saves them as `name`.
+/// Esc throws the new script away and keeps any old one.
+pub fn script_cmd(args: &[&str]) -> commands::Status {
+    let a = SCRIPT_ARGS.check(args)?;
+    let name = a.word(0).unwrap_or("");
+    console::write_line("Enter one command per line; end finishes, Esc cancels.");
+    let mut body = String::new();
+    loop {
+        let Some(line) = lineedit::read_line("... ") else {
+            console::write_line("Cancelled.");
+            return Err(1);
+        };
+        if line.trim() == "end" {
+            break;
+        }
+        body.push_str(&line);
+        body.push('\n');
+    }
+    if let Err(msg) = ramfs::write(name, body.as_bytes(), false) {
+        console::write_err(&format!("script: {}", msg));
+        return Err(1);
+    }
+    console::write_line(&format!("Saved {}; start it with: run {}", name, name));
+    Ok(())
+}
+
+/// Runs the script in RAM file `name`, ending with the status of its last
+/// command. None if there is no such file.
+pub fn run(name: &str) -> Option<commands::Status> {
+    let data = ramfs::read(name)?;
+    if DEPTH.load(Ordering::Relaxed) >= MAX_DEPTH {
+        console::write_err(&format!("run: {}: scripts nested too deeply", name));
+        return Some(Err(1));
+    }
+    DEPTH.fetch_add(1, Ordering::Relaxed);
+    let text = String::from_utf8_lossy(&data);
+    let mut more = lineedit::Continuation::new();
+    let mut result = Ok(());
+    // Where the command being read started, for errors.
+    let mut start = 0;
+    for (number, line) in text.split('\n').map(str::trim).enumerate() {
+        if !more.is_pending() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            start = number + 1;
+        }
+        match more.push(line) {
+            Ok(Some(command)) => {
+                commands::handle_line(&command);
+                result = commands::last_result();
+                if result == Err(130) {
+                    break;
+                }
+            }
+            Ok(None) => {}
+            Err(msg) => {
+                console::write_err(&format!("run: {}: line {}: {}", name, start, msg));
+                result = Err(1);
+            }
+        }
+    }
+    if more.is_pending() {
+        console::write_err(&format!(
+            "run: {}: line {}: unclosed quote or trailing \\ at end of file",
+            name, start
+        ));
+        result = Err(1);
+    }
+    DEPTH.fetch_sub(1, Ordering::Relaxed);
+    Some(result)
+}
+
+/// Called when the shell restarts, in case it died inside a script.
+pub fn on_shell_restart() {
+    DEPTH.store(0, Ordering::Relaxed);
+}
+
+/// `run <script>` or `run <app> [&]`.
+pub fn run_cmd(args: &[&str]) -> commands::Status {
+    if let [name] = args {
+        if let Some(result) = run(name) {
+            return result;
+        }
+    }
+    crate::app::run_cmd(args)
+}
@@ -0,0 +1,178 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use alloc::vec::Vec;
+use heapless::String as HString;
+use core::fmt::Write;
+use crate::{cmdline, console, keyboard, klog, memory, serial, time, timer, wait};
+
+type Detail = HString<64>;
+
+const PASS_COLOR: u32 = 0x9CFF7F;
+const FAIL_COLOR: u32 = 0xFF5050;
+// The RTC's update-in-progress flag stays set for about 2 ms.
+const RTC_UPDATE_US: u64 = 10_000;
+
+struct Check {
+    name: &'static str,
+    run: fn() -> Result<Detail, Detail>,
+}
+
+const CHECKS: &[Check] = &[
+    Check { name: "allocator", run: check_allocator },
+    Check { name: "rtc", run: check_rtc },
+    Check { name: "timer", run: check_timer },
+    Check { name: "keyboard", run: check_keyboard },
+    Check { name: "serial", run: check_serial },
+    Check { name: "framebuffer", run: check_framebuffer },
+];
+
+fn detail(args: core::fmt::Arguments) -> Detail {
+    let mut s = Detail::new();
+    let _ = s.write_fmt(args);
+    s
+}
+
+fn check_allocator() -> Result<Detail, Detail> {
+    const HEAP_BYTES: usize = 64 * 1024;
+    const FRAME_BYTES: usize = 16 * 1024;
+
+    let before = memory::heap_stats().used;
+    {
+        let mut v: Vec<u8> = Vec::new();
+        if v.try_reserve_exact(HEAP_BYTES).is_err() {
+            return Err(detail(format_args!("heap: could not allocate {} KiB", HEAP_BYTES / 1024)));
+        }
+        v.extend((0..HEAP_BYTES).map(|i| (i * 7) as u8));
+        if v.iter().enumerate().any(|(i, &b)| b != (i * 7) as u8) {
+            return Err(detail(format_args!("heap: pattern mismatch")));
+        }
+    }
+    let after = memory::heap_stats().used;
+    if after != before {
+        return Err(detail(format_args!("heap: {} bytes not returned", after.abs_diff(before))));
+    }
+
+    let Some(buf) = memory::alloc_buffer(FRAME_BYTES) else {
+        return Err(detail(format_args!("frames: could not allocate {} KiB", FRAME_BYTES / 1024)));
+    };
+    let zeroed = buf.iter().all(|&b| b == 0);
+    memory::free_buffer(buf);
+    if !zeroed {
+        return Err(detail(format_args!("frames: buffer not zeroed")));
+    }
+    Ok(detail(format_args!("{} KiB heap, {} KiB frames", HEAP_BYTES / 1024, FRAME_BYTES / 1024)))
+}
+
+fn check_rtc() -> Result<Detail, Detail> {
+    if !wait::until_micros(RTC_UPDATE_US, || !time::rtc_update_in_progress()) {
+        return Err(detail(format_args!("update flag stuck")));
+    }
+    let t = time::read_rtc_time();
+    let ok = t.second < 60
+        && t.minute < 60
+        && t.hour < 24
+        && (1..=31).contains(&t.day)
+        && (1..=12).contains(&t.month)
+        && (2000..2100).contains(&t.year);
+    let d = detail(format_args!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    ));
+    if ok { Ok(d) } else { Err(d) }
+}
+
+/// Spins until the RTC seconds register changes and returns the PIT tick it
+/// changed on, or None if it did not change within ~3 seconds of ticks.
+fn next_rtc_edge() -> Option<u64> {
+    let hz = timer::frequency() as u64;
+    let start = timer::ticks();
+    let first = time::read_rtc_time().second;
+    // Also bounded by iterations, in case the PIT is what's broken.
+    for _ in 0..20_000_000u32 {
+        if time::read_rtc_time().second != first {
+            return Some(timer::ticks());
+        }
+        if timer::ticks() - start > 3 * hz {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+fn check_timer() -> Result<Detail, Detail> {
+    let hz = timer::frequency() as u64;
+    let (Some(a), Some(b)) = (next_rtc_edge(), next_rtc_edge()) else {
+        return Err(detail(format_args!("no RTC second edge seen")));
+    };
+    let measured = b - a;
+    // One tick of jitter at each edge, plus 2% drift.
+    let slack = 2 + hz / 50;
+    let d = detail(format_args!("{} ticks per RTC second (expected {})", measured, hz));
+    if measured.abs_diff(hz) <= slack { Ok(d) } else { Err(d) }
+}
+
+fn check_keyboard() -> Result<Detail, Detail> {
+    if keyboard::controller_present() {
+        Ok(detail(format_args!("8042 controller responds")))
+    } else {
+        Err(detail(format_args!("no 8042 controller (status reads 0xFF)")))
+    }
+}
+
+fn check_serial() -> Result<Detail, Detail> {
+    if serial::loopback_test() {
+        Ok(detail(format_args!("COM1 loopback ok")))
+    } else {
+        Err(detail(format_args!("COM1 loopback failed")))
+    }
+}
+
+fn check_framebuffer() -> Result<Detail, Detail> {
+    match console::framebuffer_readback_test() {
+        Some(true) => Ok(detail(format_args!("pixel write/readback ok"))),
+        Some(false) => Err(detail(format_args!("pixel readback mismatch"))),
+        None => Err(detail(format_args!("no framebuffer"))),
+    }
+}
+
+/// Runs every check and prints a PASS/FAIL table, mirrored to serial for
+/// headless bring-up. Returns the number of failed checks.
+pub fn run() -> usize {
+    let (fg, bg) = console::default_colors();
+    console::write_line(&format!("{:<12} {:<6} {}", "Subsystem", "Result", "Details"));
+    let mut failed = 0;
+    for check in CHECKS {
+        let (ok, d) = match (check.run)() {
+            Ok(d) => (true, d),
+            Err(d) => (false, d),
+        };
+        let result = if ok { "PASS" } else { "FAIL" };
+        console::cwrite(&format!("{:<12} ", check.name), fg, bg);
+        console::cwrite(&format!("{:<6} ", result), if ok { PASS_COLOR } else { FAIL_COLOR }, bg);
+        console::cwrite_line(&d, fg, bg);
+        serial::write(&format!("selftest: {:<12} {} {}", check.name, result, d));
+        if !ok {
+            failed += 1;
+            klog::warn(&format!("selftest: {} failed: {}", check.name, d));
+        }
+    }
+    console::write_line(&format!("{} of {} checks passed.", CHECKS.len() - failed, CHECKS.len()));
+    failed
+}
+
+/// Runs the checks during boot when the kernel command line has `selftest`.
+pub fn boot_check() {
+    if cmdline::has_flag("selftest") {
+        run();
+        console::write_line("");
+    }
+}
+
+pub fn selftest_cmd() -> crate::commands::Status {
+    if run() > 0 {
+        return Err(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use raw_cpuid::CpuId;
+use crate::{aml, console};
+
+const IA32_THERM_STATUS: u32 = 0x19C;
+const IA32_TEMPERATURE_TARGET: u32 = 0x1A2;
+const IA32_PACKAGE_THERM_STATUS: u32 = 0x1B1;
+
+const DEFAULT_TJMAX: u32 = 100;
+const READING_VALID: u64 = 1 << 31;
+
+#[derive(Copy, Clone)]
+pub struct ThermalCaps {
+    pub dts: bool,
+    pub ptm: bool,
+    pub tj_max: u32,
+}
+
+pub fn caps() -> ThermalCaps {
+    let cpuid = CpuId::new();
+    let is_intel = cpuid
+        .get_vendor_info()
+        .map(|v| v.as_str() == "GenuineIntel")
+        .unwrap_or(false);
+    let (dts, ptm) = match cpuid.get_thermal_power_info() {
+        Some(t) if is_intel => (t.has_dts(), t.has_ptm()),
+        _ => (false, false),
+    };
+
+    // IA32_TEMPERATURE_TARGET only exists on family 6 parts; older chips #GP on read.
+    let family6 = cpuid
+        .get_feature_info()
+        .map(|f| f.family_id() == 6)
+        .unwrap_or(false);
+    let tj_max = if dts && family6 {
+        let target = unsafe { x86::msr::rdmsr(IA32_TEMPERATURE_TARGET) };
+        match ((target >> 16) & 0xFF) as u32 {
+            0 => DEFAULT_TJMAX,
+            v => v,
+        }
+    } else {
+        DEFAULT_TJMAX
+    };
+
+    ThermalCaps { dts, ptm, tj_max }
+}
+
+fn decode(status: u64, tj_max: u32) -> Option<u32> {
+    if status & READING_VALID == 0 {
+        return None;
+    }
+    let readout = ((status >> 16) & 0x7F) as u32;
+    Some(tj_max.saturating_sub(readout))
+}
+
+pub fn core_temp(caps: &ThermalCaps) -> Option<u32> {
+    if !caps.dts {
+        return None;
+    }
+    let status = unsafe { x86::msr::rdmsr(IA32_THERM_STATUS) };
+    decode(status, caps.tj_max)
+}
+
+pub fn package_temp(caps: &ThermalCaps) -> Option<u32> {
+    if !caps.ptm {
+        return None;
+    }
+    let status = unsafe { x86::msr::rdmsr(IA32_PACKAGE_THERM_STATUS) };
+    decode(status, caps.tj_max)
+}
+
+/// Best available CPU temperature: package sensor first, then the current core.
+pub fn cpu_temp(caps: &ThermalCaps) -> Option<u32> {
+    package_temp(caps).or_else(|| core_temp(caps))
+}
+
+/// Kelvin at 0 C, in the tenths `_TMP` returns.
+const ZERO_C_DK: i64 = 2732;
+
+pub fn sensors_cmd() {
+    let caps = caps();
+    if caps.dts {
+        console::write_line(&format!("CPU thermal sensor (TjMax {} C):", caps.tj_max));
+        match package_temp(&caps) {
+            Some(t) => console::write_line(&format!("  Package 0: {} C", t)),
+            None if caps.ptm => console::write_line("  Package 0: no valid reading"),
+            None => console::write_line("  Package 0: not supported"),
+        }
+        match core_temp(&caps) {
+            Some(t) => console::write_line(&format!("  Core (current): {} C", t)),
+            None => console::write_line("  Core (current): no valid reading"),
+        }
+    } else {
+        console::write_line("CPU thermal sensor: not reported by CPUID");
+    }
+
+    let zones = aml::thermal_zones();
+    if zones.is_empty() {
+        console::write_line("ACPI thermal zones: none found");
+        return;
+    }
+    console::write_line("ACPI thermal zones:");
+    for zone in zones {
+        let tmp = format!("{}._TMP", zone);
+        match aml::evaluate(&tmp, &[]).and_then(|v| v.int()) {
+            Ok(dk) => {
+                let c = (dk as i64 - ZERO_C_DK) as f64 / 10.0;
+                console::write_line(&format!("  {}: {:.1} C", zone, c));
+            }
+            Err(e) => console::write_line(&format!("  {}: no reading ({})", zone, e.describe())),
+        }
+    }
+}
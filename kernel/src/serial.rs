@@ -1,6 +1,9 @@
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 use lazy_static::lazy_static;
+use crate::{cmdline, virtio_console, wait};
 
 lazy_static! {
     static ref SERIAL1: Mutex<SerialPort> = {
@@ -10,11 +13,129 @@ lazy_static! {
     };
 }
 
+// Everything written here also goes to the virtio console when there is
+// one, and input is taken from both. `serial=virtio` on the kernel command
+// line leaves COM1 out of output whenever the virtio console is up.
+static UART_OUT: AtomicBool = AtomicBool::new(true);
+
+/// Applies `serial=` from the kernel command line, once the virtio console
+/// has been probed.
+pub fn init() {
+    if cmdline::value("serial") == Some("virtio") && virtio_console::present() {
+        UART_OUT.store(false, Ordering::Relaxed);
+    }
+}
+
+fn uart_out() -> bool {
+    UART_OUT.load(Ordering::Relaxed) || !virtio_console::present()
+}
+
+/// Whether COM1 or the virtio console is being written or read, for the
+/// watchdog.
+pub fn busy() -> bool {
+    SERIAL1.is_locked() || virtio_console::busy()
+}
+
 pub fn write(msg: &str) {
+    virtio_console::write(msg.as_bytes());
+    virtio_console::write(b"\r\n");
+    if !uart_out() {
+        return;
+    }
     let mut serial = SERIAL1.lock();
     for byte in msg.bytes() {
         serial.send(byte);
     }
     serial.send(b'\r');
     serial.send(b'\n');
-}
\ No newline at end of file
+}
+
+/// Writes `msg` as-is, without a line ending.
+pub fn write_raw(msg: &str) {
+    virtio_console::write(msg.as_bytes());
+    if !uart_out() {
+        return;
+    }
+    let mut serial = SERIAL1.lock();
+    for byte in msg.bytes() {
+        serial.send(byte);
+    }
+}
+
+pub fn write_bytes(bytes: &[u8]) {
+    virtio_console::write(bytes);
+    if !uart_out() {
+        return;
+    }
+    let mut serial = SERIAL1.lock();
+    for &byte in bytes {
+        serial.send_raw(byte);
+    }
+}
+
+const COM1_DATA: u16 = 0x3F8;
+const COM1_MODEM_CONTROL: u16 = 0x3FC;
+const COM1_LINE_STATUS: u16 = 0x3FD;
+
+/// Returns a received byte if one is waiting on either line, without blocking.
+pub fn try_read() -> Option<u8> {
+    let _serial = SERIAL1.lock();
+    unsafe {
+        let mut status: Port<u8> = Port::new(COM1_LINE_STATUS);
+        if status.read() & 1 != 0 {
+            let mut data: Port<u8> = Port::new(COM1_DATA);
+            return Some(data.read());
+        }
+    }
+    virtio_console::try_read()
+}
+
+/// Writes a line straight to the UART without taking the port lock, for
+/// exception and panic paths that may have interrupted a normal writer.
+pub fn emergency_write(msg: &str) {
+    virtio_console::emergency_write(msg.as_bytes());
+    virtio_console::emergency_write(b"\r\n");
+    unsafe {
+        let mut status: Port<u8> = Port::new(COM1_LINE_STATUS);
+        let mut data: Port<u8> = Port::new(COM1_DATA);
+        for byte in msg.bytes().chain(*b"\r\n") {
+            // Bounded wait so a missing UART cannot hang the fault path.
+            wait::until_micros(10_000, || status.read() & 0x20 != 0);
+            data.write(byte);
+        }
+    }
+}
+
+/// Sends a few bytes through the UART's internal loopback and checks they come back.
+pub fn loopback_test() -> bool {
+    let _serial = SERIAL1.lock();
+    unsafe {
+        let mut mcr: Port<u8> = Port::new(COM1_MODEM_CONTROL);
+        let mut status: Port<u8> = Port::new(COM1_LINE_STATUS);
+        let mut data: Port<u8> = Port::new(COM1_DATA);
+        let saved = mcr.read();
+        mcr.write(saved | 0x10);
+        // Without a UART the status register reads 0xFF, so data never stops coming.
+        let drained = wait::until_micros(10_000, || {
+            if status.read() & 1 == 0 {
+                return true;
+            }
+            data.read();
+            false
+        });
+        let mut ok = drained;
+        for byte in [0x55u8, 0xAA, 0x3C] {
+            if !ok {
+                break;
+            }
+            data.write(byte);
+            let got = wait::until_micros(10_000, || status.read() & 1 != 0).then(|| data.read());
+            if got != Some(byte) {
+                ok = false;
+                break;
+            }
+        }
+        mcr.write(saved);
+        ok
+    }
+}
@@ -0,0 +1,82 @@
+use alloc::format;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use crate::lineedit::{Input, LineEditor, SerialInput, SerialOutput};
+use crate::{cmdline, commands, console, history, serial, testmode};
+
+// An interactive shell on COM1 for a human at a terminal, sharing command
+// history with the framebuffer shell. Test mode owns the port while it is on.
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+struct SerialShell {
+    input: SerialInput,
+    editor: LineEditor,
+}
+
+static SHELL: Mutex<SerialShell> = Mutex::new(SerialShell { input: SerialInput::new(), editor: LineEditor::new() });
+
+pub fn init() {
+    if cmdline::has_flag("serialshell") {
+        set_enabled(true);
+    }
+}
+
+pub fn set_enabled(on: bool) {
+    let was = ENABLED.swap(on, Ordering::Relaxed);
+    if on && !was {
+        serial::write_raw("\r\n");
+        SHELL.lock().editor.redraw(&mut SerialOutput);
+    } else if was && !on {
+        // Stop marking pastes for whatever reads the port next.
+        serial::write_raw("\x1b[?2004l");
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Handles keys received over serial and runs finished lines. Returns true
+/// if the console was written to, so the caller can redraw its prompt.
+pub fn poll() -> bool {
+    if !enabled() || testmode::enabled() {
+        return false;
+    }
+    let mut ran = false;
+    loop {
+        let line = {
+            let mut shell = SHELL.lock();
+            let shell = &mut *shell;
+            match shell.editor.next_pasted(&mut SerialOutput) {
+                Some(line) => Some(line),
+                None => {
+                    let Some(key) = shell.input.poll_key() else {
+                        break;
+                    };
+                    shell.editor.feed(key, &mut SerialOutput)
+                }
+            }
+        };
+        if let Some(line) = line {
+            run(&line);
+            ran = true;
+        }
+    }
+    ran
+}
+
+fn run(line: &str) {
+    console::write_line("");
+    console::cwrite_line(&format!("(serial) {}", line), 0x7FB8FF, console::default_bg());
+    let _ = console::begin_capture();
+    commands::handle_line(line);
+    history::push(line);
+    if let Some(out) = console::end_capture() {
+        for l in out.lines() {
+            serial::write(l);
+        }
+    }
+    // Shows the rest of a paste, if the line came from one.
+    SHELL.lock().editor.redraw(&mut SerialOutput);
+}
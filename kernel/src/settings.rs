@@ -0,0 +1,119 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::{LinearMap, String as HString};
+use spin::Mutex;
+
+pub const MAX_KEY: usize = 32;
+pub const MAX_VALUE: usize = 64;
+const MAX_ENTRIES: usize = 32;
+
+pub type Key = HString<MAX_KEY>;
+pub type Value = HString<MAX_VALUE>;
+
+static SETTINGS: Mutex<LinearMap<Key, Value, MAX_ENTRIES>> = Mutex::new(LinearMap::new());
+// Safe mode: nothing is read back or stored, so a bad value cannot be reapplied.
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_frozen(frozen: bool) {
+    FROZEN.store(frozen, Ordering::Relaxed);
+}
+
+pub fn frozen() -> bool {
+    FROZEN.load(Ordering::Relaxed)
+}
+
+fn make_key(key: &str) -> Option<Key> {
+    let mut k = Key::new();
+    k.push_str(key).ok()?;
+    Some(k)
+}
+
+/// Whether the store's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    SETTINGS.is_locked()
+}
+
+pub fn get(key: &str) -> Option<Value> {
+    if frozen() {
+        return None;
+    }
+    let k = make_key(key)?;
+    SETTINGS.lock().get(&k).cloned()
+}
+
+pub fn set(key: &str, value: &str) -> bool {
+    if frozen() {
+        return false;
+    }
+    let Some(k) = make_key(key) else { return false; };
+    let mut v = Value::new();
+    if v.push_str(value).is_err() {
+        return false;
+    }
+    SETTINGS.lock().insert(k, v).is_ok()
+}
+
+pub fn get_u32(key: &str) -> Option<u32> {
+    get(key).and_then(|v| v.parse().ok())
+}
+
+pub fn set_u32(key: &str, value: u32) -> bool {
+    use core::fmt::Write;
+    let mut v: HString<16> = HString::new();
+    let _ = write!(v, "{}", value);
+    set(key, &v)
+}
+
+pub fn entries() -> heapless::Vec<(Key, Value), MAX_ENTRIES> {
+    SETTINGS.lock().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+/// Settings are kept across reboots in this file in the host folder, one
+/// `key=value` per line. Without a host folder they last until power-off.
+const SETTINGS_FILE: &str = ".stratos-settings";
+const SETTINGS_FILE_MAX: usize = 4 * 1024;
+
+/// Reads back the settings a previous boot saved. Returns whether there was
+/// a settings file to read.
+pub fn load() -> bool {
+    if frozen() || !crate::hostfs::attached() {
+        return false;
+    }
+    let data = match crate::hostfs::read_file(SETTINGS_FILE, SETTINGS_FILE_MAX) {
+        Ok(data) => data,
+        Err(msg) => {
+            crate::klog::debug(&alloc::format!("settings: nothing read from host {}: {}", SETTINGS_FILE, msg));
+            return false;
+        }
+    };
+    let mut loaded = 0;
+    for line in core::str::from_utf8(&data).unwrap_or("").lines() {
+        let Some((key, value)) = line.split_once('=') else { continue; };
+        if set(key, value) {
+            loaded += 1;
+        } else {
+            crate::klog::warn(&alloc::format!("settings: skipped saved {}", key));
+        }
+    }
+    crate::klog::info(&alloc::format!("settings: read {} from host {}", loaded, SETTINGS_FILE));
+    true
+}
+
+/// Writes the settings to the host folder, if there is one. Returns whether
+/// they were written.
+pub fn save() -> bool {
+    use core::fmt::Write;
+    if frozen() || !crate::hostfs::attached() {
+        return false;
+    }
+    let mut text = alloc::string::String::new();
+    for (key, value) in entries() {
+        let _ = writeln!(text, "{}={}", key, value);
+    }
+    match crate::hostfs::write_file(SETTINGS_FILE, text.as_bytes()) {
+        Ok(()) => true,
+        Err(msg) => {
+            crate::klog::warn(&alloc::format!("settings: could not save to host {}: {}", SETTINGS_FILE, msg));
+            false
+        }
+    }
+}
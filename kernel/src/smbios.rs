@@ -0,0 +1,174 @@
+#![allow(dead_code)]
+
+use bootloader_api::info::MemoryRegionKind;
+use bootloader_api::BootInfo;
+use heapless::String as HString;
+use spin::Once;
+use crate::memory;
+
+// Legacy BIOS puts the entry point on a 16-byte boundary in the F segment.
+const SCAN_START: u64 = 0xF0000;
+const SCAN_END: u64 = 0x100000;
+
+// UEFI lists the entry point in the configuration table of its system table
+// instead. The bootloader does not pass the system table on, but it stays in
+// runtime services data, where its signature finds it.
+const EFI_SYSTEM_TABLE: &[u8; 8] = b"IBI SYST";
+const EFI_SYSTEM_TABLE_LEN: usize = 120;
+const EFI_RUNTIME_SERVICES_DATA: u32 = 6;
+/// Runtime data regions are small; this bounds the scan on odd firmware.
+const EFI_SCAN_MAX: u64 = 16 * 1024 * 1024;
+const CONFIG_ENTRY_LEN: usize = 24;
+const MAX_CONFIG_ENTRIES: usize = 256;
+// Configuration table GUIDs in their in-memory byte order.
+const SMBIOS3_GUID: [u8; 16] =
+    [0x44, 0x15, 0xfd, 0xf2, 0x94, 0x97, 0x2c, 0x4a, 0x99, 0x2e, 0xe5, 0xbb, 0xcf, 0x20, 0xe3, 0x94];
+const SMBIOS_GUID: [u8; 16] =
+    [0x31, 0x2d, 0x9d, 0xeb, 0x88, 0x2d, 0xd3, 0x11, 0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d];
+
+/// Physical address of the entry point the EFI configuration table gave.
+static EFI_ENTRY: Once<u64> = Once::new();
+
+pub type Text = HString<64>;
+
+#[derive(Default)]
+pub struct SmbiosInfo {
+    pub version: (u8, u8),
+    pub bios_vendor: Text,
+    pub bios_version: Text,
+    pub bios_date: Text,
+    pub system_vendor: Text,
+    pub product: Text,
+}
+
+fn bytes(phys: u64, len: usize) -> Option<&'static [u8]> {
+    let virt = memory::phys_to_virt(phys)?;
+    Some(unsafe { core::slice::from_raw_parts(virt as *const u8, len) })
+}
+
+fn le_u16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([b[off], b[off + 1]])
+}
+
+fn le_u32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([b[off], b[off + 1], b[off + 2], b[off + 3]])
+}
+
+fn le_u64(b: &[u8], off: usize) -> u64 {
+    (le_u32(b, off + 4) as u64) << 32 | le_u32(b, off) as u64
+}
+
+/// The SMBIOS entry point from the EFI configuration table at `st`, a
+/// candidate system table.
+fn efi_entry_point(st: &[u8]) -> Option<u64> {
+    if (le_u32(st, 12) as usize) < EFI_SYSTEM_TABLE_LEN {
+        return None;
+    }
+    let count = le_u64(st, 104) as usize;
+    let table = le_u64(st, 112);
+    if count == 0 || count > MAX_CONFIG_ENTRIES || !memory::is_ram_range(table, (count * CONFIG_ENTRY_LEN) as u64) {
+        return None;
+    }
+    let entries = bytes(table, count * CONFIG_ENTRY_LEN)?;
+    // The 64-bit SMBIOS 3 entry point is preferred when both are listed.
+    [SMBIOS3_GUID, SMBIOS_GUID].iter().find_map(|guid| {
+        entries.chunks(CONFIG_ENTRY_LEN).find(|e| e[..16] == guid[..]).map(|e| le_u64(e, 16))
+    })
+}
+
+/// Finds the EFI system table and remembers the SMBIOS entry point it lists.
+pub fn init(boot: &BootInfo) {
+    let runtime_data = boot.memory_regions.iter().filter(|r| r.kind == MemoryRegionKind::UnknownUefi(EFI_RUNTIME_SERVICES_DATA));
+    for region in runtime_data {
+        let len = (region.end - region.start).min(EFI_SCAN_MAX) as usize;
+        let Some(area) = bytes(region.start, len) else { continue; };
+        for off in (0..len.saturating_sub(EFI_SYSTEM_TABLE_LEN)).step_by(8) {
+            if &area[off..off + 8] != EFI_SYSTEM_TABLE {
+                continue;
+            }
+            if let Some(entry) = efi_entry_point(&area[off..off + EFI_SYSTEM_TABLE_LEN]) {
+                EFI_ENTRY.call_once(|| entry);
+                return;
+            }
+        }
+    }
+}
+
+/// Reads an entry point: (version, table address, table length).
+fn parse_entry_point(ep: &[u8]) -> Option<((u8, u8), u64, usize)> {
+    if &ep[..5] == b"_SM3_" {
+        return Some(((ep[7], ep[8]), le_u64(ep, 0x10), le_u32(ep, 0x0C) as usize));
+    }
+    if &ep[..4] == b"_SM_" {
+        return Some(((ep[6], ep[7]), le_u32(ep, 0x18) as u64, le_u16(ep, 0x16) as usize));
+    }
+    None
+}
+
+/// Returns (version, table address, table length).
+fn find_entry_point() -> Option<((u8, u8), u64, usize)> {
+    if let Some(&phys) = EFI_ENTRY.get() {
+        if memory::is_ram_range(phys, 32) {
+            return parse_entry_point(bytes(phys, 32)?);
+        }
+    }
+    let area = bytes(SCAN_START, (SCAN_END - SCAN_START) as usize)?;
+    (0..area.len() - 32).step_by(16).find_map(|off| parse_entry_point(&area[off..off + 32]))
+}
+
+/// The `index`th (1-based) string after a structure's formatted area.
+fn string_at(strings: &[u8], index: u8) -> Text {
+    let mut out = Text::new();
+    if index == 0 {
+        return out;
+    }
+    if let Some(s) = strings.split(|&b| b == 0).nth(index as usize - 1) {
+        for &b in s {
+            let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' };
+            if out.push(c).is_err() {
+                break;
+            }
+        }
+    }
+    out
+}
+
+/// Reads the BIOS (type 0) and system (type 1) structures, if the firmware
+/// publishes an entry point.
+pub fn info() -> Option<SmbiosInfo> {
+    let (version, addr, len) = find_entry_point()?;
+    let table = bytes(addr, len)?;
+    let mut info = SmbiosInfo { version, ..Default::default() };
+
+    let mut off = 0;
+    while off + 4 <= table.len() {
+        let kind = table[off];
+        let formatted_len = table[off + 1] as usize;
+        if formatted_len < 4 || off + formatted_len > table.len() {
+            break;
+        }
+        let s = &table[off..off + formatted_len];
+        // Strings run until a double NUL.
+        let strings_start = off + formatted_len;
+        let mut end = strings_start;
+        while end + 1 < table.len() && !(table[end] == 0 && table[end + 1] == 0) {
+            end += 1;
+        }
+        let strings = &table[strings_start..end];
+        match kind {
+            0 if formatted_len > 0x08 => {
+                info.bios_vendor = string_at(strings, s[0x04]);
+                info.bios_version = string_at(strings, s[0x05]);
+                info.bios_date = string_at(strings, s[0x08]);
+            }
+            1 if formatted_len > 0x06 => {
+                info.system_vendor = string_at(strings, s[0x04]);
+                info.product = string_at(strings, s[0x05]);
+            }
+            127 => break,
+            _ => {}
+        }
+        off = end + 2;
+    }
+    Some(info)
+}
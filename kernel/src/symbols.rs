@@ -0,0 +1,105 @@
+use alloc::format;
+use bootloader_api::BootInfo;
+use spin::Once;
+use crate::args::{self, req, Spec};
+use crate::{commands, console};
+
+// The kernel's function symbols, for turning raw addresses from exception
+// dumps back into names. The build writes the table after the config in the
+// bootloader ramdisk (see os/build.rs for the layout). The ramdisk sits in
+// frames the bootloader keeps for itself, so the table is read in place.
+
+const MAGIC: &[u8] = b"STRATSYM";
+const HEADER_LEN: usize = MAGIC.len() + 4;
+const ENTRY_LEN: usize = 16;
+
+struct Table {
+    entries: &'static [u8],
+    names: &'static [u8],
+    count: usize,
+    /// Where the bootloader placed the kernel relative to its link address.
+    load_offset: u64,
+}
+
+static TABLE: Once<Table> = Once::new();
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from(u32_at(b, at)) | u64::from(u32_at(b, at + 4)) << 32
+}
+
+/// Finds the symbol table in the ramdisk, if the build put one there.
+pub fn init(boot: &BootInfo) {
+    let Some(addr) = boot.ramdisk_addr.into_option() else { return; };
+    let ramdisk = unsafe { core::slice::from_raw_parts(addr as *const u8, boot.ramdisk_len as usize) };
+    let Some(nul) = ramdisk.iter().position(|&b| b == 0) else { return; };
+    let blob = &ramdisk[nul + 1..];
+    if blob.len() < HEADER_LEN || &blob[..MAGIC.len()] != MAGIC {
+        return;
+    }
+    let count = u32_at(blob, MAGIC.len()) as usize;
+    let names_at = HEADER_LEN + count * ENTRY_LEN;
+    if blob.len() < names_at {
+        return;
+    }
+    TABLE.call_once(|| Table {
+        entries: &blob[HEADER_LEN..names_at],
+        names: &blob[names_at..],
+        count,
+        load_offset: boot.kernel_image_offset,
+    });
+}
+
+pub fn available() -> bool {
+    TABLE.get().is_some()
+}
+
+/// The function containing `addr` and how far into it `addr` is. Does not
+/// allocate or lock, so fault paths may call it.
+pub fn lookup(addr: u64) -> Option<(&'static str, u64)> {
+    let table = TABLE.get()?;
+    let target = addr.checked_sub(table.load_offset)?;
+    let start_of = |i: usize| u64_at(table.entries, i * ENTRY_LEN);
+    // Binary search for the last symbol starting at or below the address.
+    let (mut lo, mut hi) = (0, table.count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if start_of(mid) <= target {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let i = lo.checked_sub(1)?;
+    let entry = i * ENTRY_LEN;
+    let offset = target - start_of(i);
+    if offset >= u64::from(u32_at(table.entries, entry + 8)) {
+        return None;
+    }
+    let name = table.names.get(u32_at(table.entries, entry + 12) as usize..)?;
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some((core::str::from_utf8(&name[..end]).unwrap_or("?"), offset))
+}
+
+pub static ADDR2SYM_ARGS: Spec = Spec { command: "addr2sym", args: &[req("addr", args::ANY_INT)] };
+
+/// `addr2sym <addr>`: the function an address falls in, e.g. a RIP from an
+/// exception dump.
+pub fn addr2sym_cmd(args: &[&str]) -> commands::Status {
+    let addr = ADDR2SYM_ARGS.check(args)?.int(0).unwrap_or(0);
+    if !available() {
+        console::write_err("addr2sym: no symbol table; it is added when the boot image is built");
+        return Err(1);
+    }
+    match lookup(addr) {
+        Some((name, offset)) => console::write_line(&format!("{:#x} = {}+{:#x}", addr, name, offset)),
+        None => {
+            console::write_err(&format!("addr2sym: {:#x} is not in a known function", addr));
+            return Err(1);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,645 @@
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+use heapless::String as HString;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use alloc::format;
+use alloc::string::String;
+use crate::commands::format_bytes;
+use crate::memory::{self, AppId};
+use crate::{console, keyboard, klog, timer, wait};
+
+pub type TaskId = u32;
+pub type TaskEntry = fn(usize) -> i32;
+
+pub const BOOT_TASK: TaskId = 0;
+const STACK_SIZE: usize = 16 * 1024;
+const NO_TASK: TaskId = u32::MAX;
+
+pub const STATUS_FAULT: i32 = -1;
+pub const STATUS_WATCHDOG: i32 = -2;
+const MAX_TASKS: usize = 8;
+
+/// Static priorities, 0 (most urgent) to `LOWEST_PRIORITY`. The shell runs at
+/// `SHELL_PRIORITY`; everything else starts at `DEFAULT_PRIORITY` unless `nice`d.
+pub type Priority = u8;
+pub const SHELL_PRIORITY: Priority = 0;
+pub const DEFAULT_PRIORITY: Priority = 2;
+pub const LOWEST_PRIORITY: Priority = 4;
+// A ready task passed over this many times is treated as one level more
+// urgent, so low priority work is slowed down but never starved.
+const AGING_STEP: u32 = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    Ready,
+    Running,
+    Exited(i32),
+}
+
+struct Task {
+    id: TaskId,
+    name: HString<16>,
+    app: Option<AppId>,
+    state: TaskState,
+    cpu_ticks: u64,
+    sched: SchedStats,
+    priority: Priority,
+    passed_over: u32,
+    rsp: u64,
+    entry: Option<(TaskEntry, usize)>,
+    stack: Option<Box<[u8]>>,
+}
+
+/// Scheduling latency is the TSC time from a task becoming ready to being switched in.
+#[derive(Copy, Clone, Default)]
+pub struct SchedStats {
+    pub runs: u64,
+    pub total_latency: u64,
+    pub max_latency: u64,
+    ready_since: u64,
+}
+
+#[derive(Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: HString<16>,
+    pub app: Option<AppId>,
+    pub state: TaskState,
+    pub cpu_ticks: u64,
+    pub sched: SchedStats,
+    pub priority: Priority,
+}
+
+struct Scheduler {
+    // Boxed so each task keeps its address; see the context switch in `yield_now`.
+    #[allow(clippy::vec_box)]
+    tasks: Vec<Box<Task>>,
+    current: usize,
+    next_id: TaskId,
+}
+
+static SCHED: Mutex<Option<Scheduler>> = Mutex::new(None);
+// Mirrors of scheduler state that interrupt and exception handlers read without locking.
+static CURRENT_TASK: AtomicU32 = AtomicU32::new(BOOT_TASK);
+static SUPERVISED_TASK: AtomicU32 = AtomicU32::new(NO_TASK);
+static LAST_YIELD_TICK: AtomicU64 = AtomicU64::new(0);
+static WATCHDOG_BUDGET_SECS: AtomicU64 = AtomicU64::new(10);
+static TRACE_SWITCHES: AtomicBool = AtomicBool::new(false);
+// Priority given to newly spawned tasks; `nice` changes it for one command.
+static SPAWN_PRIORITY: AtomicU8 = AtomicU8::new(DEFAULT_PRIORITY);
+
+/// Called with the task, its app and its exit status when a task exits.
+pub type ExitHook = fn(TaskId, Option<AppId>, i32);
+
+static EXIT_HOOKS: Mutex<heapless::Vec<ExitHook, 8>> = Mutex::new(heapless::Vec::new());
+
+// switch_context(save_rsp: *mut u64, load_rsp: u64)
+global_asm!(
+    ".global stratos_switch_context",
+    "stratos_switch_context:",
+    "push rbx",
+    "push rbp",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbp",
+    "pop rbx",
+    "ret",
+);
+
+// Entered via a rewritten interrupt return address, so the stack alignment is unknown.
+global_asm!(
+    ".global stratos_abort_trampoline",
+    "stratos_abort_trampoline:",
+    "and rsp, -16",
+    "call stratos_watchdog_abort",
+    "ud2",
+);
+
+extern "C" {
+    fn stratos_switch_context(save_rsp: *mut u64, load_rsp: u64);
+    fn stratos_abort_trampoline();
+}
+
+fn make_name(name: &str) -> HString<16> {
+    let mut s = HString::new();
+    for ch in name.chars() {
+        if s.push(ch).is_err() {
+            break;
+        }
+    }
+    s
+}
+
+/// Adopts the boot context as task 0.
+pub fn init() {
+    let shell = Box::new(Task {
+        id: BOOT_TASK,
+        name: make_name("kernel"),
+        app: None,
+        state: TaskState::Running,
+        cpu_ticks: 0,
+        sched: SchedStats::default(),
+        priority: DEFAULT_PRIORITY,
+        passed_over: 0,
+        rsp: 0,
+        entry: None,
+        stack: None,
+    });
+    *SCHED.lock() = Some(Scheduler { tasks: vec![shell], current: 0, next_id: 1 });
+}
+
+pub fn register_exit_hook(hook: ExitHook) {
+    let _ = EXIT_HOOKS.lock().push(hook);
+}
+
+pub fn spawn(name: &str, app: Option<AppId>, entry: TaskEntry, arg: usize) -> Option<TaskId> {
+    spawn_with_stack(name, app, entry, arg, STACK_SIZE)
+}
+
+pub fn spawn_with_stack(
+    name: &str,
+    app: Option<AppId>,
+    entry: TaskEntry,
+    arg: usize,
+    stack_size: usize,
+) -> Option<TaskId> {
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHED.lock();
+        let sched = guard.as_mut()?;
+        reap(sched);
+        if sched.tasks.len() >= MAX_TASKS {
+            return None;
+        }
+
+        let mut stack = vec![0u8; stack_size].into_boxed_slice();
+        let top = (stack.as_mut_ptr() as u64 + stack_size as u64) & !0xF;
+        // Layout (growing down): fake return slot, trampoline, then six callee-saved registers.
+        let frame = top - 16 - 6 * 8;
+        unsafe {
+            let p = frame as *mut u64;
+            for i in 0..6 {
+                p.add(i).write(0);
+            }
+            p.add(6).write(task_trampoline as *const () as u64);
+            p.add(7).write(0);
+        }
+
+        let id = sched.next_id;
+        sched.next_id += 1;
+        sched.tasks.push(Box::new(Task {
+            id,
+            name: make_name(name),
+            app,
+            state: TaskState::Ready,
+            cpu_ticks: 0,
+            sched: SchedStats { ready_since: rdtsc(), ..SchedStats::default() },
+            priority: SPAWN_PRIORITY.load(Ordering::Relaxed),
+            passed_over: 0,
+            rsp: frame,
+            entry: Some((entry, arg)),
+            stack: Some(stack),
+        }));
+        Some(id)
+    })
+}
+
+// Frees exited tasks other than the one currently running (it is still on its stack).
+fn reap(sched: &mut Scheduler) {
+    let current_id = sched.tasks[sched.current].id;
+    sched.tasks.retain(|t| t.id == BOOT_TASK || t.id == current_id || !matches!(t.state, TaskState::Exited(_)));
+    sched.current = sched.tasks.iter().position(|t| t.id == current_id).unwrap_or(0);
+}
+
+/// Puts `id` under the watchdog: it is aborted on CPU faults or when it runs
+/// longer than the budget without yielding.
+pub fn supervise(id: TaskId) {
+    LAST_YIELD_TICK.store(timer::ticks(), Ordering::Relaxed);
+    SUPERVISED_TASK.store(id, Ordering::Relaxed);
+}
+
+/// Stops supervising, for code that parks the CPU on purpose.
+pub fn release_watchdog() {
+    SUPERVISED_TASK.store(NO_TASK, Ordering::Relaxed);
+}
+
+/// Sets the watchdog budget in seconds; 0 disables the time check.
+pub fn set_watchdog_budget(secs: u64) {
+    WATCHDOG_BUDGET_SECS.store(secs, Ordering::Relaxed);
+}
+
+pub fn watchdog_budget() -> u64 {
+    WATCHDOG_BUDGET_SECS.load(Ordering::Relaxed)
+}
+
+pub fn current_is_supervised() -> bool {
+    CURRENT_TASK.load(Ordering::Relaxed) == SUPERVISED_TASK.load(Ordering::Relaxed)
+}
+
+/// Whether the interrupted code holds a lock the abort path or the restarted
+/// shell needs. The abort never returns to release it, so it would stay held
+/// for good; the watchdog waits for the lock to be let go instead.
+fn holds_shared_lock() -> bool {
+    memory::heap_busy()
+        || crate::klog::busy()
+        || crate::settings::busy()
+        || crate::commands::busy()
+        || crate::env::busy()
+        || crate::history::busy()
+        || crate::hostfs::busy()
+        || crate::ramfs::busy()
+        || crate::clipboard::busy()
+        || crate::thud::busy()
+        || crate::serial::busy()
+        || console::output_busy()
+}
+
+/// Called from the timer interrupt. If the supervised task has hogged the CPU
+/// past its budget, the interrupt returns into an abort path instead.
+pub fn watchdog_tick(frame: &mut InterruptStackFrame) {
+    let budget = watchdog_budget();
+    if budget == 0 || !current_is_supervised() {
+        return;
+    }
+    let since = timer::ticks().saturating_sub(LAST_YIELD_TICK.load(Ordering::Relaxed));
+    if since <= budget * timer::frequency() as u64 || holds_shared_lock() {
+        return;
+    }
+    LAST_YIELD_TICK.store(timer::ticks(), Ordering::Relaxed);
+    unsafe {
+        frame.as_mut().update(|f| {
+            f.instruction_pointer = VirtAddr::new(stratos_abort_trampoline as *const () as u64);
+        });
+    }
+}
+
+#[no_mangle]
+extern "C" fn stratos_watchdog_abort() -> ! {
+    let secs = watchdog_budget();
+    // Not klog: its serial echo would take locks the watchdog doesn't check.
+    crate::serial::emergency_write(&format!("watchdog: task {} exceeded its {}s budget", current_id(), secs));
+    console::write_line("");
+    console::cwrite_line(
+        &format!("Watchdog: command ran for over {}s without yielding; aborted.", secs),
+        0xFF5050,
+        console::default_bg(),
+    );
+    exit(STATUS_WATCHDOG);
+}
+
+extern "C" fn task_trampoline() -> ! {
+    let (entry, arg) = {
+        let guard = SCHED.lock();
+        let sched = guard.as_ref().expect("scheduler not initialized");
+        sched.tasks[sched.current].entry.expect("task without entry")
+    };
+    interrupts::enable();
+    let code = entry(arg);
+    exit(code);
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Switches to the next ready task, if any. Returns false when there was nothing else to run.
+pub fn yield_now() -> bool {
+    LAST_YIELD_TICK.store(timer::ticks(), Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        let (save, load, from, to, latency) = {
+            let mut guard = SCHED.lock();
+            let Some(sched) = guard.as_mut() else { return false; };
+            let n = sched.tasks.len();
+            let cur = sched.current;
+            // Most urgent effective priority wins; ties go round-robin from the current task.
+            let Some(next) = (1..n)
+                .map(|off| (cur + off) % n)
+                .filter(|&i| sched.tasks[i].state == TaskState::Ready)
+                .min_by_key(|&i| {
+                    let t = &sched.tasks[i];
+                    (t.priority as u32).saturating_sub(t.passed_over / AGING_STEP)
+                })
+            else {
+                return false;
+            };
+            for (i, t) in sched.tasks.iter_mut().enumerate() {
+                if i != next && i != cur && t.state == TaskState::Ready {
+                    t.passed_over = t.passed_over.saturating_add(1);
+                }
+            }
+            sched.tasks[next].passed_over = 0;
+            let now = rdtsc();
+            if sched.tasks[cur].state == TaskState::Running {
+                sched.tasks[cur].state = TaskState::Ready;
+                sched.tasks[cur].sched.ready_since = now;
+            }
+            let stats = &mut sched.tasks[next].sched;
+            let latency = now.saturating_sub(stats.ready_since);
+            stats.runs += 1;
+            stats.total_latency += latency;
+            stats.max_latency = stats.max_latency.max(latency);
+            sched.tasks[next].state = TaskState::Running;
+            sched.current = next;
+            CURRENT_TASK.store(sched.tasks[next].id, Ordering::Relaxed);
+            let save = &mut sched.tasks[cur].rsp as *mut u64;
+            let load = sched.tasks[next].rsp;
+            (save, load, sched.tasks[cur].id, sched.tasks[next].id, latency)
+        };
+        if TRACE_SWITCHES.load(Ordering::Relaxed) {
+            trace_switch(from, to, latency);
+        }
+        // The boxes keep each Task at a stable address, so `save` stays valid after unlocking.
+        unsafe { stratos_switch_context(save, load) };
+        true
+    })
+}
+
+/// Ends the current task with `code`, runs exit hooks, and never returns.
+pub fn exit(code: i32) -> ! {
+    let (id, app) = interrupts::without_interrupts(|| {
+        let mut guard = SCHED.lock();
+        let sched = guard.as_mut().expect("scheduler not initialized");
+        let task = &mut sched.tasks[sched.current];
+        task.state = TaskState::Exited(code);
+        (task.id, task.app)
+    });
+    let hooks = EXIT_HOOKS.lock().clone();
+    for hook in hooks.iter() {
+        hook(id, app, code);
+    }
+    loop {
+        if !yield_now() {
+            // Only the shell can be left; it is never blocked, so this is unreachable in practice.
+            crate::idle::idle();
+        }
+    }
+}
+
+/// Called from the timer interrupt for ticks that were not spent idle.
+pub fn account_tick() {
+    // Every other user of SCHED masks interrupts, so this only fails if that invariant breaks.
+    if let Some(mut guard) = SCHED.try_lock() {
+        if let Some(sched) = guard.as_mut() {
+            let cur = sched.current;
+            sched.tasks[cur].cpu_ticks += 1;
+        }
+    }
+}
+
+pub fn current_id() -> TaskId {
+    interrupts::without_interrupts(|| {
+        SCHED.lock().as_ref().map(|s| s.tasks[s.current].id).unwrap_or(BOOT_TASK)
+    })
+}
+
+pub fn current_app() -> Option<AppId> {
+    interrupts::without_interrupts(|| {
+        SCHED.lock().as_ref().and_then(|s| s.tasks[s.current].app)
+    })
+}
+
+/// Like `current_app`, but gives up instead of spinning if the scheduler is
+/// locked, for fault handlers that may have interrupted it.
+pub fn try_current_app() -> Option<AppId> {
+    SCHED.try_lock()?.as_ref().and_then(|s| s.tasks[s.current].app)
+}
+
+/// Sets the static priority of task `id`. Returns false if there is no such task.
+pub fn set_priority(id: TaskId, priority: Priority) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut guard = SCHED.lock();
+        let Some(task) = guard.as_mut().and_then(|s| s.tasks.iter_mut().find(|t| t.id == id)) else {
+            return false;
+        };
+        task.priority = priority.min(LOWEST_PRIORITY);
+        true
+    })
+}
+
+pub fn priority(id: TaskId) -> Option<Priority> {
+    interrupts::without_interrupts(|| {
+        SCHED.lock().as_ref()?.tasks.iter().find(|t| t.id == id).map(|t| t.priority)
+    })
+}
+
+/// Priority for tasks spawned from now on; returns the previous one.
+pub fn set_spawn_priority(priority: Priority) -> Priority {
+    SPAWN_PRIORITY.swap(priority.min(LOWEST_PRIORITY), Ordering::Relaxed)
+}
+
+pub fn state(id: TaskId) -> Option<TaskState> {
+    interrupts::without_interrupts(|| {
+        SCHED.lock().as_ref()?.tasks.iter().find(|t| t.id == id).map(|t| t.state)
+    })
+}
+
+pub fn list() -> Vec<TaskInfo> {
+    interrupts::without_interrupts(|| {
+        let guard = SCHED.lock();
+        let Some(sched) = guard.as_ref() else { return Vec::new(); };
+        sched
+            .tasks
+            .iter()
+            .map(|t| TaskInfo {
+                id: t.id,
+                name: t.name.clone(),
+                app: t.app,
+                state: t.state,
+                cpu_ticks: t.cpu_ticks,
+                sched: t.sched,
+                priority: t.priority,
+            })
+            .collect()
+    })
+}
+
+/// Records a context switch in the klog ring only; echoing it would itself
+/// disturb the timing being traced.
+fn trace_switch(from: TaskId, to: TaskId, latency: u64) {
+    use core::fmt::Write;
+    let mut line: HString<64> = HString::new();
+    let _ = write!(line, "sched: {} -> {} after {}", from, to, format_latency(latency));
+    klog::try_log(klog::Level::Debug, &line);
+}
+
+pub fn set_trace(on: bool) {
+    TRACE_SWITCHES.store(on, Ordering::Relaxed);
+}
+
+fn format_latency(cycles: u64) -> HString<16> {
+    use core::fmt::Write;
+    let mut s = HString::new();
+    let _ = match wait::tsc_hz() {
+        0 => write!(s, "{}cy", cycles),
+        hz => match cycles as u128 * 1_000_000 / hz as u128 {
+            us if us >= 10_000 => write!(s, "{}ms", us / 1000),
+            us => write!(s, "{}us", us),
+        },
+    };
+    s
+}
+
+const SCHEDSTAT_USAGE: &str = "Usage: schedstat [reset | trace on|off]";
+
+/// `schedstat`: per-task switch counts, CPU time and scheduling latency.
+pub fn schedstat_cmd(args: &[&str]) -> crate::commands::Status {
+    match args {
+        [] => {}
+        [cmd] if cmd.eq_ignore_ascii_case("reset") => {
+            interrupts::without_interrupts(|| {
+                if let Some(sched) = SCHED.lock().as_mut() {
+                    for t in sched.tasks.iter_mut() {
+                        t.sched = SchedStats { ready_since: t.sched.ready_since, ..SchedStats::default() };
+                    }
+                }
+            });
+            console::write_line("Scheduler statistics reset.");
+            return Ok(());
+        }
+        [cmd, state] if cmd.eq_ignore_ascii_case("trace") => {
+            match *state {
+                s if s.eq_ignore_ascii_case("on") => set_trace(true),
+                s if s.eq_ignore_ascii_case("off") => set_trace(false),
+                _ => {
+                    console::write_err(SCHEDSTAT_USAGE);
+                    return Err(2);
+                }
+            }
+            console::write_line(if TRACE_SWITCHES.load(Ordering::Relaxed) {
+                "Context switches are now traced to the kernel log (see dmesg)."
+            } else {
+                "Context switch tracing off."
+            });
+            return Ok(());
+        }
+        _ => {
+            console::write_err(SCHEDSTAT_USAGE);
+            return Err(2);
+        }
+    }
+
+    console::write_line("  TID      RUNS    CPU TIME   MAX LAT   AVG LAT  NAME");
+    for t in list() {
+        let avg = t.sched.total_latency.checked_div(t.sched.runs).unwrap_or(0);
+        console::write_line(&format!(
+            "{:>5}  {:>8}  {:>10}  {:>8}  {:>8}  {}",
+            t.id,
+            t.sched.runs,
+            format_cpu_time(t.cpu_ticks),
+            format_latency(t.sched.max_latency),
+            format_latency(avg),
+            t.name
+        ));
+    }
+    if TRACE_SWITCHES.load(Ordering::Relaxed) {
+        console::write_line("Tracing context switches to the kernel log.");
+    }
+    Ok(())
+}
+
+fn state_name(state: TaskState) -> &'static str {
+    match state {
+        TaskState::Ready => "ready",
+        TaskState::Running => "running",
+        TaskState::Exited(_) => "exited",
+    }
+}
+
+fn format_cpu_time(ticks: u64) -> String {
+    let hz = timer::frequency() as u64;
+    let secs = ticks / hz;
+    let hundredths = (ticks % hz) * 100 / hz;
+    format!("{}:{:02}.{:02}", secs / 60, secs % 60, hundredths)
+}
+
+fn format_mem(app: Option<AppId>) -> String {
+    match app.and_then(memory::app_stats) {
+        Some(st) => format!("{}/{}", format_bytes::<16>(st.used), format_bytes::<16>(st.total)),
+        None => String::from("-"),
+    }
+}
+
+fn format_app(app: Option<AppId>) -> String {
+    match app {
+        Some(id) => format!("{}", id),
+        None => String::from("-"),
+    }
+}
+
+pub fn ps_cmd() {
+    console::write_line("  TID  APP  PRI  STATE     CPU TIME    MEMORY               NAME");
+    for t in list() {
+        console::write_line(&format!(
+            "{:>5}  {:>3}  {:>3}  {:<8}  {:>10}  {:<19}  {}",
+            t.id,
+            format_app(t.app),
+            t.priority,
+            state_name(t.state),
+            format_cpu_time(t.cpu_ticks),
+            format_mem(t.app),
+            t.name
+        ));
+    }
+}
+
+/// Live task view; redraws every second until a key is pressed.
+pub fn top_cmd() {
+    let hz = timer::frequency() as u64;
+    let mut prev: Vec<(TaskId, u64)> = Vec::new();
+    let mut prev_at = timer::ticks();
+    loop {
+        let now = timer::ticks();
+        let elapsed = now.saturating_sub(prev_at).max(1);
+        let tasks = list();
+
+        console::clear_screen();
+        console::write_line(&format!(
+            "top - up {}s, {} tasks, {}% idle  (press any key to exit)",
+            timer::seconds(),
+            tasks.len(),
+            crate::idle::idle_percent()
+        ));
+        console::write_line("");
+        console::write_line("  TID  APP  STATE      CPU%  CPU TIME    MEMORY               NAME");
+        for t in tasks.iter() {
+            let before = prev.iter().find(|(id, _)| *id == t.id).map(|(_, c)| *c).unwrap_or(t.cpu_ticks);
+            let pct = (t.cpu_ticks.saturating_sub(before) * 100 / elapsed).min(100);
+            console::write_line(&format!(
+                "{:>5}  {:>3}  {:<8}  {:>5}  {:>10}  {:<19}  {}",
+                t.id,
+                format_app(t.app),
+                state_name(t.state),
+                pct,
+                format_cpu_time(t.cpu_ticks),
+                format_mem(t.app),
+                t.name
+            ));
+        }
+
+        prev = tasks.iter().map(|t| (t.id, t.cpu_ticks)).collect();
+        prev_at = now;
+        let deadline = now + hz;
+        while timer::ticks() < deadline {
+            if keyboard::poll_event().is_some() {
+                return;
+            }
+            timer::request_wakeup(deadline);
+            crate::idle::idle();
+        }
+    }
+}
@@ -0,0 +1,93 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use heapless::String as HString;
+use spin::Mutex;
+use crate::{cmdline, commands, console, serial};
+
+// Host protocol, one line each way:
+//   host -> kernel: a shell command line terminated by '\n'
+//   kernel -> host: "@@BEGIN <seq>", then "| <line>" per output line,
+//                   then "@@END <seq> <status>"
+// "@@READY" is sent whenever test mode is switched on or the shell restarts.
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SEQ: AtomicU32 = AtomicU32::new(0);
+static IN_FLIGHT: AtomicU32 = AtomicU32::new(0);
+static LINE: Mutex<HString<256>> = Mutex::new(HString::new());
+
+pub fn init() {
+    if cmdline::has_flag("testmode") {
+        set_enabled(true);
+    }
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+    LINE.lock().clear();
+    if on {
+        serial::write("@@READY");
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Runs any complete command lines received over serial. Returns true if the
+/// console was written to, so the caller can redraw its prompt.
+pub fn poll() -> bool {
+    if !enabled() {
+        return false;
+    }
+    let mut ran = false;
+    while let Some(byte) = serial::try_read() {
+        match byte {
+            b'\r' => {}
+            b'\n' => {
+                let line = core::mem::take(&mut *LINE.lock());
+                run(&line);
+                ran = true;
+            }
+            b => {
+                // Oversized lines are truncated; the host sees the result of what fit.
+                let _ = LINE.lock().push(b as char);
+            }
+        }
+    }
+    ran
+}
+
+fn run(line: &str) {
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed) + 1;
+    console::write_line("");
+    console::cwrite_line(&format!("(serial) {}", line), 0x7FB8FF, console::default_bg());
+
+    serial::write(&format!("@@BEGIN {}", seq));
+    IN_FLIGHT.store(seq, Ordering::Relaxed);
+    let _ = console::begin_capture();
+    commands::handle_line(line);
+    finish(seq, commands::last_status());
+}
+
+fn finish(seq: u32, status: i32) {
+    IN_FLIGHT.store(0, Ordering::Relaxed);
+    if let Some(out) = console::end_capture() {
+        for l in out.lines() {
+            serial::write(&format!("| {}", l));
+        }
+    }
+    serial::write(&format!("@@END {} {}", seq, status));
+}
+
+/// Closes the frame of a command that took the shell down with it.
+pub fn on_shell_restart(status: i32) {
+    let seq = IN_FLIGHT.load(Ordering::Relaxed);
+    if seq != 0 {
+        finish(seq, status);
+    }
+    if enabled() {
+        serial::write("@@READY");
+    }
+}
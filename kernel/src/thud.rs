@@ -1,33 +1,156 @@
 #![allow(dead_code)]
 
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use spin::Mutex;
 use heapless::{String as HString, Vec};
-use crate::console::{with_console, HudAlign};
+use crate::console::{with_console, Console, HudAlign};
+use crate::events;
 use alloc::boxed::Box;
-use core::fmt::Write;
+use x86_64::instructions::interrupts;
 
 pub trait HudModule {
-    fn name(&self) -> &'static str;
+    fn name(&self) -> &str;
     fn alignment(&self) -> HudAlign { HudAlign::Right }
+    fn color(&self) -> Option<u32> { None }
+    /// Centiseconds between `update` calls. The HUD redraws once a second
+    /// regardless, so values above 100 only skip updates.
+    fn interval(&self) -> u32 { 100 }
+    /// When the row overflows, the lowest priority modules are dropped first.
+    fn priority(&self) -> u8 { 50 }
     fn update(&mut self);
     fn render(&self) -> HString<64>;
 }
 
+pub const MAX_MODULES: usize = 16;
+pub const MAX_NAME: usize = 16;
+
+/// Drawn between the left, center and right groups.
+pub const DEFAULT_SEPARATOR: char = '\u{2502}';
+/// Spaces between parts, and on each side of a separator.
+pub const DEFAULT_PADDING: usize = 2;
+pub const MAX_PADDING: usize = 4;
+
+// A group's parts, plus room for a separator.
+type HudGroup = Vec<(HString<64>, Option<u32>), { MAX_MODULES + 1 }>;
+
+struct Slot {
+    module: Box<dyn HudModule + Send>,
+    next_update: u64,
+    /// Set with `os hud color`; wins over the module's own color.
+    color: Option<u32>,
+}
+
 static ENABLED: AtomicBool = AtomicBool::new(false);
 static NEEDS_REDRAW: AtomicBool = AtomicBool::new(false);
-static MODULES: Mutex<Vec<Box<dyn HudModule + Send>, 8>> = Mutex::new(Vec::new());
+static MODULES: Mutex<Vec<Slot, MAX_MODULES>> = Mutex::new(Vec::new());
+// Earliest tick any module wants an update; checked every tick without taking the lock.
+static NEXT_DUE: AtomicU64 = AtomicU64::new(0);
+// The separator as a char, or 0 for none.
+static SEPARATOR: AtomicU32 = AtomicU32::new(DEFAULT_SEPARATOR as u32);
+static PADDING: AtomicUsize = AtomicUsize::new(DEFAULT_PADDING);
 
 static mut TICK_COUNT: u64 = 0;
 
+/// Whether the module list's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    MODULES.is_locked()
+}
+
 pub fn init() {
+    events::subscribe(events::TIME_SYNCED, request_redraw);
+    events::subscribe(events::THEME_CHANGED, request_redraw);
 }
 
-pub fn register(module: Box<dyn HudModule + Send>) {
-    let mut mods = MODULES.lock();
-    if mods.len() < mods.capacity() {
-        mods.push(module).ok();
+fn request_redraw(_: &events::Event) {
+    NEEDS_REDRAW.store(true, Ordering::Release);
+}
+
+fn ticks() -> u64 {
+    unsafe { TICK_COUNT }
+}
+
+/// Adds a module at any time, e.g. from a command or a late-loaded driver.
+/// Fails if the HUD is full or a module with the same name exists.
+pub fn register(module: Box<dyn HudModule + Send>) -> Result<(), &'static str> {
+    // The timer interrupt redraws the HUD under this lock.
+    let result = interrupts::without_interrupts(|| {
+        let mut mods = MODULES.lock();
+        if mods.iter().any(|s| s.module.name() == module.name()) {
+            return Err("a HUD module with that name exists");
+        }
+        mods.push(Slot { module, next_update: 0, color: None })
+            .map_err(|_| "HUD module table is full")
+    });
+    if result.is_ok() {
+        NEXT_DUE.store(0, Ordering::Relaxed);
+        NEEDS_REDRAW.store(true, Ordering::Release);
     }
+    result
+}
+
+/// Removes the module called `name`. Returns false if there is none.
+pub fn unregister(name: &str) -> bool {
+    let removed = interrupts::without_interrupts(|| {
+        let mut mods = MODULES.lock();
+        let before = mods.len();
+        mods.retain(|s| s.module.name() != name);
+        mods.len() != before
+    });
+    if removed {
+        NEEDS_REDRAW.store(true, Ordering::Release);
+    }
+    removed
+}
+
+/// Overrides the color of the module called `name`, or with None hands it
+/// back to the module. Returns false if there is no such module.
+pub fn set_color(name: &str, color: Option<u32>) -> bool {
+    let found = interrupts::without_interrupts(|| {
+        let mut mods = MODULES.lock();
+        let Some(slot) = mods.iter_mut().find(|s| s.module.name() == name) else { return false; };
+        slot.color = color;
+        true
+    });
+    if found {
+        NEEDS_REDRAW.store(true, Ordering::Release);
+    }
+    found
+}
+
+pub fn separator() -> Option<char> {
+    char::from_u32(SEPARATOR.load(Ordering::Relaxed)).filter(|&c| c != '\0')
+}
+
+pub fn padding() -> usize {
+    PADDING.load(Ordering::Relaxed)
+}
+
+/// Sets the separator and padding; None draws no separators.
+pub fn set_style(separator: Option<char>, padding: usize) {
+    SEPARATOR.store(separator.map_or(0, |c| c as u32), Ordering::Relaxed);
+    PADDING.store(padding.min(MAX_PADDING), Ordering::Relaxed);
+    NEEDS_REDRAW.store(true, Ordering::Release);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Names of the registered HUD modules, in draw order.
+pub fn module_names() -> Vec<HString<MAX_NAME>, MAX_MODULES> {
+    interrupts::without_interrupts(|| {
+        MODULES.lock().iter().map(|s| truncate_name(s.module.name())).collect()
+    })
+}
+
+fn truncate_name(name: &str) -> HString<MAX_NAME> {
+    let mut out = HString::new();
+    for ch in name.chars() {
+        if out.push(ch).is_err() {
+            break;
+        }
+    }
+    out
 }
 
 pub fn enable() {
@@ -42,49 +165,136 @@ pub fn disable() {
 
 pub fn on_100hz_tick() {
     if !ENABLED.load(Ordering::Relaxed) { return; }
-    unsafe {
+    let now = unsafe {
         TICK_COUNT = TICK_COUNT.wrapping_add(1);
-        if TICK_COUNT % 100 == 0 {
-            NEEDS_REDRAW.store(true, Ordering::Release);
-            poll_draw();
-        }
+        TICK_COUNT
+    };
+    if now % 100 == 0 || now >= NEXT_DUE.load(Ordering::Relaxed) {
+        NEEDS_REDRAW.store(true, Ordering::Release);
+        poll_draw();
     }
 }
 
+struct Part {
+    text: HString<64>,
+    color: Option<u32>,
+    align: HudAlign,
+    priority: u8,
+}
+
 pub fn poll_draw() {
     if !ENABLED.load(Ordering::Acquire) { return; }
     if !NEEDS_REDRAW.swap(false, Ordering::AcqRel) { return; }
 
-    let mut left_buf = HString::<128>::new();
-    let mut center_buf = HString::<128>::new();
-    let mut right_buf = HString::<128>::new();
+    let now = ticks();
+    let mut parts: Vec<Part, MAX_MODULES> = Vec::new();
+    let mut next_due = u64::MAX;
 
     let mut modules = MODULES.lock();
-    for m in modules.iter_mut() {
-        m.update();
-        let part = m.render();
-        match m.alignment() {
-            HudAlign::Left => { let _ = write!(left_buf, "{}  ", part); }
-            HudAlign::Center => { let _ = write!(center_buf, "{}  ", part); }
-            HudAlign::Right => { let _ = write!(right_buf, "{}  ", part); }
+    for slot in modules.iter_mut() {
+        let m = &mut slot.module;
+        if now >= slot.next_update {
+            m.update();
+            slot.next_update = now + m.interval().max(1) as u64;
+        }
+        next_due = next_due.min(slot.next_update);
+        let mut text = m.render();
+        trim_trailing_ws(&mut text);
+        if text.is_empty() {
+            continue;
         }
+        let color = slot.color.or(m.color());
+        let _ = parts.push(Part { text, color, align: m.alignment(), priority: m.priority() });
     }
+    drop(modules);
+    NEXT_DUE.store(next_due, Ordering::Relaxed);
 
-    trim_trailing_ws(&mut left_buf);
-    trim_trailing_ws(&mut center_buf);
-    trim_trailing_ws(&mut right_buf);
-
+    let sep = separator();
+    let pad = padding();
     with_console(|c| {
-        let (fg, _) = c.default_colors();
+        let cols = c.hud_cols();
+        // Drop the lowest priority part (latest registered on ties) until the row fits.
+        while row_width(&parts, sep.is_some(), pad) > cols {
+            let Some(victim) = parts
+                .iter()
+                .enumerate()
+                .min_by_key(|(i, p)| (p.priority, usize::MAX - i))
+                .map(|(i, _)| i)
+            else {
+                break;
+            };
+            parts.remove(victim);
+        }
+
+        let mut left: HudGroup = Vec::new();
+        let mut center: HudGroup = Vec::new();
+        let mut right: HudGroup = Vec::new();
+        for p in parts.iter() {
+            let group = match p.align {
+                HudAlign::Left => &mut left,
+                HudAlign::Center => &mut center,
+                HudAlign::Right => &mut right,
+            };
+            let _ = group.push((p.text.clone(), p.color));
+        }
+
+        let (fg, bg) = c.default_colors();
+        if let Some(sep) = sep {
+            // The left group ends with a separator and the right one starts
+            // with one, whenever there is something on the other side.
+            let mut text = HString::new();
+            let _ = text.push(sep);
+            let part = (text, Some(halfway(fg, bg)));
+            if !left.is_empty() && (!center.is_empty() || !right.is_empty()) {
+                let _ = left.push(part.clone());
+            }
+            if !right.is_empty() && (!left.is_empty() || !center.is_empty()) {
+                let _ = right.insert(0, part);
+            }
+        }
         c.hud_begin();
-        c.hud_draw_text(left_buf.as_str(), fg, HudAlign::Left);
-        c.hud_draw_text(center_buf.as_str(), fg, HudAlign::Center);
-        c.hud_draw_text(right_buf.as_str(), fg, HudAlign::Right);
+        draw_group(c, &left, fg, HudAlign::Left, pad);
+        draw_group(c, &center, fg, HudAlign::Center, pad);
+        draw_group(c, &right, fg, HudAlign::Right, pad);
         c.hud_present();
     });
 }
 
-fn trim_trailing_ws(s: &mut HString<128>) {
+/// Columns needed to draw every part: `pad` spaces between parts, and a
+/// separator with padding for each group boundary, counted generously.
+fn row_width(parts: &[Part], separators: bool, pad: usize) -> usize {
+    let text: usize = parts.iter().map(|p| p.text.chars().count()).sum();
+    let mut width = text + parts.len().saturating_sub(1) * pad;
+    if separators {
+        let has = |a: HudAlign| parts.iter().any(|p| p.align == a);
+        let (left, center, right) = (has(HudAlign::Left), has(HudAlign::Center), has(HudAlign::Right));
+        let count = (left && (center || right)) as usize + (right && (left || center)) as usize;
+        width += count * (1 + pad);
+    }
+    width
+}
+
+/// The color midway between `a` and `b`, for separators that sit back from
+/// the text whatever the theme.
+fn halfway(a: u32, b: u32) -> u32 {
+    let channel = |shift: u32| ((((a >> shift) & 0xFF) + ((b >> shift) & 0xFF)) / 2) << shift;
+    channel(16) | channel(8) | channel(0)
+}
+
+fn draw_group(c: &mut Console, group: &HudGroup, fg: u32, align: HudAlign, pad: usize) {
+    if group.is_empty() {
+        return;
+    }
+    let total: usize = group.iter().map(|(s, _)| s.chars().count()).sum::<usize>()
+        + (group.len() - 1) * pad;
+    let mut x = c.hud_align_x(total, align);
+    for (text, color) in group.iter() {
+        c.hud_draw_text_at(x, text.as_str(), color.unwrap_or(fg));
+        x += text.chars().count() + pad;
+    }
+}
+
+fn trim_trailing_ws<const N: usize>(s: &mut HString<N>) {
     while let Some(ch) = s.chars().rev().next() {
         if ch.is_whitespace() {
             s.pop();
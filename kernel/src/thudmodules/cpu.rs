@@ -0,0 +1,26 @@
+#![allow(dead_code)]
+
+use core::fmt::Write;
+use heapless::String as HString;
+use crate::{thud::{HudModule, register}, console::HudAlign, idle};
+use alloc::boxed::Box;
+
+pub struct CpuHud;
+
+impl HudModule for CpuHud {
+    fn name(&self) -> &'static str { "cpu" }
+
+    fn alignment(&self) -> HudAlign { HudAlign::Left }
+
+    fn update(&mut self) {}
+
+    fn render(&self) -> HString<64> {
+        let mut out: HString<64> = HString::new();
+        let _ = write!(out, "Idle: {}%", idle::idle_percent());
+        out
+    }
+}
+
+pub fn init() {
+    let _ = register(Box::new(CpuHud));
+}
@@ -0,0 +1,55 @@
+#![allow(dead_code)]
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use heapless::String as HString;
+use crate::{thud::{HudModule, register}, console::HudAlign, time};
+use alloc::boxed::Box;
+
+const WARN_COLOR: u32 = 0xFFD37F;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static THRESHOLD_SECS: AtomicU64 = AtomicU64::new(time::DRIFT_LIMIT_SECS);
+
+/// Shown only while the tick clock has drifted from the RTC by more than the
+/// threshold. Reads what the shell's periodic check recorded, since the RTC
+/// is not read from the timer interrupt.
+pub struct DriftHud {
+    drift: i64,
+}
+
+impl HudModule for DriftHud {
+    fn name(&self) -> &'static str { "drift" }
+
+    fn alignment(&self) -> HudAlign { HudAlign::Right }
+
+    fn color(&self) -> Option<u32> { Some(WARN_COLOR) }
+
+    fn update(&mut self) {
+        self.drift = time::drift().map_or(0, |(d, _)| d);
+    }
+
+    fn render(&self) -> HString<64> {
+        let mut out: HString<64> = HString::new();
+        if ENABLED.load(Ordering::Relaxed) && self.drift.unsigned_abs() > THRESHOLD_SECS.load(Ordering::Relaxed) {
+            let _ = write!(out, "Clock {:+}s: os time sync", self.drift);
+        }
+        out
+    }
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+pub fn set_threshold(secs: u64) {
+    THRESHOLD_SECS.store(secs, Ordering::Relaxed);
+}
+
+pub fn threshold() -> u64 {
+    THRESHOLD_SECS.load(Ordering::Relaxed)
+}
+
+pub fn init() {
+    let _ = register(Box::new(DriftHud { drift: 0 }));
+}
@@ -35,7 +35,7 @@ impl HudModule for Mem {
 }
 
 pub fn init() {
-    register(Box::new(Mem));
+    let _ = register(Box::new(Mem));
 }
 
 fn format_bytes<const N: usize>(b: u64) -> HString<N> {
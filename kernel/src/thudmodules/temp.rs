@@ -0,0 +1,67 @@
+#![allow(dead_code)]
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use heapless::String as HString;
+use crate::{thud::{HudModule, register}, console::HudAlign, sensors};
+use alloc::boxed::Box;
+
+const WARN_COLOR: u32 = 0xFF3030;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static THRESHOLD_C: AtomicU32 = AtomicU32::new(85);
+
+pub struct TempHud {
+    caps: Option<sensors::ThermalCaps>,
+    last: Option<u32>,
+}
+
+impl HudModule for TempHud {
+    fn name(&self) -> &'static str { "temp" }
+
+    fn alignment(&self) -> HudAlign { HudAlign::Left }
+
+    fn color(&self) -> Option<u32> {
+        match self.last {
+            Some(t) if t >= THRESHOLD_C.load(Ordering::Relaxed) => Some(WARN_COLOR),
+            _ => None,
+        }
+    }
+
+    fn update(&mut self) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            self.last = None;
+            return;
+        }
+        let caps = *self.caps.get_or_insert_with(sensors::caps);
+        self.last = sensors::cpu_temp(&caps);
+    }
+
+    fn render(&self) -> HString<64> {
+        let mut out: HString<64> = HString::new();
+        if !ENABLED.load(Ordering::Relaxed) {
+            return out;
+        }
+        match self.last {
+            Some(t) => { let _ = write!(out, "CPU: {} C", t); }
+            None => { let _ = out.push_str("CPU: -- C"); }
+        }
+        out
+    }
+}
+
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+pub fn set_threshold(celsius: u32) {
+    THRESHOLD_C.store(celsius, Ordering::Relaxed);
+}
+
+pub fn threshold() -> u32 {
+    THRESHOLD_C.load(Ordering::Relaxed)
+}
+
+pub fn init() {
+    let _ = register(Box::new(TempHud { caps: None, last: None }));
+}
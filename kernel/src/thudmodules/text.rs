@@ -0,0 +1,42 @@
+#![allow(dead_code)]
+
+use heapless::String as HString;
+use crate::{thud::{self, HudModule, MAX_NAME}, console::HudAlign};
+use alloc::boxed::Box;
+
+/// Fixed text added from the shell or a script with `os hud add`.
+pub struct TextHud {
+    name: HString<MAX_NAME>,
+    align: HudAlign,
+    text: HString<64>,
+    priority: u8,
+}
+
+impl HudModule for TextHud {
+    fn name(&self) -> &str { &self.name }
+
+    fn alignment(&self) -> HudAlign { self.align }
+
+    fn priority(&self) -> u8 { self.priority }
+
+    // Nothing to refresh.
+    fn interval(&self) -> u32 { u32::MAX }
+
+    fn update(&mut self) {}
+
+    fn render(&self) -> HString<64> {
+        self.text.clone()
+    }
+}
+
+pub fn add(name: &str, align: HudAlign, text: &str, priority: u8) -> Result<(), &'static str> {
+    let mut n = HString::new();
+    n.push_str(name).map_err(|_| "HUD module name is too long")?;
+    let mut t = HString::new();
+    for ch in text.chars() {
+        if t.push(ch).is_err() {
+            break;
+        }
+    }
+    thud::register(Box::new(TextHud { name: n, align, text: t, priority }))
+}
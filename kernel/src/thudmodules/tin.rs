@@ -12,6 +12,9 @@ impl HudModule for Tin {
 
     fn alignment(&self) -> HudAlign { HudAlign::Right }
 
+    // The clock is the last thing to give up when the row is full.
+    fn priority(&self) -> u8 { 90 }
+
     fn update(&mut self) {
     }
 
@@ -24,5 +27,5 @@ impl HudModule for Tin {
 }
 
 pub fn init() {
-    register(Box::new(Tin));
+    let _ = register(Box::new(Tin));
 }
@@ -4,10 +4,6 @@ use heapless::String as HString;
 use crate::{thud::{HudModule, register}, console::HudAlign};
 use alloc::boxed::Box;
 
-extern "C" {
-    static mut TICKS: u64;
-}
-
 pub struct UptimeHud;
 
 impl HudModule for UptimeHud {
@@ -22,8 +18,8 @@ impl HudModule for UptimeHud {
 
         let _ = out.push_str("Uptime: ");
 
-        let (hours, mins, secs) = unsafe {
-            let secs = TICKS / 100;
+        let (hours, mins, secs) = {
+            let secs = crate::timer::seconds();
             let mins = secs / 60;
             let hours = mins / 60;
             (hours, mins % 60, secs % 60)
@@ -40,7 +36,7 @@ impl HudModule for UptimeHud {
 }
 
 pub fn init() {
-    register(Box::new(UptimeHud));
+    let _ = register(Box::new(UptimeHud));
 }
 
 fn push_2digits(out: &mut HString<64>, v: u32) {
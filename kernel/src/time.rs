@@ -1,14 +1,46 @@
 #![allow(unused_unsafe)]
 
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use spin::Mutex;
-use x86_64::instructions::port::Port;
+use x86_64::instructions::{interrupts, port::Port};
 use heapless::String as HString;
 
 pub static DISPLAY_24H: AtomicBool = AtomicBool::new(false);
+const SETTING_FORMAT: &str = "time.format";
 
 static BASE_TIME: Mutex<Option<DateTime>> = Mutex::new(None);
 static UPTIME_SECONDS: Mutex<u64> = Mutex::new(0);
+// Wall-clock seconds at boot, fixed the first time the clock is set so later
+// resyncs do not move it. 0 until then. Atomic so crash paths can read it.
+static BOOT_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// How often the shell compares the tick clock with the RTC.
+const DRIFT_CHECK_SECS: u64 = 60;
+/// Drift beyond this is worth a resync; `os time sync` uses the same bound.
+pub const DRIFT_LIMIT_SECS: u64 = 2;
+// Tick clock minus RTC at the last check, and the uptime second it was taken
+// (u64::MAX before the first). Atomics so the HUD can read them.
+static DRIFT: AtomicI64 = AtomicI64::new(0);
+static DRIFT_CHECKED: AtomicU64 = AtomicU64::new(u64::MAX);
+
+pub const RTC_IRQ: u8 = 8;
+
+const RTC_REG_SEC_ALARM: u8 = 0x01;
+const RTC_REG_MIN_ALARM: u8 = 0x03;
+const RTC_REG_HOUR_ALARM: u8 = 0x05;
+const RTC_REG_B: u8 = 0x0B;
+const RTC_REG_C: u8 = 0x0C;
+const RTC_B_ALARM_IRQ: u8 = 0x20;
+const RTC_C_ALARM_FLAG: u8 = 0x20;
+
+pub struct Alarm {
+    pub hour: u8,
+    pub minute: u8,
+    pub command: HString<64>,
+}
+
+static ALARM: Mutex<Option<Alarm>> = Mutex::new(None);
+static ALARM_FIRED: AtomicBool = AtomicBool::new(false);
 
 #[derive(Copy, Clone)]
 pub struct DateTime {
@@ -38,6 +70,30 @@ fn days_in_month(year: u64, month: u64) -> u64 {
     days
 }
 
+const WEEKDAY_NAMES: [&str; 7] = [
+    "Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday",
+];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+// Sakamoto's method, 0 = Sunday.
+fn day_of_week(y: u64, m: u64, d: u64) -> u64 {
+    const T: [u64; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let y = if m < 3 { y - 1 } else { y };
+    (y + y / 4 - y / 100 + y / 400 + T[(m - 1) as usize] + d) % 7
+}
+
+fn day_of_year(y: u64, m: u64, d: u64) -> u64 {
+    let mut days = d;
+    for month in 1..m {
+        days += days_in_month(y, month);
+    }
+    days
+}
+
 fn read_rtc_register(reg: u8) -> u8 {
     unsafe {
         let mut cmos_address = Port::<u8>::new(0x70);
@@ -47,11 +103,29 @@ fn read_rtc_register(reg: u8) -> u8 {
     }
 }
 
+fn write_rtc_register(reg: u8, value: u8) {
+    unsafe {
+        let mut cmos_address = Port::<u8>::new(0x70);
+        let mut cmos_data = Port::<u8>::new(0x71);
+        cmos_address.write(reg);
+        cmos_data.write(value);
+    }
+}
+
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Status register A bit 7: the RTC is about to change its time registers.
+pub(crate) fn rtc_update_in_progress() -> bool {
+    read_rtc_register(0x0A) & 0x80 != 0
+}
+
 fn bcd_to_binary(value: u8) -> u8 {
     ((value / 16) * 10) + (value & 0xF)
 }
 
-fn read_rtc_time() -> DateTime {
+pub(crate) fn read_rtc_time() -> DateTime {
     let second = bcd_to_binary(read_rtc_register(0x00));
     let minute = bcd_to_binary(read_rtc_register(0x02));
     let hour = bcd_to_binary(read_rtc_register(0x04));
@@ -68,6 +142,269 @@ pub fn init_time() {
     *base = Some(rtc);
     let mut uptime = UPTIME_SECONDS.lock();
     *uptime = 0;
+    drop((base, uptime));
+    if BOOT_EPOCH.load(Ordering::Relaxed) == 0 {
+        if let Some(now) = current_time_secs() {
+            BOOT_EPOCH.store(now.saturating_sub(crate::timer::seconds()), Ordering::Relaxed);
+        }
+    }
+    crate::events::publish(crate::events::TIME_SYNCED, "");
+}
+
+/// Switches between 12- and 24-hour display and keeps the choice in settings.
+pub fn set_24h(on: bool) {
+    DISPLAY_24H.store(on, Ordering::Relaxed);
+    crate::settings::set(SETTING_FORMAT, if on { "24hr" } else { "12hr" });
+}
+
+/// Re-applies the time format saved in settings.
+pub fn apply_saved_format() {
+    if let Some(format) = crate::settings::get(SETTING_FORMAT) {
+        DISPLAY_24H.store(format == "24hr", Ordering::Relaxed);
+    }
+}
+
+/// Wall-clock time the kernel booted, in seconds since 1970.
+pub fn boot_time_secs() -> Option<u64> {
+    match BOOT_EPOCH.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(secs),
+    }
+}
+
+pub fn format_datetime(secs: u64) -> HString<32> {
+    let (y, m, d, h, min, s) = secs_to_ymd_hms(secs);
+    let mut out = HString::new();
+    let _ = core::fmt::write(&mut out, format_args!("{y:04}-{m:02}-{d:02} {h:02}:{min:02}:{s:02}"));
+    out
+}
+
+fn format_span(secs: u64) -> HString<32> {
+    let mut out = HString::new();
+    let (d, h, m, s) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    let _ = if d > 0 {
+        core::fmt::write(&mut out, format_args!("{}d {}h {}m", d, h, m))
+    } else if h > 0 {
+        core::fmt::write(&mut out, format_args!("{}h {}m {}s", h, m, s))
+    } else {
+        core::fmt::write(&mut out, format_args!("{}m {}s", m, s))
+    };
+    out
+}
+
+/// One line for crash reports: boot time and uptime. Lock-free, no heap.
+pub fn crash_stamp() -> HString<64> {
+    let mut out = HString::new();
+    let up = crate::timer::seconds();
+    let _ = match boot_time_secs() {
+        Some(boot) => core::fmt::write(&mut out, format_args!("Booted {} (up {}s)", format_datetime(boot), up)),
+        None => core::fmt::write(&mut out, format_args!("Boot time unknown (up {}s)", up)),
+    };
+    out
+}
+
+/// `boottime`: when the kernel booted, cross-checked against the clock.
+pub fn boottime_cmd() {
+    let Some(boot) = boot_time_secs() else {
+        crate::console::write_line("Boot time is not known yet (clock not initialized).");
+        return;
+    };
+    let up = crate::timer::seconds();
+    crate::console::write_line(&alloc::format!("Booted:  {}", format_datetime(boot)));
+    crate::console::write_line(&alloc::format!("Uptime:  {}", format_span(up)));
+    if let Some(now) = current_time_secs() {
+        crate::console::write_line(&alloc::format!("Clock:   {}", format_datetime(now)));
+        let expected = boot + up;
+        if now.abs_diff(expected) > 2 {
+            crate::console::write_line(&alloc::format!(
+                "Clock is {}s {} boot time + uptime (resynced since boot).",
+                now.abs_diff(expected),
+                if now > expected { "ahead of" } else { "behind" }
+            ));
+        }
+    }
+}
+
+/// `since HH:MM`: time elapsed since the most recent HH:MM on the clock.
+pub fn since_cmd(args: &[&str]) -> crate::commands::Status {
+    let parsed = args.first().and_then(|a| a.split_once(':')).and_then(|(h, m)| {
+        let (h, m) = (h.parse::<u64>().ok()?, m.parse::<u64>().ok()?);
+        (h < 24 && m < 60).then_some((h, m))
+    });
+    let (Some((h, m)), 1) = (parsed, args.len()) else {
+        crate::console::write_err("Usage: since <HH:MM>");
+        return Err(2);
+    };
+    let Some(now) = current_time_secs() else {
+        crate::console::write_line("Clock not initialized.");
+        return Ok(());
+    };
+    let mut target = now - now % 86400 + h * 3600 + m * 60;
+    if target > now {
+        target -= 86400;
+    }
+    crate::console::write_line(&alloc::format!("{} since {}.", format_span(now - target), format_datetime(target)));
+    if let Some(boot) = boot_time_secs() {
+        if target < boot {
+            crate::console::write_line(&alloc::format!("That was {} before boot.", format_span(boot - target)));
+        } else {
+            crate::console::write_line(&alloc::format!("That was {} after boot.", format_span(target - boot)));
+        }
+    }
+    Ok(())
+}
+
+const SLEEP_USAGE: &str = "Usage: sleep <seconds> | sleep <n>ms";
+const MAX_SLEEP_MS: u64 = 24 * 3600 * 1000;
+
+/// `sleep 5`, `sleep 2s` or `sleep 250ms`. Ctrl+C ends it early with status 130.
+pub fn sleep_cmd(args: &[&str]) -> crate::commands::Status {
+    let arg = args[0].to_ascii_lowercase();
+    let ms = if let Some(n) = arg.strip_suffix("ms") {
+        n.parse::<u64>().ok()
+    } else {
+        arg.strip_suffix('s').unwrap_or(&arg).parse::<u64>().ok().and_then(|s| s.checked_mul(1000))
+    };
+    let Some(ms) = ms.filter(|&ms| ms <= MAX_SLEEP_MS) else {
+        crate::console::write_err(SLEEP_USAGE);
+        return Err(2);
+    };
+    crate::wait::bms_interruptible(ms);
+    Ok(())
+}
+
+/// Routes the RTC interrupt through the PIC. The alarm itself stays off until
+/// `set_alarm`.
+pub fn init_rtc_irq() {
+    interrupts::without_interrupts(|| {
+        // Reading C clears any flag left over from firmware so the line can fire again.
+        read_rtc_register(RTC_REG_C);
+        crate::pic::unmask(RTC_IRQ);
+    });
+}
+
+/// Called from the IRQ8 handler. The RTC holds the line until C is read.
+pub fn handle_rtc_irq() {
+    if read_rtc_register(RTC_REG_C) & RTC_C_ALARM_FLAG != 0 {
+        ALARM_FIRED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Programs the RTC alarm for the next HH:MM:00 on the RTC clock. The RTC
+/// matches only time of day, so the alarm fires once and is then cleared.
+pub fn set_alarm(hour: u8, minute: u8, command: &str) -> Result<(), &'static str> {
+    if hour > 23 || minute > 59 {
+        return Err("alarm time must be HH:MM (24-hour)");
+    }
+    let mut cmd = HString::new();
+    cmd.push_str(command).map_err(|_| "alarm command is too long")?;
+    interrupts::without_interrupts(|| {
+        *ALARM.lock() = Some(Alarm { hour, minute, command: cmd });
+        ALARM_FIRED.store(false, Ordering::Relaxed);
+        let b = read_rtc_register(RTC_REG_B);
+        write_rtc_register(RTC_REG_B, b & !RTC_B_ALARM_IRQ);
+        write_rtc_register(RTC_REG_SEC_ALARM, binary_to_bcd(0));
+        write_rtc_register(RTC_REG_MIN_ALARM, binary_to_bcd(minute));
+        write_rtc_register(RTC_REG_HOUR_ALARM, binary_to_bcd(hour));
+        read_rtc_register(RTC_REG_C);
+        write_rtc_register(RTC_REG_B, b | RTC_B_ALARM_IRQ);
+    });
+    Ok(())
+}
+
+pub fn clear_alarm() -> bool {
+    interrupts::without_interrupts(|| {
+        let b = read_rtc_register(RTC_REG_B);
+        write_rtc_register(RTC_REG_B, b & !RTC_B_ALARM_IRQ);
+        ALARM_FIRED.store(false, Ordering::Relaxed);
+        ALARM.lock().take().is_some()
+    })
+}
+
+pub fn alarm() -> Option<(u8, u8, HString<64>)> {
+    interrupts::without_interrupts(|| {
+        ALARM.lock().as_ref().map(|a| (a.hour, a.minute, a.command.clone()))
+    })
+}
+
+/// Runs the alarm's command once it has fired. Returns true if the console
+/// was written to, so the caller can redraw its prompt.
+pub fn poll_alarm() -> bool {
+    if !ALARM_FIRED.swap(false, Ordering::Relaxed) {
+        return false;
+    }
+    let Some((hour, minute, command)) = alarm() else { return false; };
+    clear_alarm();
+    crate::console::write_line("");
+    crate::console::write_line(&alloc::format!("Alarm {:02}:{:02}: {}", hour, minute, command));
+    crate::commands::handle_command(&command);
+    true
+}
+
+fn rtc_secs(rtc: &DateTime) -> u64 {
+    ymd_hms_to_secs(rtc.year as u64, rtc.month as u64, rtc.day as u64, rtc.hour as u64, rtc.minute as u64, rtc.second as u64)
+}
+
+/// Compares the tick clock with the RTC and records the difference, tick
+/// minus RTC in seconds. None if the clock is not set or the RTC is in the
+/// middle of an update.
+pub fn check_drift() -> Option<i64> {
+    // With interrupts off the RTC interrupt cannot move the CMOS index
+    // between our register reads.
+    let (now, rtc) = interrupts::without_interrupts(|| {
+        if rtc_update_in_progress() {
+            return None;
+        }
+        Some((current_time_secs()?, read_rtc_time()))
+    })?;
+    let drift = now as i64 - rtc_secs(&rtc) as i64;
+    DRIFT.store(drift, Ordering::Relaxed);
+    DRIFT_CHECKED.store(crate::timer::seconds(), Ordering::Relaxed);
+    Some(drift)
+}
+
+/// The last recorded drift and the uptime second it was measured at.
+pub fn drift() -> Option<(i64, u64)> {
+    match DRIFT_CHECKED.load(Ordering::Relaxed) {
+        u64::MAX => None,
+        at => Some((DRIFT.load(Ordering::Relaxed), at)),
+    }
+}
+
+/// Called from the shell loop; checks the drift once a minute.
+pub fn poll_drift() {
+    let last = DRIFT_CHECKED.load(Ordering::Relaxed);
+    if last == u64::MAX || crate::timer::seconds() >= last + DRIFT_CHECK_SECS {
+        check_drift();
+    }
+}
+
+fn format_drift(drift: i64) -> HString<48> {
+    let mut out = HString::new();
+    let _ = match drift {
+        0 => core::fmt::write(&mut out, format_args!("none")),
+        d if d > 0 => core::fmt::write(&mut out, format_args!("+{}s (tick clock ahead of RTC)", d)),
+        d => core::fmt::write(&mut out, format_args!("{}s (tick clock behind RTC)", d)),
+    };
+    out
+}
+
+/// `os time status`: the tick clock next to the RTC, and how far apart they are.
+fn status() {
+    let Some(drift) = check_drift().or_else(|| drift().map(|(d, _)| d)) else {
+        crate::console::write_line("Time not initialized yet.");
+        return;
+    };
+    let rtc = interrupts::without_interrupts(read_rtc_time);
+    if let Some(now) = current_time_secs() {
+        crate::console::write_line(&alloc::format!("Tick clock: {}", format_datetime(now)));
+    }
+    crate::console::write_line(&alloc::format!("RTC:        {}", format_datetime(rtc_secs(&rtc))));
+    crate::console::write_line(&alloc::format!("Drift:      {}", format_drift(drift)));
+    crate::console::write_line(&alloc::format!("Checked every {}s while the shell is idle.", DRIFT_CHECK_SECS));
+    if drift.unsigned_abs() > DRIFT_LIMIT_SECS {
+        crate::console::write_line("Run `os time sync` to resync to the RTC.");
+    }
 }
 
 pub fn tick_second() {
@@ -140,46 +477,42 @@ fn secs_to_ymd_hms(mut secs: u64) -> (u64, u64, u64, u64, u64, u64) {
 pub fn time_cmd(args: &[&str]) {
     match args.get(0).copied() {
         Some("help") => {
-            crate::console::write_line("Usage: os time [12hr|24hr|sync|help]");
+            crate::console::write_line("Usage: os time [12hr|24hr|sync|status|alarm|help]");
             crate::console::write_line("  12hr   Set display format to 12-hour mode");
             crate::console::write_line("  24hr   Set display format to 24-hour mode");
             crate::console::write_line("  sync   Resync OS time to RTC time if drift detected");
+            crate::console::write_line("  status Compare OS time with the RTC and show the drift");
+            crate::console::write_line("  alarm  HH:MM <command> | off  Run a command at an RTC time");
             crate::console::write_line("  help   Show this message");
         }
         Some("24hr") => {
-            DISPLAY_24H.store(true, Ordering::Relaxed);
+            set_24h(true);
+            crate::settings::save();
             crate::console::write_line("Set time format: 24-hour");
         }
         Some("12hr") => {
-            DISPLAY_24H.store(false, Ordering::Relaxed);
+            set_24h(false);
+            crate::settings::save();
             crate::console::write_line("Set time format: 12-hour");
         }
+        Some("status") => status(),
         Some("sync") => {
             if let Some(current_secs) = current_time_secs() {
                 let rtc = read_rtc_time();
-                let rtc_secs = ymd_hms_to_secs(
-                    rtc.year as u64,
-                    rtc.month as u64,
-                    rtc.day as u64,
-                    rtc.hour as u64,
-                    rtc.minute as u64,
-                    rtc.second as u64,
-                );
-                let drift = if rtc_secs > current_secs {
-                    rtc_secs - current_secs
-                } else {
-                    current_secs - rtc_secs
-                };
+                let drift = rtc_secs(&rtc).abs_diff(current_secs);
 
-                if drift > 2 {
+                if drift > DRIFT_LIMIT_SECS {
                     let mut base = BASE_TIME.lock();
                     *base = Some(rtc);
                     let mut uptime = UPTIME_SECONDS.lock();
                     *uptime = 0;
+                    drop((base, uptime));
+                    crate::events::publish(crate::events::TIME_SYNCED, "");
                     crate::console::write_line("Time re-synced to RTC.");
                 } else {
                     crate::console::write_line("Clock is in sync with RTC.");
                 }
+                check_drift();
             } else {
                 crate::console::write_line("Time not initialized yet, initializing...");
                 init_time();
@@ -220,6 +553,143 @@ pub fn time_cmd(args: &[&str]) {
     }
 }
 
+const DATE_DEFAULT_FORMAT: &str = "%A %Y-%m-%d %H:%M:%S";
+
+fn format_date(secs: u64, fmt: &str) -> HString<128> {
+    use core::fmt::Write;
+
+    let (y, m, d, h, min, s) = secs_to_ymd_hms(secs);
+    let mut out: HString<128> = HString::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            let _ = out.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('Y') => write!(out, "{:04}", y),
+            Some('m') => write!(out, "{:02}", m),
+            Some('d') => write!(out, "{:02}", d),
+            Some('H') => write!(out, "{:02}", h),
+            Some('M') => write!(out, "{:02}", min),
+            Some('S') => write!(out, "{:02}", s),
+            Some('j') => write!(out, "{:03}", day_of_year(y, m, d)),
+            Some('A') => write!(out, "{}", WEEKDAY_NAMES[day_of_week(y, m, d) as usize]),
+            Some('%') | None => write!(out, "%"),
+            Some(other) => write!(out, "%{}", other),
+        };
+    }
+    out
+}
+
+pub fn date_cmd(args: &[&str]) -> crate::commands::Status {
+    let mut fmt: HString<128> = HString::new();
+    for (i, word) in args.iter().enumerate() {
+        if i > 0 {
+            let _ = fmt.push(' ');
+        }
+        let _ = fmt.push_str(word);
+    }
+
+    let fmt = if fmt.is_empty() {
+        DATE_DEFAULT_FORMAT
+    } else if let Some(rest) = fmt.strip_prefix('+') {
+        rest
+    } else {
+        crate::console::write_err("Usage: date [+format]  (%Y %m %d %H %M %S %j %A %%)");
+        return Err(2);
+    };
+
+    match current_time_secs() {
+        Some(secs) => crate::console::write_line(format_date(secs, fmt).as_str()),
+        None => crate::console::write_line("Time not initialized yet."),
+    }
+    Ok(())
+}
+
+const CAL_USAGE: &str = "Usage: cal [month] [year]";
+
+pub fn cal_cmd(args: &[&str]) -> crate::commands::Status {
+    use crate::console;
+    use core::fmt::Write;
+
+    let today = current_time_secs().map(|secs| {
+        let (y, m, d, _, _, _) = secs_to_ymd_hms(secs);
+        (y, m, d)
+    });
+    let (cur_y, cur_m) = today.map(|(y, m, _)| (y, m)).unwrap_or((1970, 1));
+
+    let month = match args.first() {
+        Some(raw) => match raw.parse::<u64>() {
+            Ok(v) if (1..=12).contains(&v) => v,
+            _ => {
+                console::write_err(CAL_USAGE);
+                return Err(2);
+            }
+        },
+        None => cur_m,
+    };
+    let year = match args.get(1) {
+        Some(raw) => match raw.parse::<u64>() {
+            Ok(v) if (1..=9999).contains(&v) => v,
+            _ => {
+                console::write_err(CAL_USAGE);
+                return Err(2);
+            }
+        },
+        None => cur_y,
+    };
+
+    let mut title: HString<32> = HString::new();
+    let _ = write!(title, "{} {}", MONTH_NAMES[(month - 1) as usize], year);
+    let mut header: HString<32> = HString::new();
+    let pad = 20usize.saturating_sub(title.len()) / 2;
+    for _ in 0..pad {
+        let _ = header.push(' ');
+    }
+    let _ = header.push_str(&title);
+    console::write_line(&header);
+    console::write_line("Su Mo Tu We Th Fr Sa");
+
+    let highlight = match today {
+        Some((y, m, d)) if y == year && m == month => Some(d),
+        _ => None,
+    };
+    let (fg, bg) = console::default_colors();
+    let first = day_of_week(year, month, 1);
+    let days = days_in_month(year, month);
+
+    let mut col = 0;
+    let mut row: HString<32> = HString::new();
+    for _ in 0..first {
+        let _ = row.push_str("   ");
+        col += 1;
+    }
+    for day in 1..=days {
+        let mut cell: HString<4> = HString::new();
+        let _ = write!(cell, "{:>2}", day);
+        if Some(day) == highlight {
+            console::write(&row);
+            row.clear();
+            console::cwrite(&cell, bg, fg);
+        } else {
+            let _ = row.push_str(&cell);
+        }
+        col += 1;
+        if col == 7 {
+            console::write_line(&row);
+            row.clear();
+            col = 0;
+        } else {
+            let _ = row.push(' ');
+        }
+    }
+    if col != 0 {
+        console::write_line(row.trim_end());
+    }
+    Ok(())
+}
+
 pub fn format_hud_time() -> HString<32> {
     let mut out: HString<32> = HString::new();
 
@@ -1,47 +1,130 @@
 #![allow(dead_code)]
 
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
 use x86_64::instructions::port::Port;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::{commands, time};
+use crate::{cmdline, time};
 
 const PIT_FREQUENCY: u32 = 1193182;
-const DESIRED_FREQUENCY: u32 = 100;
+const DEFAULT_FREQUENCY: u32 = 100;
+pub const SUPPORTED_FREQUENCIES: [u32; 3] = [100, 250, 1000];
 const PIT_COMMAND_PORT: u16 = 0x43;
 const PIT_CHANNEL0_PORT: u16 = 0x40;
 
+const PIT_CH0_PERIODIC: u8 = 0x36;
+const PIT_CH0_ONESHOT: u8 = 0x30;
+const PIT_CH0_LATCH: u8 = 0x00;
+const PIT_CH0_READ_STATUS: u8 = 0xE2;
+const PIT_STATUS_OUT: u8 = 0x80;
+
+static FREQUENCY: AtomicU32 = AtomicU32::new(DEFAULT_FREQUENCY);
+static TICKLESS: AtomicBool = AtomicBool::new(false);
+// Earliest tick some sleeper asked to be woken at; u64::MAX when nobody is waiting.
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+// Ticks covered by the armed one-shot countdown, 0 while ticking periodically.
+static ONESHOT_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Programs the PIT at the `hz=` rate from the kernel command line (100, 250
+/// or 1000; default 100). `tickless` enables tickless idle.
 pub fn init_pit() {
-    let divisor: u16 = (PIT_FREQUENCY / DESIRED_FREQUENCY) as u16;
+    if let Some(hz) = cmdline::value("hz")
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|hz| SUPPORTED_FREQUENCIES.contains(hz))
+    {
+        FREQUENCY.store(hz, Ordering::Relaxed);
+    }
+    TICKLESS.store(cmdline::has_flag("tickless"), Ordering::Relaxed);
+    program_periodic();
+}
 
+fn divisor() -> u64 {
+    (PIT_FREQUENCY / frequency()) as u64
+}
+
+fn program(mode: u8, count: u16) {
     unsafe {
         let mut cmd: Port<u8> = Port::new(PIT_COMMAND_PORT);
         let mut data: Port<u8> = Port::new(PIT_CHANNEL0_PORT);
 
-        cmd.write(0x36);
+        cmd.write(mode);
 
-        data.write((divisor & 0xFF) as u8);
-        data.write((divisor >> 8) as u8);
+        data.write((count & 0xFF) as u8);
+        data.write((count >> 8) as u8);
+    }
+}
+
+fn program_periodic() {
+    program(PIT_CH0_PERIODIC, divisor() as u16);
+}
+
+fn read_counter() -> u16 {
+    unsafe {
+        let mut cmd: Port<u8> = Port::new(PIT_COMMAND_PORT);
+        let mut data: Port<u8> = Port::new(PIT_CHANNEL0_PORT);
+        cmd.write(PIT_CH0_LATCH);
+        let lo = data.read() as u16;
+        let hi = data.read() as u16;
+        (hi << 8) | lo
+    }
+}
+
+/// OUT goes high once a one-shot countdown reaches zero.
+fn oneshot_expired() -> bool {
+    unsafe {
+        Port::<u8>::new(PIT_COMMAND_PORT).write(PIT_CH0_READ_STATUS);
+        Port::<u8>::new(PIT_CHANNEL0_PORT).read() & PIT_STATUS_OUT != 0
     }
 }
 
 static mut SUBSECOND_TICKS: u64 = 0;
+static mut CENTISECOND_ACC: u64 = 0;
 static mut TICKS: u64 = 0;
 
-pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    crate::console::tick();
-    commands::tick();
-
+/// Accounts `n` elapsed ticks. Returns true if a 1/100 s boundary was crossed.
+/// Must run with interrupts disabled.
+fn advance(n: u64) -> bool {
+    let hz = frequency() as u64;
+    crate::idle::on_tick(n);
     unsafe {
-        TICKS = TICKS.wrapping_add(1);
-        SUBSECOND_TICKS = SUBSECOND_TICKS.wrapping_add(1);
+        TICKS = TICKS.wrapping_add(n);
+        if NEXT_DEADLINE.load(Ordering::Relaxed) <= TICKS {
+            NEXT_DEADLINE.store(u64::MAX, Ordering::Relaxed);
+        }
 
-        if SUBSECOND_TICKS >= (DESIRED_FREQUENCY as u64) {
-            SUBSECOND_TICKS = 0;
+        SUBSECOND_TICKS += n;
+        while SUBSECOND_TICKS >= hz {
+            SUBSECOND_TICKS -= hz;
             time::tick_second();
         }
+
+        CENTISECOND_ACC += n * 100;
+        if CENTISECOND_ACC >= hz {
+            CENTISECOND_ACC %= hz;
+            return true;
+        }
     }
+    false
+}
 
-    crate::thud::on_100hz_tick();
+pub extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: InterruptStackFrame) {
+    crate::interrupts::count(crate::interrupts::Irq::Timer);
+    let n = match ONESHOT_TICKS.swap(0, Ordering::Relaxed) {
+        0 => 1,
+        armed => {
+            program_periodic();
+            armed
+        }
+    };
+    let centisecond = advance(n);
+    crate::task::watchdog_tick(&mut stack_frame);
+
+    // Cursor blink and HUD run at 100 Hz whatever the tick rate is.
+    if centisecond {
+        crate::console::tick();
+        crate::thud::on_100hz_tick();
+    }
     crate::thud::poll_draw();
 
     unsafe {
@@ -50,14 +133,70 @@ pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptSta
     }
 }
 
+/// Asks for the CPU to be awake by `tick`. Sleepers call this before idling
+/// so tickless idle knows how far it may stretch the timer.
+pub fn request_wakeup(tick: u64) {
+    NEXT_DEADLINE.fetch_min(tick, Ordering::Relaxed);
+}
+
+pub fn set_tickless(on: bool) {
+    TICKLESS.store(on, Ordering::Relaxed);
+}
+
+pub fn tickless() -> bool {
+    TICKLESS.load(Ordering::Relaxed)
+}
+
+/// Halts until the next interrupt. In tickless mode the PIT is switched to a
+/// single countdown up to the next requested deadline (bounded by its 16-bit
+/// counter), so an idle CPU is not woken every tick.
+pub fn halt() {
+    if !tickless() {
+        interrupts::enable_and_hlt();
+        return;
+    }
+
+    interrupts::disable();
+    let div = divisor();
+    let max_ahead = u16::MAX as u64 / div;
+    let ahead = NEXT_DEADLINE
+        .load(Ordering::Relaxed)
+        .saturating_sub(ticks())
+        .min(max_ahead);
+    if ahead < 2 {
+        interrupts::enable_and_hlt();
+        return;
+    }
+    program(PIT_CH0_ONESHOT, (div * ahead) as u16);
+    ONESHOT_TICKS.store(ahead, Ordering::Relaxed);
+    interrupts::enable_and_hlt();
+
+    // Another interrupt woke us first: account for the whole ticks that did
+    // pass and go back to ticking periodically.
+    interrupts::without_interrupts(|| {
+        let armed = ONESHOT_TICKS.load(Ordering::Relaxed);
+        if armed == 0 || oneshot_expired() {
+            // Either the timer interrupt already ran or it is pending.
+            return;
+        }
+        ONESHOT_TICKS.store(0, Ordering::Relaxed);
+        let remaining = (read_counter() as u64).min(armed * div);
+        program_periodic();
+        let elapsed = (armed * div - remaining) / div;
+        if elapsed > 0 {
+            advance(elapsed);
+        }
+    });
+}
+
 pub fn ticks() -> u64 {
     unsafe { TICKS }
 }
 
 pub fn seconds() -> u64 {
-    unsafe { TICKS / (DESIRED_FREQUENCY as u64) }
+    unsafe { TICKS / frequency() as u64 }
 }
 
 pub fn frequency() -> u32 {
-    DESIRED_FREQUENCY
-}
\ No newline at end of file
+    FREQUENCY.load(Ordering::Relaxed)
+}
@@ -0,0 +1,102 @@
+use alloc::format;
+use crate::{console, keyboard, rng, settings, timer};
+use crate::keyboard::KeyEvent;
+
+const BEST_KEY: &str = "typetest.best_wpm";
+const PROMPT_COLOR: u32 = 0x7FB8FF;
+const OK_COLOR: u32 = 0x7FFF7F;
+const MISS_COLOR: u32 = 0xFF5F5F;
+
+const SENTENCES: &[&str] = &[
+    "the quick brown fox jumps over the lazy dog",
+    "a kernel is the part of the system that never gets to sleep in",
+    "every interrupt deserves a handler and every handler deserves an eoi",
+    "pack my box with five dozen liquor jugs",
+    "rust makes it hard to shoot yourself in the foot but not impossible",
+    "the framebuffer does not care how you feel about double buffering",
+    "sphinx of black quartz judge my vow",
+    "stratos boots faster than you can find the power button",
+];
+
+pub fn typetest_cmd() {
+    let target = SENTENCES[rng::below(SENTENCES.len() as u64) as usize];
+    let target: heapless::Vec<char, 128> = target.chars().collect();
+
+    console::write_line("Type the sentence below, then press Enter (Esc cancels):");
+    let mut s = heapless::String::<128>::new();
+    for ch in target.iter() {
+        let _ = s.push(*ch);
+    }
+    console::cwrite_line(&s, PROMPT_COLOR, console::default_bg());
+
+    let bg = console::default_bg();
+    let mut typed: heapless::Vec<char, 128> = heapless::Vec::new();
+    let mut keystrokes: u32 = 0;
+    let mut misses: u32 = 0;
+    let mut start: Option<u64> = None;
+
+    loop {
+        match keyboard::wait_event() {
+            KeyEvent::Char(c) => {
+                if typed.len() >= target.len() {
+                    continue;
+                }
+                start.get_or_insert_with(timer::ticks);
+                keystrokes += 1;
+                let ok = target.get(typed.len()) == Some(&c);
+                if !ok {
+                    misses += 1;
+                }
+                let _ = typed.push(c);
+                console::cput_char(c, if ok { OK_COLOR } else { MISS_COLOR }, bg);
+            }
+            KeyEvent::Backspace if !typed.is_empty() => {
+                typed.pop();
+                console::with_console(|c| c.backspace());
+            }
+            KeyEvent::Enter => break,
+            KeyEvent::Escape => {
+                console::write_line("");
+                console::write_line("Typing test cancelled.");
+                return;
+            }
+            _ => {}
+        }
+    }
+    console::write_line("");
+
+    let Some(start) = start else {
+        console::write_line("Nothing typed.");
+        return;
+    };
+    let elapsed_ticks = timer::ticks().saturating_sub(start).max(1);
+    let freq = timer::frequency() as u64;
+    let correct = typed
+        .iter()
+        .zip(target.iter())
+        .filter(|(a, b)| a == b)
+        .count() as u64;
+
+    // Standard "word" = 5 characters; only correctly placed characters count.
+    let wpm = (correct * 60 * freq / 5 / elapsed_ticks) as u32;
+    let accuracy = (keystrokes.saturating_sub(misses) * 100).checked_div(keystrokes).unwrap_or(0);
+
+    console::write_line(&format!(
+        "Time: {}.{:02}s  WPM: {}  Accuracy: {}%",
+        elapsed_ticks / freq,
+        (elapsed_ticks % freq) * 100 / freq,
+        wpm,
+        accuracy
+    ));
+    if typed.len() < target.len() {
+        console::write_line("(sentence not finished)");
+    }
+
+    let best = settings::get_u32(BEST_KEY).unwrap_or(0);
+    if wpm > best && typed.len() == target.len() {
+        settings::set_u32(BEST_KEY, wpm);
+        console::cwrite_line(&format!("New best: {} WPM!", wpm), OK_COLOR, bg);
+    } else if best > 0 {
+        console::write_line(&format!("Best: {} WPM", best));
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::format;
+use alloc::vec::Vec;
+use crate::{console, memory, ramfs};
+
+// `view` shows images kept in RAM files: binary netpbm (P5 grayscale or P6
+// color, as `memviz` exports them) or bare RGB/RGBA pixels, whose size is
+// guessed from the screen's aspect ratio.
+
+const VIEW_USAGE: &str = "Usage: view [-d <seconds>] <file...>";
+const DEFAULT_DELAY_SECONDS: u64 = 5;
+const MAX_DELAY_SECONDS: u64 = 3600;
+
+/// Decoded pixels, kept in frames rather than on the small kernel heap.
+struct Image {
+    buf: &'static mut [u8],
+    len: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Image {
+    fn new(len: usize, width: usize, height: usize) -> Result<Self, &'static str> {
+        let buf = memory::alloc_buffer(len).ok_or("not enough memory")?;
+        Ok(Image { buf, len, width, height })
+    }
+
+    fn pixels(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        memory::free_buffer(core::mem::take(&mut self.buf));
+    }
+}
+
+/// The next whitespace-separated header field of a netpbm file, skipping
+/// `#` comments. Leaves `at` on the whitespace after the field.
+fn header_field<'a>(data: &'a [u8], at: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match data.get(*at)? {
+            b'#' => {
+                while *data.get(*at)? != b'\n' {
+                    *at += 1;
+                }
+            }
+            c if c.is_ascii_whitespace() => *at += 1,
+            _ => break,
+        }
+    }
+    let start = *at;
+    while data.get(*at).is_some_and(|c| !c.is_ascii_whitespace()) {
+        *at += 1;
+    }
+    Some(&data[start..*at])
+}
+
+fn header_number(data: &[u8], at: &mut usize) -> Result<usize, &'static str> {
+    let field = header_field(data, at).ok_or("truncated header")?;
+    core::str::from_utf8(field).ok().and_then(|s| s.parse().ok()).ok_or("bad header")
+}
+
+fn decode_netpbm(data: &[u8]) -> Result<Image, &'static str> {
+    let gray = data.starts_with(b"P5");
+    let mut at = 2;
+    let width = header_number(data, &mut at)?;
+    let height = header_number(data, &mut at)?;
+    let maxval = header_number(data, &mut at)?;
+    if maxval == 0 || maxval > 255 {
+        return Err("only 8-bit netpbm images are supported");
+    }
+    // Exactly one whitespace byte separates the header from the pixels.
+    let pixels = data.get(at + 1..).unwrap_or(&[]);
+    let len = width.checked_mul(height).ok_or("image too large")? * if gray { 1 } else { 3 };
+    if width == 0 || height == 0 || pixels.len() < len {
+        return Err("truncated image");
+    }
+    let scale = |v: u8| (v as usize * 255 / maxval) as u8;
+    let image = Image::new(width * height * 3, width, height)?;
+    if gray {
+        for (px, &v) in image.buf.chunks_exact_mut(3).zip(&pixels[..len]) {
+            px.fill(scale(v));
+        }
+    } else {
+        for (out, &v) in image.buf.iter_mut().zip(&pixels[..len]) {
+            *out = scale(v);
+        }
+    }
+    Ok(image)
+}
+
+fn decode(data: &[u8]) -> Result<Image, &'static str> {
+    if data.starts_with(b"P5") || data.starts_with(b"P6") {
+        return decode_netpbm(data);
+    }
+    if data.is_empty() || !(data.len().is_multiple_of(3) || data.len().is_multiple_of(4)) {
+        return Err("not a netpbm image or raw RGB/RGBA pixels");
+    }
+    let image = Image::new(data.len(), 0, 0)?;
+    image.buf[..data.len()].copy_from_slice(data);
+    Ok(image)
+}
+
+/// `view [-d <seconds>] <file...>`: shows each file full screen for the
+/// delay. Any key moves on early; Esc or q stops.
+pub fn view_cmd(args: &[&str]) -> crate::commands::Status {
+    let (delay, names) = match args {
+        ["-d", secs, rest @ ..] => match secs.parse::<u64>() {
+            Ok(s) if (1..=MAX_DELAY_SECONDS).contains(&s) => (s, rest),
+            _ => {
+                console::write_err(&format!("view: delay must be 1-{} seconds", MAX_DELAY_SECONDS));
+                return Err(1);
+            }
+        },
+        _ => (DEFAULT_DELAY_SECONDS, args),
+    };
+    if names.is_empty() {
+        console::write_err(VIEW_USAGE);
+        return Err(2);
+    }
+
+    let mut images = Vec::new();
+    for name in names {
+        let Some(data) = ramfs::read(name) else {
+            console::write_err(&format!("view: no file {}", name));
+            return Err(1);
+        };
+        match decode(&data) {
+            Ok(image) => images.push(image),
+            Err(msg) => {
+                console::write_err(&format!("view: {}: {}", name, msg));
+                return Err(1);
+            }
+        }
+    }
+    let frames: Vec<(&[u8], usize, usize)> = images.iter().map(|i| (i.pixels(), i.width, i.height)).collect();
+    console::slideshow(&frames, delay * 1000);
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use x86_64::instructions::port::Port;
+use crate::{memory, pci};
+
+// The legacy (virtio 0.9.5) PCI transport, shared by the virtio drivers.
+// Devices are found by their transitional PCI ID, driven through the I/O
+// ports in BAR0 and polled; no driver here takes interrupts.
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+// Legacy register offsets from BAR0.
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_PFN: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_STATUS: u16 = 0x12;
+/// Device-specific config starts here while MSI-X is off.
+pub const REG_CONFIG: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 0x80;
+
+pub const DESC_F_NEXT: u16 = 1;
+pub const DESC_F_WRITE: u16 = 2;
+
+pub const PAGE: usize = memory::FRAME_SIZE as usize;
+
+/// Page-aligned, zeroed physical memory and where it is mapped.
+#[derive(Clone, Copy)]
+pub struct Dma {
+    pub phys: u64,
+    pub virt: u64,
+}
+
+impl Dma {
+    pub fn alloc(pages: usize) -> Option<Self> {
+        let phys = memory::alloc_frames(pages)?;
+        let Some(virt) = memory::phys_to_virt(phys) else {
+            memory::free_frames(phys, pages);
+            return None;
+        };
+        unsafe { ptr::write_bytes(virt as *mut u8, 0, pages * PAGE) };
+        Some(Dma { phys, virt })
+    }
+
+    /// The memory as bytes. Only valid while the device is not writing it.
+    pub fn bytes(&mut self, len: usize) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt as *mut u8, len) }
+    }
+}
+
+/// A split virtqueue in the legacy layout: descriptors, then the available
+/// ring, then the used ring on the next page boundary.
+pub struct Queue {
+    index: u16,
+    mem: Dma,
+    pub size: u16,
+    used_offset: u64,
+    next_avail: u16,
+    last_used: u16,
+}
+
+impl Queue {
+    fn layout(size: u16) -> (u64, usize) {
+        let size = size as u64;
+        let align = |n: u64| n.div_ceil(PAGE as u64) * PAGE as u64;
+        let used_offset = align(16 * size + 6 + 2 * size);
+        let total = used_offset + align(6 + 8 * size);
+        (used_offset, (total / PAGE as u64) as usize)
+    }
+
+    pub fn set_desc(&mut self, i: u16, phys: u64, len: u32, flags: u16, next: u16) {
+        let d = self.mem.virt + 16 * i as u64;
+        unsafe {
+            ptr::write_volatile(d as *mut u64, phys);
+            ptr::write_volatile((d + 8) as *mut u32, len);
+            ptr::write_volatile((d + 12) as *mut u16, flags);
+            ptr::write_volatile((d + 14) as *mut u16, next);
+        }
+    }
+
+    /// Puts the chain starting at descriptor `i` on the available ring. The
+    /// device still has to be notified.
+    pub fn offer(&mut self, i: u16) {
+        let avail = self.mem.virt + 16 * self.size as u64;
+        let slot = avail + 4 + 2 * (self.next_avail % self.size) as u64;
+        unsafe { ptr::write_volatile(slot as *mut u16, i) };
+        self.next_avail = self.next_avail.wrapping_add(1);
+        fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile((avail + 2) as *mut u16, self.next_avail) };
+        fence(Ordering::SeqCst);
+    }
+
+    /// The next chain the device has finished with, and how many bytes it
+    /// wrote into it.
+    pub fn take_used(&mut self) -> Option<(u16, usize)> {
+        let used = self.mem.virt + self.used_offset;
+        let idx = unsafe { ptr::read_volatile((used + 2) as *const u16) };
+        if idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let entry = used + 4 + 8 * (self.last_used % self.size) as u64;
+        self.last_used = self.last_used.wrapping_add(1);
+        unsafe {
+            let id = ptr::read_volatile(entry as *const u32);
+            let len = ptr::read_volatile((entry + 4) as *const u32);
+            Some((id as u16, len as usize))
+        }
+    }
+}
+
+/// A virtio device's I/O port window.
+#[derive(Clone, Copy)]
+pub struct Device {
+    pub io: u16,
+}
+
+impl Device {
+    /// Finds the first device with transitional PCI ID `device_id` and lets
+    /// it answer its ports and do DMA.
+    pub fn find(device_id: u16) -> Result<Option<Device>, &'static str> {
+        let Some(dev) = pci::scan().into_iter().find(|d| d.vendor_id == VENDOR_ID && d.device_id == device_id) else {
+            return Ok(None);
+        };
+        let bar0 = pci::config_read32(dev.bus, dev.device, dev.function, 0x10);
+        if bar0 & 1 == 0 {
+            return Err("BAR0 is not an I/O port range");
+        }
+        pci::enable_io_and_bus_master(&dev);
+        Ok(Some(Device { io: (bar0 & !0x3) as u16 }))
+    }
+
+    fn read8(&self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io + reg).read() }
+    }
+
+    fn write8(&self, reg: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io + reg).write(value) }
+    }
+
+    /// Resets the device and agrees on features: the ones in `wanted` that
+    /// it offers, which are returned.
+    pub fn begin(&self, wanted: u32) -> u32 {
+        self.write8(REG_STATUS, 0);
+        self.write8(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let features = unsafe { Port::<u32>::new(self.io + REG_DEVICE_FEATURES).read() } & wanted;
+        unsafe { Port::<u32>::new(self.io + REG_GUEST_FEATURES).write(features) };
+        features
+    }
+
+    /// Selects queue `index` and hands it memory. None if the device does
+    /// not have that queue or there is no memory for it.
+    pub fn queue(&self, index: u16) -> Option<Queue> {
+        let size = unsafe {
+            Port::<u16>::new(self.io + REG_QUEUE_SELECT).write(index);
+            Port::<u16>::new(self.io + REG_QUEUE_SIZE).read()
+        };
+        if size == 0 {
+            return None;
+        }
+        let (used_offset, pages) = Queue::layout(size);
+        let mem = Dma::alloc(pages)?;
+        unsafe { Port::<u32>::new(self.io + REG_QUEUE_PFN).write((mem.phys / PAGE as u64) as u32) };
+        Some(Queue { index, mem, size, used_offset, next_avail: 0, last_used: 0 })
+    }
+
+    /// Tells the device setup is done and it may start using the queues.
+    pub fn ready(&self) {
+        self.write8(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+    }
+
+    /// Tells the device the driver gave up on it.
+    pub fn fail(&self) {
+        self.write8(REG_STATUS, self.read8(REG_STATUS) | STATUS_FAILED);
+    }
+
+    pub fn notify(&self, queue: &Queue) {
+        unsafe { Port::<u16>::new(self.io + REG_QUEUE_NOTIFY).write(queue.index) };
+    }
+
+    pub fn config_read8(&self, offset: u16) -> u8 {
+        self.read8(REG_CONFIG + offset)
+    }
+
+    pub fn config_read16(&self, offset: u16) -> u16 {
+        unsafe { Port::<u16>::new(self.io + REG_CONFIG + offset).read() }
+    }
+}
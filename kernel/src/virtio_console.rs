@@ -0,0 +1,179 @@
+use alloc::format;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::virtio::{Device, Dma, Queue, DESC_F_WRITE, PAGE, REG_CONFIG};
+use crate::{klog, wait};
+
+// A virtio console, as QEMU gives with `-device virtio-serial-pci -device
+// virtconsole,chardev=...`, used as a second serial line next to COM1. Only
+// the first port is driven. Output goes out a page per notify instead of a
+// port write per byte, which is what makes big dumps quick.
+
+/// Transitional virtio console; modern-only devices are not handled.
+const DEVICE_ID: u16 = 0x1003;
+// Config: cols, rows, max_nr_ports, emerg_wr.
+const REG_EMERG_WR: u16 = REG_CONFIG + 8;
+const F_EMERG_WRITE: u32 = 1 << 2;
+
+const RECEIVEQ: u16 = 0;
+const TRANSMITQ: u16 = 1;
+
+/// The receive page is cut into this many buffers, all kept posted.
+const RX_BUFFERS: usize = 16;
+const RX_BUFFER_LEN: usize = PAGE / RX_BUFFERS;
+/// How long a send may take before the device is given up on.
+const TX_TIMEOUT_US: u64 = 100_000;
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+static PRESENT: AtomicBool = AtomicBool::new(false);
+// Kept outside the lock so fault paths can write without taking it. Zero
+// when the device has no emergency write register.
+static EMERG_PORT: AtomicU16 = AtomicU16::new(0);
+
+struct Console {
+    dev: Device,
+    rx: Queue,
+    tx: Queue,
+    rx_buf: Dma,
+    tx_buf: Dma,
+    /// Receive buffer being read from: descriptor, bytes in it, bytes read.
+    reading: Option<(u16, usize, usize)>,
+}
+
+impl Console {
+    fn send(&mut self, bytes: &[u8]) -> bool {
+        for chunk in bytes.chunks(PAGE) {
+            unsafe {
+                ptr::copy_nonoverlapping(chunk.as_ptr(), self.tx_buf.virt as *mut u8, chunk.len());
+            }
+            self.tx.set_desc(0, self.tx_buf.phys, chunk.len() as u32, 0, 0);
+            self.tx.offer(0);
+            self.dev.notify(&self.tx);
+            let tx = &mut self.tx;
+            if !wait::until_micros(TX_TIMEOUT_US, || tx.take_used().is_some()) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        let (id, len, pos) = match self.reading {
+            Some(r) => r,
+            None => {
+                let (id, len) = self.rx.take_used()?;
+                (id, len.min(RX_BUFFER_LEN), 0)
+            }
+        };
+        if pos >= len {
+            self.repost(id);
+            return self.recv();
+        }
+        let byte = unsafe {
+            ptr::read_volatile((self.rx_buf.virt + (id as usize * RX_BUFFER_LEN + pos) as u64) as *const u8)
+        };
+        if pos + 1 < len {
+            self.reading = Some((id, len, pos + 1));
+        } else {
+            self.repost(id);
+        }
+        Some(byte)
+    }
+
+    fn repost(&mut self, id: u16) {
+        self.reading = None;
+        self.rx.offer(id);
+        self.dev.notify(&self.rx);
+    }
+}
+
+fn bring_up(dev: Device) -> Result<Console, &'static str> {
+    let features = dev.begin(F_EMERG_WRITE);
+    let rx = dev.queue(RECEIVEQ).ok_or("no receive queue")?;
+    let tx = dev.queue(TRANSMITQ).ok_or("no transmit queue")?;
+    let rx_buf = Dma::alloc(1).ok_or("out of memory")?;
+    let tx_buf = Dma::alloc(1).ok_or("out of memory")?;
+    let mut con = Console { dev, rx, tx, rx_buf, tx_buf, reading: None };
+    let posted = RX_BUFFERS.min(con.rx.size as usize);
+    for i in 0..posted as u16 {
+        let phys = rx_buf.phys + (i as usize * RX_BUFFER_LEN) as u64;
+        con.rx.set_desc(i, phys, RX_BUFFER_LEN as u32, DESC_F_WRITE, 0);
+        con.rx.offer(i);
+    }
+
+    dev.ready();
+    dev.notify(&con.rx);
+    if features & F_EMERG_WRITE != 0 {
+        EMERG_PORT.store(dev.io + REG_EMERG_WR, Ordering::Relaxed);
+    }
+    Ok(con)
+}
+
+/// Looks for a virtio console on the PCI bus and starts it.
+pub fn init() {
+    let dev = match Device::find(DEVICE_ID) {
+        Ok(Some(dev)) => dev,
+        Ok(None) => return,
+        Err(msg) => {
+            klog::warn(&format!("virtio-console: {}", msg));
+            return;
+        }
+    };
+    match bring_up(dev) {
+        Ok(con) => {
+            *CONSOLE.lock() = Some(con);
+            PRESENT.store(true, Ordering::Relaxed);
+            klog::info(&format!("virtio-console: ready at I/O {:#x}", dev.io));
+        }
+        Err(msg) => {
+            dev.fail();
+            klog::warn(&format!("virtio-console: {}", msg));
+        }
+    }
+}
+
+pub fn present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// Sends `bytes` as-is. A device that stops answering is dropped, so a
+/// wedged host side cannot stall every log line.
+pub fn write(bytes: &[u8]) {
+    if !present() {
+        return;
+    }
+    let mut lock = CONSOLE.lock();
+    let Some(con) = lock.as_mut() else { return; };
+    if !con.send(bytes) {
+        *lock = None;
+        PRESENT.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Whether the device's lock is held, for the watchdog.
+pub fn busy() -> bool {
+    CONSOLE.is_locked()
+}
+
+/// Returns a received byte if one is waiting, without blocking.
+pub fn try_read() -> Option<u8> {
+    if !present() {
+        return None;
+    }
+    CONSOLE.lock().as_mut()?.recv()
+}
+
+/// Writes through the device's emergency register, one port write per
+/// byte, without taking any lock. Does nothing if the device has none.
+pub fn emergency_write(bytes: &[u8]) {
+    let port = EMERG_PORT.load(Ordering::Relaxed);
+    if port == 0 {
+        return;
+    }
+    let mut reg = Port::<u32>::new(port);
+    for &b in bytes {
+        unsafe { reg.write(b as u32) };
+    }
+}
@@ -1,9 +1,13 @@
 #![allow(dead_code)]
 
-use x86_64::instructions::hlt;
-use crate::timer;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+use crate::{idle, timer};
 
 static mut INITIALIZED: bool = false;
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+const CALIBRATION_TICKS: u64 = 5;
 
 pub fn init() {
     unsafe {
@@ -12,13 +16,75 @@ pub fn init() {
         }
         INITIALIZED = true;
     }
+    calibrate_tsc();
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Measures the TSC against PIT ticks. Needs interrupts enabled.
+fn calibrate_tsc() {
+    let hz = timer::frequency() as u64;
+    let edge = timer::ticks() + 1;
+    while timer::ticks() < edge {
+        core::hint::spin_loop();
+    }
+    let t0 = rdtsc();
+    while timer::ticks() < edge + CALIBRATION_TICKS {
+        core::hint::spin_loop();
+    }
+    let cycles = rdtsc() - t0;
+    TSC_HZ.store(cycles * hz / CALIBRATION_TICKS, Ordering::Relaxed);
+}
+
+/// Calibrated TSC frequency, or 0 before `init`.
+pub fn tsc_hz() -> u64 {
+    TSC_HZ.load(Ordering::Relaxed)
+}
+
+/// Roughly 1 us: the classic ISA delay port write.
+fn io_delay() {
+    unsafe { Port::<u8>::new(0x80).write(0) };
+}
+
+/// Spins until `done` returns true or `us` microseconds pass. Returns whether
+/// `done` succeeded. Does not yield or need interrupts, so it is usable from
+/// drivers and fault paths; before calibration it falls back to I/O delays.
+pub fn until_micros(us: u64, mut done: impl FnMut() -> bool) -> bool {
+    let hz = tsc_hz();
+    if hz == 0 {
+        for _ in 0..us {
+            if done() {
+                return true;
+            }
+            io_delay();
+        }
+        return done();
+    }
+    let deadline = rdtsc() + us * hz / 1_000_000;
+    loop {
+        if done() {
+            return true;
+        }
+        if rdtsc() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Busy-waits `us` microseconds using the TSC.
+pub fn micros(us: u64) {
+    until_micros(us, || false);
 }
 
 pub fn bsec(seconds: u64) {
     let start = timer::ticks();
     let end = start + seconds * timer::frequency() as u64;
     while timer::ticks() < end {
-        hlt();
+        timer::request_wakeup(end);
+        idle::idle();
     }
 }
 
@@ -26,8 +92,23 @@ pub fn bms(ms: u64) {
     let start = timer::ticks();
     let ticks = (ms * timer::frequency() as u64) / 1000;
     while timer::ticks() - start < ticks {
-        hlt();
+        timer::request_wakeup(start + ticks);
+        idle::idle();
+    }
+}
+
+/// Like `bms`, but Ctrl+C cuts it short. Returns false if it did.
+pub fn bms_interruptible(ms: u64) -> bool {
+    let start = timer::ticks();
+    let ticks = (ms * timer::frequency() as u64) / 1000;
+    while timer::ticks() - start < ticks {
+        if crate::keyboard::cancel_requested() {
+            return false;
+        }
+        timer::request_wakeup(start + ticks);
+        idle::idle();
     }
+    true
 }
 
 pub struct Wait {
@@ -47,8 +128,13 @@ impl Wait {
         }
     }
 
+    /// Also asks for a wakeup at the target, since callers idle between polls.
     pub fn done(&self) -> bool {
-        timer::ticks() >= self.target_tick
+        if timer::ticks() >= self.target_tick {
+            return true;
+        }
+        timer::request_wakeup(self.target_tick);
+        false
     }
 
     pub fn remaining(&self) -> u64 {
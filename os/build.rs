@@ -1,20 +1,118 @@
 use std::env;
+use std::fs;
 use std::path::PathBuf;
 
+/// `resolution=WxH` from the config, if present and well formed.
+fn resolution(cfg: &str) -> Option<(u64, u64)> {
+    cfg.lines()
+        .map(|l| l.split('#').next().unwrap_or("").trim())
+        .filter_map(|l| l.split_once('='))
+        .find(|(k, _)| k.trim() == "resolution")
+        .and_then(|(_, v)| v.trim().split_once('x'))
+        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+}
+
+/// Little-endian field readers for the ELF parser below.
+fn u16_at(b: &[u8], at: usize) -> usize {
+    u16::from_le_bytes(b[at..at + 2].try_into().unwrap()) as usize
+}
+
+fn u32_at(b: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap()) as usize
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+/// The kernel's function symbols, sorted by address, in the layout
+/// `kernel/src/symbols.rs` reads: "STRATSYM", a u32 count, then per symbol
+/// a u64 address, u32 size and u32 name offset, then the names, each ending
+/// in a NUL. Empty if the ELF has no symbol table.
+fn symbol_table(elf: &[u8]) -> Vec<u8> {
+    const SHT_SYMTAB: u32 = 2;
+    const STT_FUNC: u8 = 2;
+    let mut syms = Vec::new();
+    if elf.len() >= 64 && &elf[..4] == b"\x7fELF" {
+        let shoff = u64_at(elf, 0x28) as usize;
+        let shentsize = u16_at(elf, 0x3A);
+        let shnum = u16_at(elf, 0x3C);
+        for i in 0..shnum {
+            let sh = shoff + i * shentsize;
+            if u32_at(elf, sh + 4) as u32 != SHT_SYMTAB {
+                continue;
+            }
+            let (offset, size) = (u64_at(elf, sh + 0x18) as usize, u64_at(elf, sh + 0x20) as usize);
+            let strtab = shoff + u32_at(elf, sh + 0x28) * shentsize;
+            let str_off = u64_at(elf, strtab + 0x18) as usize;
+            for sym in elf[offset..offset + size].chunks_exact(24) {
+                let (value, len) = (u64_at(sym, 8), u64_at(sym, 16));
+                if sym[4] & 0xF != STT_FUNC || value == 0 || len == 0 {
+                    continue;
+                }
+                let start = str_off + u32_at(sym, 0);
+                let end = elf[start..].iter().position(|&b| b == 0).map_or(elf.len(), |n| start + n);
+                syms.push((value, len as u32, format!("{:#}", rustc_demangle::demangle(&String::from_utf8_lossy(&elf[start..end])))));
+            }
+        }
+    }
+    syms.sort_by_key(|s| s.0);
+    syms.dedup_by_key(|s| s.0);
+    if syms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut out = b"STRATSYM".to_vec();
+    out.extend_from_slice(&(syms.len() as u32).to_le_bytes());
+    let mut names = Vec::new();
+    for (value, len, name) in &syms {
+        out.extend_from_slice(&value.to_le_bytes());
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+    out.extend_from_slice(&names);
+    out
+}
+
 fn main() {
     let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
 
     let kernel_path = PathBuf::from(
         env::var_os("CARGO_BIN_FILE_KERNEL_kernel")
             .expect("kernel artifact env var not found; check bin name"),
     );
 
+    // stratos.cfg goes onto the FAT partition as the bootloader's ramdisk,
+    // which the kernel reads at boot. The resolution request has to be known
+    // before the kernel runs, so it becomes the bootloader's boot.json.
+    // The kernel's symbol table rides along after the config, past a NUL,
+    // since the kernel cannot know its own addresses when it is compiled.
+    let cfg_path = manifest_dir.join("stratos.cfg");
+    let cfg = fs::read_to_string(&cfg_path).ok();
+    let kernel_elf = fs::read(&kernel_path).expect("failed to read the kernel binary");
+    let mut ramdisk = cfg.clone().unwrap_or_default().into_bytes();
+    ramdisk.push(0);
+    ramdisk.extend_from_slice(&symbol_table(&kernel_elf));
+    let ramdisk_path = out_dir.join("ramdisk");
+    fs::write(&ramdisk_path, &ramdisk).expect("failed to write the ramdisk");
+
     let uefi_img = out_dir.join("uefi.img");
-    bootloader::UefiBoot::new(&kernel_path)
-        .create_disk_image(&uefi_img)
+    let mut boot = bootloader::UefiBoot::new(&kernel_path);
+    boot.set_ramdisk(&ramdisk_path);
+    if let Some((width, height)) = cfg.as_deref().and_then(resolution) {
+        let mut boot_config = bootloader::BootConfig::default();
+        boot_config.frame_buffer.minimum_framebuffer_width = Some(width);
+        boot_config.frame_buffer.minimum_framebuffer_height = Some(height);
+        boot.set_boot_config(&boot_config);
+    }
+    boot.create_disk_image(&uefi_img)
         .expect("failed to build UEFI disk image");
 
     println!("cargo:rerun-if-changed={}", kernel_path.display());
+    println!("cargo:rerun-if-changed={}", cfg_path.display());
 
     println!("cargo:rustc-env=UEFI_IMG={}", uefi_img.display());
 }